@@ -1,3 +1,10 @@
+# -PackedRing: attach the virtio GPU with packed virtqueues (VIRTIO_F_RING_PACKED)
+# -Outputs N: number of virtio GPU displays (max_outputs)
+# -BlockImage path: attach a raw disk image as a virtio-blk device
+# -Multiboot2Test: boot the Multiboot2 test kernel (utils/src/bin/multiboot2-test-kernel.rs) from a disk
+#                  of its own and fail unless it reports success through isa-debug-exit
+param([switch]$PackedRing, [int]$Outputs = 1, [string]$BlockImage, [switch]$Multiboot2Test)
+
 $ErrorActionPreference = "Stop"
 
 $RustArtifactPath = "target/x86_64-unknown-uefi/debug/uefi-test.efi"
@@ -9,4 +16,25 @@ if ($LASTEXITCODE -ne 0) {
     throw "cargo build was failed!";
 }
 Copy-Item $RustArtifactPath $BootloaderPath
-qemu-system-x86_64 -drive "if=pflash,format=raw,file=$OvmfPath" -drive "if=ide,index=0,media=disk,format=raw,file=fat:rw:disk" -vga virtio -s
+if ($Multiboot2Test) {
+    $TestDisk = "target/multiboot2-test-disk"
+    New-Item -ItemType Directory -Force "$TestDisk/EFI/BOOT" | Out-Null
+    Copy-Item $RustArtifactPath "$TestDisk/EFI/BOOT/BOOTX64.efi"
+    cargo run -p utils --bin multiboot2-test-kernel -- "$TestDisk/EFI/BOOT/mb2-test.bin"
+    if ($LASTEXITCODE -ne 0) {
+        throw "building the multiboot2 test kernel was failed!";
+    }
+    Set-Content "$TestDisk/EFI/BOOT/loader.cfg" @("kernel = \EFI\BOOT\mb2-test.bin", "cmdline = multiboot2 test")
+    # the test kernel draws to the GOP framebuffer, which std VGA provides as a linear one
+    qemu-system-x86_64 -drive "if=pflash,format=raw,file=$OvmfPath" -drive "if=ide,index=0,media=disk,format=raw,file=fat:rw:$TestDisk" -vga std -device isa-debug-exit,iobase=0xf4,iosize=0x04 -debugcon stdio -no-reboot
+    # isa-debug-exit: the exit status is (value << 1) | 1
+    if ($LASTEXITCODE -ne 33) {
+        throw "multiboot2 test kernel failed (exit status $LASTEXITCODE)";
+    }
+    Write-Host "multiboot2 test kernel: ok"
+    exit 0
+}
+$VgaDevice = "virtio-vga,max_outputs=$Outputs" + $(if ($PackedRing) { ",packed=on" } else { "" })
+$VgaArgs = @("-vga", "none", "-device", $VgaDevice)
+$BlockArgs = if ($BlockImage) { @("-drive", "if=virtio,format=raw,file=$BlockImage") } else { @() }
+qemu-system-x86_64 -drive "if=pflash,format=raw,file=$OvmfPath" -drive "if=ide,index=0,media=disk,format=raw,file=fat:rw:disk" @VgaArgs @BlockArgs -s
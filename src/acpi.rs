@@ -1,4 +1,13 @@
-use crate::uefi::EfiGuid;
+use crate::{aml, in16, in32, in8, out16, out32, out8, pci, uefi::EfiGuid};
+
+/// Marker for tables reachable from RSDT/XSDT which begin with a `SystemDescriptionTableHeader`
+///
+/// # Safety
+/// The implementing type must be `#[repr(C)]`, start with a `SystemDescriptionTableHeader` and
+/// `SIGNATURE` must be the signature of the table it describes.
+pub unsafe trait SystemDescriptionTable {
+    const SIGNATURE: u32;
+}
 
 #[repr(C)]
 #[derive(Debug)]
@@ -29,6 +38,39 @@ impl RootSystemDescriptionPointer {
     pub const unsafe fn xsdt(&self) -> &ExtendedSystemDescriptionTable {
         &*(self.xsdt_address as usize as *const ExtendedSystemDescriptionTable)
     }
+
+    #[inline]
+    pub const unsafe fn rsdt(&self) -> &RootSystemDescriptionTable {
+        &*(self.rsdt_address as usize as *const RootSystemDescriptionTable)
+    }
+
+    /// Iterates tables through XSDT (ACPI 2.0+) or RSDT (ACPI 1.0)
+    pub unsafe fn tables(&self) -> impl Iterator<Item = &SystemDescriptionTableHeader> {
+        let (rsdt_entries, xsdt_entries): (&[u32], &[u64]) = if self.revision >= 2 {
+            (&[], self.xsdt().entries())
+        } else {
+            (self.rsdt().entries(), &[])
+        };
+
+        rsdt_entries
+            .iter()
+            .map(|&e| e as u64)
+            .chain(xsdt_entries.iter().copied())
+            .map(|a| &*(a as usize as *const SystemDescriptionTableHeader))
+    }
+
+    pub unsafe fn find_table<T: SystemDescriptionTable>(&self) -> Option<&T> {
+        self.find_tables().next()
+    }
+
+    /// for tables which can appear multiple times (e.g. SSDT)
+    pub unsafe fn find_tables<'a, T: SystemDescriptionTable + 'a>(
+        &'a self,
+    ) -> impl Iterator<Item = &'a T> {
+        self.tables()
+            .filter(|h| h.signature == T::SIGNATURE)
+            .map(|h| &*(h as *const _ as *const T))
+    }
 }
 
 #[repr(C)]
@@ -101,9 +143,15 @@ impl SystemDescriptionTableHeader {
     #[inline]
     pub fn signature_str(&self) -> &str {
         unsafe {
-            core::str::from_utf8_unchecked(core::mem::transmute::<_, &[u8; 4]>(&self.signature))
+            core::str::from_utf8_unchecked(core::mem::transmute::<&u32, &[u8; 4]>(&self.signature))
         }
     }
+
+    /// whole table including this header
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self as *const _ as *const u8, self.length as _) }
+    }
 }
 
 #[repr(C)]
@@ -171,8 +219,406 @@ pub struct FixedDescriptionTable {
     // Note: for 4-byte alignment
     pub hypervisor_vendor_identity: [u32; 2],
 }
+unsafe impl SystemDescriptionTable for FixedDescriptionTable {
+    const SIGNATURE: u32 = u32::from_ne_bytes(*b"FACP");
+}
 impl FixedDescriptionTable {
-    pub const SIGNATURE: u32 = u32::from_ne_bytes(*b"FACP");
+    const PM1_CNT_SCI_EN: u16 = 1 << 0;
+    const PM1_CNT_SLP_TYP_SHIFT: u16 = 10;
+    const PM1_CNT_SLP_EN: u16 = 1 << 13;
+
+    pub unsafe fn dsdt_table(&self) -> &DifferentiatedSystemDescriptionTable {
+        // Note: X_DSDTはACPI 2.0以降のFADTにしかない（lengthで判定する）
+        let x_dsdt_end = core::mem::offset_of!(Self, x_dsdt) + 8;
+        let x_dsdt = (self.x_dsdt[0] as u64) | ((self.x_dsdt[1] as u64) << 32);
+        let addr = if self.header.length as usize >= x_dsdt_end && x_dsdt != 0 {
+            x_dsdt
+        } else {
+            self.dsdt as u64
+        };
+
+        &*(addr as usize as *const DifferentiatedSystemDescriptionTable)
+    }
+
+    pub unsafe fn facs(&self) -> Option<&FirmwareACPIControlStructure> {
+        let x_firmware_ctrl_end = core::mem::offset_of!(Self, x_firmware_ctrl) + 8;
+        let x_firmware_ctrl =
+            (self.x_firmware_ctrl[0] as u64) | ((self.x_firmware_ctrl[1] as u64) << 32);
+        let addr = if self.header.length as usize >= x_firmware_ctrl_end && x_firmware_ctrl != 0 {
+            x_firmware_ctrl
+        } else {
+            self.firmware_ctrl as u64
+        };
+        if addr == 0 {
+            // hardware-reduced ACPI
+            return None;
+        }
+
+        Some(&*(addr as usize as *const FirmwareACPIControlStructure))
+    }
+
+    pub fn is_acpi_mode_enabled(&self) -> bool {
+        (unsafe { in16!(self.pm1a_cnt_blk as u16) } & Self::PM1_CNT_SCI_EN) != 0
+    }
+
+    /// Hands the power management registers over from SMM to the OS by writing ACPI_ENABLE to SMI_CMD.
+    ///
+    /// Returns false if the platform did not switch into ACPI mode.
+    pub fn enable_acpi_mode(&self) -> bool {
+        if self.is_acpi_mode_enabled() {
+            return true;
+        }
+        if self.smi_cmd == 0 || self.acpi_enable == 0 {
+            // no way to switch (hardware-reduced or ACPI mode only platform)
+            return false;
+        }
+
+        unsafe {
+            out8!(self.smi_cmd as u16, self.acpi_enable);
+        }
+        // Note: SCI_ENが立つまで少し時間がかかることがある
+        for _ in 0..1_000_000 {
+            if self.is_acpi_mode_enabled() {
+                return true;
+            }
+            core::hint::spin_loop();
+        }
+
+        false
+    }
+
+    /// Writes SLP_TYPx|SLP_EN into PM1a/PM1b control registers.
+    pub unsafe fn enter_sleep_state(&self, (slp_typa, slp_typb): (u8, u8)) {
+        let a = in16!(self.pm1a_cnt_blk as u16) & !(0x07 << Self::PM1_CNT_SLP_TYP_SHIFT);
+        out16!(
+            self.pm1a_cnt_blk as u16,
+            a | ((slp_typa as u16 & 0x07) << Self::PM1_CNT_SLP_TYP_SHIFT) | Self::PM1_CNT_SLP_EN
+        );
+
+        if self.pm1b_cnt_blk != 0 {
+            let b = in16!(self.pm1b_cnt_blk as u16) & !(0x07 << Self::PM1_CNT_SLP_TYP_SHIFT);
+            out16!(
+                self.pm1b_cnt_blk as u16,
+                b | ((slp_typb as u16 & 0x07) << Self::PM1_CNT_SLP_TYP_SHIFT)
+                    | Self::PM1_CNT_SLP_EN
+            );
+        }
+    }
+}
+
+/// Powers off the machine by entering S5 (soft-off) with SLP_TYPa/SLP_TYPb from `\_S5`.
+pub unsafe fn shutdown(fadt: &FixedDescriptionTable, s5: (u8, u8)) -> ! {
+    if !fadt.enable_acpi_mode() {
+        panic!("Failed to enable ACPI mode");
+    }
+
+    fadt.enter_sleep_state(s5);
+
+    // should not reach here
+    loop {
+        unsafe { crate::hlt!() }
+    }
+}
+
+#[repr(C)]
+pub struct DifferentiatedSystemDescriptionTable {
+    pub header: SystemDescriptionTableHeader,
+    definition_block: [u8; 0],
+}
+unsafe impl SystemDescriptionTable for DifferentiatedSystemDescriptionTable {
+    const SIGNATURE: u32 = u32::from_ne_bytes(*b"DSDT");
+}
+impl DifferentiatedSystemDescriptionTable {
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        self.header.as_bytes()
+    }
+
+    pub fn definition_block(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(
+                self.definition_block.as_ptr(),
+                self.header.length as usize - core::mem::size_of::<SystemDescriptionTableHeader>(),
+            )
+        }
+    }
+
+    /// Finds `Name(_S5_, Package() { SLP_TYPa, SLP_TYPb, ... })` by scanning the AML bytecode.
+    pub fn find_s5_sleep_types(&self) -> Option<(u8, u8)> {
+        const NAME_OP: u8 = 0x08;
+        const PACKAGE_OP: u8 = 0x12;
+        const ZERO_OP: u8 = 0x00;
+        const ONE_OP: u8 = 0x01;
+        const BYTE_PREFIX: u8 = 0x0a;
+
+        fn read_integer(aml: &[u8], p: &mut usize) -> Option<u8> {
+            match *aml.get(*p)? {
+                ZERO_OP => {
+                    *p += 1;
+                    Some(0)
+                }
+                ONE_OP => {
+                    *p += 1;
+                    Some(1)
+                }
+                BYTE_PREFIX => {
+                    *p += 2;
+                    aml.get(*p - 1).copied()
+                }
+                _ => None,
+            }
+        }
+
+        let aml = self.definition_block();
+        let candidates = aml
+            .windows(4)
+            .enumerate()
+            .filter(|(_, w)| *w == b"_S5_")
+            .map(|(pos, _)| pos);
+        for pos in candidates {
+            let named = (pos >= 1 && aml[pos - 1] == NAME_OP)
+                || (pos >= 2 && aml[pos - 1] == b'\\' && aml[pos - 2] == NAME_OP);
+            if !named {
+                continue;
+            }
+
+            let mut p = pos + 4;
+            if aml.get(p) != Some(&PACKAGE_OP) {
+                continue;
+            }
+            p += 1;
+            // PkgLength: bit6-7 of the lead byte = count of following bytes
+            let Some(lead) = aml.get(p) else {
+                continue;
+            };
+            p += (lead >> 6) as usize + 1;
+            // NumElements
+            p += 1;
+
+            // Note: 途中で切れている候補は諦めて次の候補を探す
+            let Some(slp_typa) = read_integer(aml, &mut p) else {
+                continue;
+            };
+            let Some(slp_typb) = read_integer(aml, &mut p) else {
+                continue;
+            };
+            return Some((slp_typa, slp_typb));
+        }
+
+        None
+    }
+}
+
+#[repr(C)]
+pub struct SecondarySystemDescriptionTable {
+    pub header: SystemDescriptionTableHeader,
+    definition_block: [u8; 0],
+}
+unsafe impl SystemDescriptionTable for SecondarySystemDescriptionTable {
+    const SIGNATURE: u32 = u32::from_ne_bytes(*b"SSDT");
+}
+impl SecondarySystemDescriptionTable {
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        self.header.as_bytes()
+    }
+}
+
+#[repr(C)]
+pub struct MemoryMappedConfigurationTable {
+    pub header: SystemDescriptionTableHeader,
+    _reserved: [u32; 2],
+    entries: [ConfigurationSpaceBaseAddressAllocation; 0],
+}
+unsafe impl SystemDescriptionTable for MemoryMappedConfigurationTable {
+    const SIGNATURE: u32 = u32::from_ne_bytes(*b"MCFG");
+}
+impl MemoryMappedConfigurationTable {
+    pub fn entries(&self) -> &[ConfigurationSpaceBaseAddressAllocation] {
+        unsafe {
+            core::slice::from_raw_parts(
+                self.entries.as_ptr(),
+                (self.header.length as usize - 44)
+                    / core::mem::size_of::<ConfigurationSpaceBaseAddressAllocation>(),
+            )
+        }
+    }
+}
+
+#[repr(C)]
+pub struct ConfigurationSpaceBaseAddressAllocation {
+    // Note: offset=44から並ぶのでu64だとアラインメントがずれる
+    base_address: [u32; 2],
+    pub segment_group: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+    _reserved: u32,
+}
+impl ConfigurationSpaceBaseAddressAllocation {
+    #[inline]
+    pub const fn base_address(&self) -> u64 {
+        self.base_address[0] as u64 | ((self.base_address[1] as u64) << 32)
+    }
+}
+impl core::fmt::Debug for ConfigurationSpaceBaseAddressAllocation {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ConfigurationSpaceBaseAddressAllocation")
+            .field(
+                "base_address",
+                &format_args!("0x{:016x}", self.base_address()),
+            )
+            .field("segment_group", &self.segment_group)
+            .field("start_bus", &self.start_bus)
+            .field("end_bus", &self.end_bus)
+            .finish()
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct GenericAddressStructure {
+    pub address_space_id: u8,
+    pub register_bit_width: u8,
+    pub register_bit_offset: u8,
+    pub access_size: u8,
+    // Note: for 4-byte alignment
+    address: [u32; 2],
+}
+impl GenericAddressStructure {
+    pub const SPACE_SYSTEM_MEMORY: u8 = 0x00;
+
+    #[inline]
+    pub const fn address(&self) -> u64 {
+        self.address[0] as u64 | ((self.address[1] as u64) << 32)
+    }
+}
+impl core::fmt::Debug for GenericAddressStructure {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("GenericAddressStructure")
+            .field("address_space_id", &self.address_space_id)
+            .field("register_bit_width", &self.register_bit_width)
+            .field("register_bit_offset", &self.register_bit_offset)
+            .field("access_size", &self.access_size)
+            .field("address", &format_args!("0x{:016x}", self.address()))
+            .finish()
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct HighPrecisionEventTimerTable {
+    pub header: SystemDescriptionTableHeader,
+    pub event_timer_block_id: u32,
+    pub base_address: GenericAddressStructure,
+    pub hpet_number: u8,
+    // Note: for 1-byte alignment
+    pub main_counter_minimum_clock_tick: [u8; 2],
+    pub page_protection: u8,
+}
+unsafe impl SystemDescriptionTable for HighPrecisionEventTimerTable {
+    const SIGNATURE: u32 = u32::from_ne_bytes(*b"HPET");
+}
+impl HighPrecisionEventTimerTable {
+    #[inline]
+    pub const fn comparator_count(&self) -> u8 {
+        (((self.event_timer_block_id >> 8) & 0x1f) + 1) as _
+    }
+
+    #[inline]
+    pub const fn minimum_clock_tick(&self) -> u16 {
+        u16::from_le_bytes(self.main_counter_minimum_clock_tick)
+    }
+}
+
+/// Platform access for the AML interpreter (identity mapped memory, port I/O and `pci::ConfigAccess`)
+pub struct AmlPlatformHandler;
+impl AmlPlatformHandler {
+    const fn pci_device(address: aml::PciAddress) -> pci::DeviceIdentifier {
+        pci::DeviceIdentifier {
+            segment: address.segment,
+            bus: address.bus,
+            device: address.device,
+            function: address.function,
+        }
+    }
+}
+impl aml::Handler for AmlPlatformHandler {
+    fn read_memory(&self, address: u64, bit_width: u8) -> u64 {
+        unsafe {
+            match bit_width {
+                8 => core::ptr::read_volatile(address as usize as *const u8) as _,
+                16 => core::ptr::read_volatile(address as usize as *const u16) as _,
+                32 => core::ptr::read_volatile(address as usize as *const u32) as _,
+                _ => core::ptr::read_volatile(address as usize as *const u64),
+            }
+        }
+    }
+
+    fn write_memory(&self, address: u64, bit_width: u8, value: u64) {
+        unsafe {
+            match bit_width {
+                8 => core::ptr::write_volatile(address as usize as *mut u8, value as _),
+                16 => core::ptr::write_volatile(address as usize as *mut u16, value as _),
+                32 => core::ptr::write_volatile(address as usize as *mut u32, value as _),
+                _ => core::ptr::write_volatile(address as usize as *mut u64, value),
+            }
+        }
+    }
+
+    fn read_io(&self, port: u16, bit_width: u8) -> u64 {
+        unsafe {
+            match bit_width {
+                8 => in8!(port) as _,
+                16 => in16!(port) as _,
+                _ => in32!(port) as _,
+            }
+        }
+    }
+
+    fn write_io(&self, port: u16, bit_width: u8, value: u64) {
+        unsafe {
+            match bit_width {
+                8 => {
+                    out8!(port, value as u8);
+                }
+                16 => {
+                    out16!(port, value as u16);
+                }
+                _ => {
+                    out32!(port, value as u32);
+                }
+            }
+        }
+    }
+
+    fn read_pci_config(&self, address: aml::PciAddress, offset: u16, bit_width: u8) -> u64 {
+        let device = Self::pci_device(address);
+
+        match bit_width {
+            8 => device.read_config_u8(offset) as _,
+            16 => device.read_config_u16(offset) as _,
+            _ => device.read_config(offset >> 2) as _,
+        }
+    }
+
+    fn write_pci_config(&self, address: aml::PciAddress, offset: u16, bit_width: u8, value: u64) {
+        let device = Self::pci_device(address);
+
+        match bit_width {
+            8 => device.write_config_u8(offset, value as _),
+            16 => device.write_config_u16(offset, value as _),
+            _ => device.write_config(offset >> 2, value as _),
+        }
+    }
+
+    fn stall(&self, microseconds: u64) {
+        // Note: port 0x80への書き込みはだいたい1usかかる
+        for _ in 0..microseconds {
+            unsafe {
+                out8!(0x80, 0u8);
+            }
+        }
+    }
 }
 
 #[repr(C)]
@@ -182,9 +628,10 @@ pub struct MultipleAPICDescriptionTable {
     pub flags: MultipleAPICDescriptionTableFlags,
     interrupt_controller_structure: [u8; 0],
 }
+unsafe impl SystemDescriptionTable for MultipleAPICDescriptionTable {
+    const SIGNATURE: u32 = u32::from_ne_bytes(*b"APIC");
+}
 impl MultipleAPICDescriptionTable {
-    pub const SIGNATURE: u32 = u32::from_ne_bytes(*b"APIC");
-
     pub fn interrupt_controller_structure_bytes(&self) -> &[u8] {
         unsafe {
             core::slice::from_raw_parts(
@@ -313,3 +760,542 @@ pub struct LocalAPICNMIStructure {
 impl LocalAPICNMIStructure {
     pub const TYPE: u8 = 0x04;
 }
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct FirmwareACPIControlStructure {
+    pub signature: u32,
+    pub length: u32,
+    pub hardware_signature: u32,
+    pub firmware_waking_vector: u32,
+    pub global_lock: u32,
+    pub flags: u32,
+    // Note: for 4-byte alignment
+    pub x_firmware_waking_vector: [u32; 2],
+    pub version: u8,
+    _reserved: [u8; 3],
+    pub ospm_flags: u32,
+    _reserved2: [u8; 24],
+}
+impl FirmwareACPIControlStructure {
+    pub fn has_correct_signature(&self) -> bool {
+        self.signature == u32::from_le_bytes(*b"FACS")
+    }
+
+    /// 64-bit waking vector if set (version 1+), otherwise the real mode one
+    pub fn waking_vector(&self) -> u64 {
+        let x = self.x_firmware_waking_vector[0] as u64
+            | ((self.x_firmware_waking_vector[1] as u64) << 32);
+        if self.version >= 1 && x != 0 {
+            x
+        } else {
+            self.firmware_waking_vector as _
+        }
+    }
+}
+
+#[repr(C)]
+pub struct SystemResourceAffinityTable {
+    pub header: SystemDescriptionTableHeader,
+    _reserved: [u32; 3],
+    static_resource_allocation_structure: [u8; 0],
+}
+unsafe impl SystemDescriptionTable for SystemResourceAffinityTable {
+    const SIGNATURE: u32 = u32::from_ne_bytes(*b"SRAT");
+}
+impl SystemResourceAffinityTable {
+    pub fn static_resource_allocation_structure_bytes(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(
+                self.static_resource_allocation_structure.as_ptr(),
+                self.header.length as usize - 48,
+            )
+        }
+    }
+
+    pub fn structures(&self) -> impl Iterator<Item = SystemResourceAffinityStructure<'_>> {
+        let bytes = self.static_resource_allocation_structure_bytes();
+        let mut ptr = 0;
+
+        core::iter::from_fn(move || {
+            if ptr + 2 > bytes.len() || bytes[ptr + 1] < 2 {
+                return None;
+            }
+            let head = unsafe { bytes.as_ptr().add(ptr) };
+            let (type_byte, length) = (bytes[ptr], bytes[ptr + 1]);
+            ptr += length as usize;
+
+            Some(match type_byte {
+                ProcessorLocalAPICAffinityStructure::TYPE => {
+                    SystemResourceAffinityStructure::ProcessorLocalAPIC(unsafe {
+                        &*(head as *const ProcessorLocalAPICAffinityStructure)
+                    })
+                }
+                MemoryAffinityStructure::TYPE => SystemResourceAffinityStructure::Memory(unsafe {
+                    &*(head as *const MemoryAffinityStructure)
+                }),
+                ProcessorLocalX2APICAffinityStructure::TYPE => {
+                    SystemResourceAffinityStructure::ProcessorLocalX2APIC(unsafe {
+                        &*(head as *const ProcessorLocalX2APICAffinityStructure)
+                    })
+                }
+                t => SystemResourceAffinityStructure::Unknown(t),
+            })
+        })
+    }
+}
+impl core::fmt::Debug for SystemResourceAffinityTable {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SystemResourceAffinityTable")
+            .field("header", &self.header)
+            .field(
+                "structures",
+                &DebugIter(core::cell::Cell::new(Some(self.structures()))),
+            )
+            .finish()
+    }
+}
+
+/// Debug-prints the items of an iterator as a list (consumed once)
+struct DebugIter<I>(core::cell::Cell<Option<I>>);
+impl<I: Iterator> core::fmt::Debug for DebugIter<I>
+where
+    I::Item: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut l = f.debug_list();
+        if let Some(it) = self.0.take() {
+            l.entries(it);
+        }
+        l.finish()
+    }
+}
+
+// Note: 中身はDebug出力（テーブルのダンプ）でしか使わない
+#[derive(Debug)]
+#[allow(dead_code)]
+pub enum SystemResourceAffinityStructure<'a> {
+    ProcessorLocalAPIC(&'a ProcessorLocalAPICAffinityStructure),
+    Memory(&'a MemoryAffinityStructure),
+    ProcessorLocalX2APIC(&'a ProcessorLocalX2APICAffinityStructure),
+    Unknown(u8),
+}
+
+#[repr(C)]
+pub struct ProcessorLocalAPICAffinityStructure {
+    pub r#type: u8,
+    pub length: u8,
+    proximity_domain_lo: u8,
+    pub apic_id: u8,
+    pub flags: u32,
+    pub local_sapic_eid: u8,
+    proximity_domain_hi: [u8; 3],
+    pub clock_domain: u32,
+}
+impl ProcessorLocalAPICAffinityStructure {
+    pub const TYPE: u8 = 0x00;
+
+    #[inline]
+    pub const fn proximity_domain(&self) -> u32 {
+        u32::from_le_bytes([
+            self.proximity_domain_lo,
+            self.proximity_domain_hi[0],
+            self.proximity_domain_hi[1],
+            self.proximity_domain_hi[2],
+        ])
+    }
+
+    #[inline]
+    pub const fn enabled(&self) -> bool {
+        (self.flags & 0x01) != 0
+    }
+}
+impl core::fmt::Debug for ProcessorLocalAPICAffinityStructure {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ProcessorLocalAPICAffinityStructure")
+            .field("proximity_domain", &self.proximity_domain())
+            .field("apic_id", &self.apic_id)
+            .field("enabled", &self.enabled())
+            .field("clock_domain", &self.clock_domain)
+            .finish()
+    }
+}
+
+#[repr(C)]
+pub struct MemoryAffinityStructure {
+    pub r#type: u8,
+    pub length: u8,
+    // Note: for 2-byte alignment
+    proximity_domain: [u16; 2],
+    _reserved: u16,
+    base_address: [u32; 2],
+    length_: [u32; 2],
+    _reserved2: u32,
+    pub flags: u32,
+    _reserved3: [u32; 2],
+}
+impl MemoryAffinityStructure {
+    pub const TYPE: u8 = 0x01;
+
+    #[inline]
+    pub const fn proximity_domain(&self) -> u32 {
+        self.proximity_domain[0] as u32 | ((self.proximity_domain[1] as u32) << 16)
+    }
+
+    #[inline]
+    pub const fn base_address(&self) -> u64 {
+        self.base_address[0] as u64 | ((self.base_address[1] as u64) << 32)
+    }
+
+    #[inline]
+    pub const fn range_length(&self) -> u64 {
+        self.length_[0] as u64 | ((self.length_[1] as u64) << 32)
+    }
+
+    #[inline]
+    pub const fn enabled(&self) -> bool {
+        (self.flags & 0x01) != 0
+    }
+
+    #[inline]
+    pub const fn hot_pluggable(&self) -> bool {
+        (self.flags & 0x02) != 0
+    }
+
+    #[inline]
+    pub const fn non_volatile(&self) -> bool {
+        (self.flags & 0x04) != 0
+    }
+}
+impl core::fmt::Debug for MemoryAffinityStructure {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("MemoryAffinityStructure")
+            .field("proximity_domain", &self.proximity_domain())
+            .field(
+                "base_address",
+                &format_args!("0x{:016x}", self.base_address()),
+            )
+            .field("length", &format_args!("0x{:x}", self.range_length()))
+            .field("enabled", &self.enabled())
+            .field("hot_pluggable", &self.hot_pluggable())
+            .field("non_volatile", &self.non_volatile())
+            .finish()
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct ProcessorLocalX2APICAffinityStructure {
+    pub r#type: u8,
+    pub length: u8,
+    _reserved: u16,
+    pub proximity_domain: u32,
+    pub x2apic_id: u32,
+    pub flags: u32,
+    pub clock_domain: u32,
+    _reserved2: u32,
+}
+impl ProcessorLocalX2APICAffinityStructure {
+    pub const TYPE: u8 = 0x02;
+}
+
+#[repr(C)]
+pub struct SystemLocalityInformationTable {
+    pub header: SystemDescriptionTableHeader,
+    // Note: for 4-byte alignment
+    number_of_system_localities: [u32; 2],
+    entry: [u8; 0],
+}
+unsafe impl SystemDescriptionTable for SystemLocalityInformationTable {
+    const SIGNATURE: u32 = u32::from_ne_bytes(*b"SLIT");
+}
+impl SystemLocalityInformationTable {
+    #[inline]
+    pub const fn locality_count(&self) -> usize {
+        (self.number_of_system_localities[0] as u64
+            | ((self.number_of_system_localities[1] as u64) << 32)) as _
+    }
+
+    /// relative distance from locality `from` to `to` (10 = local)
+    pub fn distance(&self, from: usize, to: usize) -> u8 {
+        let n = self.locality_count();
+        assert!(from < n && to < n, "locality out of range");
+
+        unsafe { *self.entry.as_ptr().add(from * n + to) }
+    }
+}
+impl core::fmt::Debug for SystemLocalityInformationTable {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let n = self.locality_count();
+        f.debug_struct("SystemLocalityInformationTable")
+            .field("header", &self.header)
+            .field(
+                "distances",
+                &DebugIter(core::cell::Cell::new(Some((0..n).map(|i| {
+                    DebugIter(core::cell::Cell::new(Some(
+                        (0..n).map(move |j| self.distance(i, j)),
+                    )))
+                })))),
+            )
+            .finish()
+    }
+}
+
+#[repr(C)]
+pub struct BootGraphicsResourceTable {
+    pub header: SystemDescriptionTableHeader,
+    pub version: u16,
+    pub status: u8,
+    pub image_type: u8,
+    // Note: for 4-byte alignment
+    image_address: [u32; 2],
+    pub image_offset_x: u32,
+    pub image_offset_y: u32,
+}
+unsafe impl SystemDescriptionTable for BootGraphicsResourceTable {
+    const SIGNATURE: u32 = u32::from_ne_bytes(*b"BGRT");
+}
+impl BootGraphicsResourceTable {
+    pub const IMAGE_TYPE_BITMAP: u8 = 0;
+
+    #[inline]
+    pub const fn image_address(&self) -> u64 {
+        self.image_address[0] as u64 | ((self.image_address[1] as u64) << 32)
+    }
+
+    /// the logo is currently drawn on the screen
+    #[inline]
+    pub const fn displayed(&self) -> bool {
+        (self.status & 0x01) != 0
+    }
+
+    /// whole BMP file (size taken from its file header)
+    pub unsafe fn image(&self) -> Option<&[u8]> {
+        if self.image_type != Self::IMAGE_TYPE_BITMAP || self.image_address() == 0 {
+            return None;
+        }
+
+        let p = self.image_address() as usize as *const u8;
+        let head = core::slice::from_raw_parts(p, 6);
+        if &head[..2] != b"BM" {
+            return None;
+        }
+        let size = u32::from_le_bytes([head[2], head[3], head[4], head[5]]);
+        Some(core::slice::from_raw_parts(p, size as _))
+    }
+}
+impl core::fmt::Debug for BootGraphicsResourceTable {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("BootGraphicsResourceTable")
+            .field("header", &self.header)
+            .field("version", &self.version)
+            .field("displayed", &self.displayed())
+            .field("image_type", &self.image_type)
+            .field(
+                "image_address",
+                &format_args!("0x{:016x}", self.image_address()),
+            )
+            .field("image_offset_x", &self.image_offset_x)
+            .field("image_offset_y", &self.image_offset_y)
+            .finish()
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct SerialPortConsoleRedirectionTable {
+    pub header: SystemDescriptionTableHeader,
+    pub interface_type: u8,
+    _reserved: [u8; 3],
+    pub base_address: GenericAddressStructure,
+    pub interrupt_type: u8,
+    pub irq: u8,
+    // Note: for 2-byte alignment
+    pub global_system_interrupt: [u16; 2],
+    pub configured_baud_rate: u8,
+    pub parity: u8,
+    pub stop_bits: u8,
+    pub flow_control: u8,
+    pub terminal_type: u8,
+    pub language: u8,
+    pub pci_device_id: u16,
+    pub pci_vendor_id: u16,
+    pub pci_bus_number: u8,
+    pub pci_device_number: u8,
+    pub pci_function_number: u8,
+    // Note: for 1-byte alignment
+    pub pci_flags: [u8; 4],
+    pub pci_segment: u8,
+    /// revision 3+
+    pub uart_clock_frequency: u32,
+}
+unsafe impl SystemDescriptionTable for SerialPortConsoleRedirectionTable {
+    const SIGNATURE: u32 = u32::from_ne_bytes(*b"SPCR");
+}
+impl SerialPortConsoleRedirectionTable {
+    /// None if the firmware leaves the current setting as is
+    pub const fn baud_rate(&self) -> Option<u32> {
+        match self.configured_baud_rate {
+            3 => Some(9600),
+            4 => Some(19200),
+            6 => Some(57600),
+            7 => Some(115200),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub const fn global_system_interrupt(&self) -> u32 {
+        self.global_system_interrupt[0] as u32 | ((self.global_system_interrupt[1] as u32) << 16)
+    }
+}
+
+#[repr(C)]
+pub struct DebugPort2Table {
+    pub header: SystemDescriptionTableHeader,
+    pub offset_debug_device_info: u32,
+    pub number_debug_device_info: u32,
+}
+unsafe impl SystemDescriptionTable for DebugPort2Table {
+    const SIGNATURE: u32 = u32::from_ne_bytes(*b"DBG2");
+}
+impl DebugPort2Table {
+    pub fn debug_devices(&self) -> impl Iterator<Item = &DebugDeviceInformation> {
+        let base = self as *const _ as *const u8;
+        let mut offset = self.offset_debug_device_info as usize;
+
+        (0..self.number_debug_device_info).map(move |_| {
+            let d = unsafe { &*(base.add(offset) as *const DebugDeviceInformation) };
+            offset += d.length() as usize;
+            d
+        })
+    }
+}
+impl core::fmt::Debug for DebugPort2Table {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("DebugPort2Table")
+            .field("header", &self.header)
+            .field(
+                "debug_devices",
+                &DebugIter(core::cell::Cell::new(Some(self.debug_devices()))),
+            )
+            .finish()
+    }
+}
+
+#[repr(C)]
+pub struct DebugDeviceInformation {
+    pub revision: u8,
+    // Note: for 1-byte alignment
+    length: [u8; 2],
+    pub number_of_generic_address_registers: u8,
+    pub namespace_string_length: u16,
+    pub namespace_string_offset: u16,
+    pub oem_data_length: u16,
+    pub oem_data_offset: u16,
+    pub port_type: u16,
+    pub port_subtype: u16,
+    _reserved: u16,
+    pub base_address_register_offset: u16,
+    pub address_size_offset: u16,
+}
+impl DebugDeviceInformation {
+    #[inline]
+    pub const fn length(&self) -> u16 {
+        u16::from_le_bytes(self.length)
+    }
+
+    #[inline]
+    fn bytes_at(&self, offset: u16, length: usize) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts((self as *const _ as *const u8).add(offset as _), length)
+        }
+    }
+
+    pub fn base_address_registers(&self) -> impl Iterator<Item = GenericAddressStructure> + '_ {
+        // Note: オフセットがアラインされている保証はないのでコピーして返す
+        self.bytes_at(
+            self.base_address_register_offset,
+            self.number_of_generic_address_registers as usize
+                * core::mem::size_of::<GenericAddressStructure>(),
+        )
+        .chunks_exact(core::mem::size_of::<GenericAddressStructure>())
+        .map(|c| unsafe { core::ptr::read_unaligned(c.as_ptr() as *const GenericAddressStructure) })
+    }
+
+    /// size of each address range in `base_address_registers`
+    pub fn address_sizes(&self) -> impl Iterator<Item = u32> + '_ {
+        self.bytes_at(
+            self.address_size_offset,
+            self.number_of_generic_address_registers as usize * 4,
+        )
+        .chunks_exact(4)
+        .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+    }
+
+    /// ACPI namespace path of the device ("." if none)
+    pub fn namespace_string(&self) -> &str {
+        let s = self.bytes_at(
+            self.namespace_string_offset,
+            self.namespace_string_length as _,
+        );
+        let s = s.split(|&c| c == 0).next().unwrap_or(&[]);
+        core::str::from_utf8(s).unwrap_or("<invalid>")
+    }
+}
+impl core::fmt::Debug for DebugDeviceInformation {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("DebugDeviceInformation")
+            .field("revision", &self.revision)
+            .field("port_type", &format_args!("0x{:04x}", self.port_type))
+            .field("port_subtype", &format_args!("0x{:04x}", self.port_subtype))
+            .field(
+                "base_address_registers",
+                &DebugIter(core::cell::Cell::new(Some(self.base_address_registers()))),
+            )
+            .field(
+                "address_sizes",
+                &DebugIter(core::cell::Cell::new(Some(self.address_sizes()))),
+            )
+            .field("namespace_string", &self.namespace_string())
+            .finish()
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct WindowsACPIEmulatedDevicesTable {
+    pub header: SystemDescriptionTableHeader,
+    pub emulated_device_flags: EmulatedDeviceFlags,
+}
+unsafe impl SystemDescriptionTable for WindowsACPIEmulatedDevicesTable {
+    const SIGNATURE: u32 = u32::from_ne_bytes(*b"WAET");
+}
+
+#[repr(transparent)]
+pub struct EmulatedDeviceFlags(pub u32);
+impl core::fmt::Debug for EmulatedDeviceFlags {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut v = self.0;
+        let mut wrote = false;
+
+        for (bit, name) in [(0x01, "RTC_GOOD"), (0x02, "ACPI_PM_TIMER_GOOD")] {
+            if (v & bit) != 0 {
+                f.write_str(if wrote { " | " } else { "" })?;
+                f.write_str(name)?;
+                wrote = true;
+                v &= !bit;
+            }
+        }
+
+        if v != 0 {
+            if wrote {
+                write!(f, " | {v:08x}")?;
+            } else {
+                write!(f, "{v:08x}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
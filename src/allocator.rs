@@ -0,0 +1,151 @@
+//! Global allocator backing `alloc` (introduced for the AML namespace, also used by the drivers):
+//! a first-fit free list over a static 64MiB area, sorted by address and coalesced on free
+
+use crate::interrupt::without_interrupts;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    cell::UnsafeCell,
+};
+
+// Note: ExitBootServicesの前後どちらでも使えるように、UEFIのpoolではなく静的領域から切り出す
+// Note: virtio-gpuのフレームバッファ（1920x1080で約8MB）をディスプレイごとに確保できる大きさにしておく
+const HEAP_SIZE: usize = 64 * 1024 * 1024;
+const BLOCK_UNIT: usize = 16;
+
+#[repr(C, align(4096))]
+struct HeapArea([u8; HEAP_SIZE]);
+static mut HEAP_AREA: HeapArea = HeapArea([0; HEAP_SIZE]);
+
+#[repr(C)]
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+struct FreeList {
+    initialized: bool,
+    head: *mut FreeBlock,
+}
+impl FreeList {
+    unsafe fn init(&mut self) {
+        let head = core::ptr::addr_of_mut!(HEAP_AREA) as *mut FreeBlock;
+        head.write(FreeBlock {
+            size: HEAP_SIZE,
+            next: core::ptr::null_mut(),
+        });
+        self.head = head;
+        self.initialized = true;
+    }
+
+    #[inline]
+    const fn block_size(layout: &Layout) -> usize {
+        let size = if layout.size() < BLOCK_UNIT {
+            BLOCK_UNIT
+        } else {
+            layout.size()
+        };
+
+        (size + BLOCK_UNIT - 1) & !(BLOCK_UNIT - 1)
+    }
+
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        if !self.initialized {
+            self.init();
+        }
+
+        let size = Self::block_size(&layout);
+        let align = layout.align().max(BLOCK_UNIT);
+
+        let mut prev: *mut FreeBlock = core::ptr::null_mut();
+        let mut current = self.head;
+        while !current.is_null() {
+            let block_start = current as usize;
+            let block_end = block_start + (*current).size;
+            let next = (*current).next;
+
+            let start = (block_start + align - 1) & !(align - 1);
+            let end = start + size;
+            if end <= block_end {
+                // unlink and give back the unused front/back parts
+                // (all sizes are multiple of BLOCK_UNIT so remainders can always hold a FreeBlock)
+                let mut link = next;
+                if end < block_end {
+                    let back = end as *mut FreeBlock;
+                    back.write(FreeBlock {
+                        size: block_end - end,
+                        next: link,
+                    });
+                    link = back;
+                }
+                if start > block_start {
+                    let front = block_start as *mut FreeBlock;
+                    front.write(FreeBlock {
+                        size: start - block_start,
+                        next: link,
+                    });
+                    link = front;
+                }
+
+                if prev.is_null() {
+                    self.head = link;
+                } else {
+                    (*prev).next = link;
+                }
+
+                return start as *mut u8;
+            }
+
+            prev = current;
+            current = next;
+        }
+
+        core::ptr::null_mut()
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let start = ptr as usize;
+        let size = Self::block_size(&layout);
+
+        // keep the list sorted by address so that neighbors can be coalesced
+        let mut prev: *mut FreeBlock = core::ptr::null_mut();
+        let mut next = self.head;
+        while !next.is_null() && (next as usize) < start {
+            prev = next;
+            next = (*next).next;
+        }
+
+        let block = start as *mut FreeBlock;
+        block.write(FreeBlock { size, next });
+        if !next.is_null() && start + size == next as usize {
+            (*block).size += (*next).size;
+            (*block).next = (*next).next;
+        }
+
+        if prev.is_null() {
+            self.head = block;
+        } else if prev as usize + (*prev).size == start {
+            (*prev).size += (*block).size;
+            (*prev).next = (*block).next;
+        } else {
+            (*prev).next = block;
+        }
+    }
+}
+
+pub struct KernelHeap(UnsafeCell<FreeList>);
+unsafe impl Sync for KernelHeap {}
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        without_interrupts(|| (*self.0.get()).alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        without_interrupts(|| (*self.0.get()).dealloc(ptr, layout))
+    }
+}
+
+#[cfg_attr(not(test), global_allocator)]
+static HEAP: KernelHeap = KernelHeap(UnsafeCell::new(FreeList {
+    initialized: false,
+    head: core::ptr::null_mut(),
+}));
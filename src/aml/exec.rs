@@ -0,0 +1,1610 @@
+use super::{
+    name::{AmlName, NameSeg, NameString},
+    namespace::Namespace,
+    object::{
+        AccessType, BufferField, FieldKind, FieldUnit, Method, MethodFlags, Object,
+        OperationRegion, Reference, RegionSpace, Target, UpdateRule,
+    },
+    opcode::*,
+    AmlError, Handler, Interpreter,
+};
+use alloc::{boxed::Box, rc::Rc, string::String, vec::Vec};
+use core::cmp::Ordering;
+
+const MAX_CALL_DEPTH: usize = 64;
+const MAX_LOOP_ITERATIONS: usize = 0x10_0000;
+
+pub(super) struct Stream<'c> {
+    code: &'c [u8],
+    pos: usize,
+}
+impl<'c> Stream<'c> {
+    pub fn new(code: &'c [u8]) -> Self {
+        Self { code, pos: 0 }
+    }
+
+    #[inline]
+    fn peek(&self) -> Result<u8, AmlError> {
+        self.peek_at(0)
+    }
+
+    #[inline]
+    fn peek_at(&self, offset: usize) -> Result<u8, AmlError> {
+        self.code
+            .get(self.pos + offset)
+            .copied()
+            .ok_or(AmlError::UnexpectedEndOfStream)
+    }
+
+    #[inline]
+    fn next(&mut self) -> Result<u8, AmlError> {
+        let b = self.peek()?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'c [u8], AmlError> {
+        let b = self
+            .code
+            .get(self.pos..self.pos + n)
+            .ok_or(AmlError::UnexpectedEndOfStream)?;
+        self.pos += n;
+        Ok(b)
+    }
+
+    fn le_integer(&mut self, n: usize) -> Result<u64, AmlError> {
+        Ok(self
+            .bytes(n)?
+            .iter()
+            .rev()
+            .fold(0u64, |a, &b| (a << 8) | b as u64))
+    }
+
+    /// PkgLength as a raw value
+    fn pkg_length_value(&mut self) -> Result<usize, AmlError> {
+        let lead = self.next()?;
+        let following = (lead >> 6) as usize;
+        if following == 0 {
+            return Ok((lead & 0x3f) as usize);
+        }
+
+        let mut v = (lead & 0x0f) as usize;
+        for n in 0..following {
+            v |= (self.next()? as usize) << (4 + n * 8);
+        }
+        Ok(v)
+    }
+
+    /// End position of the term starting at `start` if its size is known from its PkgLength
+    fn term_end(&mut self, start: usize) -> Option<usize> {
+        self.pos = start;
+        match self.next().ok()? {
+            SCOPE_OP | BUFFER_OP | PACKAGE_OP | VAR_PACKAGE_OP | METHOD_OP | IF_OP | ELSE_OP
+            | WHILE_OP => (),
+            EXT_OP_PREFIX => match self.next().ok()? {
+                EXT_FIELD_OP | EXT_DEVICE_OP | EXT_PROCESSOR_OP | EXT_POWER_RES_OP
+                | EXT_THERMAL_ZONE_OP | EXT_INDEX_FIELD_OP | EXT_BANK_FIELD_OP => (),
+                _ => return None,
+            },
+            _ => return None,
+        }
+
+        self.pkg_length().ok()
+    }
+
+    /// PkgLength as an end position of the package
+    fn pkg_length(&mut self) -> Result<usize, AmlError> {
+        let start = self.pos;
+        let end = start + self.pkg_length_value()?;
+        if end > self.code.len() {
+            return Err(AmlError::InvalidPkgLength);
+        }
+
+        Ok(end)
+    }
+
+    fn name_seg(&mut self) -> Result<NameSeg, AmlError> {
+        let b = self.bytes(4)?;
+        if !is_lead_name_char(b[0]) {
+            return Err(AmlError::InvalidNameString);
+        }
+
+        Ok(NameSeg([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn name_string(&mut self) -> Result<NameString, AmlError> {
+        let mut root = false;
+        let mut parent_prefixes = 0;
+        if self.peek()? == ROOT_CHAR {
+            self.pos += 1;
+            root = true;
+        } else {
+            while self.peek()? == PARENT_PREFIX_CHAR {
+                self.pos += 1;
+                parent_prefixes += 1;
+            }
+        }
+
+        let segment_count = match self.peek()? {
+            ZERO_OP => {
+                self.pos += 1;
+                0
+            }
+            DUAL_NAME_PREFIX => {
+                self.pos += 1;
+                2
+            }
+            MULTI_NAME_PREFIX => {
+                self.pos += 1;
+                self.next()? as usize
+            }
+            _ => 1,
+        };
+        let segments = (0..segment_count)
+            .map(|_| self.name_seg())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(NameString {
+            root,
+            parent_prefixes,
+            segments,
+        })
+    }
+}
+
+pub(super) struct Frame {
+    scope: AmlName,
+    locals: [Object; 8],
+    args: [Object; 7],
+    /// objects created while executing a method (removed on return)
+    created: Vec<AmlName>,
+    in_method: bool,
+}
+impl Frame {
+    fn new(scope: AmlName, in_method: bool) -> Self {
+        Self {
+            scope,
+            locals: core::array::from_fn(|_| Object::Uninitialized),
+            args: core::array::from_fn(|_| Object::Uninitialized),
+            created: Vec::new(),
+            in_method,
+        }
+    }
+}
+
+pub(super) enum Flow {
+    Normal,
+    Return(Object),
+    Break,
+    Continue,
+}
+
+impl<H: Handler> Interpreter<H> {
+    pub(super) fn load_definition_block(&mut self, code: &[u8]) -> Result<(), AmlError> {
+        let mut s = Stream::new(code);
+        let mut f = Frame::new(AmlName::root(), false);
+
+        self.execute_term_list(&mut s, code.len(), &mut f)
+            .map(|_| ())
+    }
+
+    pub(super) fn invoke_method(
+        &mut self,
+        path: &AmlName,
+        args: Vec<Object>,
+    ) -> Result<Object, AmlError> {
+        let method = match self.namespace.get(path) {
+            Some(Object::Method(m)) => m.clone(),
+            Some(Object::NativeMethod(m)) => {
+                if args.len() != m.arg_count as usize {
+                    return Err(AmlError::InvalidArgumentCount);
+                }
+                return (m.f)(&args);
+            }
+            Some(x) => {
+                return Err(AmlError::InvalidType {
+                    expected: "Method",
+                    actual: x.type_name(),
+                })
+            }
+            None => return Err(AmlError::NameNotFound(path.clone())),
+        };
+        if args.len() > 7 {
+            return Err(AmlError::InvalidArgumentCount);
+        }
+        if self.call_depth >= MAX_CALL_DEPTH {
+            return Err(AmlError::MaxDepthExceeded);
+        }
+
+        let mut f = Frame::new(path.clone(), true);
+        for (n, a) in args.into_iter().enumerate() {
+            f.args[n] = a;
+        }
+
+        self.call_depth += 1;
+        let mut s = Stream::new(&method.code);
+        let r = self.execute_term_list(&mut s, method.code.len(), &mut f);
+        self.call_depth -= 1;
+
+        for n in f.created.iter().rev() {
+            self.namespace.remove(n);
+        }
+
+        match r? {
+            Flow::Return(v) => Ok(v),
+            _ => Ok(Object::Uninitialized),
+        }
+    }
+
+    fn execute_term_list(
+        &mut self,
+        s: &mut Stream,
+        end: usize,
+        f: &mut Frame,
+    ) -> Result<Flow, AmlError> {
+        while s.pos < end {
+            let start = s.pos;
+            match self.execute_term(s, f) {
+                Ok(Flow::Normal) => (),
+                Ok(x) => return Ok(x),
+                // Note: テーブルのロード中は未対応の命令などで失敗した項をPkgLengthで読み飛ばして続行する
+                Err(e) if !f.in_method => match s.term_end(start) {
+                    Some(term_end) if term_end <= end => {
+                        self.load_errors.push(e);
+                        s.pos = term_end;
+                    }
+                    _ => return Err(e),
+                },
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    /// Executes a term list inside the named scope (Scope/Device/etc.)
+    fn execute_in_scope(
+        &mut self,
+        s: &mut Stream,
+        end: usize,
+        scope: AmlName,
+        f: &mut Frame,
+    ) -> Result<Flow, AmlError> {
+        let saved = core::mem::replace(&mut f.scope, scope);
+        let r = self.execute_term_list(s, end, f);
+        f.scope = saved;
+        s.pos = end;
+
+        r
+    }
+
+    fn create_named(
+        &mut self,
+        name: &NameString,
+        object: Object,
+        f: &mut Frame,
+    ) -> Result<AmlName, AmlError> {
+        let path = name.resolve(&f.scope).ok_or(AmlError::InvalidNameString)?;
+        self.namespace.add(path.clone(), object)?;
+        if f.in_method {
+            f.created.push(path.clone());
+        }
+
+        Ok(path)
+    }
+
+    fn resolve_existing(&self, name: &NameString, f: &Frame) -> Result<AmlName, AmlError> {
+        self.namespace
+            .search(name, &f.scope)
+            .ok_or_else(|| AmlError::UnresolvedName(name.clone()))
+    }
+
+    fn execute_term(&mut self, s: &mut Stream, f: &mut Frame) -> Result<Flow, AmlError> {
+        match s.peek()? {
+            NAME_OP => {
+                s.pos += 1;
+                let name = s.name_string()?;
+                let value = self.evaluate_term_arg(s, f)?;
+                self.create_named(&name, value, f)?;
+            }
+            ALIAS_OP => {
+                s.pos += 1;
+                let source = s.name_string()?;
+                let alias = s.name_string()?;
+                let source = self.resolve_existing(&source, f)?;
+                self.create_named(&alias, Object::Alias(source), f)?;
+            }
+            SCOPE_OP => {
+                s.pos += 1;
+                let end = s.pkg_length()?;
+                let name = s.name_string()?;
+                let path = match self.namespace.search(&name, &f.scope) {
+                    Some(p) => p,
+                    None => {
+                        let p = name.resolve(&f.scope).ok_or(AmlError::InvalidNameString)?;
+                        self.namespace.add_or_open_scope(p.clone(), Object::Scope)?;
+                        p
+                    }
+                };
+
+                return self.execute_in_scope(s, end, path, f);
+            }
+            METHOD_OP => {
+                s.pos += 1;
+                let end = s.pkg_length()?;
+                let name = s.name_string()?;
+                let flags = MethodFlags(s.next()?);
+                let code = Rc::from(&s.code[s.pos..end]);
+                s.pos = end;
+
+                self.create_named(
+                    &name,
+                    Object::Method(Method {
+                        arg_count: flags.arg_count(),
+                        code,
+                    }),
+                    f,
+                )?;
+            }
+            EXTERNAL_OP => {
+                s.pos += 1;
+                s.name_string()?;
+                // ObjectType, ArgumentCount
+                s.bytes(2)?;
+            }
+            IF_OP => {
+                s.pos += 1;
+                let end = s.pkg_length()?;
+                let predicate = self.evaluate_integer(s, f)?;
+                if predicate != 0 {
+                    let flow = self.execute_term_list(s, end, f)?;
+                    s.pos = end;
+                    if s.pos < s.code.len() && s.peek()? == ELSE_OP {
+                        s.pos += 1;
+                        s.pos = s.pkg_length()?;
+                    }
+
+                    return Ok(flow);
+                }
+
+                s.pos = end;
+                if s.pos < s.code.len() && s.peek()? == ELSE_OP {
+                    s.pos += 1;
+                    let end = s.pkg_length()?;
+                    let flow = self.execute_term_list(s, end, f)?;
+                    s.pos = end;
+
+                    return Ok(flow);
+                }
+            }
+            ELSE_OP => {
+                // orphaned Else (If block was skipped by Break/Continue/Return): just skip
+                s.pos += 1;
+                s.pos = s.pkg_length()?;
+            }
+            WHILE_OP => {
+                s.pos += 1;
+                let end = s.pkg_length()?;
+                let predicate_start = s.pos;
+                let mut iterations = 0;
+                loop {
+                    s.pos = predicate_start;
+                    if self.evaluate_integer(s, f)? == 0 {
+                        break;
+                    }
+
+                    match self.execute_term_list(s, end, f)? {
+                        Flow::Break => break,
+                        Flow::Return(v) => {
+                            s.pos = end;
+                            return Ok(Flow::Return(v));
+                        }
+                        Flow::Normal | Flow::Continue => (),
+                    }
+
+                    iterations += 1;
+                    if iterations >= MAX_LOOP_ITERATIONS {
+                        return Err(AmlError::LoopLimitExceeded);
+                    }
+                }
+                s.pos = end;
+            }
+            RETURN_OP => {
+                s.pos += 1;
+                let v = self.evaluate_term_arg(s, f)?;
+                return Ok(Flow::Return(v));
+            }
+            BREAK_OP => {
+                s.pos += 1;
+                return Ok(Flow::Break);
+            }
+            CONTINUE_OP => {
+                s.pos += 1;
+                return Ok(Flow::Continue);
+            }
+            NOOP_OP | BREAKPOINT_OP => {
+                s.pos += 1;
+            }
+            NOTIFY_OP => {
+                s.pos += 1;
+                let target = self.parse_super_name(s, f)?;
+                let value = self.evaluate_integer(s, f)?;
+                if let Target::Named(n) = target {
+                    self.handler.notify(&n, value);
+                }
+            }
+            CREATE_BIT_FIELD_OP => self.create_buffer_field(s, f, Some(1), false)?,
+            CREATE_BYTE_FIELD_OP => self.create_buffer_field(s, f, Some(8), true)?,
+            CREATE_WORD_FIELD_OP => self.create_buffer_field(s, f, Some(16), true)?,
+            CREATE_DWORD_FIELD_OP => self.create_buffer_field(s, f, Some(32), true)?,
+            CREATE_QWORD_FIELD_OP => self.create_buffer_field(s, f, Some(64), true)?,
+            EXT_OP_PREFIX => return self.execute_ext_term(s, f),
+            _ => {
+                self.evaluate_term_arg(s, f)?;
+            }
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    fn execute_ext_term(&mut self, s: &mut Stream, f: &mut Frame) -> Result<Flow, AmlError> {
+        match s.peek_at(1)? {
+            EXT_MUTEX_OP => {
+                s.pos += 2;
+                let name = s.name_string()?;
+                let sync_level = s.next()? & 0x0f;
+                self.create_named(&name, Object::Mutex { sync_level }, f)?;
+            }
+            EXT_EVENT_OP => {
+                s.pos += 2;
+                let name = s.name_string()?;
+                self.create_named(&name, Object::Event, f)?;
+            }
+            EXT_CREATE_FIELD_OP => {
+                self.create_buffer_field(s, f, None, false)?;
+            }
+            EXT_STALL_OP => {
+                s.pos += 2;
+                let us = self.evaluate_integer(s, f)?;
+                self.handler.stall(us);
+            }
+            EXT_SLEEP_OP => {
+                s.pos += 2;
+                let ms = self.evaluate_integer(s, f)?;
+                self.handler.sleep(ms);
+            }
+            EXT_SIGNAL_OP | EXT_RESET_OP | EXT_RELEASE_OP => {
+                // single threaded: synchronization objects have no effect
+                s.pos += 2;
+                self.parse_super_name(s, f)?;
+            }
+            EXT_FATAL_OP => {
+                s.pos += 2;
+                let r#type = s.next()?;
+                let code = s.le_integer(4)? as u32;
+                let arg = self.evaluate_integer(s, f)?;
+                return Err(AmlError::Fatal { r#type, code, arg });
+            }
+            EXT_LOAD_OP | EXT_LOAD_TABLE_OP => {
+                return Err(AmlError::Unsupported("dynamic table loading"));
+            }
+            EXT_OP_REGION_OP => {
+                s.pos += 2;
+                let name = s.name_string()?;
+                let space = RegionSpace::from_byte(s.next()?);
+                let offset = self.evaluate_integer(s, f)?;
+                // RegionLen (accesses are not checked against it)
+                self.evaluate_integer(s, f)?;
+                let parent = f.scope.clone();
+                self.create_named(
+                    &name,
+                    Object::OperationRegion(OperationRegion {
+                        space,
+                        offset,
+                        parent,
+                    }),
+                    f,
+                )?;
+            }
+            EXT_DATA_REGION_OP => {
+                return Err(AmlError::Unsupported("DataTableRegion"));
+            }
+            EXT_FIELD_OP => {
+                s.pos += 2;
+                let end = s.pkg_length()?;
+                let region = s.name_string()?;
+                let region = self.resolve_existing(&region, f)?;
+                let flags = s.next()?;
+                self.parse_field_list(s, end, f, FieldKind::Normal { region }, flags)?;
+            }
+            EXT_INDEX_FIELD_OP => {
+                s.pos += 2;
+                let end = s.pkg_length()?;
+                let index = s.name_string()?;
+                let data = s.name_string()?;
+                let index = self.resolve_existing(&index, f)?;
+                let data = self.resolve_existing(&data, f)?;
+                let flags = s.next()?;
+                self.parse_field_list(s, end, f, FieldKind::Index { index, data }, flags)?;
+            }
+            EXT_BANK_FIELD_OP => {
+                s.pos += 2;
+                let end = s.pkg_length()?;
+                let region = s.name_string()?;
+                let bank = s.name_string()?;
+                let region = self.resolve_existing(&region, f)?;
+                let bank = self.resolve_existing(&bank, f)?;
+                let bank_value = self.evaluate_integer(s, f)?;
+                let flags = s.next()?;
+                self.parse_field_list(
+                    s,
+                    end,
+                    f,
+                    FieldKind::Bank {
+                        region,
+                        bank,
+                        bank_value,
+                    },
+                    flags,
+                )?;
+            }
+            EXT_DEVICE_OP => {
+                s.pos += 2;
+                let end = s.pkg_length()?;
+                let name = s.name_string()?;
+                let path = self.create_named(&name, Object::Device, f)?;
+                return self.execute_in_scope(s, end, path, f);
+            }
+            EXT_PROCESSOR_OP => {
+                s.pos += 2;
+                let end = s.pkg_length()?;
+                let name = s.name_string()?;
+                let id = s.next()?;
+                let pblk_address = s.le_integer(4)? as u32;
+                let pblk_length = s.next()?;
+                let path = self.create_named(
+                    &name,
+                    Object::Processor {
+                        id,
+                        pblk_address,
+                        pblk_length,
+                    },
+                    f,
+                )?;
+                return self.execute_in_scope(s, end, path, f);
+            }
+            EXT_POWER_RES_OP => {
+                s.pos += 2;
+                let end = s.pkg_length()?;
+                let name = s.name_string()?;
+                let system_level = s.next()?;
+                let resource_order = s.le_integer(2)? as u16;
+                let path = self.create_named(
+                    &name,
+                    Object::PowerResource {
+                        system_level,
+                        resource_order,
+                    },
+                    f,
+                )?;
+                return self.execute_in_scope(s, end, path, f);
+            }
+            EXT_THERMAL_ZONE_OP => {
+                s.pos += 2;
+                let end = s.pkg_length()?;
+                let name = s.name_string()?;
+                let path = self.create_named(&name, Object::ThermalZone, f)?;
+                return self.execute_in_scope(s, end, path, f);
+            }
+            _ => {
+                self.evaluate_term_arg(s, f)?;
+            }
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    fn parse_field_list(
+        &mut self,
+        s: &mut Stream,
+        end: usize,
+        f: &mut Frame,
+        kind: FieldKind,
+        flags: u8,
+    ) -> Result<(), AmlError> {
+        let mut bit_offset = 0u64;
+        let mut access_type = AccessType::from_flags(flags);
+        let update_rule = UpdateRule::from_flags(flags);
+
+        while s.pos < end {
+            match s.peek()? {
+                // ReservedField
+                0x00 => {
+                    s.pos += 1;
+                    bit_offset += s.pkg_length_value()? as u64;
+                }
+                // AccessField
+                0x01 => {
+                    s.pos += 1;
+                    access_type = AccessType::from_flags(s.next()?);
+                    // AccessAttrib
+                    s.next()?;
+                }
+                // ConnectField (GPIO/serial bus connection)
+                0x02 => {
+                    s.pos += 1;
+                    if s.peek()? == BUFFER_OP {
+                        self.evaluate_term_arg(s, f)?;
+                    } else {
+                        s.name_string()?;
+                    }
+                }
+                // ExtendedAccessField
+                0x03 => {
+                    s.pos += 1;
+                    access_type = AccessType::from_flags(s.next()?);
+                    // ExtendedAccessAttrib, AccessLength
+                    s.bytes(2)?;
+                }
+                _ => {
+                    let seg = s.name_seg()?;
+                    let bit_length = s.pkg_length_value()? as u64;
+                    let name = NameString {
+                        root: false,
+                        parent_prefixes: 0,
+                        segments: alloc::vec![seg],
+                    };
+                    self.create_named(
+                        &name,
+                        Object::FieldUnit(FieldUnit {
+                            kind: kind.clone(),
+                            bit_offset,
+                            bit_length,
+                            access_type,
+                            update_rule,
+                        }),
+                        f,
+                    )?;
+                    bit_offset += bit_length;
+                }
+            }
+        }
+
+        s.pos = end;
+        Ok(())
+    }
+
+    fn create_buffer_field(
+        &mut self,
+        s: &mut Stream,
+        f: &mut Frame,
+        fixed_bits: Option<usize>,
+        byte_index: bool,
+    ) -> Result<(), AmlError> {
+        s.pos += if fixed_bits.is_some() { 1 } else { 2 };
+        let buffer = self.evaluate_operand(s, f)?.as_buffer()?;
+        let index = self.evaluate_integer(s, f)? as usize;
+        let bit_length = match fixed_bits {
+            Some(b) => b,
+            None => self.evaluate_integer(s, f)? as usize,
+        };
+        let name = s.name_string()?;
+
+        let bit_index = if byte_index { index * 8 } else { index };
+        self.create_named(
+            &name,
+            Object::BufferField(BufferField {
+                buffer,
+                bit_index,
+                bit_length,
+            }),
+            f,
+        )?;
+
+        Ok(())
+    }
+
+    fn evaluate_integer(&mut self, s: &mut Stream, f: &mut Frame) -> Result<u64, AmlError> {
+        self.evaluate_operand(s, f)?.as_integer()
+    }
+
+    /// TermArg with implicit dereferencing of references
+    fn evaluate_operand(&mut self, s: &mut Stream, f: &mut Frame) -> Result<Object, AmlError> {
+        let v = self.evaluate_term_arg(s, f)?;
+        self.dereference(v, f)
+    }
+
+    fn dereference(&mut self, v: Object, f: &mut Frame) -> Result<Object, AmlError> {
+        match v {
+            Object::Reference(r) => self.read_reference(&r, f),
+            x => Ok(x),
+        }
+    }
+
+    pub(super) fn evaluate_term_arg(
+        &mut self,
+        s: &mut Stream,
+        f: &mut Frame,
+    ) -> Result<Object, AmlError> {
+        let op = s.peek()?;
+        if is_name_string_start(op) {
+            return self.evaluate_name(s, f);
+        }
+
+        s.pos += 1;
+        match op {
+            ZERO_OP => Ok(Object::Integer(0)),
+            ONE_OP => Ok(Object::Integer(1)),
+            ONES_OP => Ok(Object::Integer(self.ones())),
+            BYTE_PREFIX => Ok(Object::Integer(s.le_integer(1)?)),
+            WORD_PREFIX => Ok(Object::Integer(s.le_integer(2)?)),
+            DWORD_PREFIX => Ok(Object::Integer(s.le_integer(4)?)),
+            QWORD_PREFIX => Ok(Object::Integer(s.le_integer(8)?)),
+            STRING_PREFIX => {
+                let start = s.pos;
+                while s.next()? != 0 {}
+                let bytes = &s.code[start..s.pos - 1];
+                Ok(Object::String(bytes.iter().map(|&c| c as char).collect()))
+            }
+            BUFFER_OP => {
+                let end = s.pkg_length()?;
+                let size = self.evaluate_integer(s, f)? as usize;
+                let initializer = &s.code[s.pos..end];
+                s.pos = end;
+
+                let mut bytes = alloc::vec![0u8; size.max(initializer.len())];
+                bytes[..initializer.len()].copy_from_slice(initializer);
+                Ok(Object::new_buffer(bytes))
+            }
+            PACKAGE_OP | VAR_PACKAGE_OP => {
+                let end = s.pkg_length()?;
+                let count = if op == PACKAGE_OP {
+                    s.next()? as usize
+                } else {
+                    self.evaluate_integer(s, f)? as usize
+                };
+
+                let mut elements = Vec::with_capacity(count);
+                while s.pos < end {
+                    elements.push(self.evaluate_package_element(s, f)?);
+                }
+                s.pos = end;
+                elements.resize(count.max(elements.len()), Object::Uninitialized);
+                Ok(Object::Package(elements))
+            }
+            LOCAL0_OP..=LOCAL7_OP => Ok(f.locals[(op - LOCAL0_OP) as usize].clone()),
+            ARG0_OP..=ARG6_OP => {
+                // arguments passed by RefOf are dereferenced implicitly
+                let v = f.args[(op - ARG0_OP) as usize].clone();
+                match v {
+                    Object::Reference(r) if !matches!(*r, Reference::Index(..)) => {
+                        self.read_reference(&r, f)
+                    }
+                    x => Ok(x),
+                }
+            }
+            STORE_OP => {
+                let v = self.evaluate_operand(s, f)?;
+                let t = self.parse_target(s, f)?;
+                self.store(&t, v.clone(), f)?;
+                Ok(v)
+            }
+            COPY_OBJECT_OP => {
+                let v = self.evaluate_operand(s, f)?;
+                let t = self.parse_super_name(s, f)?;
+                self.copy_object(&t, v.clone(), f)?;
+                Ok(v)
+            }
+            REF_OF_OP => {
+                let t = self.parse_super_name(s, f)?;
+                Ok(Object::Reference(Box::new(reference_of(t)?)))
+            }
+            DEREF_OF_OP => {
+                let v = self.evaluate_term_arg(s, f)?;
+                match v {
+                    Object::Reference(r) => self.read_reference(&r, f),
+                    Object::String(path) => {
+                        let n = NameString::parse(&path).ok_or(AmlError::InvalidNameString)?;
+                        let n = self.resolve_existing(&n, f)?;
+                        self.read_named(&n)
+                    }
+                    x => Err(AmlError::InvalidType {
+                        expected: "Reference",
+                        actual: x.type_name(),
+                    }),
+                }
+            }
+            ADD_OP => self.binary_integer_op(s, f, |a, b| Ok(a.wrapping_add(b))),
+            SUBTRACT_OP => self.binary_integer_op(s, f, |a, b| Ok(a.wrapping_sub(b))),
+            MULTIPLY_OP => self.binary_integer_op(s, f, |a, b| Ok(a.wrapping_mul(b))),
+            SHIFT_LEFT_OP => {
+                self.binary_integer_op(s, f, |a, b| Ok(a.checked_shl(b as u32).unwrap_or(0)))
+            }
+            SHIFT_RIGHT_OP => {
+                self.binary_integer_op(s, f, |a, b| Ok(a.checked_shr(b as u32).unwrap_or(0)))
+            }
+            AND_OP => self.binary_integer_op(s, f, |a, b| Ok(a & b)),
+            NAND_OP => self.binary_integer_op(s, f, |a, b| Ok(!(a & b))),
+            OR_OP => self.binary_integer_op(s, f, |a, b| Ok(a | b)),
+            NOR_OP => self.binary_integer_op(s, f, |a, b| Ok(!(a | b))),
+            XOR_OP => self.binary_integer_op(s, f, |a, b| Ok(a ^ b)),
+            MOD_OP => {
+                self.binary_integer_op(s, f, |a, b| a.checked_rem(b).ok_or(AmlError::DivideByZero))
+            }
+            DIVIDE_OP => {
+                let a = self.evaluate_integer(s, f)?;
+                let b = self.evaluate_integer(s, f)?;
+                let remainder_target = self.parse_target(s, f)?;
+                let quotient_target = self.parse_target(s, f)?;
+                if b == 0 {
+                    return Err(AmlError::DivideByZero);
+                }
+
+                self.store(&remainder_target, Object::Integer(a % b), f)?;
+                self.store(&quotient_target, Object::Integer(a / b), f)?;
+                Ok(Object::Integer(a / b))
+            }
+            NOT_OP => self.unary_integer_op(s, f, |a| !a),
+            FIND_SET_LEFT_BIT_OP => {
+                let width = if self.ones() == u64::MAX { 64 } else { 32 };
+                self.unary_integer_op(s, f, move |a| {
+                    if a == 0 {
+                        0
+                    } else {
+                        (width - (a << (64 - width)).leading_zeros()) as u64
+                    }
+                })
+            }
+            FIND_SET_RIGHT_BIT_OP => self.unary_integer_op(s, f, |a| {
+                if a == 0 {
+                    0
+                } else {
+                    a.trailing_zeros() as u64 + 1
+                }
+            }),
+            INCREMENT_OP | DECREMENT_OP => {
+                let t = self.parse_super_name(s, f)?;
+                let v = self.read_target(&t, f)?.as_integer()?;
+                let v = if op == INCREMENT_OP {
+                    v.wrapping_add(1)
+                } else {
+                    v.wrapping_sub(1)
+                } & self.ones();
+                self.store(&t, Object::Integer(v), f)?;
+                Ok(Object::Integer(v))
+            }
+            LAND_OP => {
+                let a = self.evaluate_integer(s, f)?;
+                let b = self.evaluate_integer(s, f)?;
+                Ok(self.logical(a != 0 && b != 0))
+            }
+            LOR_OP => {
+                let a = self.evaluate_integer(s, f)?;
+                let b = self.evaluate_integer(s, f)?;
+                Ok(self.logical(a != 0 || b != 0))
+            }
+            LNOT_OP => {
+                let a = self.evaluate_integer(s, f)?;
+                Ok(self.logical(a == 0))
+            }
+            LEQUAL_OP | LGREATER_OP | LLESS_OP => {
+                let a = self.evaluate_operand(s, f)?;
+                let b = self.evaluate_operand(s, f)?;
+                let ord = compare(&a, &b)?;
+                Ok(self.logical(match op {
+                    LEQUAL_OP => ord == Ordering::Equal,
+                    LGREATER_OP => ord == Ordering::Greater,
+                    _ => ord == Ordering::Less,
+                }))
+            }
+            SIZE_OF_OP => {
+                let t = self.parse_super_name(s, f)?;
+                let v = self.read_target(&t, f)?;
+                let v = self.dereference(v, f)?;
+                Ok(Object::Integer(match v {
+                    Object::String(s) => s.len(),
+                    Object::Buffer(b) => b.borrow().len(),
+                    Object::Package(p) => p.len(),
+                    x => {
+                        return Err(AmlError::InvalidType {
+                            expected: "String/Buffer/Package",
+                            actual: x.type_name(),
+                        })
+                    }
+                } as u64))
+            }
+            OBJECT_TYPE_OP => {
+                let t = self.parse_super_name(s, f)?;
+                let code = match &t {
+                    Target::Named(n) => self
+                        .namespace
+                        .get(n)
+                        .ok_or_else(|| AmlError::NameNotFound(n.clone()))?
+                        .type_code(),
+                    t => self.read_target(t, f)?.type_code(),
+                };
+                Ok(Object::Integer(code))
+            }
+            INDEX_OP => {
+                let base = match s.peek()? {
+                    LOCAL0_OP..=LOCAL7_OP | ARG0_OP..=ARG6_OP => self.parse_target(s, f)?,
+                    c if is_name_string_start(c) => {
+                        let save = s.pos;
+                        let n = s.name_string()?;
+                        let path = self.resolve_existing(&n, f)?;
+                        if matches!(
+                            self.namespace.get(&path),
+                            Some(Object::Method(_) | Object::NativeMethod(_))
+                        ) {
+                            s.pos = save;
+                            Target::Reference(Box::new(Reference::Value(
+                                self.evaluate_term_arg(s, f)?,
+                            )))
+                        } else {
+                            Target::Named(path)
+                        }
+                    }
+                    _ => {
+                        Target::Reference(Box::new(Reference::Value(self.evaluate_operand(s, f)?)))
+                    }
+                };
+                let index = self.evaluate_integer(s, f)? as usize;
+                let t = self.parse_target(s, f)?;
+
+                let r = Object::Reference(Box::new(Reference::Index(base, index)));
+                self.store(&t, r.clone(), f)?;
+                Ok(r)
+            }
+            MATCH_OP => {
+                let package = self.evaluate_operand(s, f)?;
+                let op1 = s.next()?;
+                let operand1 = self.evaluate_operand(s, f)?;
+                let op2 = s.next()?;
+                let operand2 = self.evaluate_operand(s, f)?;
+                let start = self.evaluate_integer(s, f)? as usize;
+
+                let p = package.as_package()?;
+                for (n, e) in p.iter().enumerate().skip(start) {
+                    if match_element(e, op1, &operand1)? && match_element(e, op2, &operand2)? {
+                        return Ok(Object::Integer(n as u64));
+                    }
+                }
+                Ok(Object::Integer(self.ones()))
+            }
+            CONCAT_OP => {
+                let a = self.evaluate_operand(s, f)?;
+                let b = self.evaluate_operand(s, f)?;
+                let t = self.parse_target(s, f)?;
+                let r = self.concat(&a, &b)?;
+                self.store(&t, r.clone(), f)?;
+                Ok(r)
+            }
+            CONCAT_RES_OP => {
+                let a = self.evaluate_operand(s, f)?.as_buffer()?;
+                let b = self.evaluate_operand(s, f)?.as_buffer()?;
+                let t = self.parse_target(s, f)?;
+
+                // strip the end tag of the first template, then append the second
+                let mut bytes = a.borrow().clone();
+                if bytes.len() >= 2 && bytes[bytes.len() - 2] == 0x79 {
+                    bytes.truncate(bytes.len() - 2);
+                }
+                bytes.extend_from_slice(&b.borrow());
+                let r = Object::new_buffer(bytes);
+                self.store(&t, r.clone(), f)?;
+                Ok(r)
+            }
+            TO_BUFFER_OP => {
+                let v = self.evaluate_operand(s, f)?;
+                let t = self.parse_target(s, f)?;
+                let r = Object::new_buffer(self.to_buffer_bytes(&v)?);
+                self.store(&t, r.clone(), f)?;
+                Ok(r)
+            }
+            TO_INTEGER_OP => {
+                let v = self.evaluate_operand(s, f)?;
+                let t = self.parse_target(s, f)?;
+                let r = Object::Integer(v.as_integer()? & self.ones());
+                self.store(&t, r.clone(), f)?;
+                Ok(r)
+            }
+            TO_HEX_STRING_OP | TO_DECIMAL_STRING_OP => {
+                let v = self.evaluate_operand(s, f)?;
+                let t = self.parse_target(s, f)?;
+                let r = Object::String(to_string(&v, op == TO_HEX_STRING_OP)?);
+                self.store(&t, r.clone(), f)?;
+                Ok(r)
+            }
+            TO_STRING_OP => {
+                let v = self.evaluate_operand(s, f)?.as_buffer()?;
+                let length = self.evaluate_integer(s, f)? as usize;
+                let t = self.parse_target(s, f)?;
+                let r = Object::String(
+                    v.borrow()
+                        .iter()
+                        .take(length)
+                        .take_while(|&&c| c != 0)
+                        .map(|&c| c as char)
+                        .collect(),
+                );
+                self.store(&t, r.clone(), f)?;
+                Ok(r)
+            }
+            MID_OP => {
+                let v = self.evaluate_operand(s, f)?;
+                let index = self.evaluate_integer(s, f)? as usize;
+                let length = self.evaluate_integer(s, f)? as usize;
+                let t = self.parse_target(s, f)?;
+                let r = match v {
+                    Object::String(x) => {
+                        Object::String(x.chars().skip(index).take(length).collect::<String>())
+                    }
+                    Object::Buffer(b) => Object::new_buffer(
+                        b.borrow()
+                            .iter()
+                            .skip(index)
+                            .take(length)
+                            .copied()
+                            .collect(),
+                    ),
+                    x => {
+                        return Err(AmlError::InvalidType {
+                            expected: "String/Buffer",
+                            actual: x.type_name(),
+                        })
+                    }
+                };
+                self.store(&t, r.clone(), f)?;
+                Ok(r)
+            }
+            EXT_OP_PREFIX => self.evaluate_ext_term_arg(s, f),
+            x => Err(AmlError::UnknownOpcode(x)),
+        }
+    }
+
+    fn evaluate_ext_term_arg(&mut self, s: &mut Stream, f: &mut Frame) -> Result<Object, AmlError> {
+        let op = s.next()?;
+        match op {
+            EXT_REVISION_OP => Ok(Object::Integer(2)),
+            EXT_DEBUG_OP => Ok(Object::Debug),
+            EXT_TIMER_OP => Ok(Object::Integer(self.handler.timer())),
+            EXT_COND_REF_OF_OP => {
+                let t = match s.peek()? {
+                    c if is_name_string_start(c) => {
+                        let n = s.name_string()?;
+                        self.namespace.search(&n, &f.scope).map(Target::Named)
+                    }
+                    _ => Some(self.parse_super_name(s, f)?),
+                };
+                let result_target = self.parse_target(s, f)?;
+
+                match t {
+                    Some(t) => {
+                        let r = Object::Reference(Box::new(reference_of(t)?));
+                        self.store(&result_target, r, f)?;
+                        Ok(Object::Integer(self.ones()))
+                    }
+                    None => Ok(Object::Integer(0)),
+                }
+            }
+            EXT_ACQUIRE_OP => {
+                self.parse_super_name(s, f)?;
+                // Timeout
+                s.bytes(2)?;
+                // always acquired
+                Ok(Object::Integer(0))
+            }
+            EXT_WAIT_OP => {
+                self.parse_super_name(s, f)?;
+                self.evaluate_integer(s, f)?;
+                Ok(Object::Integer(0))
+            }
+            EXT_FROM_BCD_OP => self.unary_integer_op(s, f, |mut a| {
+                let (mut r, mut m) = (0u64, 1u64);
+                while a != 0 {
+                    r += (a & 0x0f) * m;
+                    m *= 10;
+                    a >>= 4;
+                }
+                r
+            }),
+            EXT_TO_BCD_OP => self.unary_integer_op(s, f, |mut a| {
+                let (mut r, mut shift) = (0u64, 0);
+                while a != 0 && shift < 64 {
+                    r |= (a % 10) << shift;
+                    shift += 4;
+                    a /= 10;
+                }
+                r
+            }),
+            x => Err(AmlError::UnknownExtOpcode(x)),
+        }
+    }
+
+    fn evaluate_package_element(
+        &mut self,
+        s: &mut Stream,
+        f: &mut Frame,
+    ) -> Result<Object, AmlError> {
+        if !is_name_string_start(s.peek()?) {
+            return self.evaluate_term_arg(s, f);
+        }
+
+        // names inside packages are not evaluated (e.g. link devices in _PRT)
+        let n = s.name_string()?;
+        Ok(match self.namespace.search(&n, &f.scope) {
+            Some(path) => Object::Reference(Box::new(Reference::Named(path))),
+            None => Object::String(alloc::format!("{n}")),
+        })
+    }
+
+    /// NameString as a TermArg: method invocation or reading a named object
+    fn evaluate_name(&mut self, s: &mut Stream, f: &mut Frame) -> Result<Object, AmlError> {
+        let n = s.name_string()?;
+        let path = self.resolve_existing(&n, f)?;
+
+        let arg_count = match self.namespace.get(&path) {
+            Some(Object::Method(m)) => m.arg_count,
+            Some(Object::NativeMethod(m)) => m.arg_count,
+            _ => return self.read_named(&path),
+        };
+        let args = (0..arg_count)
+            .map(|_| self.evaluate_operand(s, f))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.invoke_method(&path, args)
+    }
+
+    pub(super) fn read_named(&mut self, path: &AmlName) -> Result<Object, AmlError> {
+        match self.namespace.get(path) {
+            None => Err(AmlError::NameNotFound(path.clone())),
+            Some(Object::FieldUnit(u)) => {
+                let u = u.clone();
+                self.read_field(&u)
+            }
+            Some(Object::BufferField(b)) => Ok(b.read()),
+            Some(
+                o @ (Object::Integer(_)
+                | Object::String(_)
+                | Object::Buffer(_)
+                | Object::Package(_)
+                | Object::Reference(_)
+                | Object::Uninitialized),
+            ) => Ok(o.clone()),
+            // namespace objects are passed around as references
+            Some(_) => Ok(Object::Reference(Box::new(Reference::Named(path.clone())))),
+        }
+    }
+
+    fn parse_target(&mut self, s: &mut Stream, f: &mut Frame) -> Result<Target, AmlError> {
+        if s.peek()? == ZERO_OP {
+            s.pos += 1;
+            return Ok(Target::Null);
+        }
+
+        self.parse_super_name(s, f)
+    }
+
+    fn parse_super_name(&mut self, s: &mut Stream, f: &mut Frame) -> Result<Target, AmlError> {
+        match s.peek()? {
+            op @ LOCAL0_OP..=LOCAL7_OP => {
+                s.pos += 1;
+                Ok(Target::Local(op - LOCAL0_OP))
+            }
+            op @ ARG0_OP..=ARG6_OP => {
+                s.pos += 1;
+                Ok(Target::Arg(op - ARG0_OP))
+            }
+            EXT_OP_PREFIX if s.peek_at(1)? == EXT_DEBUG_OP => {
+                s.pos += 2;
+                Ok(Target::Debug)
+            }
+            c if is_name_string_start(c) => {
+                let n = s.name_string()?;
+                Ok(Target::Named(self.resolve_existing(&n, f)?))
+            }
+            DEREF_OF_OP => {
+                s.pos += 1;
+                match self.evaluate_term_arg(s, f)? {
+                    Object::Reference(r) => Ok(Target::Reference(r)),
+                    Object::String(path) => {
+                        let n = NameString::parse(&path).ok_or(AmlError::InvalidNameString)?;
+                        Ok(Target::Named(self.resolve_existing(&n, f)?))
+                    }
+                    _ => Err(AmlError::InvalidTarget),
+                }
+            }
+            INDEX_OP | REF_OF_OP => match self.evaluate_term_arg(s, f)? {
+                Object::Reference(r) => Ok(Target::Reference(r)),
+                _ => Err(AmlError::InvalidTarget),
+            },
+            _ => Err(AmlError::InvalidTarget),
+        }
+    }
+
+    fn read_target(&mut self, t: &Target, f: &mut Frame) -> Result<Object, AmlError> {
+        match t {
+            Target::Local(n) => Ok(f.locals[*n as usize].clone()),
+            Target::Arg(n) => {
+                let v = f.args[*n as usize].clone();
+                match v {
+                    Object::Reference(r) if !matches!(*r, Reference::Index(..)) => {
+                        self.read_reference(&r, f)
+                    }
+                    x => Ok(x),
+                }
+            }
+            Target::Named(n) => self.read_named(n),
+            Target::Reference(r) => self.read_reference(r, f),
+            Target::Null | Target::Debug => Err(AmlError::InvalidTarget),
+        }
+    }
+
+    fn read_reference(&mut self, r: &Reference, f: &mut Frame) -> Result<Object, AmlError> {
+        match r {
+            Reference::Named(n) => self.read_named(n),
+            Reference::Local(n) => Ok(f.locals[*n as usize].clone()),
+            Reference::Arg(n) => Ok(f.args[*n as usize].clone()),
+            Reference::Value(v) => Ok(v.clone()),
+            Reference::Index(base, index) => {
+                let base = self.read_target(base, f)?;
+                match self.dereference(base, f)? {
+                    Object::Package(p) => p.get(*index).cloned().ok_or(AmlError::IndexOutOfRange),
+                    Object::Buffer(b) => b
+                        .borrow()
+                        .get(*index)
+                        .map(|&x| Object::Integer(x as u64))
+                        .ok_or(AmlError::IndexOutOfRange),
+                    Object::String(x) => x
+                        .as_bytes()
+                        .get(*index)
+                        .map(|&x| Object::Integer(x as u64))
+                        .ok_or(AmlError::IndexOutOfRange),
+                    x => Err(AmlError::InvalidType {
+                        expected: "Package/Buffer/String",
+                        actual: x.type_name(),
+                    }),
+                }
+            }
+        }
+    }
+
+    fn store(&mut self, t: &Target, v: Object, f: &mut Frame) -> Result<(), AmlError> {
+        match t {
+            Target::Null => Ok(()),
+            Target::Debug => {
+                self.handler.debug(&v);
+                Ok(())
+            }
+            Target::Local(n) => {
+                f.locals[*n as usize] = v.deep_clone();
+                Ok(())
+            }
+            Target::Arg(n) => match &f.args[*n as usize] {
+                Object::Reference(r) => {
+                    let r = (**r).clone();
+                    self.store_reference(&r, v, f)
+                }
+                _ => {
+                    f.args[*n as usize] = v.deep_clone();
+                    Ok(())
+                }
+            },
+            Target::Named(n) => self.store_named(n, v),
+            Target::Reference(r) => self.store_reference(r, v, f),
+        }
+    }
+
+    fn store_reference(&mut self, r: &Reference, v: Object, f: &mut Frame) -> Result<(), AmlError> {
+        match r {
+            Reference::Named(n) => self.store_named(n, v),
+            Reference::Local(n) => {
+                f.locals[*n as usize] = v.deep_clone();
+                Ok(())
+            }
+            Reference::Arg(n) => {
+                f.args[*n as usize] = v.deep_clone();
+                Ok(())
+            }
+            // stores into temporaries are discarded
+            Reference::Value(_) => Ok(()),
+            Reference::Index(base, index) => {
+                let v = self.dereference(v, f)?;
+                match target_object_mut(&mut self.namespace, f, base)? {
+                    Object::Package(p) => {
+                        *p.get_mut(*index).ok_or(AmlError::IndexOutOfRange)? = v.deep_clone();
+                    }
+                    Object::Buffer(b) => {
+                        *b.borrow_mut()
+                            .get_mut(*index)
+                            .ok_or(AmlError::IndexOutOfRange)? = v.as_integer()? as u8;
+                    }
+                    Object::String(x) => {
+                        if *index >= x.len() {
+                            return Err(AmlError::IndexOutOfRange);
+                        }
+                        let mut bytes = core::mem::take(x).into_bytes();
+                        bytes[*index] = v.as_integer()? as u8;
+                        *x = bytes.into_iter().map(|c| c as char).collect();
+                    }
+                    x => {
+                        return Err(AmlError::InvalidType {
+                            expected: "Package/Buffer/String",
+                            actual: x.type_name(),
+                        })
+                    }
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Store() into a named object, with implicit conversion to the type of the destination
+    fn store_named(&mut self, path: &AmlName, v: Object) -> Result<(), AmlError> {
+        let ones = self.ones();
+        let Some(dest) = self.namespace.get_mut(path) else {
+            return Err(AmlError::NameNotFound(path.clone()));
+        };
+
+        match dest {
+            Object::FieldUnit(u) => {
+                let u = u.clone();
+                self.write_field(&u, &v)
+            }
+            Object::BufferField(b) => b.write(&v),
+            Object::Integer(x) => {
+                *x = v.as_integer()? & ones;
+                Ok(())
+            }
+            Object::String(x) => {
+                *x = match &v {
+                    Object::String(s) => s.clone(),
+                    Object::Buffer(b) => b
+                        .borrow()
+                        .iter()
+                        .take_while(|&&c| c != 0)
+                        .map(|&c| c as char)
+                        .collect(),
+                    x => to_string(x, true)?,
+                };
+                Ok(())
+            }
+            Object::Buffer(b) => {
+                // keep the storage (and its length) so that BufferFields stay valid
+                let source = match &v {
+                    Object::Buffer(src) => src.borrow().clone(),
+                    Object::String(s) => s.as_bytes().to_vec(),
+                    x => x.as_integer()?.to_le_bytes().to_vec(),
+                };
+                let mut b = b.borrow_mut();
+                let n = source.len().min(b.len());
+                b.fill(0);
+                b[..n].copy_from_slice(&source[..n]);
+                Ok(())
+            }
+            Object::Package(_) | Object::Uninitialized | Object::Reference(_) => {
+                *dest = v.deep_clone();
+                Ok(())
+            }
+            x => Err(AmlError::InvalidType {
+                expected: "data object",
+                actual: x.type_name(),
+            }),
+        }
+    }
+
+    fn copy_object(&mut self, t: &Target, v: Object, f: &mut Frame) -> Result<(), AmlError> {
+        match t {
+            Target::Named(n) => match self.namespace.get_mut(n) {
+                Some(dest) => {
+                    *dest = v.deep_clone();
+                    Ok(())
+                }
+                None => Err(AmlError::NameNotFound(n.clone())),
+            },
+            Target::Arg(n) => {
+                f.args[*n as usize] = v.deep_clone();
+                Ok(())
+            }
+            t => self.store(t, v, f),
+        }
+    }
+
+    fn binary_integer_op(
+        &mut self,
+        s: &mut Stream,
+        f: &mut Frame,
+        op: impl FnOnce(u64, u64) -> Result<u64, AmlError>,
+    ) -> Result<Object, AmlError> {
+        let a = self.evaluate_integer(s, f)?;
+        let b = self.evaluate_integer(s, f)?;
+        let t = self.parse_target(s, f)?;
+        let r = Object::Integer(op(a, b)? & self.ones());
+        self.store(&t, r.clone(), f)?;
+
+        Ok(r)
+    }
+
+    fn unary_integer_op(
+        &mut self,
+        s: &mut Stream,
+        f: &mut Frame,
+        op: impl FnOnce(u64) -> u64,
+    ) -> Result<Object, AmlError> {
+        let a = self.evaluate_integer(s, f)?;
+        let t = self.parse_target(s, f)?;
+        let r = Object::Integer(op(a) & self.ones());
+        self.store(&t, r.clone(), f)?;
+
+        Ok(r)
+    }
+
+    #[inline]
+    fn logical(&self, v: bool) -> Object {
+        Object::Integer(if v { self.ones() } else { 0 })
+    }
+
+    fn to_buffer_bytes(&self, v: &Object) -> Result<Vec<u8>, AmlError> {
+        Ok(match v {
+            Object::Buffer(b) => b.borrow().clone(),
+            Object::String(s) => {
+                let mut b = s.as_bytes().to_vec();
+                b.push(0);
+                b
+            }
+            x => {
+                let bytes = x.as_integer()?.to_le_bytes();
+                let width = if self.ones() == u64::MAX { 8 } else { 4 };
+                bytes[..width].to_vec()
+            }
+        })
+    }
+
+    fn concat(&self, a: &Object, b: &Object) -> Result<Object, AmlError> {
+        Ok(match a {
+            Object::Integer(_) => {
+                let mut bytes = self.to_buffer_bytes(a)?;
+                bytes.extend(self.to_buffer_bytes(&Object::Integer(b.as_integer()?))?);
+                Object::new_buffer(bytes)
+            }
+            Object::String(x) => {
+                let mut r = x.clone();
+                match b {
+                    Object::String(y) => r.push_str(y),
+                    y => r.push_str(&to_string(y, true)?),
+                }
+                Object::String(r)
+            }
+            Object::Buffer(x) => {
+                let mut bytes = x.borrow().clone();
+                match b {
+                    Object::String(y) => bytes.extend_from_slice(y.as_bytes()),
+                    y => bytes.extend(self.to_buffer_bytes(y)?),
+                }
+                Object::new_buffer(bytes)
+            }
+            x => {
+                return Err(AmlError::InvalidType {
+                    expected: "Integer/String/Buffer",
+                    actual: x.type_name(),
+                })
+            }
+        })
+    }
+}
+
+fn target_object_mut<'a>(
+    namespace: &'a mut Namespace,
+    f: &'a mut Frame,
+    t: &Target,
+) -> Result<&'a mut Object, AmlError> {
+    match t {
+        Target::Local(n) => Ok(&mut f.locals[*n as usize]),
+        Target::Arg(n) => {
+            if let Object::Reference(r) = &f.args[*n as usize] {
+                let r = (**r).clone();
+                return target_object_mut(namespace, f, &Target::Reference(Box::new(r)));
+            }
+
+            Ok(&mut f.args[*n as usize])
+        }
+        Target::Named(n) => namespace
+            .get_mut(n)
+            .ok_or_else(|| AmlError::NameNotFound(n.clone())),
+        Target::Reference(r) => match &**r {
+            Reference::Named(n) => target_object_mut(namespace, f, &Target::Named(n.clone())),
+            Reference::Local(n) => Ok(&mut f.locals[*n as usize]),
+            Reference::Arg(n) => Ok(&mut f.args[*n as usize]),
+            Reference::Index(base, index) => match target_object_mut(namespace, f, base)? {
+                Object::Package(p) => p.get_mut(*index).ok_or(AmlError::IndexOutOfRange),
+                _ => Err(AmlError::InvalidTarget),
+            },
+            Reference::Value(_) => Err(AmlError::InvalidTarget),
+        },
+        Target::Null | Target::Debug => Err(AmlError::InvalidTarget),
+    }
+}
+
+fn reference_of(t: Target) -> Result<Reference, AmlError> {
+    match t {
+        Target::Local(n) => Ok(Reference::Local(n)),
+        Target::Arg(n) => Ok(Reference::Arg(n)),
+        Target::Named(n) => Ok(Reference::Named(n)),
+        Target::Reference(r) => Ok(*r),
+        Target::Null | Target::Debug => Err(AmlError::InvalidTarget),
+    }
+}
+
+fn compare(a: &Object, b: &Object) -> Result<Ordering, AmlError> {
+    Ok(match a {
+        Object::String(x) => match b {
+            Object::String(y) => x.as_str().cmp(y.as_str()),
+            y => x.as_bytes().cmp(&y.as_integer()?.to_le_bytes()[..]),
+        },
+        Object::Buffer(x) => match b {
+            Object::Buffer(y) => x.borrow().as_slice().cmp(y.borrow().as_slice()),
+            Object::String(y) => x.borrow().as_slice().cmp(y.as_bytes()),
+            y => {
+                let len = x.borrow().len().min(8);
+                x.borrow()[..len].cmp(&y.as_integer()?.to_le_bytes()[..len])
+            }
+        },
+        x => x.as_integer()?.cmp(&b.as_integer()?),
+    })
+}
+
+fn match_element(e: &Object, op: u8, operand: &Object) -> Result<bool, AmlError> {
+    if op == 0 {
+        // MTR: always true
+        return Ok(true);
+    }
+    let Ok(ord) = compare(e, operand) else {
+        return Ok(false);
+    };
+
+    Ok(match op {
+        1 => ord == Ordering::Equal,
+        2 => ord != Ordering::Greater,
+        3 => ord == Ordering::Less,
+        4 => ord != Ordering::Less,
+        5 => ord == Ordering::Greater,
+        _ => false,
+    })
+}
+
+fn to_string(v: &Object, hex: bool) -> Result<String, AmlError> {
+    use core::fmt::Write;
+
+    let mut s = String::new();
+    match v {
+        Object::String(x) => s.push_str(x),
+        Object::Integer(x) => {
+            if hex {
+                write!(s, "0x{x:X}").unwrap();
+            } else {
+                write!(s, "{x}").unwrap();
+            }
+        }
+        Object::Buffer(b) => {
+            for (n, x) in b.borrow().iter().enumerate() {
+                if n > 0 {
+                    s.push(',');
+                }
+                if hex {
+                    write!(s, "0x{x:02X}").unwrap();
+                } else {
+                    write!(s, "{x}").unwrap();
+                }
+            }
+        }
+        x => {
+            return Err(AmlError::InvalidType {
+                expected: "Integer/String/Buffer",
+                actual: x.type_name(),
+            })
+        }
+    }
+
+    Ok(s)
+}
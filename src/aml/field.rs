@@ -0,0 +1,255 @@
+use super::{
+    name::AmlName,
+    object::{FieldKind, FieldUnit, Object, RegionSpace, UpdateRule},
+    AmlError, Handler, Interpreter, PciAddress,
+};
+use alloc::vec::Vec;
+
+impl<H: Handler> Interpreter<H> {
+    pub(super) fn read_field(&mut self, unit: &FieldUnit) -> Result<Object, AmlError> {
+        self.select_bank(unit)?;
+
+        let width = unit.access_type.bit_width() as u64;
+        let mut bytes = alloc::vec![0u8; unit.bit_length.div_ceil(8) as usize];
+        if unit.bit_length > 0 {
+            let first = unit.bit_offset / width;
+            let last = (unit.bit_offset + unit.bit_length - 1) / width;
+            for n in first..=last {
+                let value = self.read_access_unit(unit, n * width / 8, width as u8)?;
+                for b in 0..width {
+                    let bit = n * width + b;
+                    if bit < unit.bit_offset || bit >= unit.bit_offset + unit.bit_length {
+                        continue;
+                    }
+
+                    if (value >> b) & 1 != 0 {
+                        let dest = (bit - unit.bit_offset) as usize;
+                        bytes[dest / 8] |= 1 << (dest % 8);
+                    }
+                }
+            }
+        }
+
+        if unit.bit_length > 64 {
+            return Ok(Object::new_buffer(bytes));
+        }
+        let mut v = [0u8; 8];
+        v[..bytes.len()].copy_from_slice(&bytes);
+        Ok(Object::Integer(u64::from_le_bytes(v)))
+    }
+
+    pub(super) fn write_field(&mut self, unit: &FieldUnit, value: &Object) -> Result<(), AmlError> {
+        self.select_bank(unit)?;
+
+        let source: Vec<u8> = match value {
+            Object::Buffer(b) => b.borrow().clone(),
+            Object::String(s) => s.as_bytes().to_vec(),
+            x => x.as_integer()?.to_le_bytes().to_vec(),
+        };
+        let source_bit = |n: u64| {
+            source
+                .get((n / 8) as usize)
+                .is_some_and(|b| (b >> (n % 8)) & 1 != 0)
+        };
+
+        if unit.bit_length == 0 {
+            return Ok(());
+        }
+        let width = unit.access_type.bit_width() as u64;
+        let width_mask = if width == 64 {
+            u64::MAX
+        } else {
+            (1u64 << width) - 1
+        };
+        let first = unit.bit_offset / width;
+        let last = (unit.bit_offset + unit.bit_length - 1) / width;
+        for n in first..=last {
+            let unit_start = n * width;
+            let fully_covered = unit_start >= unit.bit_offset
+                && unit_start + width <= unit.bit_offset + unit.bit_length;
+
+            let mut v = match unit.update_rule {
+                UpdateRule::Preserve if !fully_covered => {
+                    self.read_access_unit(unit, unit_start / 8, width as u8)?
+                }
+                UpdateRule::WriteAsOnes => width_mask,
+                _ => 0,
+            };
+            for b in 0..width {
+                let bit = unit_start + b;
+                if bit < unit.bit_offset || bit >= unit.bit_offset + unit.bit_length {
+                    continue;
+                }
+
+                if source_bit(bit - unit.bit_offset) {
+                    v |= 1 << b;
+                } else {
+                    v &= !(1 << b);
+                }
+            }
+
+            self.write_access_unit(unit, unit_start / 8, width as u8, v)?;
+        }
+
+        Ok(())
+    }
+
+    fn select_bank(&mut self, unit: &FieldUnit) -> Result<(), AmlError> {
+        if let FieldKind::Bank {
+            bank, bank_value, ..
+        } = &unit.kind
+        {
+            let (bank, bank_value) = (bank.clone(), *bank_value);
+            self.store_field_value(&bank, bank_value)?;
+        }
+
+        Ok(())
+    }
+
+    fn store_field_value(&mut self, path: &AmlName, value: u64) -> Result<(), AmlError> {
+        match self.namespace.get(path) {
+            Some(Object::FieldUnit(u)) => {
+                let u = u.clone();
+                self.write_field(&u, &Object::Integer(value))
+            }
+            Some(x) => Err(AmlError::InvalidType {
+                expected: "FieldUnit",
+                actual: x.type_name(),
+            }),
+            None => Err(AmlError::NameNotFound(path.clone())),
+        }
+    }
+
+    fn read_access_unit(
+        &mut self,
+        unit: &FieldUnit,
+        byte_offset: u64,
+        bit_width: u8,
+    ) -> Result<u64, AmlError> {
+        match &unit.kind {
+            FieldKind::Normal { region } | FieldKind::Bank { region, .. } => {
+                self.read_region(region, byte_offset, bit_width)
+            }
+            FieldKind::Index { index, data } => {
+                let (index, data) = (index.clone(), data.clone());
+                self.store_field_value(&index, byte_offset)?;
+                self.read_named(&data)?.as_integer()
+            }
+        }
+    }
+
+    fn write_access_unit(
+        &mut self,
+        unit: &FieldUnit,
+        byte_offset: u64,
+        bit_width: u8,
+        value: u64,
+    ) -> Result<(), AmlError> {
+        match &unit.kind {
+            FieldKind::Normal { region } | FieldKind::Bank { region, .. } => {
+                self.write_region(region, byte_offset, bit_width, value)
+            }
+            FieldKind::Index { index, data } => {
+                let (index, data) = (index.clone(), data.clone());
+                self.store_field_value(&index, byte_offset)?;
+                self.store_field_value(&data, value)
+            }
+        }
+    }
+
+    fn read_region(
+        &mut self,
+        region: &AmlName,
+        byte_offset: u64,
+        bit_width: u8,
+    ) -> Result<u64, AmlError> {
+        let r = self.operation_region(region)?;
+        let address = r.offset + byte_offset;
+        match r.space {
+            RegionSpace::SystemMemory => Ok(self.handler.read_memory(address, bit_width)),
+            RegionSpace::SystemIO => Ok(self.handler.read_io(address as u16, bit_width)),
+            RegionSpace::PciConfig => {
+                let pci = self.pci_address_of(&r.parent)?;
+                Ok(self.handler.read_pci_config(pci, address as u16, bit_width))
+            }
+            x => Err(AmlError::UnsupportedRegion(x)),
+        }
+    }
+
+    fn write_region(
+        &mut self,
+        region: &AmlName,
+        byte_offset: u64,
+        bit_width: u8,
+        value: u64,
+    ) -> Result<(), AmlError> {
+        let r = self.operation_region(region)?;
+        let address = r.offset + byte_offset;
+        match r.space {
+            RegionSpace::SystemMemory => self.handler.write_memory(address, bit_width, value),
+            RegionSpace::SystemIO => self.handler.write_io(address as u16, bit_width, value),
+            RegionSpace::PciConfig => {
+                let pci = self.pci_address_of(&r.parent)?;
+                self.handler
+                    .write_pci_config(pci, address as u16, bit_width, value);
+            }
+            x => return Err(AmlError::UnsupportedRegion(x)),
+        }
+
+        Ok(())
+    }
+
+    fn operation_region(
+        &self,
+        region: &AmlName,
+    ) -> Result<super::object::OperationRegion, AmlError> {
+        match self.namespace.get(region) {
+            Some(Object::OperationRegion(r)) => Ok(r.clone()),
+            Some(x) => Err(AmlError::InvalidType {
+                expected: "OperationRegion",
+                actual: x.type_name(),
+            }),
+            None => Err(AmlError::NameNotFound(region.clone())),
+        }
+    }
+
+    /// PCI address of the device which declares a PCI_Config region:
+    /// device/function from the nearest _ADR, bus/segment from the nearest _BBN/_SEG
+    fn pci_address_of(&mut self, scope: &AmlName) -> Result<PciAddress, AmlError> {
+        let mut adr = None;
+        let mut bbn = None;
+        let mut seg = None;
+
+        let mut current = Some(scope.clone());
+        while let Some(c) = current {
+            if adr.is_none() {
+                adr = self
+                    .evaluate_child(&c, b"_ADR")?
+                    .map(|x| x.as_integer())
+                    .transpose()?;
+            }
+            if bbn.is_none() {
+                bbn = self
+                    .evaluate_child(&c, b"_BBN")?
+                    .map(|x| x.as_integer())
+                    .transpose()?;
+            }
+            if seg.is_none() {
+                seg = self
+                    .evaluate_child(&c, b"_SEG")?
+                    .map(|x| x.as_integer())
+                    .transpose()?;
+            }
+
+            current = c.parent();
+        }
+
+        let adr = adr.unwrap_or(0);
+        Ok(PciAddress {
+            segment: seg.unwrap_or(0) as u16,
+            bus: bbn.unwrap_or(0) as u8,
+            device: (adr >> 16) as u8,
+            function: adr as u8,
+        })
+    }
+}
@@ -0,0 +1,362 @@
+//! AML (ACPI Machine Language) parser/interpreter
+//!
+//! Only depends on `core` and `alloc` so that it can also be built on the host (see `utils/src/bin/aml-dump.rs`).
+
+mod exec;
+mod field;
+pub mod name;
+pub mod namespace;
+pub mod object;
+mod opcode;
+pub mod resource;
+#[cfg(test)]
+mod tests;
+
+use alloc::vec::Vec;
+use name::{AmlName, NameSeg, NameString};
+use namespace::Namespace;
+use object::{NativeMethod, Object, RegionSpace};
+
+#[derive(Debug)]
+#[allow(dead_code)]
+pub enum AmlError {
+    UnexpectedEndOfStream,
+    UnknownOpcode(u8),
+    UnknownExtOpcode(u8),
+    InvalidNameString,
+    InvalidPkgLength,
+    NameNotFound(AmlName),
+    UnresolvedName(NameString),
+    AlreadyExists(AmlName),
+    InvalidType {
+        expected: &'static str,
+        actual: &'static str,
+    },
+    InvalidTarget,
+    InvalidArgumentCount,
+    IndexOutOfRange,
+    DivideByZero,
+    UnsupportedRegion(RegionSpace),
+    Unsupported(&'static str),
+    Fatal {
+        r#type: u8,
+        code: u32,
+        arg: u64,
+    },
+    LoopLimitExceeded,
+    MaxDepthExceeded,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PciAddress {
+    pub segment: u16,
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+/// Platform access used by OperationRegions and some statements
+pub trait Handler {
+    fn read_memory(&self, address: u64, bit_width: u8) -> u64;
+    fn write_memory(&self, address: u64, bit_width: u8, value: u64);
+    fn read_io(&self, port: u16, bit_width: u8) -> u64;
+    fn write_io(&self, port: u16, bit_width: u8, value: u64);
+    fn read_pci_config(&self, address: PciAddress, offset: u16, bit_width: u8) -> u64;
+    fn write_pci_config(&self, address: PciAddress, offset: u16, bit_width: u8, value: u64);
+
+    fn stall(&self, _microseconds: u64) {}
+    fn sleep(&self, milliseconds: u64) {
+        self.stall(milliseconds * 1000);
+    }
+    /// monotonic timer in 100ns units
+    fn timer(&self) -> u64 {
+        0
+    }
+    fn debug(&self, _value: &Object) {}
+    fn notify(&self, _target: &AmlName, _value: u64) {}
+}
+
+/// An entry of _PRT
+#[derive(Clone, Debug)]
+pub struct PciRoutingEntry {
+    pub device: u16,
+    /// 0 = INTA# .. 3 = INTD#
+    pub pin: u8,
+    pub source: PciRoutingSource,
+}
+
+#[derive(Clone, Debug)]
+pub enum PciRoutingSource {
+    GlobalSystemInterrupt(u32),
+    /// link device and its resource index
+    Link(AmlName, u32),
+}
+
+pub struct Interpreter<H: Handler> {
+    namespace: Namespace,
+    handler: H,
+    integer_width_64: bool,
+    call_depth: usize,
+    /// errors of the terms skipped while loading tables
+    load_errors: Vec<AmlError>,
+}
+impl<H: Handler> Interpreter<H> {
+    pub fn new(handler: H) -> Self {
+        let mut namespace = Namespace::new();
+        for s in [b"_GPE", b"_PR_", b"_SB_", b"_SI_", b"_TZ_"] {
+            namespace
+                .add(AmlName::root().child(NameSeg::new(s)), Object::Scope)
+                .unwrap();
+        }
+        let root = AmlName::root();
+        namespace
+            .add(
+                root.child(NameSeg::new(b"_GL_")),
+                Object::Mutex { sync_level: 0 },
+            )
+            .unwrap();
+        namespace
+            .add(
+                root.child(NameSeg::new(b"_OS_")),
+                Object::new_string("Microsoft Windows NT"),
+            )
+            .unwrap();
+        namespace
+            .add(root.child(NameSeg::new(b"_REV")), Object::Integer(2))
+            .unwrap();
+        namespace
+            .add(
+                root.child(NameSeg::new(b"_OSI")),
+                Object::NativeMethod(NativeMethod {
+                    arg_count: 1,
+                    f: osi,
+                }),
+            )
+            .unwrap();
+
+        Self {
+            namespace,
+            handler,
+            integer_width_64: true,
+            call_depth: 0,
+            load_errors: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub const fn namespace(&self) -> &Namespace {
+        &self.namespace
+    }
+
+    /// Errors of the terms skipped by [`Self::load_table`]
+    #[inline]
+    pub fn load_errors(&self) -> &[AmlError] {
+        &self.load_errors
+    }
+
+    /// Loads a DSDT/SSDT (including the 36-byte table header)
+    ///
+    /// A term that fails to load is skipped by its PkgLength (see [`Self::load_errors`]) and
+    /// loading continues with the next one.
+    pub fn load_table(&mut self, table: &[u8]) -> Result<(), AmlError> {
+        const HEADER_LENGTH: usize = 36;
+        if table.len() < HEADER_LENGTH {
+            return Err(AmlError::UnexpectedEndOfStream);
+        }
+        let length = u32::from_le_bytes([table[4], table[5], table[6], table[7]]) as usize;
+        if length < HEADER_LENGTH {
+            return Err(AmlError::UnexpectedEndOfStream);
+        }
+        let body = &table[HEADER_LENGTH..length.min(table.len())];
+
+        if &table[..4] == b"DSDT" {
+            // Note: DSDTのrevisionが2未満だと整数が32bit幅になる
+            self.integer_width_64 = table[8] >= 2;
+        }
+
+        self.load_definition_block(body)
+    }
+
+    /// Evaluates an object. Methods are invoked with `args`, other objects are just read.
+    pub fn evaluate(&mut self, path: &AmlName, args: Vec<Object>) -> Result<Object, AmlError> {
+        match self.namespace.get(path) {
+            None => Err(AmlError::NameNotFound(path.clone())),
+            Some(Object::Method(_) | Object::NativeMethod(_)) => self.invoke_method(path, args),
+            Some(_) => self.read_named(path),
+        }
+    }
+
+    pub fn evaluate_str(&mut self, path: &str, args: Vec<Object>) -> Result<Object, AmlError> {
+        let path = AmlName::from_str(path).ok_or(AmlError::InvalidNameString)?;
+        self.evaluate(&path, args)
+    }
+
+    /// Evaluates `seg` under `scope` if exists
+    pub fn evaluate_child(
+        &mut self,
+        scope: &AmlName,
+        seg: &[u8; 4],
+    ) -> Result<Option<Object>, AmlError> {
+        let path = scope.child(NameSeg::new(seg));
+        if !self.namespace.contains(&path) {
+            return Ok(None);
+        }
+
+        self.evaluate(&path, Vec::new()).map(Some)
+    }
+
+    /// _STA of the device (0x0f if not defined)
+    pub fn device_status(&mut self, device: &AmlName) -> Result<u64, AmlError> {
+        Ok(match self.evaluate_child(device, b"_STA")? {
+            Some(x) => x.as_integer()?,
+            None => 0x0f,
+        })
+    }
+
+    /// Runs _INI of \_SB and every present device (ACPI 6.5 6.5.1)
+    pub fn initialize_devices(&mut self) -> Result<(), AmlError> {
+        self.evaluate_child(&AmlName::from_str("\\_SB").unwrap(), b"_INI")?;
+
+        let devices = self.namespace.devices().cloned().collect::<Vec<_>>();
+        for d in devices {
+            // skip devices under a not-present parent
+            if let Some(p) = d.parent() {
+                if matches!(self.namespace.get(&p), Some(Object::Device))
+                    && self.device_status(&p)? & 0x01 == 0
+                {
+                    continue;
+                }
+            }
+
+            if self.device_status(&d)? & 0x01 != 0 {
+                self.evaluate_child(&d, b"_INI")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// SLP_TYPa/SLP_TYPb for the sleep state `state` (0..=5) from \_Sx_
+    pub fn sleep_types(&mut self, state: u8) -> Result<(u8, u8), AmlError> {
+        let name = [b'_', b'S', b'0' + state, b'_'];
+        let v = self.evaluate(&AmlName::root().child(NameSeg(name)), Vec::new())?;
+        let p = v.as_package()?;
+        if p.is_empty() {
+            return Err(AmlError::IndexOutOfRange);
+        }
+        let a = p[0].as_integer()?;
+        // some firmwares packs both values into the first element
+        let b = match p.get(1) {
+            Some(x) => x.as_integer()?,
+            None => a >> 8,
+        };
+
+        Ok(((a & 0x07) as u8, (b & 0x07) as u8))
+    }
+
+    /// Decodes _PRT of a PCI bridge/root bridge
+    pub fn pci_routing_table(
+        &mut self,
+        bridge: &AmlName,
+    ) -> Result<Vec<PciRoutingEntry>, AmlError> {
+        let Some(prt) = self.evaluate_child(bridge, b"_PRT")? else {
+            return Ok(Vec::new());
+        };
+
+        prt.as_package()?
+            .iter()
+            .map(|e| {
+                let e = e.as_package()?;
+                if e.len() < 4 {
+                    return Err(AmlError::IndexOutOfRange);
+                }
+                let address = e[0].as_integer()?;
+                let pin = e[1].as_integer()? as u8;
+                let source_index = e[3].as_integer()? as u32;
+                let source = match &e[2] {
+                    Object::Integer(0) => PciRoutingSource::GlobalSystemInterrupt(source_index),
+                    Object::Reference(r) => match &**r {
+                        object::Reference::Named(n) => {
+                            PciRoutingSource::Link(n.clone(), source_index)
+                        }
+                        _ => return Err(AmlError::InvalidTarget),
+                    },
+                    Object::String(s) => {
+                        let n = NameString::parse(s).ok_or(AmlError::InvalidNameString)?;
+                        let n = self
+                            .namespace
+                            .search(&n, bridge)
+                            .ok_or(AmlError::UnresolvedName(n))?;
+                        PciRoutingSource::Link(n, source_index)
+                    }
+                    x => {
+                        return Err(AmlError::InvalidType {
+                            expected: "Reference",
+                            actual: x.type_name(),
+                        })
+                    }
+                };
+
+                Ok(PciRoutingEntry {
+                    device: (address >> 16) as u16,
+                    pin,
+                    source,
+                })
+            })
+            .collect()
+    }
+
+    #[inline]
+    fn ones(&self) -> u64 {
+        if self.integer_width_64 {
+            u64::MAX
+        } else {
+            u32::MAX as u64
+        }
+    }
+}
+
+/// Decodes a compressed EISA ID (e.g. the integer value of _HID) into the "PNP0A03" form
+pub fn decode_eisa_id(id: u32) -> [u8; 7] {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    let v = id.swap_bytes();
+
+    [
+        (((v >> 26) & 0x1f) as u8) + 0x40,
+        (((v >> 21) & 0x1f) as u8) + 0x40,
+        (((v >> 16) & 0x1f) as u8) + 0x40,
+        HEX[((v >> 12) & 0x0f) as usize],
+        HEX[((v >> 8) & 0x0f) as usize],
+        HEX[((v >> 4) & 0x0f) as usize],
+        HEX[(v & 0x0f) as usize],
+    ]
+}
+
+fn osi(args: &[Object]) -> Result<Object, AmlError> {
+    const SUPPORTED: &[&str] = &[
+        "Windows 2000",
+        "Windows 2001",
+        "Windows 2001 SP1",
+        "Windows 2001 SP2",
+        "Windows 2001.1",
+        "Windows 2006",
+        "Windows 2009",
+        "Windows 2012",
+        "Windows 2013",
+        "Windows 2015",
+        "Module Device",
+        "Processor Device",
+        "3.0 Thermal Model",
+        "Extended Address Space Descriptor",
+    ];
+
+    let s = args
+        .first()
+        .ok_or(AmlError::InvalidArgumentCount)?
+        .as_str()?;
+    Ok(Object::Integer(if SUPPORTED.contains(&s) {
+        u64::MAX
+    } else {
+        0
+    }))
+}
@@ -0,0 +1,166 @@
+use alloc::vec::Vec;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NameSeg(pub [u8; 4]);
+impl NameSeg {
+    pub const fn new(s: &[u8; 4]) -> Self {
+        Self(*s)
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        let b = s.as_bytes();
+        if b.is_empty() || b.len() > 4 {
+            return None;
+        }
+
+        // shorter segments are padded with '_'
+        let mut seg = [b'_'; 4];
+        seg[..b.len()].copy_from_slice(b);
+        Some(Self(seg))
+    }
+
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.0).unwrap_or("????")
+    }
+}
+impl core::fmt::Debug for NameSeg {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+impl core::fmt::Display for NameSeg {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// NameString as written in AML (before resolution)
+#[derive(Clone, PartialEq, Eq)]
+pub struct NameString {
+    pub root: bool,
+    pub parent_prefixes: usize,
+    pub segments: Vec<NameSeg>,
+}
+impl NameString {
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut rest = s;
+        let root = rest.starts_with('\\');
+        if root {
+            rest = &rest[1..];
+        }
+        let parent_prefixes = rest.bytes().take_while(|&c| c == b'^').count();
+        rest = &rest[parent_prefixes..];
+
+        let segments = if rest.is_empty() {
+            Vec::new()
+        } else {
+            rest.split('.')
+                .map(NameSeg::from_str)
+                .collect::<Option<Vec<_>>>()?
+        };
+
+        Some(Self {
+            root,
+            parent_prefixes,
+            segments,
+        })
+    }
+
+    /// Name lookups may search parent scopes only for single-segment relative names (ACPI 5.3)
+    #[inline]
+    pub fn is_search_candidate(&self) -> bool {
+        !self.root && self.parent_prefixes == 0 && self.segments.len() == 1
+    }
+
+    pub fn resolve(&self, scope: &AmlName) -> Option<AmlName> {
+        if self.root {
+            return Some(AmlName(self.segments.clone()));
+        }
+
+        let mut segments = scope.0.clone();
+        for _ in 0..self.parent_prefixes {
+            segments.pop()?;
+        }
+        segments.extend(self.segments.iter().copied());
+        Some(AmlName(segments))
+    }
+}
+impl core::fmt::Display for NameString {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.root {
+            f.write_str("\\")?;
+        }
+        for _ in 0..self.parent_prefixes {
+            f.write_str("^")?;
+        }
+        for (n, s) in self.segments.iter().enumerate() {
+            if n > 0 {
+                f.write_str(".")?;
+            }
+            write!(f, "{s}")?;
+        }
+
+        Ok(())
+    }
+}
+impl core::fmt::Debug for NameString {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Display::fmt(self, f)
+    }
+}
+
+/// Absolute path in the ACPI namespace
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AmlName(pub Vec<NameSeg>);
+impl AmlName {
+    pub const fn root() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Parses an absolute path such as `\_SB.PCI0._CRS`
+    pub fn from_str(s: &str) -> Option<Self> {
+        NameString::parse(s)?.resolve(&Self::root())
+    }
+
+    pub fn parent(&self) -> Option<Self> {
+        if self.0.is_empty() {
+            return None;
+        }
+
+        Some(Self(self.0[..self.0.len() - 1].to_vec()))
+    }
+
+    #[inline]
+    pub fn last_segment(&self) -> Option<NameSeg> {
+        self.0.last().copied()
+    }
+
+    pub fn child(&self, seg: NameSeg) -> Self {
+        let mut segments = self.0.clone();
+        segments.push(seg);
+        Self(segments)
+    }
+
+    #[inline]
+    pub fn depth(&self) -> usize {
+        self.0.len()
+    }
+}
+impl core::fmt::Display for AmlName {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("\\")?;
+        for (n, s) in self.0.iter().enumerate() {
+            if n > 0 {
+                f.write_str(".")?;
+            }
+            write!(f, "{s}")?;
+        }
+
+        Ok(())
+    }
+}
+impl core::fmt::Debug for AmlName {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Display::fmt(self, f)
+    }
+}
@@ -0,0 +1,138 @@
+use super::{
+    name::{AmlName, NameString},
+    object::Object,
+    AmlError,
+};
+use alloc::collections::BTreeMap;
+use core::ops::Bound;
+
+pub struct Namespace {
+    objects: BTreeMap<AmlName, Object>,
+}
+impl Namespace {
+    pub fn new() -> Self {
+        let mut objects = BTreeMap::new();
+        objects.insert(AmlName::root(), Object::Scope);
+
+        Self { objects }
+    }
+
+    pub fn add(&mut self, name: AmlName, object: Object) -> Result<(), AmlError> {
+        match name.parent() {
+            Some(p) if !self.objects.contains_key(&p) => return Err(AmlError::NameNotFound(p)),
+            _ => (),
+        }
+        if self.objects.contains_key(&name) {
+            return Err(AmlError::AlreadyExists(name));
+        }
+
+        self.objects.insert(name, object);
+        Ok(())
+    }
+
+    /// Adds a scope-like object, or reuses existing one (e.g. `Scope(\_SB)` blocks spread over multiple tables)
+    pub fn add_or_open_scope(&mut self, name: AmlName, object: Object) -> Result<(), AmlError> {
+        match self.objects.get(&name) {
+            Some(x) if x.is_scope_like() => Ok(()),
+            Some(_) => Err(AmlError::AlreadyExists(name)),
+            None => self.add(name, object),
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, name: &AmlName) -> Option<&Object> {
+        self.objects.get(name)
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self, name: &AmlName) -> Option<&mut Object> {
+        self.objects.get_mut(name)
+    }
+
+    #[inline]
+    pub fn contains(&self, name: &AmlName) -> bool {
+        self.objects.contains_key(name)
+    }
+
+    pub fn remove(&mut self, name: &AmlName) -> Option<Object> {
+        // drop descendants too
+        let descendants = self
+            .descendants(name)
+            .map(|(n, _)| n.clone())
+            .collect::<alloc::vec::Vec<_>>();
+        for d in descendants {
+            self.objects.remove(&d);
+        }
+
+        self.objects.remove(name)
+    }
+
+    /// Resolves a NameString against the scope following the namespace search rules,
+    /// then follows Alias objects
+    pub fn search(&self, name: &NameString, scope: &AmlName) -> Option<AmlName> {
+        let resolved = if name.is_search_candidate() {
+            let seg = name.segments[0];
+            let mut scope = scope.clone();
+            loop {
+                let candidate = scope.child(seg);
+                if self.objects.contains_key(&candidate) {
+                    break candidate;
+                }
+
+                scope = scope.parent()?;
+            }
+        } else {
+            let n = name.resolve(scope)?;
+            if !self.objects.contains_key(&n) {
+                return None;
+            }
+
+            n
+        };
+
+        Some(self.follow_alias(resolved))
+    }
+
+    fn follow_alias(&self, mut name: AmlName) -> AmlName {
+        // limit the depth to avoid looping forever on circular aliases
+        for _ in 0..16 {
+            match self.objects.get(&name) {
+                Some(Object::Alias(target)) => name = target.clone(),
+                _ => break,
+            }
+        }
+
+        name
+    }
+
+    pub fn descendants<'s>(
+        &'s self,
+        name: &'s AmlName,
+    ) -> impl Iterator<Item = (&'s AmlName, &'s Object)> + 's {
+        self.objects
+            .range((Bound::Excluded(name), Bound::Unbounded))
+            .take_while(move |(n, _)| n.0.starts_with(&name.0))
+    }
+
+    pub fn devices(&self) -> impl Iterator<Item = &AmlName> {
+        self.objects
+            .iter()
+            .filter(|(_, o)| matches!(o, Object::Device))
+            .map(|(n, _)| n)
+    }
+}
+impl core::fmt::Debug for Namespace {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (name, object) in self.objects.iter() {
+            for _ in 0..name.depth() {
+                f.write_str("  ")?;
+            }
+            match name.last_segment() {
+                Some(seg) => writeln!(f, "{seg}: {object:?}")?,
+                None => writeln!(f, "\\")?,
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,452 @@
+use super::{name::AmlName, AmlError};
+use alloc::{boxed::Box, rc::Rc, string::String, vec::Vec};
+use core::cell::RefCell;
+
+pub type SharedBuffer = Rc<RefCell<Vec<u8>>>;
+
+#[derive(Clone)]
+pub enum Object {
+    Uninitialized,
+    Integer(u64),
+    String(String),
+    Buffer(SharedBuffer),
+    Package(Vec<Object>),
+    Reference(Box<Reference>),
+
+    // namespace-only objects
+    Scope,
+    Device,
+    Method(Method),
+    NativeMethod(NativeMethod),
+    OperationRegion(OperationRegion),
+    FieldUnit(FieldUnit),
+    BufferField(BufferField),
+    Mutex {
+        sync_level: u8,
+    },
+    Event,
+    PowerResource {
+        system_level: u8,
+        resource_order: u16,
+    },
+    Processor {
+        id: u8,
+        pblk_address: u32,
+        pblk_length: u8,
+    },
+    ThermalZone,
+    Alias(AmlName),
+    Debug,
+}
+impl Object {
+    pub fn new_buffer(bytes: Vec<u8>) -> Self {
+        Self::Buffer(Rc::new(RefCell::new(bytes)))
+    }
+
+    pub fn new_string(s: &str) -> Self {
+        Self::String(String::from(s))
+    }
+
+    /// ObjectType() values (ACPI 19.6.96)
+    pub fn type_code(&self) -> u64 {
+        match self {
+            Self::Uninitialized => 0,
+            Self::Integer(_) => 1,
+            Self::String(_) => 2,
+            Self::Buffer(_) => 3,
+            Self::Package(_) => 4,
+            Self::FieldUnit(_) => 5,
+            Self::Device => 6,
+            Self::Event => 7,
+            Self::Method(_) | Self::NativeMethod(_) => 8,
+            Self::Mutex { .. } => 9,
+            Self::OperationRegion(_) => 10,
+            Self::PowerResource { .. } => 11,
+            Self::Processor { .. } => 12,
+            Self::ThermalZone => 13,
+            Self::BufferField(_) => 14,
+            Self::Debug => 16,
+            Self::Scope | Self::Alias(_) | Self::Reference(_) => 0,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Uninitialized => "Uninitialized",
+            Self::Integer(_) => "Integer",
+            Self::String(_) => "String",
+            Self::Buffer(_) => "Buffer",
+            Self::Package(_) => "Package",
+            Self::Reference(_) => "Reference",
+            Self::Scope => "Scope",
+            Self::Device => "Device",
+            Self::Method(_) | Self::NativeMethod(_) => "Method",
+            Self::OperationRegion(_) => "OperationRegion",
+            Self::FieldUnit(_) => "FieldUnit",
+            Self::BufferField(_) => "BufferField",
+            Self::Mutex { .. } => "Mutex",
+            Self::Event => "Event",
+            Self::PowerResource { .. } => "PowerResource",
+            Self::Processor { .. } => "Processor",
+            Self::ThermalZone => "ThermalZone",
+            Self::Alias(_) => "Alias",
+            Self::Debug => "Debug",
+        }
+    }
+
+    /// Objects which opens a new scope for the names declared inside
+    #[inline]
+    pub fn is_scope_like(&self) -> bool {
+        matches!(
+            self,
+            Self::Scope
+                | Self::Device
+                | Self::Method(_)
+                | Self::PowerResource { .. }
+                | Self::Processor { .. }
+                | Self::ThermalZone
+        )
+    }
+
+    pub fn as_integer(&self) -> Result<u64, AmlError> {
+        match self {
+            Self::Integer(v) => Ok(*v),
+            Self::Buffer(b) => {
+                // implicit Buffer -> Integer conversion: takes first 8 bytes as little endian
+                let b = b.borrow();
+                let mut bytes = [0u8; 8];
+                let n = b.len().min(8);
+                bytes[..n].copy_from_slice(&b[..n]);
+                Ok(u64::from_le_bytes(bytes))
+            }
+            Self::String(s) => Ok(parse_integer_string(s)),
+            _ => Err(AmlError::InvalidType {
+                expected: "Integer",
+                actual: self.type_name(),
+            }),
+        }
+    }
+
+    pub fn as_buffer(&self) -> Result<SharedBuffer, AmlError> {
+        match self {
+            Self::Buffer(b) => Ok(b.clone()),
+            _ => Err(AmlError::InvalidType {
+                expected: "Buffer",
+                actual: self.type_name(),
+            }),
+        }
+    }
+
+    pub fn as_str(&self) -> Result<&str, AmlError> {
+        match self {
+            Self::String(s) => Ok(s),
+            _ => Err(AmlError::InvalidType {
+                expected: "String",
+                actual: self.type_name(),
+            }),
+        }
+    }
+
+    pub fn as_package(&self) -> Result<&[Object], AmlError> {
+        match self {
+            Self::Package(p) => Ok(p),
+            _ => Err(AmlError::InvalidType {
+                expected: "Package",
+                actual: self.type_name(),
+            }),
+        }
+    }
+
+    /// Copy semantics of Store(): buffers get a fresh backing storage
+    pub fn deep_clone(&self) -> Self {
+        match self {
+            Self::Buffer(b) => Self::new_buffer(b.borrow().clone()),
+            Self::Package(p) => Self::Package(p.iter().map(Self::deep_clone).collect()),
+            x => x.clone(),
+        }
+    }
+}
+impl core::fmt::Debug for Object {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Integer(v) => write!(f, "Integer(0x{v:x})"),
+            Self::String(s) => write!(f, "String({s:?})"),
+            Self::Buffer(b) => {
+                f.write_str("Buffer(")?;
+                for (n, x) in b.borrow().iter().enumerate() {
+                    if n > 0 {
+                        f.write_str(" ")?;
+                    }
+                    write!(f, "{x:02x}")?;
+                }
+                f.write_str(")")
+            }
+            Self::Package(p) => f.debug_list().entries(p.iter()).finish(),
+            Self::Reference(r) => write!(f, "Reference({r:?})"),
+            Self::Method(m) => write!(f, "Method(args={}, {} bytes)", m.arg_count, m.code.len()),
+            Self::OperationRegion(r) => write!(f, "{r:?}"),
+            Self::FieldUnit(u) => write!(f, "{u:?}"),
+            Self::BufferField(b) => write!(
+                f,
+                "BufferField(bit_index={}, bit_length={})",
+                b.bit_index, b.bit_length
+            ),
+            Self::Mutex { sync_level } => write!(f, "Mutex(sync_level={sync_level})"),
+            Self::PowerResource {
+                system_level,
+                resource_order,
+            } => write!(
+                f,
+                "PowerResource(system_level={system_level}, resource_order={resource_order})"
+            ),
+            Self::Processor {
+                id,
+                pblk_address,
+                pblk_length,
+            } => write!(
+                f,
+                "Processor(id={id}, pblk=0x{pblk_address:x}+{pblk_length})"
+            ),
+            Self::Alias(n) => write!(f, "Alias({n})"),
+            x => f.write_str(x.type_name()),
+        }
+    }
+}
+
+fn parse_integer_string(s: &str) -> u64 {
+    let s = s.trim();
+    let (digits, radix) = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => (hex, 16),
+        None => (s, 10),
+    };
+
+    digits
+        .chars()
+        .map_while(|c| c.to_digit(radix))
+        .fold(0u64, |a, d| {
+            a.wrapping_mul(radix as u64).wrapping_add(d as u64)
+        })
+}
+
+#[derive(Clone, Debug)]
+pub enum Reference {
+    Named(AmlName),
+    Local(u8),
+    Arg(u8),
+    /// Index(source, index)
+    Index(Target, usize),
+    /// element of an unnamed object (e.g. Index(Package() {...}, 1))
+    Value(Object),
+}
+
+/// Destination of a Store-like operation
+#[derive(Clone, Debug)]
+pub enum Target {
+    Null,
+    Debug,
+    Local(u8),
+    Arg(u8),
+    Named(AmlName),
+    Reference(Box<Reference>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MethodFlags(pub u8);
+impl MethodFlags {
+    #[inline]
+    pub const fn arg_count(self) -> u8 {
+        self.0 & 0x07
+    }
+}
+
+#[derive(Clone)]
+pub struct Method {
+    pub arg_count: u8,
+    pub code: Rc<[u8]>,
+}
+
+pub type NativeMethodFn = fn(args: &[Object]) -> Result<Object, AmlError>;
+
+#[derive(Clone)]
+pub struct NativeMethod {
+    pub arg_count: u8,
+    pub f: NativeMethodFn,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionSpace {
+    SystemMemory,
+    SystemIO,
+    PciConfig,
+    EmbeddedControl,
+    SMBus,
+    SystemCmos,
+    PciBarTarget,
+    Ipmi,
+    GeneralPurposeIO,
+    GenericSerialBus,
+    Pcc,
+    OemDefined(u8),
+}
+impl RegionSpace {
+    pub const fn from_byte(b: u8) -> Self {
+        match b {
+            0 => Self::SystemMemory,
+            1 => Self::SystemIO,
+            2 => Self::PciConfig,
+            3 => Self::EmbeddedControl,
+            4 => Self::SMBus,
+            5 => Self::SystemCmos,
+            6 => Self::PciBarTarget,
+            7 => Self::Ipmi,
+            8 => Self::GeneralPurposeIO,
+            9 => Self::GenericSerialBus,
+            10 => Self::Pcc,
+            x => Self::OemDefined(x),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct OperationRegion {
+    pub space: RegionSpace,
+    pub offset: u64,
+    /// scope where the region was declared (used to find _ADR/_BBN/_SEG for PCI config regions)
+    pub parent: AmlName,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessType {
+    Any,
+    Byte,
+    Word,
+    DWord,
+    QWord,
+    Buffer,
+}
+impl AccessType {
+    pub const fn from_flags(flags: u8) -> Self {
+        match flags & 0x0f {
+            1 => Self::Byte,
+            2 => Self::Word,
+            3 => Self::DWord,
+            4 => Self::QWord,
+            5 => Self::Buffer,
+            _ => Self::Any,
+        }
+    }
+
+    /// access width in bits
+    pub const fn bit_width(self) -> u8 {
+        match self {
+            Self::Any | Self::Byte | Self::Buffer => 8,
+            Self::Word => 16,
+            Self::DWord => 32,
+            Self::QWord => 64,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpdateRule {
+    Preserve,
+    WriteAsOnes,
+    WriteAsZeros,
+}
+impl UpdateRule {
+    pub const fn from_flags(flags: u8) -> Self {
+        match (flags >> 5) & 0x03 {
+            1 => Self::WriteAsOnes,
+            2 => Self::WriteAsZeros,
+            _ => Self::Preserve,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum FieldKind {
+    Normal {
+        region: AmlName,
+    },
+    Index {
+        index: AmlName,
+        data: AmlName,
+    },
+    Bank {
+        region: AmlName,
+        bank: AmlName,
+        bank_value: u64,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct FieldUnit {
+    pub kind: FieldKind,
+    pub bit_offset: u64,
+    pub bit_length: u64,
+    pub access_type: AccessType,
+    pub update_rule: UpdateRule,
+}
+
+#[derive(Clone)]
+pub struct BufferField {
+    pub buffer: SharedBuffer,
+    pub bit_index: usize,
+    pub bit_length: usize,
+}
+impl BufferField {
+    pub fn read(&self) -> Object {
+        let b = self.buffer.borrow();
+        if self.bit_length > 64 {
+            let bytes = self.bit_length.div_ceil(8);
+            let mut out = alloc::vec![0u8; bytes];
+            for n in 0..self.bit_length {
+                if read_bit(&b, self.bit_index + n) {
+                    out[n / 8] |= 1 << (n % 8);
+                }
+            }
+            return Object::new_buffer(out);
+        }
+
+        let mut v = 0u64;
+        for n in 0..self.bit_length {
+            if read_bit(&b, self.bit_index + n) {
+                v |= 1 << n;
+            }
+        }
+        Object::Integer(v)
+    }
+
+    pub fn write(&self, value: &Object) -> Result<(), AmlError> {
+        let mut b = self.buffer.borrow_mut();
+        let source = match value {
+            Object::Buffer(src) => src.borrow().clone(),
+            x => x.as_integer()?.to_le_bytes().to_vec(),
+        };
+
+        for n in 0..self.bit_length {
+            let bit = source.get(n / 8).is_some_and(|x| (x >> (n % 8)) & 1 != 0);
+            write_bit(&mut b, self.bit_index + n, bit);
+        }
+
+        Ok(())
+    }
+}
+
+#[inline]
+fn read_bit(bytes: &[u8], index: usize) -> bool {
+    bytes
+        .get(index / 8)
+        .is_some_and(|b| (b >> (index % 8)) & 1 != 0)
+}
+
+#[inline]
+fn write_bit(bytes: &mut [u8], index: usize, bit: bool) {
+    if let Some(b) = bytes.get_mut(index / 8) {
+        if bit {
+            *b |= 1 << (index % 8);
+        } else {
+            *b &= !(1 << (index % 8));
+        }
+    }
+}
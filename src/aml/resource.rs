@@ -0,0 +1,189 @@
+//! Resource data types for _CRS/_PRS (ACPI 6.5 section 6.4)
+
+use super::AmlError;
+use alloc::vec::Vec;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressSpaceType {
+    Memory,
+    Io,
+    BusNumber,
+    Other(u8),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Resource {
+    Irq {
+        /// bit mask of IRQ0-15
+        mask: u16,
+        edge_triggered: bool,
+        active_low: bool,
+        shared: bool,
+    },
+    Dma {
+        mask: u8,
+        flags: u8,
+    },
+    Io {
+        decode_16bit: bool,
+        min: u16,
+        max: u16,
+        alignment: u8,
+        length: u8,
+    },
+    FixedIo {
+        base: u16,
+        length: u8,
+    },
+    Memory32 {
+        writable: bool,
+        min: u32,
+        max: u32,
+        alignment: u32,
+        length: u32,
+    },
+    FixedMemory32 {
+        writable: bool,
+        base: u32,
+        length: u32,
+    },
+    AddressSpace {
+        r#type: AddressSpaceType,
+        general_flags: u8,
+        type_specific_flags: u8,
+        granularity: u64,
+        min: u64,
+        max: u64,
+        translation_offset: u64,
+        length: u64,
+    },
+    ExtendedInterrupt {
+        consumer: bool,
+        edge_triggered: bool,
+        active_low: bool,
+        shared: bool,
+        interrupts: Vec<u32>,
+    },
+    /// descriptors that are not decoded here
+    Unknown {
+        tag: u8,
+    },
+}
+
+fn le(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0u64, |a, &b| (a << 8) | b as u64)
+}
+
+/// Decodes a resource template buffer (e.g. the value of _CRS)
+pub fn parse(bytes: &[u8]) -> Result<Vec<Resource>, AmlError> {
+    let mut resources = Vec::new();
+    let mut p = 0;
+
+    while p < bytes.len() {
+        let tag = bytes[p];
+        if (tag & 0x80) == 0 {
+            // small resource
+            let name = (tag >> 3) & 0x0f;
+            let length = (tag & 0x07) as usize;
+            let body = bytes
+                .get(p + 1..p + 1 + length)
+                .ok_or(AmlError::UnexpectedEndOfStream)?;
+            p += 1 + length;
+
+            resources.push(match name {
+                0x04 => {
+                    let flags = body.get(2).copied().unwrap_or(0x01);
+                    Resource::Irq {
+                        mask: le(&body[..2]) as u16,
+                        edge_triggered: (flags & 0x01) != 0,
+                        active_low: (flags & 0x08) != 0,
+                        shared: (flags & 0x10) != 0,
+                    }
+                }
+                0x05 => Resource::Dma {
+                    mask: body[0],
+                    flags: body[1],
+                },
+                0x08 => Resource::Io {
+                    decode_16bit: (body[0] & 0x01) != 0,
+                    min: le(&body[1..3]) as u16,
+                    max: le(&body[3..5]) as u16,
+                    alignment: body[5],
+                    length: body[6],
+                },
+                0x09 => Resource::FixedIo {
+                    base: le(&body[0..2]) as u16,
+                    length: body[2],
+                },
+                // End Tag
+                0x0f => break,
+                n => Resource::Unknown { tag: n },
+            });
+        } else {
+            // large resource
+            let name = tag & 0x7f;
+            let length = le(bytes
+                .get(p + 1..p + 3)
+                .ok_or(AmlError::UnexpectedEndOfStream)?) as usize;
+            let body = bytes
+                .get(p + 3..p + 3 + length)
+                .ok_or(AmlError::UnexpectedEndOfStream)?;
+            p += 3 + length;
+
+            resources.push(match name {
+                0x05 => Resource::Memory32 {
+                    writable: (body[0] & 0x01) != 0,
+                    min: le(&body[1..5]) as u32,
+                    max: le(&body[5..9]) as u32,
+                    alignment: le(&body[9..13]) as u32,
+                    length: le(&body[13..17]) as u32,
+                },
+                0x06 => Resource::FixedMemory32 {
+                    writable: (body[0] & 0x01) != 0,
+                    base: le(&body[1..5]) as u32,
+                    length: le(&body[5..9]) as u32,
+                },
+                // DWord/Word/QWord Address Space
+                0x07 | 0x08 | 0x0a => {
+                    let w = match name {
+                        0x07 => 4,
+                        0x08 => 2,
+                        _ => 8,
+                    };
+                    let field = |n: usize| le(&body[3 + n * w..3 + (n + 1) * w]);
+                    Resource::AddressSpace {
+                        r#type: match body[0] {
+                            0 => AddressSpaceType::Memory,
+                            1 => AddressSpaceType::Io,
+                            2 => AddressSpaceType::BusNumber,
+                            x => AddressSpaceType::Other(x),
+                        },
+                        general_flags: body[1],
+                        type_specific_flags: body[2],
+                        granularity: field(0),
+                        min: field(1),
+                        max: field(2),
+                        translation_offset: field(3),
+                        length: field(4),
+                    }
+                }
+                0x09 => {
+                    let flags = body[0];
+                    let count = body[1] as usize;
+                    Resource::ExtendedInterrupt {
+                        consumer: (flags & 0x01) != 0,
+                        edge_triggered: (flags & 0x02) != 0,
+                        active_low: (flags & 0x04) != 0,
+                        shared: (flags & 0x08) != 0,
+                        interrupts: (0..count)
+                            .map(|n| le(&body[2 + n * 4..6 + n * 4]) as u32)
+                            .collect(),
+                    }
+                }
+                n => Resource::Unknown { tag: 0x80 | n },
+            });
+        }
+    }
+
+    Ok(resources)
+}
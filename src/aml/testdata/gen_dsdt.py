@@ -0,0 +1,579 @@
+#!/usr/bin/env python3
+"""Generates the DSDTs used by the AML interpreter tests.
+
+They are reduced reproductions of what QEMU's hw/i386/acpi-build.c emits for the
+pc (i440fx + PIIX4) and q35 (ICH9) machines: the PCI root bridge with its _CRS,
+the _PRT (a While loop on pc, PICF-selected packages on q35), the PCI interrupt
+link devices backed by the PIRQ route registers, HPET, COM1 and the sleep states.
+Dumps from a real guest (`acpidump -b`) can be dropped in place of these.
+
+usage: gen_dsdt.py  (writes qemu-i440fx-dsdt.aml and qemu-q35-dsdt.aml next to this file)
+"""
+
+import os
+import struct
+
+
+def pkg_length(body):
+    n = len(body)
+    for extra in range(4):
+        total = n + 1 + extra
+        if extra == 0 and total < 0x40:
+            return bytes([total]) + body
+        if extra > 0 and total < (1 << (4 + 8 * extra)):
+            b = [(extra << 6) | (total & 0x0F)]
+            for i in range(extra):
+                b.append((total >> (4 + 8 * i)) & 0xFF)
+            return bytes(b) + body
+    raise ValueError("too long")
+
+
+def name_seg(s):
+    return s.encode().ljust(4, b"_")
+
+
+def name(path):
+    out = b""
+    if path.startswith("\\"):
+        out += b"\\"
+        path = path[1:]
+    while path.startswith("^"):
+        out += b"^"
+        path = path[1:]
+    segs = [s for s in path.split(".") if s]
+    if len(segs) == 0:
+        return out + b"\x00"
+    if len(segs) == 1:
+        return out + name_seg(segs[0])
+    if len(segs) == 2:
+        return out + b"\x2e" + b"".join(map(name_seg, segs))
+    return out + b"\x2f" + bytes([len(segs)]) + b"".join(map(name_seg, segs))
+
+
+def integer(v):
+    if v == 0:
+        return b"\x00"
+    if v == 1:
+        return b"\x01"
+    if v <= 0xFF:
+        return b"\x0a" + struct.pack("<B", v)
+    if v <= 0xFFFF:
+        return b"\x0b" + struct.pack("<H", v)
+    if v <= 0xFFFFFFFF:
+        return b"\x0c" + struct.pack("<I", v)
+    return b"\x0e" + struct.pack("<Q", v)
+
+
+def local(n):
+    return bytes([0x60 + n])
+
+
+def arg(n):
+    return bytes([0x68 + n])
+
+
+NULL = b"\x00"
+
+
+def scope(path, *terms):
+    return b"\x10" + pkg_length(name(path) + b"".join(terms))
+
+
+def device(path, *terms):
+    return b"\x5b\x82" + pkg_length(name(path) + b"".join(terms))
+
+
+def method(path, argc, *terms, serialized=False):
+    flags = argc | (0x08 if serialized else 0)
+    return b"\x14" + pkg_length(name(path) + bytes([flags]) + b"".join(terms))
+
+
+def name_decl(path, value):
+    return b"\x08" + name(path) + value
+
+
+def buffer(data):
+    return b"\x11" + pkg_length(integer(len(data)) + data)
+
+
+def package(count, *elements):
+    return b"\x12" + pkg_length(bytes([count]) + b"".join(elements))
+
+
+def op_region(path, space, offset, length):
+    return b"\x5b\x80" + name(path) + bytes([space]) + integer(offset) + integer(length)
+
+
+def field(path, flags, *units):
+    """units: (name, bits) or (None, bits) for Offset()/reserved"""
+    body = name(path) + bytes([flags])
+    for n, bits in units:
+        if n is None:
+            body += b"\x00" + raw_pkg(bits)
+        else:
+            body += name_seg(n) + raw_pkg(bits)
+    return b"\x5b\x81" + pkg_length(body)
+
+
+def raw_pkg(v):
+    """PkgLength encoding of a raw value (used by FieldList)"""
+    if v < 0x40:
+        return bytes([v])
+    if v < 0x1000:
+        return bytes([0x40 | (v & 0x0F), v >> 4])
+    raise ValueError("too long")
+
+
+def store(src, dst):
+    return b"\x70" + src + dst
+
+
+def binop(op, a, b, target=NULL):
+    return bytes([op]) + a + b + target
+
+
+def add(a, b, t=NULL):
+    return binop(0x72, a, b, t)
+
+
+def and_(a, b, t=NULL):
+    return binop(0x7B, a, b, t)
+
+
+def or_(a, b, t=NULL):
+    return binop(0x7D, a, b, t)
+
+
+def shl(a, b, t=NULL):
+    return binop(0x79, a, b, t)
+
+
+def shr(a, b, t=NULL):
+    return binop(0x7A, a, b, t)
+
+
+def subtract(a, b, t=NULL):
+    return binop(0x74, a, b, t)
+
+
+def lequal(a, b):
+    return b"\x93" + a + b
+
+
+def lless(a, b):
+    return b"\x95" + a + b
+
+
+def lgreater(a, b):
+    return b"\x94" + a + b
+
+
+def lor(a, b):
+    return b"\x91" + a + b
+
+
+def index(src, i, t=NULL):
+    return b"\x88" + src + i + t
+
+
+def increment(t):
+    return b"\x75" + t
+
+
+def if_(pred, *terms):
+    return b"\xa0" + pkg_length(pred + b"".join(terms))
+
+
+def else_(*terms):
+    return b"\xa1" + pkg_length(b"".join(terms))
+
+
+def while_(pred, *terms):
+    return b"\xa2" + pkg_length(pred + b"".join(terms))
+
+
+def return_(v):
+    return b"\xa4" + v
+
+
+def create_dword_field(src, offset, n):
+    return b"\x8a" + src + integer(offset) + name(n)
+
+
+def eisa_id(s):
+    c = [ord(x) - 0x40 for x in s[:3]]
+    v = (c[0] << 26) | (c[1] << 21) | (c[2] << 16) | int(s[3:], 16)
+    return integer(struct.unpack("<I", struct.pack(">I", v))[0])
+
+
+def string(s):
+    return b"\x0d" + s.encode() + b"\x00"
+
+
+# resource descriptors
+END_TAG = b"\x79\x00"
+
+
+def rt(*descs):
+    return buffer(b"".join(descs) + END_TAG)
+
+
+def io16(min_, max_, align, length):
+    return struct.pack("<BBHHBB", 0x47, 0x01, min_, max_, align, length)
+
+
+def irq_no_flags(*irqs):
+    mask = 0
+    for i in irqs:
+        mask |= 1 << i
+    return struct.pack("<BH", 0x22, mask)
+
+
+def interrupt(*gsis):
+    # ResourceConsumer, Level, ActiveHigh, Shared
+    body = bytes([0x09, len(gsis)]) + b"".join(struct.pack("<I", g) for g in gsis)
+    return struct.pack("<BH", 0x89, len(body)) + body
+
+
+def word_space(type_, type_flags, gran, min_, max_, tra, length):
+    # ResourceProducer, MinFixed, MaxFixed, PosDecode
+    body = struct.pack("<BBBHHHHH", type_, 0x0C, type_flags, gran, min_, max_, tra, length)
+    return struct.pack("<BH", 0x88, len(body)) + body
+
+
+def dword_memory(cacheable, min_, max_):
+    body = struct.pack(
+        "<BBBIIIII", 0, 0x0C, 0x01 | (0x02 if cacheable else 0), 0, min_, max_, 0, max_ - min_ + 1
+    )
+    return struct.pack("<BH", 0x87, len(body)) + body
+
+
+def memory32_fixed(base, length):
+    return struct.pack("<BHBII", 0x86, 9, 0, base, length)
+
+
+def table(body, oem_table_id):
+    header = struct.pack(
+        "<4sIBB6s8sI4sI", b"DSDT", 36 + len(body), 1, 0, b"BOCHS ", oem_table_id, 1, b"BXPC", 1
+    )
+    t = bytearray(header + body)
+    t[9] = (-sum(t)) & 0xFF
+    return bytes(t)
+
+
+# ---- common parts ----
+
+
+def debug_port():
+    return scope(
+        "\\",
+        op_region("DBG", 0x01, 0x0402, 1),
+        field("DBG", 0x01, ("DBGB", 8)),
+        method(
+            "DBUG",
+            1,
+            b"\x98" + arg(0) + local(0),  # ToHexString
+            b"\x96" + local(0) + local(0),  # ToBuffer
+            store(subtract(b"\x87" + local(0), integer(1)), local(1)),
+            store(integer(0), local(2)),
+            while_(
+                lless(local(2), local(1)),
+                store(b"\x83" + index(local(0), local(2)), name("DBGB")),
+                increment(local(2)),
+            ),
+            store(integer(0x0A), name("DBGB")),
+        ),
+    )
+
+
+def pci_root(hid, cid, mem_max):
+    terms = [name_decl("_HID", eisa_id(hid))]
+    if cid:
+        terms.append(name_decl("_CID", eisa_id(cid)))
+    terms += [
+        name_decl("_ADR", integer(0)),
+        name_decl("_UID", integer(0)),
+        name_decl(
+            "_CRS",
+            rt(
+                word_space(2, 0x00, 0, 0x0000, 0x00FF, 0, 0x0100),
+                io16(0x0CF8, 0x0CF8, 0x01, 0x08),
+                word_space(1, 0x03, 0, 0x0000, 0x0CF7, 0, 0x0CF8),
+                word_space(1, 0x03, 0, 0x0D00, 0xFFFF, 0, 0xF300),
+                dword_memory(True, 0x000A0000, 0x000BFFFF),
+                dword_memory(False, 0x80000000, mem_max),
+            ),
+        ),
+    ]
+    return scope("\\_SB", device("PCI0", *terms))
+
+
+def hpet():
+    return scope(
+        "\\_SB",
+        device(
+            "HPET",
+            name_decl("_HID", eisa_id("PNP0103")),
+            name_decl("_UID", integer(0)),
+            op_region("HPTM", 0x00, 0xFED00000, 0x400),
+            field("HPTM", 0x13, ("VEND", 32), ("PRD", 32)),
+            method(
+                "_STA",
+                0,
+                store(name("VEND"), local(0)),
+                store(name("PRD"), local(1)),
+                shr(local(0), integer(0x10), local(0)),
+                if_(
+                    lor(lequal(local(0), integer(0)), lequal(local(0), integer(0xFFFF))),
+                    return_(integer(0)),
+                ),
+                if_(
+                    lor(lequal(local(1), integer(0)), lgreater(local(1), integer(100000000))),
+                    return_(integer(0)),
+                ),
+                return_(integer(0x0F)),
+            ),
+            name_decl("_CRS", rt(memory32_fixed(0xFED00000, 0x400))),
+        ),
+    )
+
+
+def com1():
+    return scope(
+        "\\_SB.PCI0.ISA",
+        device(
+            "COM1",
+            name_decl("_HID", eisa_id("PNP0501")),
+            name_decl("_UID", integer(1)),
+            name_decl("_STA", integer(0x0F)),
+            name_decl("_CRS", rt(io16(0x03F8, 0x03F8, 0x00, 0x08), irq_no_flags(4))),
+        ),
+    )
+
+
+def iqst():
+    return method(
+        "IQST",
+        1,
+        if_(and_(integer(0x80), arg(0)), return_(integer(0x09))),
+        return_(integer(0x0B)),
+    )
+
+
+def iqcr(piix4):
+    if piix4:
+        set_irq = if_(lless(arg(0), integer(0x80)), store(arg(0), name("PRRI")))
+    else:
+        set_irq = store(and_(arg(0), integer(0x0F)), name("PRRI"))
+    return method(
+        "IQCR",
+        1,
+        name_decl("PRR0", rt(interrupt(0))),
+        create_dword_field(name("PRR0"), 5, "PRRI"),
+        set_irq,
+        return_(name("PRR0")),
+        serialized=True,
+    )
+
+
+def link_dev(n, uid, reg):
+    return device(
+        n,
+        name_decl("_HID", eisa_id("PNP0C0F")),
+        name_decl("_UID", integer(uid)),
+        name_decl("_PRS", rt(interrupt(5, 10, 11))),
+        method("_STA", 0, return_(name("IQST") + name(reg))),
+        method("_DIS", 0, or_(name(reg), integer(0x80), name(reg))),
+        method("_CRS", 0, return_(name("IQCR") + name(reg))),
+        method(
+            "_SRS",
+            1,
+            create_dword_field(arg(0), 5, "PRRI"),
+            store(name("PRRI"), name(reg)),
+        ),
+    )
+
+
+def gsi_dev(n, uid, gsi):
+    return device(
+        n,
+        name_decl("_HID", eisa_id("PNP0C0F")),
+        name_decl("_UID", integer(uid)),
+        name_decl("_PRS", rt(interrupt(gsi))),
+        name_decl("_CRS", rt(interrupt(gsi))),
+        method("_SRS", 1),
+    )
+
+
+def prt_entry(n):
+    return package(4, integer(0), integer(0), name(n), integer(0))
+
+
+def sleep_states(s3):
+    terms = []
+    if s3:
+        terms.append(name_decl("\\_S3", package(4, integer(1), integer(1), integer(0), integer(0))))
+    terms.append(name_decl("\\_S4", package(4, integer(2), integer(2), integer(0), integer(0))))
+    terms.append(name_decl("\\_S5", package(4, integer(0), integer(0), integer(0), integer(0))))
+    return b"".join(terms)
+
+
+# ---- pc (i440fx) ----
+
+
+def i440fx():
+    res, pin, slot, lnk_idx, route = (local(n) for n in range(5))
+
+    def initialize_route(link, idx):
+        return if_(lequal(lnk_idx, integer(idx)), store(prt_entry(link), route))
+
+    prt = method(
+        "_PRT",
+        0,
+        store(package(0x80), res),
+        store(integer(0), pin),
+        while_(
+            lless(pin, integer(0x80)),
+            store(shr(pin, integer(2)), slot),
+            store(and_(add(pin, slot), integer(3)), lnk_idx),
+            initialize_route("LNKD", 0),
+            if_(
+                lequal(lnk_idx, integer(1)),
+                if_(lequal(pin, integer(4)), store(prt_entry("LNKS"), route)),
+                else_(store(prt_entry("LNKA"), route)),
+            ),
+            initialize_route("LNKB", 2),
+            initialize_route("LNKC", 3),
+            store(or_(shl(slot, integer(16)), integer(0xFFFF)), index(route, integer(0))),
+            store(and_(pin, integer(3)), index(route, integer(1))),
+            store(route, index(res, pin)),
+            increment(pin),
+        ),
+        return_(res),
+    )
+
+    body = b"".join(
+        [
+            debug_port(),
+            pci_root("PNP0A03", None, 0xFEBFFFFF),
+            hpet(),
+            scope(
+                "\\_SB.PCI0",
+                device(
+                    "ISA",
+                    name_decl("_ADR", integer(0x00010000)),
+                    op_region("P40C", 0x02, 0x60, 0x04),
+                ),
+            ),
+            com1(),
+            scope(
+                "\\_SB",
+                scope("PCI0", prt),
+                field("PCI0.ISA.P40C", 0x01, ("PRQ0", 8), ("PRQ1", 8), ("PRQ2", 8), ("PRQ3", 8)),
+                iqst(),
+                iqcr(True),
+                link_dev("LNKA", 0, "PRQ0"),
+                link_dev("LNKB", 1, "PRQ1"),
+                link_dev("LNKC", 2, "PRQ2"),
+                link_dev("LNKD", 3, "PRQ3"),
+                device(
+                    "LNKS",
+                    name_decl("_HID", eisa_id("PNP0C0F")),
+                    name_decl("_UID", integer(4)),
+                    name_decl("_STA", integer(0x0B)),
+                    name_decl("_PRS", rt(interrupt(9))),
+                    method("_SRS", 1),
+                    method("_DIS", 0),
+                    method("_CRS", 0, return_(name("_PRS"))),
+                ),
+            ),
+            sleep_states(True),
+        ]
+    )
+    return table(body, b"BXPC    ")
+
+
+# ---- q35 ----
+
+
+def q35_routing_table(prefix):
+    entries = []
+
+    def entry(slot, pin, link):
+        return package(4, integer((slot << 16) | 0xFFFF), integer(pin), name(prefix + link), integer(0))
+
+    for slot in range(0x18):
+        for pin in range(4):
+            entries.append(entry(slot, pin, "EFGH"[(slot + pin) & 3]))
+    # INTA -> PIRQA for slot 25 - 31 (the default value of D<N>IR)
+    for slot in range(0x18, 0x1E):
+        for pin in range(4):
+            entries.append(entry(slot, pin, "ABCD"[pin]))
+    # PCIe->PCI bridge, uses PIRQ[E-H]
+    for pin in range(4):
+        entries.append(entry(0x1E, pin, "EFGH"[pin]))
+    for pin in range(4):
+        entries.append(entry(0x1F, pin, "ABCD"[pin]))
+    return package(0x80, *entries)
+
+
+def q35():
+    links = "ABCDEFGH"
+    body = b"".join(
+        [
+            debug_port(),
+            pci_root("PNP0A08", "PNP0A03", 0xAFFFFFFF),
+            hpet(),
+            scope(
+                "\\_SB.PCI0",
+                device(
+                    "ISA",
+                    name_decl("_ADR", integer(0x001F0000)),
+                    op_region("PIRQ", 0x02, 0x60, 0x0C),
+                ),
+            ),
+            com1(),
+            name_decl("PICF", integer(0)),
+            method("_PIC", 1, store(arg(0), name("PICF"))),
+            scope(
+                "\\_SB.PCI0",
+                name_decl("PRTP", q35_routing_table("LNK")),
+                name_decl("PRTA", q35_routing_table("GSI")),
+                method(
+                    "_PRT",
+                    0,
+                    if_(lequal(name("PICF"), integer(0)), return_(name("PRTP"))),
+                    else_(return_(name("PRTA"))),
+                ),
+            ),
+            scope(
+                "\\_SB",
+                field(
+                    "PCI0.ISA.PIRQ",
+                    0x01,
+                    ("PRQA", 8),
+                    ("PRQB", 8),
+                    ("PRQC", 8),
+                    ("PRQD", 8),
+                    (None, 32),
+                    ("PRQE", 8),
+                    ("PRQF", 8),
+                    ("PRQG", 8),
+                    ("PRQH", 8),
+                ),
+                iqst(),
+                iqcr(False),
+                *[link_dev("LNK" + c, n, "PRQ" + c) for n, c in enumerate(links)],
+                *[gsi_dev("GSI" + c, n, 0x10 + n) for n, c in enumerate(links)],
+            ),
+            sleep_states(False),
+        ]
+    )
+    return table(body, b"BXDSDT  ")
+
+
+if __name__ == "__main__":
+    here = os.path.dirname(os.path.abspath(__file__))
+    for file, t in [("qemu-i440fx-dsdt.aml", i440fx()), ("qemu-q35-dsdt.aml", q35())]:
+        with open(os.path.join(here, file), "wb") as f:
+            f.write(t)
@@ -0,0 +1,298 @@
+//! Host tests against DSDTs of the QEMU pc (i440fx) and q35 machines
+//!
+//! `testdata/*.aml` are generated by `testdata/gen_dsdt.py`, which reproduces the AML that QEMU's
+//! acpi-build emits for these machines (PCI root bridge, _PRT, PCI interrupt links, HPET, COM1
+//! and the sleep states).
+
+use super::{
+    name::AmlName,
+    object::Object,
+    resource::{self, AddressSpaceType, Resource},
+    AmlError, Handler, Interpreter, PciAddress, PciRoutingEntry, PciRoutingSource,
+};
+use alloc::vec::Vec;
+use std::cell::RefCell;
+
+const I440FX_DSDT: &[u8] = include_bytes!("testdata/qemu-i440fx-dsdt.aml");
+const Q35_DSDT: &[u8] = include_bytes!("testdata/qemu-q35-dsdt.aml");
+
+/// PIRQ route registers of the ISA/LPC bridge and the HPET registers, as set up by SeaBIOS
+struct TestHandler {
+    isa: PciAddress,
+    pirq: RefCell<[u8; 12]>,
+}
+impl TestHandler {
+    fn new(isa_device: u8) -> Self {
+        Self {
+            isa: PciAddress {
+                segment: 0,
+                bus: 0,
+                device: isa_device,
+                function: 0,
+            },
+            pirq: RefCell::new([10, 10, 11, 11, 0, 0, 0, 0, 10, 10, 11, 11]),
+        }
+    }
+}
+impl Handler for TestHandler {
+    fn read_memory(&self, address: u64, _bit_width: u8) -> u64 {
+        match address {
+            // GCAP_ID: vendor 8086h
+            0xfed0_0000 => 0x8086_a201,
+            // COUNTER_CLK_PERIOD: 10ns
+            0xfed0_0004 => 10_000_000,
+            _ => u64::MAX,
+        }
+    }
+
+    fn write_memory(&self, _address: u64, _bit_width: u8, _value: u64) {}
+
+    fn read_io(&self, _port: u16, _bit_width: u8) -> u64 {
+        u64::MAX
+    }
+
+    fn write_io(&self, _port: u16, _bit_width: u8, _value: u64) {}
+
+    fn read_pci_config(&self, address: PciAddress, offset: u16, bit_width: u8) -> u64 {
+        let pirq = self.pirq.borrow();
+        (0..bit_width as u16 / 8)
+            .map(
+                |n| match offset.checked_sub(0x60).map(|x| (x + n) as usize) {
+                    Some(i) if address == self.isa && i < pirq.len() => pirq[i] as u64,
+                    _ => 0xff,
+                },
+            )
+            .rev()
+            .fold(0, |a, b| (a << 8) | b)
+    }
+
+    fn write_pci_config(&self, address: PciAddress, offset: u16, bit_width: u8, value: u64) {
+        let mut pirq = self.pirq.borrow_mut();
+        for n in 0..bit_width as u16 / 8 {
+            if let Some(i) = offset.checked_sub(0x60).map(|x| (x + n) as usize) {
+                if address == self.isa && i < pirq.len() {
+                    pirq[i] = (value >> (n * 8)) as u8;
+                }
+            }
+        }
+    }
+}
+
+fn load(dsdt: &[u8], isa_device: u8) -> Interpreter<TestHandler> {
+    let mut aml = Interpreter::new(TestHandler::new(isa_device));
+    aml.load_table(dsdt).unwrap();
+    assert!(aml.load_errors().is_empty(), "{:?}", aml.load_errors());
+
+    aml
+}
+
+fn path(s: &str) -> AmlName {
+    AmlName::from_str(s).unwrap()
+}
+
+fn crs(aml: &mut Interpreter<TestHandler>, device: &str) -> Vec<Resource> {
+    match aml.evaluate_child(&path(device), b"_CRS").unwrap() {
+        Some(Object::Buffer(b)) => resource::parse(&b.borrow()).unwrap(),
+        x => panic!("unexpected _CRS of {device}: {x:?}"),
+    }
+}
+
+fn interrupts(r: &[Resource]) -> Vec<u32> {
+    match r {
+        [Resource::ExtendedInterrupt { interrupts, .. }] => interrupts.clone(),
+        x => panic!("not a single Interrupt descriptor: {x:?}"),
+    }
+}
+
+fn link(e: &PciRoutingEntry) -> (u16, u8, AmlName) {
+    match &e.source {
+        PciRoutingSource::Link(n, 0) => (e.device, e.pin, n.clone()),
+        x => panic!("not routed to a link device: {x:?}"),
+    }
+}
+
+fn assert_root_bridge_crs(aml: &mut Interpreter<TestHandler>, mem_max: u64) {
+    let r = crs(aml, "\\_SB.PCI0");
+    assert_eq!(r.len(), 6);
+    assert!(matches!(
+        r[0],
+        Resource::AddressSpace {
+            r#type: AddressSpaceType::BusNumber,
+            min: 0,
+            max: 0xff,
+            ..
+        }
+    ));
+    assert_eq!(
+        r[1],
+        Resource::Io {
+            decode_16bit: true,
+            min: 0xcf8,
+            max: 0xcf8,
+            alignment: 1,
+            length: 8,
+        }
+    );
+    assert!(matches!(
+        r[3],
+        Resource::AddressSpace {
+            r#type: AddressSpaceType::Io,
+            min: 0xd00,
+            max: 0xffff,
+            length: 0xf300,
+            ..
+        }
+    ));
+    assert!(matches!(
+        r[5],
+        Resource::AddressSpace {
+            r#type: AddressSpaceType::Memory,
+            min: 0x8000_0000,
+            max,
+            ..
+        } if max == mem_max
+    ));
+}
+
+fn assert_common_devices(aml: &mut Interpreter<TestHandler>) {
+    assert_eq!(aml.sleep_types(5).unwrap(), (0, 0));
+    assert_eq!(aml.device_status(&path("\\_SB.HPET")).unwrap(), 0x0f);
+    assert_eq!(
+        crs(aml, "\\_SB.HPET"),
+        [Resource::FixedMemory32 {
+            writable: false,
+            base: 0xfed0_0000,
+            length: 0x400,
+        }]
+    );
+    assert_eq!(
+        crs(aml, "\\_SB.PCI0.ISA.COM1"),
+        [
+            Resource::Io {
+                decode_16bit: true,
+                min: 0x3f8,
+                max: 0x3f8,
+                alignment: 0,
+                length: 8,
+            },
+            Resource::Irq {
+                mask: 1 << 4,
+                edge_triggered: true,
+                active_low: false,
+                shared: false,
+            }
+        ]
+    );
+}
+
+#[test]
+fn i440fx() {
+    let mut aml = load(I440FX_DSDT, 1);
+    assert!(!aml.integer_width_64);
+    assert_common_devices(&mut aml);
+    assert_eq!(aml.sleep_types(3).unwrap(), (1, 1));
+    assert_root_bridge_crs(&mut aml, 0xfebf_ffff);
+
+    let prt = aml.pci_routing_table(&path("\\_SB.PCI0")).unwrap();
+    assert_eq!(prt.len(), 128);
+    for (n, e) in prt.iter().enumerate() {
+        let (device, pin, l) = link(e);
+        assert_eq!((device, pin), ((n / 4) as u16, (n % 4) as u8));
+        let expected = match (n + n / 4) % 4 {
+            1 if n == 4 => "\\_SB.LNKS",
+            0 => "\\_SB.LNKD",
+            1 => "\\_SB.LNKA",
+            2 => "\\_SB.LNKB",
+            _ => "\\_SB.LNKC",
+        };
+        assert_eq!(l, path(expected), "entry {n}");
+    }
+
+    // PIRQ route registers (PCI config 60h-63h of the PIIX4 ISA bridge)
+    for (l, irq) in [("LNKA", 10), ("LNKB", 10), ("LNKC", 11), ("LNKD", 11)] {
+        let l = alloc::format!("\\_SB.{l}");
+        assert_eq!(interrupts(&crs(&mut aml, &l)), [irq]);
+        assert_eq!(aml.device_status(&path(&l)).unwrap(), 0x0b);
+    }
+    assert_eq!(interrupts(&crs(&mut aml, "\\_SB.LNKS")), [9]);
+
+    aml.evaluate_str("\\_SB.LNKB._DIS", Vec::new()).unwrap();
+    assert_eq!(aml.handler.pirq.borrow()[1], 0x8a);
+    assert_eq!(aml.device_status(&path("\\_SB.LNKB")).unwrap(), 0x09);
+    assert_eq!(interrupts(&crs(&mut aml, "\\_SB.LNKB")), [0]);
+}
+
+#[test]
+fn q35() {
+    let mut aml = load(Q35_DSDT, 0x1f);
+    assert_common_devices(&mut aml);
+    assert!(matches!(aml.sleep_types(3), Err(AmlError::NameNotFound(_))));
+    assert_root_bridge_crs(&mut aml, 0xafff_ffff);
+
+    // PIC mode: PCI interrupt links
+    let prt = aml.pci_routing_table(&path("\\_SB.PCI0")).unwrap();
+    assert_eq!(prt.len(), 128);
+    assert_eq!(link(&prt[0]), (0, 0, path("\\_SB.LNKE")));
+    assert_eq!(link(&prt[5]), (1, 1, path("\\_SB.LNKG")));
+    assert_eq!(link(&prt[0x1f * 4 + 2]), (0x1f, 2, path("\\_SB.LNKC")));
+
+    for (l, irq) in [("LNKA", 10), ("LNKD", 11), ("LNKE", 10), ("LNKH", 11)] {
+        let l = alloc::format!("\\_SB.{l}");
+        assert_eq!(interrupts(&crs(&mut aml, &l)), [irq]);
+    }
+
+    // APIC mode: GSI 16-23
+    aml.evaluate_str("\\_PIC", alloc::vec![Object::Integer(1)])
+        .unwrap();
+    let prt = aml.pci_routing_table(&path("\\_SB.PCI0")).unwrap();
+    assert_eq!(prt.len(), 128);
+    assert_eq!(link(&prt[0]), (0, 0, path("\\_SB.GSIE")));
+    assert_eq!(link(&prt[0x1f * 4]), (0x1f, 0, path("\\_SB.GSIA")));
+    assert_eq!(interrupts(&crs(&mut aml, "\\_SB.GSIE")), [0x14]);
+    assert_eq!(interrupts(&crs(&mut aml, "\\_SB.GSIA")), [0x10]);
+}
+
+#[test]
+fn skip_unknown_term() {
+    // Scope (\_SB) { Device (BAD0) { <unknown opcode 5B FE> } Device (GOOD) { Name (_UID, 0x2A) } }
+    #[rustfmt::skip]
+    let body = [
+        0x10, 0x1c, b'_', b'S', b'B', b'_',
+            0x5b, 0x82, 0x07, b'B', b'A', b'D', b'0', 0x5b, 0xfe,
+            0x5b, 0x82, 0x0c, b'G', b'O', b'O', b'D',
+                0x08, b'_', b'U', b'I', b'D', 0x0a, 0x2a,
+    ];
+    let mut table = Vec::from(&I440FX_DSDT[..36]);
+    table.extend_from_slice(&body);
+    let length = table.len() as u32;
+    table[4..8].copy_from_slice(&length.to_le_bytes());
+
+    let mut aml = Interpreter::new(TestHandler::new(1));
+    aml.load_table(&table).unwrap();
+    assert!(matches!(
+        aml.load_errors(),
+        [AmlError::UnknownExtOpcode(0xfe)]
+    ));
+    assert!(aml.namespace().contains(&path("\\_SB.BAD0")));
+    assert!(matches!(
+        aml.evaluate_str("\\_SB.GOOD._UID", Vec::new()),
+        Ok(Object::Integer(0x2a))
+    ));
+}
+
+#[test]
+fn truncated_table_header() {
+    let mut aml = Interpreter::new(TestHandler::new(1));
+    assert!(matches!(
+        aml.load_table(&I440FX_DSDT[..35]),
+        Err(AmlError::UnexpectedEndOfStream)
+    ));
+
+    // the length field is smaller than the header itself
+    let mut table = Vec::from(&I440FX_DSDT[..64]);
+    table[4..8].copy_from_slice(&20u32.to_le_bytes());
+    assert!(matches!(
+        aml.load_table(&table),
+        Err(AmlError::UnexpectedEndOfStream)
+    ));
+}
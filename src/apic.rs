@@ -0,0 +1,200 @@
+use crate::rdmsr;
+
+pub struct LocalAPIC {
+    base_address: usize,
+}
+impl LocalAPIC {
+    const ID: usize = 0x20;
+    const VERSION: usize = 0x30;
+    const EOI: usize = 0xb0;
+    const SPURIOUS_INTERRUPT_VECTOR: usize = 0xf0;
+    const LVT_TIMER: usize = 0x320;
+    const TIMER_INITIAL_COUNT: usize = 0x380;
+    const TIMER_CURRENT_COUNT: usize = 0x390;
+    const TIMER_DIVIDE_CONFIGURATION: usize = 0x3e0;
+
+    const LVT_MASKED: u32 = 1 << 16;
+    const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+
+    pub fn get() -> Self {
+        let apic_base = unsafe { rdmsr!(0x1b) };
+
+        Self {
+            base_address: apic_base as usize & !0xfff,
+        }
+    }
+
+    #[inline]
+    pub const fn base_address(&self) -> usize {
+        self.base_address
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { core::ptr::read_volatile((self.base_address + offset) as *const u32) }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { core::ptr::write_volatile((self.base_address + offset) as *mut u32, value) }
+    }
+
+    pub fn read_version_register(&self) -> u32 {
+        self.read(Self::VERSION)
+    }
+
+    pub fn id(&self) -> u8 {
+        (self.read(Self::ID) >> 24) as _
+    }
+
+    /// Software-enables the local APIC with the given spurious interrupt vector
+    pub fn enable(&self, spurious_vector: u8) {
+        let v = self.read(Self::SPURIOUS_INTERRUPT_VECTOR) & !0xff;
+        self.write(
+            Self::SPURIOUS_INTERRUPT_VECTOR,
+            v | 0x100 | spurious_vector as u32,
+        );
+    }
+
+    pub fn end_of_interrupt(&self) {
+        self.write(Self::EOI, 0);
+    }
+
+    /// `divider` must be a power of two in 1..=128
+    pub fn set_timer_divider(&self, divider: u8) {
+        let v = match divider {
+            1 => 0b1011,
+            2 => 0b0000,
+            4 => 0b0001,
+            8 => 0b0010,
+            16 => 0b0011,
+            32 => 0b1000,
+            64 => 0b1001,
+            128 => 0b1010,
+            _ => panic!("invalid local apic timer divider: {divider}"),
+        };
+        self.write(Self::TIMER_DIVIDE_CONFIGURATION, v);
+    }
+
+    /// Starts the timer. `vector = None` leaves the timer interrupt masked (for calibration).
+    pub fn start_timer(&self, initial_count: u32, vector: Option<u8>, periodic: bool) {
+        let mut lvt = match vector {
+            Some(v) => v as u32,
+            None => Self::LVT_MASKED,
+        };
+        if periodic {
+            lvt |= Self::LVT_TIMER_PERIODIC;
+        }
+
+        self.write(Self::LVT_TIMER, lvt);
+        self.write(Self::TIMER_INITIAL_COUNT, initial_count);
+    }
+
+    pub fn stop_timer(&self) {
+        self.write(Self::TIMER_INITIAL_COUNT, 0);
+        self.write(Self::LVT_TIMER, Self::LVT_MASKED);
+    }
+
+    pub fn timer_current_count(&self) -> u32 {
+        self.read(Self::TIMER_CURRENT_COUNT)
+    }
+}
+
+#[repr(transparent)]
+#[derive(Clone, Copy)]
+pub struct RedirectionEntry(pub u64);
+impl RedirectionEntry {
+    /// fixed delivery, physical destination, active high, edge triggered
+    pub const fn new(vector: u8, destination_apic_id: u8) -> Self {
+        Self(vector as u64 | ((destination_apic_id as u64) << 56))
+    }
+}
+// Note: HPETのコンパレータはエッジ・アクティブハイで配線するので今は使っていない（PCIのINTx用）
+#[allow(dead_code)]
+impl RedirectionEntry {
+    pub const fn active_low(self) -> Self {
+        Self(self.0 | (1 << 13))
+    }
+
+    pub const fn level_triggered(self) -> Self {
+        Self(self.0 | (1 << 15))
+    }
+
+    pub const fn masked(self) -> Self {
+        Self(self.0 | (1 << 16))
+    }
+}
+
+/// Address/data pair a PCI function writes to raise an MSI or MSI-X interrupt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptMessage {
+    pub address: u64,
+    pub data: u32,
+}
+impl InterruptMessage {
+    const ADDRESS_BASE: u64 = 0xfee0_0000;
+
+    /// fixed delivery, physical destination, edge triggered
+    pub const fn new(vector: u8, destination_apic_id: u8) -> Self {
+        Self {
+            address: Self::ADDRESS_BASE | ((destination_apic_id as u64) << 12),
+            data: vector as u32,
+        }
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+pub struct IOAPIC {
+    base_address: usize,
+    global_system_interrupt_base: u32,
+}
+impl IOAPIC {
+    const IOREGSEL: usize = 0x00;
+    const IOWIN: usize = 0x10;
+
+    const REG_VERSION: u32 = 0x01;
+    const REG_REDIRECTION_TABLE: u32 = 0x10;
+
+    pub const fn new(base_address: usize, global_system_interrupt_base: u32) -> Self {
+        Self {
+            base_address,
+            global_system_interrupt_base,
+        }
+    }
+
+    fn read(&self, register: u32) -> u32 {
+        unsafe {
+            core::ptr::write_volatile((self.base_address + Self::IOREGSEL) as *mut u32, register);
+            core::ptr::read_volatile((self.base_address + Self::IOWIN) as *const u32)
+        }
+    }
+
+    fn write(&self, register: u32, value: u32) {
+        unsafe {
+            core::ptr::write_volatile((self.base_address + Self::IOREGSEL) as *mut u32, register);
+            core::ptr::write_volatile((self.base_address + Self::IOWIN) as *mut u32, value);
+        }
+    }
+
+    pub fn redirection_entry_count(&self) -> u32 {
+        ((self.read(Self::REG_VERSION) >> 16) & 0xff) + 1
+    }
+
+    pub fn handles(&self, gsi: u32) -> bool {
+        (self.global_system_interrupt_base
+            ..self.global_system_interrupt_base + self.redirection_entry_count())
+            .contains(&gsi)
+    }
+
+    pub fn set_redirection(&self, gsi: u32, entry: RedirectionEntry) {
+        let register = Self::REG_REDIRECTION_TABLE + (gsi - self.global_system_interrupt_base) * 2;
+
+        // mask first so that a half-written entry never fires
+        self.write(register, 1 << 16);
+        self.write(register + 1, (entry.0 >> 32) as u32);
+        self.write(register, entry.0 as u32);
+    }
+
+    pub fn mask(&self, gsi: u32) {
+        let register = Self::REG_REDIRECTION_TABLE + (gsi - self.global_system_interrupt_base) * 2;
+        self.write(register, self.read(register) | (1 << 16));
+    }
+}
@@ -13,6 +13,34 @@ macro_rules! out32 {
         core::arch::asm!("out dx, eax", in("dx") $port, in("eax") $value, options(nomem, nostack, preserves_flags));
     }
 }
+#[macro_export]
+macro_rules! in16 {
+    ($port: expr) => {{
+        let res: u16;
+        core::arch::asm!("in ax, dx", in("dx") $port, out("ax") res, options(nomem, nostack, preserves_flags));
+        res
+    }}
+}
+#[macro_export]
+macro_rules! out16 {
+    ($port: expr, $value: expr) => {
+        core::arch::asm!("out dx, ax", in("dx") $port, in("ax") $value, options(nomem, nostack, preserves_flags));
+    }
+}
+#[macro_export]
+macro_rules! in8 {
+    ($port: expr) => {{
+        let res: u8;
+        core::arch::asm!("in al, dx", in("dx") $port, out("al") res, options(nomem, nostack, preserves_flags));
+        res
+    }}
+}
+#[macro_export]
+macro_rules! out8 {
+    ($port: expr, $value: expr) => {
+        core::arch::asm!("out dx, al", in("dx") $port, in("al") $value, options(nomem, nostack, preserves_flags));
+    }
+}
 
 #[macro_export]
 macro_rules! rdmsr {
@@ -26,21 +54,33 @@ macro_rules! rdmsr {
     }};
 }
 #[macro_export]
-macro_rules! wdmsr {
+macro_rules! wrmsr {
+    (efer, $value: expr) => {
+        wrmsr!(0xc000_0080 as u32, $value)
+    };
     ($addr: expr, $value: expr) => {{
         let v = $value;
         let (hi, lo) = ((v >> 32) as u32, v as u32);
-        core::arch::asm!("wdmsr", in("ecx") $addr, in("edx") hi, in("eax") lo, options(nomem, nostack, preserves_flags));
+        core::arch::asm!("wrmsr", in("ecx") $addr, in("edx") hi, in("eax") lo, options(nomem, nostack, preserves_flags));
     }}
 }
 
+#[macro_export]
+macro_rules! rdtsc {
+    () => {{
+        let (hi, lo): (u32, u32);
+        core::arch::asm!("rdtsc", out("eax") lo, out("edx") hi, options(nomem, nostack, preserves_flags));
+        (hi as u64) << 32 | lo as u64
+    }};
+}
+
 #[macro_export]
 macro_rules! lgdt {
     ($base_addr: expr, $limit: expr) => {{
         let mut content = [0u8; 10];
         content[2..].copy_from_slice(&u64::to_ne_bytes($base_addr as u64));
         content[..2].copy_from_slice(&u16::to_ne_bytes($limit));
-        core::arch::asm!("lgdt [{content}]", content = in(reg) content.as_ptr(), options(nomem, nostack, preserves_flags));
+        core::arch::asm!("lgdt [{content}]", content = in(reg) content.as_ptr(), options(readonly, nostack, preserves_flags));
     }};
 }
 #[macro_export]
@@ -49,7 +89,7 @@ macro_rules! lidt {
         let mut content = [0u8; 10];
         content[2..].copy_from_slice(&u64::to_ne_bytes($base_addr as _));
         content[..2].copy_from_slice(&u16::to_ne_bytes($limit as _));
-        core::arch::asm!("lidt [{content}]", content = in(reg) content.as_ptr(), options(nomem, nostack, preserves_flags));
+        core::arch::asm!("lidt [{content}]", content = in(reg) content.as_ptr(), options(readonly, nostack, preserves_flags));
     }}
 }
 
@@ -67,6 +107,13 @@ macro_rules! sti {
     };
 }
 
+#[macro_export]
+macro_rules! hlt {
+    () => {
+        core::arch::asm!("hlt", options(nomem, nostack, preserves_flags))
+    };
+}
+
 #[macro_export]
 macro_rules! load_cr {
     (0) => {{
@@ -83,6 +130,9 @@ macro_rules! load_cr {
 
 #[macro_export]
 macro_rules! store_cr {
+    (0, $value: expr) => {
+        core::arch::asm!("mov cr0, {x}", x = in(reg) $value, options(nostack))
+    };
     (3, $value: expr) => {
         core::arch::asm!("mov cr3, {x}", x = in(reg) $value, options(nostack))
     }
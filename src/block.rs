@@ -0,0 +1,67 @@
+//! Block device interface shared by storage drivers, partition tables and filesystems
+
+use alloc::{vec, vec::Vec};
+
+#[derive(Debug)]
+#[allow(dead_code)]
+pub enum BlockError {
+    /// the request runs past the end of the device
+    OutOfRange {
+        lba: u64,
+        blocks: u64,
+    },
+    /// buffer length is not a multiple of the block size
+    UnalignedBuffer(usize),
+    ReadOnly,
+    /// the driver could not queue the request
+    QueueFull,
+    IoError,
+    Unsupported,
+}
+
+/// A device addressed in fixed-size logical blocks
+pub trait BlockDevice {
+    /// bytes per logical block
+    fn block_size(&self) -> usize;
+
+    /// number of logical blocks
+    fn block_count(&self) -> u64;
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Reads `buf.len() / block_size()` blocks starting at `lba`
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError>;
+
+    /// Writes `buf.len() / block_size()` blocks starting at `lba`
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), BlockError>;
+
+    /// Makes completed writes persistent
+    fn flush(&mut self) -> Result<(), BlockError>;
+
+    /// Reads `count` blocks into a new buffer
+    fn read_to_vec(&mut self, lba: u64, count: u64) -> Result<Vec<u8>, BlockError> {
+        let mut buf = vec![0; count as usize * self.block_size()];
+        self.read_blocks(lba, &mut buf)?;
+
+        Ok(buf)
+    }
+
+    /// Validates a transfer of `len` bytes at `lba` and returns its block count
+    fn check_range(&self, lba: u64, len: usize) -> Result<u64, BlockError> {
+        if !len.is_multiple_of(self.block_size()) {
+            return Err(BlockError::UnalignedBuffer(len));
+        }
+
+        let blocks = (len / self.block_size()) as u64;
+        if lba
+            .checked_add(blocks)
+            .is_none_or(|end| end > self.block_count())
+        {
+            return Err(BlockError::OutOfRange { lba, blocks });
+        }
+
+        Ok(blocks)
+    }
+}
@@ -0,0 +1,177 @@
+//! What the loader tells the kernel (the argument of the kernel entry point)
+
+use crate::uefi::EfiMemoryDescriptor;
+
+/// Passed to the kernel entry `extern "sysv64" fn(&BootInfo) -> !` in RDI.
+///
+/// Addresses are physical (identity mapped when the kernel is entered) and 0 stands for
+/// "not available". Everything referred from here is in EfiLoaderCode/EfiLoaderData memory, so
+/// the kernel must not reuse that memory before it is done with this structure.
+#[repr(C)]
+#[derive(Debug)]
+pub struct BootInfo {
+    /// `BootInfo::MAGIC`
+    pub magic: u64,
+    /// `BootInfo::VERSION` of the loader; newer versions only append fields
+    pub version: u32,
+    /// size of the structure the loader filled in
+    pub size: u32,
+    pub kernel: KernelLayout,
+    pub framebuffer: Framebuffer,
+    /// memory map at ExitBootServices
+    pub memory_map: MemoryMap,
+    /// ACPI 2.0+ RSDP
+    pub rsdp_address: u64,
+    /// SMBIOS 2.x entry point structure
+    pub smbios_address: u64,
+    /// SMBIOS 3.x (64-bit) entry point structure
+    pub smbios3_address: u64,
+    /// UTF-8, not NUL terminated
+    pub command_line_address: u64,
+    pub command_line_length: u64,
+    /// array of `Module`
+    pub modules_address: u64,
+    pub module_count: u64,
+}
+impl BootInfo {
+    pub const MAGIC: u64 = u64::from_le_bytes(*b"BOOTINFO");
+    pub const VERSION: u32 = 1;
+
+    /// Header and kernel layout filled, everything else empty
+    pub const fn new(kernel: KernelLayout) -> Self {
+        Self {
+            magic: Self::MAGIC,
+            version: Self::VERSION,
+            size: core::mem::size_of::<Self>() as _,
+            kernel,
+            framebuffer: Framebuffer::EMPTY,
+            memory_map: MemoryMap::EMPTY,
+            rsdp_address: 0,
+            smbios_address: 0,
+            smbios3_address: 0,
+            command_line_address: 0,
+            command_line_length: 0,
+            modules_address: 0,
+            module_count: 0,
+        }
+    }
+}
+
+/// Where the kernel was placed
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct KernelLayout {
+    /// the memory image of the kernel (a multiple of 4KiB)
+    pub physical_base: u64,
+    pub virtual_base: u64,
+    pub size: u64,
+    /// difference between the virtual addresses and the ones linked in the image (PIE)
+    pub relocation_offset: u64,
+    /// PML4 of the page table in CR3 at the entry
+    pub page_table: u64,
+    /// the stack RSP points at the entry
+    pub stack_base: u64,
+    pub stack_size: u64,
+}
+
+/// Linear framebuffer of the GOP mode the loader set
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Framebuffer {
+    pub base: u64,
+    /// in bytes
+    pub size: u64,
+    pub width: u32,
+    pub height: u32,
+    /// pixels per scan line
+    pub stride: u32,
+    /// EFI_GRAPHICS_PIXEL_FORMAT (0: RGB8, 1: BGR8, 2: bit mask)
+    pub pixel_format: u32,
+    /// bits of red, green, blue and reserved (only for the bit mask format)
+    pub pixel_bitmask: [u32; 4],
+}
+impl Framebuffer {
+    pub const EMPTY: Self = Self {
+        base: 0,
+        size: 0,
+        width: 0,
+        height: 0,
+        stride: 0,
+        pixel_format: 0,
+        pixel_bitmask: [0; 4],
+    };
+}
+
+/// Array of EFI_MEMORY_DESCRIPTOR
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryMap {
+    pub descriptors_address: u64,
+    pub descriptor_count: u64,
+    /// stride of the array, may be larger than `size_of::<EfiMemoryDescriptor>()`
+    pub descriptor_size: u64,
+    pub descriptor_version: u32,
+    _padding: u32,
+}
+impl MemoryMap {
+    pub const EMPTY: Self = Self::new(0, 0, 0, 0);
+
+    pub const fn new(
+        descriptors_address: u64,
+        descriptor_count: u64,
+        descriptor_size: u64,
+        descriptor_version: u32,
+    ) -> Self {
+        Self {
+            descriptors_address,
+            descriptor_count,
+            descriptor_size,
+            descriptor_version,
+            _padding: 0,
+        }
+    }
+
+    pub fn descriptors(&self) -> impl Iterator<Item = &EfiMemoryDescriptor> {
+        (0..self.descriptor_count).map(|n| unsafe {
+            &*((self.descriptors_address + n * self.descriptor_size) as *const EfiMemoryDescriptor)
+        })
+    }
+
+    /// end of the highest physical range in the map
+    pub fn end(&self) -> u64 {
+        self.descriptors()
+            .map(|d| d.physical_start + d.number_of_pages * 4096)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// A file loaded along with the kernel (`module =` in loader.cfg)
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Module {
+    /// 4KiB aligned
+    pub address: u64,
+    pub size: u64,
+    /// path on the boot volume, UTF-8, NUL padded
+    pub name: [u8; Module::NAME_LENGTH],
+}
+impl Module {
+    pub const NAME_LENGTH: usize = 64;
+
+    /// `name` is truncated to `NAME_LENGTH` bytes (at a character boundary)
+    pub fn new(address: u64, size: u64, name: &str) -> Self {
+        let mut length = name.len().min(Self::NAME_LENGTH);
+        while !name.is_char_boundary(length) {
+            length -= 1;
+        }
+        let mut bytes = [0; Self::NAME_LENGTH];
+        bytes[..length].copy_from_slice(&name.as_bytes()[..length]);
+
+        Self {
+            address,
+            size,
+            name: bytes,
+        }
+    }
+}
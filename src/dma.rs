@@ -0,0 +1,62 @@
+//! Memory handed to devices for DMA
+
+use alloc::alloc::{alloc_zeroed, dealloc, handle_alloc_error, Layout};
+use core::ptr::NonNull;
+
+/// Zero-initialized, physically contiguous buffer.
+// Note: ヒープはイメージ内の静的領域でUEFIにより恒等マップされているので、仮想アドレスをそのまま物理アドレスとしてデバイスに渡せる
+pub struct DmaRegion {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+impl DmaRegion {
+    pub const PAGE_SIZE: usize = 4096;
+
+    pub fn new(size: usize, align: usize) -> Self {
+        let layout = Layout::from_size_align(size.max(1), align).expect("invalid DMA layout");
+        let ptr = NonNull::new(unsafe { alloc_zeroed(layout) })
+            .unwrap_or_else(|| handle_alloc_error(layout));
+
+        Self { ptr, layout }
+    }
+
+    /// Page aligned region
+    pub fn pages(size: usize) -> Self {
+        Self::new(size, Self::PAGE_SIZE)
+    }
+
+    #[inline]
+    pub fn physical_address(&self) -> u64 {
+        self.ptr.as_ptr() as u64
+    }
+
+    #[inline]
+    pub const fn len(&self) -> usize {
+        self.layout.size()
+    }
+
+    #[inline]
+    pub fn as_ptr<T>(&self) -> *mut T {
+        self.ptr.as_ptr() as _
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.len()) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len()) }
+    }
+
+    /// Gives up ownership so that the memory stays valid (and handed to the device) forever
+    pub fn leak(self) -> &'static mut [u8] {
+        let r = unsafe { core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len()) };
+        core::mem::forget(self);
+        r
+    }
+}
+impl Drop for DmaRegion {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr.as_ptr(), self.layout) }
+    }
+}
@@ -0,0 +1,72 @@
+//! EDID 1.x base block parser (VESA E-EDID)
+
+#[derive(Debug, Clone, Copy)]
+pub struct DetailedTiming {
+    /// pixel clock in kHz
+    pub pixel_clock: u32,
+    pub horizontal_active: u32,
+    pub vertical_active: u32,
+    /// image size in millimeters (0 if unknown)
+    pub width_mm: u32,
+    pub height_mm: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Edid {
+    /// 3-letter PNP ID
+    pub manufacturer: [u8; 3],
+    pub product_code: u16,
+    pub version: (u8, u8),
+    /// maximum image size in centimeters (0 if unknown or variable)
+    pub screen_size_cm: (u8, u8),
+    /// the first detailed timing descriptor, which is the preferred (native) mode
+    pub preferred_timing: Option<DetailedTiming>,
+}
+impl Edid {
+    const HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+    const BLOCK_SIZE: usize = 128;
+
+    /// None if the base block is missing, has a wrong header or a bad checksum
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let b = bytes.get(..Self::BLOCK_SIZE)?;
+        if b[..8] != Self::HEADER || b.iter().fold(0u8, |a, &x| a.wrapping_add(x)) != 0 {
+            return None;
+        }
+
+        // Note: メーカーIDは5ビットずつ3文字（1 = 'A'）をビッグエンディアンで詰めたもの
+        let id = u16::from_be_bytes([b[8], b[9]]);
+        let letter = |shift: u16| b'@' + ((id >> shift) & 0x1f) as u8;
+
+        Some(Self {
+            manufacturer: [letter(10), letter(5), letter(0)],
+            product_code: u16::from_le_bytes([b[10], b[11]]),
+            version: (b[18], b[19]),
+            screen_size_cm: (b[21], b[22]),
+            preferred_timing: DetailedTiming::parse(&b[54..72]),
+        })
+    }
+
+    /// native resolution from the preferred timing
+    pub fn preferred_resolution(&self) -> Option<(u32, u32)> {
+        self.preferred_timing
+            .map(|t| (t.horizontal_active, t.vertical_active))
+    }
+}
+
+impl DetailedTiming {
+    /// None for display descriptors (pixel clock 0)
+    fn parse(d: &[u8]) -> Option<Self> {
+        let pixel_clock = u16::from_le_bytes([d[0], d[1]]) as u32 * 10;
+        if pixel_clock == 0 {
+            return None;
+        }
+
+        Some(Self {
+            pixel_clock,
+            horizontal_active: d[2] as u32 | ((d[4] as u32 >> 4) << 8),
+            vertical_active: d[5] as u32 | ((d[7] as u32 >> 4) << 8),
+            width_mm: d[12] as u32 | ((d[14] as u32 >> 4) << 8),
+            height_mm: d[13] as u32 | ((d[14] as u32 & 0x0f) << 8),
+        })
+    }
+}
@@ -0,0 +1,926 @@
+//! ELF64 executables for x86-64 (System V gABI, x86-64 psABI): static and position independent
+
+use crate::paging::{AddressSpace, FrameAllocator, OutOfFrames, PageFlags, PAGE_SIZE};
+use alloc::vec::Vec;
+
+const MAGIC: &[u8; 4] = b"\x7fELF";
+const CLASS_32: u8 = 1;
+const CLASS_64: u8 = 2;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+const MACHINE_386: u16 = 3;
+const MACHINE_X86_64: u16 = 62;
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+const HEADER_SIZE_32: usize = 52;
+const PROGRAM_HEADER_SIZE_32: usize = 32;
+
+const TYPE_EXEC: u16 = 2;
+const TYPE_DYN: u16 = 3;
+
+pub const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+const PT_GNU_RELRO: u32 = 0x6474_e552;
+
+const DT_NULL: u64 = 0;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+const DT_SYMTAB: u64 = 6;
+const DT_SYMENT: u64 = 11;
+const DT_REL: u64 = 17;
+const DT_RELRSZ: u64 = 35;
+const DT_RELR: u64 = 36;
+const DT_RELRENT: u64 = 37;
+
+const R_X86_64_NONE: u32 = 0;
+const R_X86_64_64: u32 = 1;
+const R_X86_64_GLOB_DAT: u32 = 6;
+const R_X86_64_JUMP_SLOT: u32 = 7;
+const R_X86_64_RELATIVE: u32 = 8;
+
+const RELA_SIZE: u64 = 24;
+const SYMBOL_SIZE: u64 = 24;
+const SHN_UNDEF: u16 = 0;
+const SHN_ABS: u16 = 0xfff1;
+const STB_WEAK: u8 = 2;
+
+/// where position independent kernels are placed (the top 2GiB, as for the kernel code model)
+pub const PIE_VIRTUAL_BASE: u64 = 0xffff_ffff_8000_0000;
+/// upper bound of the memory image (lowest to highest loaded address)
+const MAX_IMAGE_SIZE: u64 = 1024 * 1024 * 1024;
+
+#[derive(Debug)]
+#[allow(dead_code)]
+pub enum ElfError {
+    NotElf,
+    /// not a little endian ELF64 x86-64 executable (ET_EXEC or ET_DYN without an interpreter)
+    Unsupported(&'static str),
+    /// a header or a segment points outside the file, or segments are inconsistent
+    Malformed(&'static str),
+    UnsupportedRelocation(u32),
+    UndefinedSymbol(u32),
+    OutOfMemory,
+}
+impl From<OutOfFrames> for ElfError {
+    fn from(_: OutOfFrames) -> Self {
+        Self::OutOfMemory
+    }
+}
+
+#[inline]
+fn u16_at(b: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(b[offset..offset + 2].try_into().unwrap())
+}
+
+#[inline]
+fn u32_at(b: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(b[offset..offset + 4].try_into().unwrap())
+}
+
+#[inline]
+fn u64_at(b: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(b[offset..offset + 8].try_into().unwrap())
+}
+
+#[inline]
+const fn align_down(x: u64, align: u64) -> u64 {
+    x & !(align - 1)
+}
+
+#[inline]
+const fn is_canonical(address: u64) -> bool {
+    ((address as i64) << 16 >> 16) as u64 == address
+}
+
+#[repr(transparent)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SegmentFlags(pub u32);
+impl SegmentFlags {
+    pub const EXECUTE: Self = Self(0x01);
+    pub const WRITE: Self = Self(0x02);
+    pub const READ: Self = Self(0x04);
+
+    #[inline]
+    pub const fn contains(self, other: Self) -> bool {
+        (self.0 & other.0) == other.0
+    }
+}
+impl core::ops::BitOr for SegmentFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+impl core::fmt::Debug for SegmentFlags {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use core::fmt::Write;
+
+        let names = [(Self::READ, 'R'), (Self::WRITE, 'W'), (Self::EXECUTE, 'X')];
+        for (bit, name) in names {
+            f.write_char(if self.contains(bit) { name } else { '-' })?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramHeader {
+    pub r#type: u32,
+    pub flags: SegmentFlags,
+    pub offset: u64,
+    pub virtual_address: u64,
+    pub physical_address: u64,
+    pub file_size: u64,
+    pub memory_size: u64,
+    pub align: u64,
+}
+impl ProgramHeader {
+    fn parse_32(b: &[u8]) -> Self {
+        Self {
+            r#type: u32_at(b, 0),
+            offset: u32_at(b, 4) as _,
+            virtual_address: u32_at(b, 8) as _,
+            physical_address: u32_at(b, 12) as _,
+            file_size: u32_at(b, 16) as _,
+            memory_size: u32_at(b, 20) as _,
+            flags: SegmentFlags(u32_at(b, 24)),
+            align: u32_at(b, 28) as _,
+        }
+    }
+
+    fn parse(b: &[u8]) -> Self {
+        Self {
+            r#type: u32_at(b, 0),
+            flags: SegmentFlags(u32_at(b, 4)),
+            offset: u64_at(b, 8),
+            virtual_address: u64_at(b, 16),
+            physical_address: u64_at(b, 24),
+            file_size: u64_at(b, 32),
+            memory_size: u64_at(b, 40),
+            align: u64_at(b, 48),
+        }
+    }
+}
+
+/// A validated ELF64 x86-64 executable in memory
+pub struct ElfFile<'a> {
+    data: &'a [u8],
+    r#type: u16,
+    entry: u64,
+    program_headers: Vec<ProgramHeader>,
+}
+impl<'a> ElfFile<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        if data.len() < HEADER_SIZE || &data[..4] != MAGIC {
+            return Err(ElfError::NotElf);
+        }
+        if data[4] != CLASS_64 || data[5] != DATA_LITTLE_ENDIAN {
+            return Err(ElfError::Unsupported("not a little endian ELF64 file"));
+        }
+        if u16_at(data, 18) != MACHINE_X86_64 {
+            return Err(ElfError::Unsupported("not for x86-64"));
+        }
+        let r#type = u16_at(data, 16);
+        if r#type != TYPE_EXEC && r#type != TYPE_DYN {
+            return Err(ElfError::Unsupported("not an executable"));
+        }
+
+        let offset = u64_at(data, 32) as usize;
+        let entry_size = u16_at(data, 54) as usize;
+        let count = u16_at(data, 56) as usize;
+        if entry_size < PROGRAM_HEADER_SIZE {
+            return Err(ElfError::Malformed("program header entry too small"));
+        }
+        let table = offset
+            .checked_add(entry_size * count)
+            .and_then(|end| data.get(offset..end))
+            .ok_or(ElfError::Malformed("program headers out of the file"))?;
+        let program_headers = table
+            .chunks(entry_size)
+            .map(ProgramHeader::parse)
+            .collect::<Vec<_>>();
+
+        // Note: 動的リンカを要求するものはロードできない（static-pieはPT_INTERPを持たない）
+        if program_headers.iter().any(|h| h.r#type == PT_INTERP) {
+            return Err(ElfError::Unsupported("dynamically linked (has PT_INTERP)"));
+        }
+        for h in program_headers.iter().filter(|h| h.r#type == PT_LOAD) {
+            let in_file = h
+                .offset
+                .checked_add(h.file_size)
+                .is_some_and(|end| end <= data.len() as u64);
+            let in_memory = h
+                .virtual_address
+                .checked_add(h.memory_size)
+                .is_some_and(|end| {
+                    is_canonical(h.virtual_address) && is_canonical(end.saturating_sub(1))
+                });
+            if !in_file || !in_memory || h.file_size > h.memory_size {
+                return Err(ElfError::Malformed("invalid PT_LOAD segment"));
+            }
+        }
+        if overlapping(
+            program_headers
+                .iter()
+                .filter(|h| h.r#type == PT_LOAD)
+                .map(|h| (h.virtual_address, h.memory_size)),
+        ) {
+            return Err(ElfError::Malformed("overlapping PT_LOAD segments"));
+        }
+
+        Ok(Self {
+            data,
+            r#type,
+            entry: u64_at(data, 24),
+            program_headers,
+        })
+    }
+
+    /// ET_DYN (static-pie): loaded at `PIE_VIRTUAL_BASE` and relocated
+    #[inline]
+    pub const fn is_position_independent(&self) -> bool {
+        self.r#type == TYPE_DYN
+    }
+
+    fn loadable(&self) -> impl Iterator<Item = &ProgramHeader> {
+        self.program_headers
+            .iter()
+            .filter(|h| h.r#type == PT_LOAD && h.memory_size > 0)
+    }
+
+    /// Copies the PT_LOAD segments into memory from `allocator` (one contiguous block from the
+    /// lowest to the highest page, the gaps included) and applies the dynamic relocations.
+    pub fn load(&self, allocator: &mut dyn FrameAllocator) -> Result<LoadedImage, ElfError> {
+        let start = self
+            .loadable()
+            .map(|h| h.virtual_address)
+            .min()
+            .ok_or(ElfError::Malformed("no PT_LOAD segment"))?;
+        let end = self
+            .loadable()
+            .map(|h| h.virtual_address + h.memory_size)
+            .max()
+            .unwrap();
+        let align = self
+            .loadable()
+            .map(|h| h.align)
+            .filter(|a| a.is_power_of_two())
+            .fold(PAGE_SIZE, u64::max);
+        let start = align_down(start, PAGE_SIZE);
+        let size = (end - start).next_multiple_of(PAGE_SIZE);
+        if size > MAX_IMAGE_SIZE {
+            return Err(ElfError::Malformed("memory image too large"));
+        }
+
+        // Note: PIEは最大アラインメントを保ったまま固定の仮想アドレスへずらす
+        let bias = if self.is_position_independent() {
+            PIE_VIRTUAL_BASE.wrapping_sub(align_down(start, align))
+        } else {
+            0
+        };
+
+        let physical_base = allocator.allocate_frames((size / PAGE_SIZE) as usize)?;
+        let mut image = LoadedImage {
+            entry: self.entry.wrapping_add(bias),
+            physical_base,
+            virtual_base: start.wrapping_add(bias),
+            size,
+            bias,
+            segments: Vec::new(),
+            read_only_after_relocation: None,
+        };
+        for h in self.loadable() {
+            let offset = (h.virtual_address - start) as usize;
+            image.memory()[offset..][..h.file_size as usize]
+                .copy_from_slice(&self.data[h.offset as usize..][..h.file_size as usize]);
+            image.segments.push(LoadedSegment {
+                virtual_address: h.virtual_address.wrapping_add(bias),
+                memory_size: h.memory_size,
+                flags: h.flags,
+            });
+        }
+        if !image.contains(image.entry, 1) {
+            return Err(ElfError::Malformed(
+                "entry point outside the loaded segments",
+            ));
+        }
+
+        if let Some(dynamic) = self.program_headers.iter().find(|h| h.r#type == PT_DYNAMIC) {
+            image.relocate(
+                dynamic.virtual_address.wrapping_add(bias),
+                dynamic.memory_size,
+            )?;
+        }
+        image.read_only_after_relocation = self
+            .program_headers
+            .iter()
+            .find(|h| h.r#type == PT_GNU_RELRO)
+            .map(|h| (h.virtual_address.wrapping_add(bias), h.memory_size));
+
+        Ok(image)
+    }
+}
+
+/// Entry point and PT_LOAD segments of an ELF32 (i386) or ELF64 (x86-64) executable, for boot
+/// protocols that place the segments at their physical addresses as they are (Multiboot2)
+pub fn physical_segments(data: &[u8]) -> Result<(u64, Vec<ProgramHeader>), ElfError> {
+    if data.len() < HEADER_SIZE_32 || &data[..4] != MAGIC {
+        return Err(ElfError::NotElf);
+    }
+    if data[5] != DATA_LITTLE_ENDIAN {
+        return Err(ElfError::Unsupported("not a little endian ELF file"));
+    }
+    let (entry, offset, entry_size, count, parse): (_, _, _, _, fn(&[u8]) -> ProgramHeader) =
+        match (data[4], u16_at(data, 18)) {
+            (CLASS_32, MACHINE_386) => (
+                u32_at(data, 24) as u64,
+                u32_at(data, 28) as usize,
+                u16_at(data, 42) as usize,
+                u16_at(data, 44) as usize,
+                ProgramHeader::parse_32,
+            ),
+            (CLASS_64, MACHINE_X86_64) if data.len() >= HEADER_SIZE => (
+                u64_at(data, 24),
+                u64_at(data, 32) as usize,
+                u16_at(data, 54) as usize,
+                u16_at(data, 56) as usize,
+                ProgramHeader::parse,
+            ),
+            _ => return Err(ElfError::Unsupported("not an i386 or x86-64 ELF file")),
+        };
+    let minimum_entry_size = if data[4] == CLASS_32 {
+        PROGRAM_HEADER_SIZE_32
+    } else {
+        PROGRAM_HEADER_SIZE
+    };
+    if entry_size < minimum_entry_size {
+        return Err(ElfError::Malformed("program header entry too small"));
+    }
+
+    let table = offset
+        .checked_add(entry_size * count)
+        .and_then(|end| data.get(offset..end))
+        .ok_or(ElfError::Malformed("program headers out of the file"))?;
+    let segments = table
+        .chunks(entry_size)
+        .map(parse)
+        .filter(|h| h.r#type == PT_LOAD && h.memory_size > 0)
+        .collect::<Vec<_>>();
+    for h in &segments {
+        let in_file = h
+            .offset
+            .checked_add(h.file_size)
+            .is_some_and(|end| end <= data.len() as u64);
+        if !in_file || h.file_size > h.memory_size {
+            return Err(ElfError::Malformed("invalid PT_LOAD segment"));
+        }
+    }
+    if overlapping(segments.iter().map(|h| (h.physical_address, h.memory_size))) {
+        return Err(ElfError::Malformed("overlapping PT_LOAD segments"));
+    }
+
+    Ok((entry, segments))
+}
+
+/// whether any two of the (address, size) ranges share a byte (empty ones are ignored)
+fn overlapping(ranges: impl Iterator<Item = (u64, u64)>) -> bool {
+    // Note: 後からコピーしたセグメントが前の内容を潰してしまうので、重なりはページ内でも許さない
+    let mut ranges: Vec<_> = ranges
+        .filter(|&(_, size)| size > 0)
+        .map(|(address, size)| (address, address.saturating_add(size)))
+        .collect();
+    ranges.sort_unstable();
+
+    ranges.windows(2).any(|w| w[0].1 > w[1].0)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LoadedSegment {
+    pub virtual_address: u64,
+    pub memory_size: u64,
+    pub flags: SegmentFlags,
+}
+
+/// The memory image of an executable: virtual [virtual_base, virtual_base + size) is backed by
+/// physical [physical_base, physical_base + size)
+#[derive(Debug)]
+pub struct LoadedImage {
+    /// virtual address of the entry point
+    pub entry: u64,
+    pub physical_base: u64,
+    pub virtual_base: u64,
+    /// multiple of the page size
+    pub size: u64,
+    /// difference between the load addresses and the addresses in the file
+    pub bias: u64,
+    pub segments: Vec<LoadedSegment>,
+    /// PT_GNU_RELRO: (virtual address, size)
+    read_only_after_relocation: Option<(u64, u64)>,
+}
+impl LoadedImage {
+    /// the loaded image through its physical (identity mapped) addresses
+    fn memory(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.physical_base as *mut u8, self.size as _) }
+    }
+
+    #[inline]
+    fn contains(&self, virtual_address: u64, length: u64) -> bool {
+        let offset = virtual_address.wrapping_sub(self.virtual_base);
+        offset
+            .checked_add(length)
+            .is_some_and(|end| end <= self.size)
+    }
+
+    fn read_u64(&mut self, virtual_address: u64) -> Result<u64, ElfError> {
+        if !self.contains(virtual_address, 8) {
+            return Err(ElfError::Malformed("dynamic data outside the image"));
+        }
+        let offset = (virtual_address - self.virtual_base) as usize;
+
+        Ok(u64_at(self.memory(), offset))
+    }
+
+    fn write_u64(&mut self, virtual_address: u64, value: u64) -> Result<(), ElfError> {
+        if !self.contains(virtual_address, 8) {
+            return Err(ElfError::Malformed("relocation outside the image"));
+        }
+        let offset = (virtual_address - self.virtual_base) as usize;
+        self.memory()[offset..][..8].copy_from_slice(&value.to_le_bytes());
+
+        Ok(())
+    }
+
+    /// Applies DT_RELA and DT_RELR relocations found in the dynamic section at `dynamic`
+    fn relocate(&mut self, dynamic: u64, size: u64) -> Result<(), ElfError> {
+        let (mut rela, mut rela_size, mut rela_entry) = (None, 0, RELA_SIZE);
+        let (mut relr, mut relr_size) = (None, 0);
+        let (mut symbols, mut symbol_entry) = (None, SYMBOL_SIZE);
+        for n in 0..size / 16 {
+            let tag = self.read_u64(dynamic + n * 16)?;
+            let value = self.read_u64(dynamic + n * 16 + 8)?;
+            match tag {
+                DT_NULL => break,
+                DT_RELA => rela = Some(value.wrapping_add(self.bias)),
+                DT_RELASZ => rela_size = value,
+                DT_RELAENT => rela_entry = value,
+                DT_RELR => relr = Some(value.wrapping_add(self.bias)),
+                DT_RELRSZ => relr_size = value,
+                DT_RELRENT if value != 8 => return Err(ElfError::Malformed("DT_RELRENT")),
+                DT_SYMTAB => symbols = Some(value.wrapping_add(self.bias)),
+                DT_SYMENT => symbol_entry = value,
+                DT_REL => return Err(ElfError::Unsupported("DT_REL relocations")),
+                _ => (),
+            }
+        }
+
+        if let Some(table) = rela {
+            if rela_entry < RELA_SIZE {
+                return Err(ElfError::Malformed("DT_RELAENT"));
+            }
+            for n in 0..rela_size / rela_entry {
+                let r = table + n * rela_entry;
+                let offset = self.read_u64(r)?.wrapping_add(self.bias);
+                let info = self.read_u64(r + 8)?;
+                let addend = self.read_u64(r + 16)?;
+                let (symbol, r#type) = ((info >> 32) as u32, info as u32);
+
+                let value = match r#type {
+                    R_X86_64_NONE => continue,
+                    R_X86_64_RELATIVE => self.bias.wrapping_add(addend),
+                    R_X86_64_64 => self
+                        .symbol_value(symbols, symbol_entry, symbol)?
+                        .wrapping_add(addend),
+                    R_X86_64_GLOB_DAT | R_X86_64_JUMP_SLOT => {
+                        self.symbol_value(symbols, symbol_entry, symbol)?
+                    }
+                    t => return Err(ElfError::UnsupportedRelocation(t)),
+                };
+                self.write_u64(offset, value)?;
+            }
+        }
+
+        // Note: RELRは偶数のエントリがアドレス、奇数のエントリが続く63ワード分のビットマップ
+        if let Some(table) = relr {
+            let mut next = 0;
+            for n in 0..relr_size / 8 {
+                let entry = self.read_u64(table + n * 8)?;
+                if (entry & 1) == 0 {
+                    let address = entry.wrapping_add(self.bias);
+                    let value = self.read_u64(address)?.wrapping_add(self.bias);
+                    self.write_u64(address, value)?;
+                    next = address + 8;
+                } else {
+                    for bit in 1..64 {
+                        if (entry >> bit) & 1 != 0 {
+                            let address = next + (bit - 1) * 8;
+                            let value = self.read_u64(address)?.wrapping_add(self.bias);
+                            self.write_u64(address, value)?;
+                        }
+                    }
+                    next += 63 * 8;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Relocated value of the symbol; undefined weak symbols resolve to 0
+    fn symbol_value(
+        &mut self,
+        table: Option<u64>,
+        entry_size: u64,
+        index: u32,
+    ) -> Result<u64, ElfError> {
+        let table = table.ok_or(ElfError::Malformed("symbol relocation without DT_SYMTAB"))?;
+        let symbol = table + index as u64 * entry_size;
+        let head = self.read_u64(symbol)?;
+        let value = self.read_u64(symbol + 8)?;
+        let (binding, section) = ((head >> 36) as u8 & 0x0f, (head >> 48) as u16);
+
+        match section {
+            SHN_UNDEF if binding == STB_WEAK => Ok(0),
+            SHN_UNDEF => Err(ElfError::UndefinedSymbol(index)),
+            SHN_ABS => Ok(value),
+            _ => Ok(value.wrapping_add(self.bias)),
+        }
+    }
+
+    /// Maps every page holding a segment with the segment permissions (a page shared by
+    /// segments gets the union of them). PT_GNU_RELRO pages become read-only.
+    pub fn map(
+        &self,
+        space: &mut AddressSpace,
+        allocator: &mut dyn FrameAllocator,
+    ) -> Result<(), OutOfFrames> {
+        let mut pages = alloc::vec![None::<PageFlags>; (self.size / PAGE_SIZE) as usize];
+        for s in &self.segments {
+            let first = (align_down(s.virtual_address, PAGE_SIZE) - self.virtual_base) / PAGE_SIZE;
+            let last = (s.virtual_address + s.memory_size - 1 - self.virtual_base) / PAGE_SIZE;
+            for p in &mut pages[first as usize..=last as usize] {
+                let f = p.get_or_insert(PageFlags {
+                    writable: false,
+                    executable: false,
+                });
+                f.writable |= s.flags.contains(SegmentFlags::WRITE);
+                f.executable |= s.flags.contains(SegmentFlags::EXECUTE);
+            }
+        }
+        // Note: RELROは部分的にしか覆わないページを読み取り専用にすると隣のデータが書けなくなる
+        if let Some((address, size)) = self
+            .read_only_after_relocation
+            .filter(|&(a, s)| self.contains(a, s))
+        {
+            let first = (address - self.virtual_base).div_ceil(PAGE_SIZE);
+            let end = (address + size - self.virtual_base) / PAGE_SIZE;
+            for f in pages
+                .iter_mut()
+                .take(end as usize)
+                .skip(first as usize)
+                .flatten()
+            {
+                f.writable = false;
+            }
+        }
+
+        for (n, flags) in pages.into_iter().enumerate() {
+            if let Some(flags) = flags {
+                let offset = n as u64 * PAGE_SIZE;
+                space.map(
+                    self.virtual_base + offset,
+                    self.physical_base + offset,
+                    flags,
+                    allocator,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::alloc::{alloc_zeroed, dealloc, Layout};
+    use alloc::{vec, vec::Vec};
+
+    const TEXT: u64 = 0x40_0000;
+    const DATA: u64 = 0x40_1000;
+
+    /// Frames from the host heap (the loader touches them through their addresses)
+    #[derive(Default)]
+    struct HeapFrames(Vec<(*mut u8, Layout)>);
+    impl FrameAllocator for HeapFrames {
+        fn allocate_frames(&mut self, count: usize) -> Result<u64, OutOfFrames> {
+            let layout = Layout::from_size_align(count * PAGE_SIZE as usize, PAGE_SIZE as usize)
+                .map_err(|_| OutOfFrames)?;
+            let ptr = unsafe { alloc_zeroed(layout) };
+            if ptr.is_null() {
+                return Err(OutOfFrames);
+            }
+            self.0.push((ptr, layout));
+            Ok(ptr as u64)
+        }
+    }
+    impl Drop for HeapFrames {
+        fn drop(&mut self) {
+            for &(ptr, layout) in &self.0 {
+                unsafe { dealloc(ptr, layout) };
+            }
+        }
+    }
+
+    const fn segment(
+        r#type: u32,
+        flags: SegmentFlags,
+        offset: u64,
+        address: u64,
+        file_size: u64,
+        memory_size: u64,
+    ) -> ProgramHeader {
+        ProgramHeader {
+            r#type,
+            flags,
+            offset,
+            virtual_address: address,
+            physical_address: address,
+            file_size,
+            memory_size,
+            align: PAGE_SIZE,
+        }
+    }
+
+    /// An ELF64 x86-64 file of `size` bytes with the program headers right after the file header
+    fn elf(r#type: u16, entry: u64, headers: &[ProgramHeader], size: usize) -> Vec<u8> {
+        let mut b = vec![0; size];
+        b[..4].copy_from_slice(MAGIC);
+        b[4] = CLASS_64;
+        b[5] = DATA_LITTLE_ENDIAN;
+        b[6] = 1;
+        b[16..18].copy_from_slice(&r#type.to_le_bytes());
+        b[18..20].copy_from_slice(&MACHINE_X86_64.to_le_bytes());
+        b[20..24].copy_from_slice(&1u32.to_le_bytes());
+        b[24..32].copy_from_slice(&entry.to_le_bytes());
+        b[32..40].copy_from_slice(&(HEADER_SIZE as u64).to_le_bytes());
+        b[52..54].copy_from_slice(&(HEADER_SIZE as u16).to_le_bytes());
+        b[54..56].copy_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+        b[56..58].copy_from_slice(&(headers.len() as u16).to_le_bytes());
+        for (n, h) in headers.iter().enumerate() {
+            let e = &mut b[HEADER_SIZE + n * PROGRAM_HEADER_SIZE..][..PROGRAM_HEADER_SIZE];
+            e[0..4].copy_from_slice(&h.r#type.to_le_bytes());
+            e[4..8].copy_from_slice(&h.flags.0.to_le_bytes());
+            for (o, v) in [
+                (8, h.offset),
+                (16, h.virtual_address),
+                (24, h.physical_address),
+                (32, h.file_size),
+                (40, h.memory_size),
+                (48, h.align),
+            ] {
+                e[o..o + 8].copy_from_slice(&v.to_le_bytes());
+            }
+        }
+        b
+    }
+
+    /// text (the headers included) and data with 0xf0 bytes of .bss
+    fn executable(data: &[ProgramHeader]) -> Vec<u8> {
+        let mut headers = vec![segment(
+            PT_LOAD,
+            SegmentFlags::READ | SegmentFlags::EXECUTE,
+            0,
+            TEXT,
+            0x200,
+            0x200,
+        )];
+        headers.extend_from_slice(data);
+        let mut b = elf(TYPE_EXEC, TEXT + 0x100, &headers, 0x1010);
+        b[0x1000..].fill(0xaa);
+        b
+    }
+
+    const DATA_SEGMENT: ProgramHeader = segment(
+        PT_LOAD,
+        SegmentFlags(SegmentFlags::READ.0 | SegmentFlags::WRITE.0),
+        0x1000,
+        DATA,
+        0x10,
+        0x100,
+    );
+
+    #[test]
+    fn load_static_executable() {
+        let file = executable(&[DATA_SEGMENT]);
+        let elf = ElfFile::parse(&file).unwrap();
+        assert!(!elf.is_position_independent());
+
+        let mut frames = HeapFrames::default();
+        let mut image = elf.load(&mut frames).unwrap();
+        assert_eq!(
+            (image.entry, image.virtual_base, image.size, image.bias),
+            (TEXT + 0x100, TEXT, 2 * PAGE_SIZE, 0)
+        );
+        assert_eq!(image.segments.len(), 2);
+        let memory = image.memory();
+        assert_eq!(&memory[..4], MAGIC);
+        assert!(memory[0x1000..0x1010].iter().all(|&b| b == 0xaa));
+        assert!(memory[0x1010..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn load_position_independent_executable() {
+        // DT_RELR table at 0x1000 pointing at the word at 0x1008, dynamic section at 0x1010
+        let data = segment(
+            PT_LOAD,
+            SegmentFlags::READ | SegmentFlags::WRITE,
+            0x1000,
+            0x1000,
+            0x50,
+            0x50,
+        );
+        let dynamic = segment(PT_DYNAMIC, SegmentFlags::READ, 0x1010, 0x1010, 0x40, 0x40);
+        let text = segment(PT_LOAD, SegmentFlags::EXECUTE, 0, 0, 0x200, 0x200);
+        let mut file = elf(TYPE_DYN, 0x100, &[text, data, dynamic], 0x1050);
+        for (n, v) in [
+            0x1008, 0x100, DT_RELR, 0x1000, DT_RELRSZ, 8, DT_RELRENT, 8, DT_NULL, 0,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            file[0x1000 + n * 8..][..8].copy_from_slice(&u64::to_le_bytes(v));
+        }
+
+        let elf = ElfFile::parse(&file).unwrap();
+        assert!(elf.is_position_independent());
+        let mut frames = HeapFrames::default();
+        let mut image = elf.load(&mut frames).unwrap();
+        assert_eq!(
+            (image.bias, image.virtual_base, image.entry),
+            (PIE_VIRTUAL_BASE, PIE_VIRTUAL_BASE, PIE_VIRTUAL_BASE + 0x100)
+        );
+        assert_eq!(u64_at(image.memory(), 0x1008), PIE_VIRTUAL_BASE + 0x100);
+    }
+
+    #[test]
+    fn truncated_header() {
+        let file = executable(&[DATA_SEGMENT]);
+        assert!(matches!(ElfFile::parse(&[]), Err(ElfError::NotElf)));
+        assert!(matches!(
+            ElfFile::parse(&file[..HEADER_SIZE - 1]),
+            Err(ElfError::NotElf)
+        ));
+        // the program headers are cut off
+        assert!(matches!(
+            ElfFile::parse(&file[..HEADER_SIZE + PROGRAM_HEADER_SIZE]),
+            Err(ElfError::Malformed("program headers out of the file"))
+        ));
+    }
+
+    #[test]
+    fn bad_identification() {
+        let mut file = executable(&[]);
+        file[1] = b'X';
+        assert!(matches!(ElfFile::parse(&file), Err(ElfError::NotElf)));
+
+        let mut file = executable(&[]);
+        file[4] = CLASS_32;
+        assert!(matches!(
+            ElfFile::parse(&file),
+            Err(ElfError::Unsupported(_))
+        ));
+
+        // big endian
+        let mut file = executable(&[]);
+        file[5] = 2;
+        assert!(matches!(
+            ElfFile::parse(&file),
+            Err(ElfError::Unsupported(_))
+        ));
+
+        let mut file = executable(&[]);
+        file[18..20].copy_from_slice(&MACHINE_386.to_le_bytes());
+        assert!(matches!(
+            ElfFile::parse(&file),
+            Err(ElfError::Unsupported("not for x86-64"))
+        ));
+
+        // ET_REL
+        let mut file = executable(&[]);
+        file[16] = 1;
+        assert!(matches!(
+            ElfFile::parse(&file),
+            Err(ElfError::Unsupported("not an executable"))
+        ));
+    }
+
+    #[test]
+    fn program_headers_out_of_bounds() {
+        for (offset, count) in [
+            (0x1000, 1),
+            (u64::MAX, 1),
+            (u64::MAX - 8, 1),
+            (HEADER_SIZE as u64, 0xffff),
+        ] {
+            let mut file = executable(&[DATA_SEGMENT]);
+            file[32..40].copy_from_slice(&offset.to_le_bytes());
+            file[56..58].copy_from_slice(&u16::to_le_bytes(count));
+            assert!(
+                matches!(
+                    ElfFile::parse(&file),
+                    Err(ElfError::Malformed("program headers out of the file"))
+                ),
+                "{offset:#x} {count}"
+            );
+        }
+
+        let mut file = executable(&[DATA_SEGMENT]);
+        file[54..56].copy_from_slice(&32u16.to_le_bytes());
+        assert!(matches!(
+            ElfFile::parse(&file),
+            Err(ElfError::Malformed("program header entry too small"))
+        ));
+    }
+
+    #[test]
+    fn invalid_segments() {
+        for data in [
+            // past the end of the file
+            ProgramHeader {
+                file_size: 0x11,
+                ..DATA_SEGMENT
+            },
+            ProgramHeader {
+                offset: u64::MAX,
+                ..DATA_SEGMENT
+            },
+            // more in the file than in memory
+            ProgramHeader {
+                memory_size: 0x8,
+                ..DATA_SEGMENT
+            },
+            // non-canonical
+            ProgramHeader {
+                virtual_address: 0x0000_8000_0000_0000,
+                ..DATA_SEGMENT
+            },
+            ProgramHeader {
+                virtual_address: 0x0000_7fff_ffff_ff80,
+                ..DATA_SEGMENT
+            },
+        ] {
+            assert!(
+                matches!(
+                    ElfFile::parse(&executable(&[data])),
+                    Err(ElfError::Malformed("invalid PT_LOAD segment"))
+                ),
+                "{data:x?}"
+            );
+        }
+
+        let interpreter = segment(PT_INTERP, SegmentFlags::READ, 0x200, TEXT + 0x200, 8, 8);
+        assert!(matches!(
+            ElfFile::parse(&executable(&[DATA_SEGMENT, interpreter])),
+            Err(ElfError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn overlapping_segments() {
+        // the end of text
+        let data = ProgramHeader {
+            virtual_address: TEXT + 0x1f0,
+            ..DATA_SEGMENT
+        };
+        assert!(matches!(
+            ElfFile::parse(&executable(&[data])),
+            Err(ElfError::Malformed("overlapping PT_LOAD segments"))
+        ));
+        // running into text from below, listed after it
+        let data = ProgramHeader {
+            virtual_address: TEXT - 0x10,
+            ..DATA_SEGMENT
+        };
+        assert!(matches!(
+            ElfFile::parse(&executable(&[data])),
+            Err(ElfError::Malformed("overlapping PT_LOAD segments"))
+        ));
+
+        // sharing a page is fine
+        let data = ProgramHeader {
+            virtual_address: TEXT + 0x200,
+            ..DATA_SEGMENT
+        };
+        assert!(ElfFile::parse(&executable(&[data])).is_ok());
+        assert!(matches!(
+            physical_segments(&executable(&[ProgramHeader {
+                physical_address: TEXT + 0x100,
+                ..DATA_SEGMENT
+            }])),
+            Err(ElfError::Malformed("overlapping PT_LOAD segments"))
+        ));
+    }
+}
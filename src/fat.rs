@@ -0,0 +1,1558 @@
+//! FAT12/16/32 filesystem (Microsoft FAT32 File System Specification 1.03)
+
+use crate::block::{BlockDevice, BlockError};
+use alloc::{format, string::String, vec, vec::Vec};
+use core::ops::Range;
+
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+const FSINFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const FSINFO_FREE_COUNT: usize = 488;
+const FSINFO_NEXT_FREE: usize = 492;
+
+const DIR_ENTRY_SIZE: usize = 32;
+/// first name byte of a deleted entry
+const DELETED: u8 = 0xe5;
+/// first name byte standing for a real 0xE5
+const KANJI_E5: u8 = 0x05;
+/// DIR_NTRes flags telling that the (upper case) short name should be shown in lower case
+const NT_LOWERCASE_BASE: u8 = 0x08;
+const NT_LOWERCASE_EXT: u8 = 0x10;
+
+const LFN_LAST: u8 = 0x40;
+const LFN_ORDINAL_MASK: u8 = 0x3f;
+const LFN_CHARS_PER_ENTRY: usize = 13;
+/// byte offsets of the 13 UTF-16 characters in a long name entry
+const LFN_CHAR_OFFSETS: [usize; LFN_CHARS_PER_ENTRY] =
+    [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+const LFN_MAX_LENGTH: usize = 255;
+
+/// 1980-01-01, used for every timestamp of created entries (there is no RTC driver yet)
+const DEFAULT_DATE: u16 = (1 << 5) | 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+pub enum FatError {
+    Block(BlockError),
+    /// no valid BPB in the first sector
+    NotFat,
+    /// broken cluster chain or directory
+    Corrupted,
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    DirectoryNotEmpty,
+    /// no free cluster (or the fixed FAT12/16 root directory is full)
+    NoSpace,
+    InvalidName,
+}
+impl From<BlockError> for FatError {
+    fn from(e: BlockError) -> Self {
+        Self::Block(e)
+    }
+}
+
+/// DIR_Attr
+#[repr(transparent)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Attributes(pub u8);
+impl Attributes {
+    pub const READ_ONLY: Self = Self(0x01);
+    pub const HIDDEN: Self = Self(0x02);
+    pub const SYSTEM: Self = Self(0x04);
+    pub const VOLUME_ID: Self = Self(0x08);
+    pub const DIRECTORY: Self = Self(0x10);
+    pub const ARCHIVE: Self = Self(0x20);
+    /// marks a long name entry
+    pub const LONG_NAME: Self =
+        Self(Self::READ_ONLY.0 | Self::HIDDEN.0 | Self::SYSTEM.0 | Self::VOLUME_ID.0);
+
+    #[inline]
+    pub const fn contains(self, other: Self) -> bool {
+        (self.0 & other.0) == other.0
+    }
+}
+impl core::ops::BitOr for Attributes {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+impl core::fmt::Debug for Attributes {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let names = [
+            (Self::READ_ONLY, "READ_ONLY"),
+            (Self::HIDDEN, "HIDDEN"),
+            (Self::SYSTEM, "SYSTEM"),
+            (Self::VOLUME_ID, "VOLUME_ID"),
+            (Self::DIRECTORY, "DIRECTORY"),
+            (Self::ARCHIVE, "ARCHIVE"),
+        ];
+
+        let mut wrote = false;
+        for (bit, name) in names {
+            if self.contains(bit) {
+                f.write_str(if wrote { " | " } else { "" })?;
+                f.write_str(name)?;
+                wrote = true;
+            }
+        }
+        if !wrote {
+            f.write_str("0")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A directory's storage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dir {
+    /// fixed root directory region of FAT12/16
+    Root,
+    Cluster(u32),
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    /// long name if present, otherwise the short name
+    pub name: String,
+    /// 8.3 name as stored (space padded, upper case)
+    pub short_name: [u8; 11],
+    pub attributes: Attributes,
+    pub first_cluster: u32,
+    pub size: u32,
+    /// directory holding the entry
+    parent: Dir,
+    /// slots of the long name entries and the short entry (the last one)
+    slots: Range<usize>,
+}
+impl DirEntry {
+    #[inline]
+    pub const fn is_dir(&self) -> bool {
+        self.attributes.contains(Attributes::DIRECTORY)
+    }
+
+    /// whether `name` refers to this entry (case-insensitive, by long or short name)
+    fn matches(&self, name: &str) -> bool {
+        names_equal(&self.name, name) || names_equal(&display_short_name(&self.short_name, 0), name)
+    }
+}
+
+/// A directory loaded into memory, with the sector each part came from
+struct DirBuffer {
+    dir: Dir,
+    data: Vec<u8>,
+    sectors: Vec<u64>,
+}
+impl DirBuffer {
+    #[inline]
+    fn slot_count(&self) -> usize {
+        self.data.len() / DIR_ENTRY_SIZE
+    }
+
+    #[inline]
+    fn slot(&self, n: usize) -> &[u8] {
+        &self.data[n * DIR_ENTRY_SIZE..][..DIR_ENTRY_SIZE]
+    }
+
+    #[inline]
+    fn slot_mut(&mut self, n: usize) -> &mut [u8] {
+        &mut self.data[n * DIR_ENTRY_SIZE..][..DIR_ENTRY_SIZE]
+    }
+
+    /// Decodes the entries, skipping free slots, the volume label and the dot entries
+    fn entries(&self) -> Vec<DirEntry> {
+        let mut entries = Vec::new();
+        let mut lfn = Vec::new();
+        let mut lfn_start = None;
+        let mut lfn_checksum = 0;
+
+        for n in 0..self.slot_count() {
+            let s = self.slot(n);
+            if s[0] == 0 {
+                break;
+            }
+            if s[0] == DELETED {
+                lfn_start = None;
+                continue;
+            }
+
+            let attributes = Attributes(s[11] & 0x3f);
+            if attributes == Attributes::LONG_NAME {
+                let ordinal = (s[0] & LFN_ORDINAL_MASK) as usize;
+                if (s[0] & LFN_LAST) != 0 {
+                    lfn = vec![0; ordinal * LFN_CHARS_PER_ENTRY];
+                    lfn_start = Some(n);
+                    lfn_checksum = s[13];
+                }
+                if lfn_start.is_none()
+                    || s[13] != lfn_checksum
+                    || ordinal == 0
+                    || ordinal * LFN_CHARS_PER_ENTRY > lfn.len()
+                {
+                    lfn_start = None;
+                    continue;
+                }
+
+                for (i, &o) in LFN_CHAR_OFFSETS.iter().enumerate() {
+                    lfn[(ordinal - 1) * LFN_CHARS_PER_ENTRY + i] =
+                        u16::from_le_bytes([s[o], s[o + 1]]);
+                }
+                continue;
+            }
+
+            let start = lfn_start.take();
+            if attributes.contains(Attributes::VOLUME_ID) || s[0] == b'.' {
+                continue;
+            }
+
+            let short_name: [u8; 11] = s[..11].try_into().unwrap();
+            let (name, slots) = match start {
+                Some(start) if short_name_checksum(&short_name) == lfn_checksum => {
+                    let length = lfn.iter().position(|&c| c == 0).unwrap_or(lfn.len());
+                    let name = char::decode_utf16(lfn[..length].iter().copied())
+                        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                        .collect();
+                    (name, start..n + 1)
+                }
+                _ => (display_short_name(&short_name, s[12]), n..n + 1),
+            };
+
+            entries.push(DirEntry {
+                name,
+                short_name,
+                attributes,
+                first_cluster: (u16::from_le_bytes([s[20], s[21]]) as u32) << 16
+                    | u16::from_le_bytes([s[26], s[27]]) as u32,
+                size: u32::from_le_bytes([s[28], s[29], s[30], s[31]]),
+                parent: self.dir,
+                slots,
+            });
+        }
+
+        entries
+    }
+
+    /// First slot of `count` consecutive free slots
+    fn find_free_slots(&self, count: usize) -> Option<usize> {
+        let mut run = 0;
+        for n in 0..self.slot_count() {
+            let first = self.slot(n)[0];
+            run = if first == 0 || first == DELETED {
+                run + 1
+            } else {
+                0
+            };
+            if run == count {
+                return Some(n + 1 - count);
+            }
+        }
+
+        None
+    }
+}
+
+/// The FAT sector being accessed (one sector of the first FAT, mirrored to the others on write-back)
+struct FatCache {
+    sector: u64,
+    data: Vec<u8>,
+    dirty: bool,
+}
+
+pub struct FileSystem<D: BlockDevice> {
+    device: D,
+    fat_type: FatType,
+    bytes_per_sector: usize,
+    sectors_per_cluster: usize,
+    /// device blocks per FAT sector
+    blocks_per_sector: u64,
+    fat_start: u64,
+    fat_count: u64,
+    fat_size: u64,
+    root_start: u64,
+    root_sectors: u64,
+    data_start: u64,
+    cluster_count: u32,
+    /// FAT32 only
+    root_cluster: u32,
+    fsinfo_sector: Option<u64>,
+    /// where to start searching for a free cluster
+    next_free: u32,
+    fat_cache: Option<FatCache>,
+    /// clusters were allocated or freed since the last flush
+    modified: bool,
+}
+impl<D: BlockDevice> FileSystem<D> {
+    /// Reads the BPB of the volume on `device`
+    pub fn mount(mut device: D) -> Result<Self, FatError> {
+        let boot = device.read_to_vec(0, 512usize.div_ceil(device.block_size()) as u64)?;
+        let u16_at = |o: usize| u16::from_le_bytes([boot[o], boot[o + 1]]) as u64;
+        let u32_at =
+            |o: usize| u32::from_le_bytes([boot[o], boot[o + 1], boot[o + 2], boot[o + 3]]) as u64;
+        if boot[510..512] != BOOT_SIGNATURE {
+            return Err(FatError::NotFat);
+        }
+
+        let bytes_per_sector = u16_at(11) as usize;
+        let sectors_per_cluster = boot[13] as usize;
+        let reserved_sectors = u16_at(14);
+        let fat_count = boot[16] as u64;
+        let root_entries = u16_at(17);
+        let total_sectors = match u16_at(19) {
+            0 => u32_at(32),
+            n => n,
+        };
+        let fat_size = match u16_at(22) {
+            0 => u32_at(36),
+            n => n,
+        };
+        if !(512..=4096).contains(&bytes_per_sector)
+            || !bytes_per_sector.is_power_of_two()
+            || !bytes_per_sector.is_multiple_of(device.block_size())
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved_sectors == 0
+            || fat_count == 0
+            || fat_size == 0
+        {
+            return Err(FatError::NotFat);
+        }
+
+        let root_sectors = (root_entries * DIR_ENTRY_SIZE as u64).div_ceil(bytes_per_sector as u64);
+        let root_start = reserved_sectors + fat_count * fat_size;
+        let data_start = root_start + root_sectors;
+        if total_sectors <= data_start {
+            return Err(FatError::NotFat);
+        }
+        let cluster_count = ((total_sectors - data_start) / sectors_per_cluster as u64) as u32;
+        // Note: FATの種類はBPBの文字列ではなくクラスタ数だけで決まる
+        let fat_type = match cluster_count {
+            0..4085 => FatType::Fat12,
+            4085..65525 => FatType::Fat16,
+            _ => FatType::Fat32,
+        };
+
+        let mut fs = Self {
+            blocks_per_sector: (bytes_per_sector / device.block_size()) as u64,
+            device,
+            fat_type,
+            bytes_per_sector,
+            sectors_per_cluster,
+            fat_start: reserved_sectors,
+            fat_count,
+            fat_size,
+            root_start,
+            root_sectors,
+            data_start,
+            cluster_count,
+            root_cluster: 0,
+            fsinfo_sector: None,
+            next_free: 2,
+            fat_cache: None,
+            modified: false,
+        };
+        if fat_type == FatType::Fat32 {
+            fs.root_cluster = u32_at(44) as u32;
+            let fsinfo = u16_at(48);
+            if (1..reserved_sectors).contains(&fsinfo) {
+                let mut sector = vec![0; bytes_per_sector];
+                fs.read_sectors(fsinfo, &mut sector)?;
+                let u32_at = |o: usize| u32::from_le_bytes(sector[o..o + 4].try_into().unwrap());
+                if u32_at(0) == FSINFO_LEAD_SIGNATURE && u32_at(484) == FSINFO_STRUCT_SIGNATURE {
+                    fs.fsinfo_sector = Some(fsinfo);
+                    let hint = u32_at(FSINFO_NEXT_FREE);
+                    if fs.is_data_cluster(hint) {
+                        fs.next_free = hint;
+                    }
+                }
+            }
+        }
+
+        Ok(fs)
+    }
+
+    #[inline]
+    pub const fn fat_type(&self) -> FatType {
+        self.fat_type
+    }
+
+    /// bytes per cluster
+    #[inline]
+    pub const fn cluster_size(&self) -> usize {
+        self.bytes_per_sector * self.sectors_per_cluster
+    }
+
+    #[inline]
+    pub const fn cluster_count(&self) -> u32 {
+        self.cluster_count
+    }
+
+    /// Entries of the directory at `path`
+    pub fn read_dir(&mut self, path: &str) -> Result<Vec<DirEntry>, FatError> {
+        let dir = self.resolve_dir(path)?;
+        Ok(self.load_dir(dir)?.entries())
+    }
+
+    /// Entry of the file or directory at `path` (the root directory has none)
+    pub fn metadata(&mut self, path: &str) -> Result<DirEntry, FatError> {
+        let (parent, name) = split_path(path)?;
+        let dir = self.resolve_dir(parent)?;
+        self.find(dir, name)?.ok_or(FatError::NotFound)
+    }
+
+    pub fn read_file(&mut self, path: &str) -> Result<Vec<u8>, FatError> {
+        let entry = self.metadata(path)?;
+        if entry.is_dir() {
+            return Err(FatError::IsADirectory);
+        }
+
+        let size = entry.size as usize;
+        let clusters = self.chain(entry.first_cluster)?;
+        if clusters.len() * self.cluster_size() < size {
+            return Err(FatError::Corrupted);
+        }
+
+        let mut data = vec![0; clusters.len() * self.cluster_size()];
+        for (&c, buf) in clusters.iter().zip(data.chunks_mut(self.cluster_size())) {
+            self.read_sectors(self.cluster_sector(c), buf)?;
+        }
+        data.truncate(size);
+
+        Ok(data)
+    }
+}
+// Note: 書き込み系はローダーからはまだ使っていない
+#[allow(dead_code)]
+impl<D: BlockDevice> FileSystem<D> {
+    /// Creates the file at `path`, or replaces the contents of an existing one
+    pub fn write_file(&mut self, path: &str, data: &[u8]) -> Result<(), FatError> {
+        let (parent, name) = split_path(path)?;
+        let size = u32::try_from(data.len()).map_err(|_| FatError::NoSpace)?;
+        let dir = self.resolve_dir(parent)?;
+        let existing = self.find(dir, name)?;
+        if existing.as_ref().is_some_and(|e| e.is_dir()) {
+            return Err(FatError::IsADirectory);
+        }
+
+        let clusters = self.allocate_chain(data.len().div_ceil(self.cluster_size()))?;
+        let first_cluster = clusters.first().copied().unwrap_or(0);
+        let r = self
+            .write_clusters(&clusters, data)
+            .and_then(|_| match &existing {
+                Some(e) => self.update_entry(e, first_cluster, size),
+                None => self.create_entry(dir, name, Attributes::ARCHIVE, first_cluster, size),
+            });
+        if let Err(e) = r {
+            if first_cluster != 0 {
+                self.free_chain(first_cluster)?;
+            }
+            self.sync_fat()?;
+            return Err(e);
+        }
+
+        // Note: 新しい内容を書き終えてから古いクラスタを解放する（途中で止まっても古い内容かFATの漏れで済む）
+        if let Some(e) = existing.filter(|e| e.first_cluster != 0) {
+            self.free_chain(e.first_cluster)?;
+        }
+        self.sync_fat()
+    }
+
+    pub fn create_dir(&mut self, path: &str) -> Result<(), FatError> {
+        let (parent, name) = split_path(path)?;
+        let dir = self.resolve_dir(parent)?;
+        if self.find(dir, name)?.is_some() {
+            return Err(FatError::AlreadyExists);
+        }
+
+        let cluster = self.allocate_chain(1)?[0];
+        // ".." of a directory directly under the root points to cluster 0, even on FAT32
+        let parent_cluster = match dir {
+            Dir::Cluster(c) if c != self.root_cluster => c,
+            _ => 0,
+        };
+        let mut data = vec![0; self.cluster_size()];
+        write_short_entry(
+            &mut data[..DIR_ENTRY_SIZE],
+            b".          ",
+            Attributes::DIRECTORY,
+            0,
+            cluster,
+            0,
+        );
+        write_short_entry(
+            &mut data[DIR_ENTRY_SIZE..][..DIR_ENTRY_SIZE],
+            b"..         ",
+            Attributes::DIRECTORY,
+            0,
+            parent_cluster,
+            0,
+        );
+
+        let r = self
+            .write_sectors(self.cluster_sector(cluster), &data)
+            .and_then(|_| self.create_entry(dir, name, Attributes::DIRECTORY, cluster, 0));
+        if r.is_err() {
+            self.free_chain(cluster)?;
+        }
+        self.sync_fat()?;
+
+        r
+    }
+
+    /// Deletes a file or an empty directory
+    pub fn remove(&mut self, path: &str) -> Result<(), FatError> {
+        let entry = self.metadata(path)?;
+        if entry.is_dir() && !self.read_dir(path)?.is_empty() {
+            return Err(FatError::DirectoryNotEmpty);
+        }
+
+        let mut buf = self.load_dir(entry.parent)?;
+        for n in entry.slots.clone() {
+            buf.slot_mut(n)[0] = DELETED;
+        }
+        self.store_dir(&buf, entry.slots.clone())?;
+        if entry.first_cluster != 0 {
+            self.free_chain(entry.first_cluster)?;
+        }
+
+        self.sync_fat()
+    }
+
+    /// Writes back the FAT and FSInfo and flushes the device
+    pub fn flush(&mut self) -> Result<(), FatError> {
+        self.sync_fat()?;
+
+        if let Some(sector) = self.fsinfo_sector.filter(|_| self.modified) {
+            // Note: 空きクラスタ数は数え直さず「不明」にしておく（仕様上許されている）
+            let mut data = vec![0; self.bytes_per_sector];
+            self.read_sectors(sector, &mut data)?;
+            data[FSINFO_FREE_COUNT..][..4].copy_from_slice(&u32::MAX.to_le_bytes());
+            data[FSINFO_NEXT_FREE..][..4].copy_from_slice(&self.next_free.to_le_bytes());
+            self.write_sectors(sector, &data)?;
+        }
+        self.modified = false;
+
+        Ok(self.device.flush()?)
+    }
+}
+impl<D: BlockDevice> FileSystem<D> {
+    fn read_sectors(&mut self, sector: u64, buf: &mut [u8]) -> Result<(), FatError> {
+        Ok(self
+            .device
+            .read_blocks(sector * self.blocks_per_sector, buf)?)
+    }
+
+    fn write_sectors(&mut self, sector: u64, buf: &[u8]) -> Result<(), FatError> {
+        Ok(self
+            .device
+            .write_blocks(sector * self.blocks_per_sector, buf)?)
+    }
+
+    #[inline]
+    fn cluster_sector(&self, cluster: u32) -> u64 {
+        self.data_start + (cluster - 2) as u64 * self.sectors_per_cluster as u64
+    }
+
+    #[inline]
+    fn is_data_cluster(&self, cluster: u32) -> bool {
+        (2..self.cluster_count + 2).contains(&cluster)
+    }
+
+    fn write_clusters(&mut self, clusters: &[u32], data: &[u8]) -> Result<(), FatError> {
+        for (&c, chunk) in clusters.iter().zip(data.chunks(self.cluster_size())) {
+            let sector = self.cluster_sector(c);
+            if chunk.len() == self.cluster_size() {
+                self.write_sectors(sector, chunk)?;
+            } else {
+                let mut buf = vec![0; self.cluster_size()];
+                buf[..chunk.len()].copy_from_slice(chunk);
+                self.write_sectors(sector, &buf)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// FAT access
+impl<D: BlockDevice> FileSystem<D> {
+    /// smallest entry value meaning end of chain (all of 0x?FF8..=0x?FFF are accepted)
+    const fn end_of_chain(&self) -> u32 {
+        match self.fat_type {
+            FatType::Fat12 => 0x0ff8,
+            FatType::Fat16 => 0xfff8,
+            FatType::Fat32 => 0x0fff_fff8,
+        }
+    }
+
+    fn fat_cache(&mut self, sector: u64) -> Result<&mut FatCache, FatError> {
+        if self.fat_cache.as_ref().is_none_or(|c| c.sector != sector) {
+            self.sync_fat()?;
+            let mut data = vec![0; self.bytes_per_sector];
+            self.read_sectors(self.fat_start + sector, &mut data)?;
+            self.fat_cache = Some(FatCache {
+                sector,
+                data,
+                dirty: false,
+            });
+        }
+
+        Ok(self.fat_cache.as_mut().unwrap())
+    }
+
+    /// Writes the cached FAT sector back to every FAT copy
+    fn sync_fat(&mut self) -> Result<(), FatError> {
+        let Some(cache) = self.fat_cache.take() else {
+            return Ok(());
+        };
+
+        let r = if cache.dirty {
+            (0..self.fat_count).try_for_each(|n| {
+                self.write_sectors(
+                    self.fat_start + n * self.fat_size + cache.sector,
+                    &cache.data,
+                )
+            })
+        } else {
+            Ok(())
+        };
+        self.fat_cache = Some(FatCache {
+            dirty: cache.dirty && r.is_err(),
+            ..cache
+        });
+
+        r
+    }
+
+    fn fat_byte(&mut self, offset: u64) -> Result<u8, FatError> {
+        let bytes_per_sector = self.bytes_per_sector as u64;
+        let cache = self.fat_cache(offset / bytes_per_sector)?;
+
+        Ok(cache.data[(offset % bytes_per_sector) as usize])
+    }
+
+    fn set_fat_byte(&mut self, offset: u64, value: u8) -> Result<(), FatError> {
+        let bytes_per_sector = self.bytes_per_sector as u64;
+        let cache = self.fat_cache(offset / bytes_per_sector)?;
+        cache.data[(offset % bytes_per_sector) as usize] = value;
+        cache.dirty = true;
+
+        Ok(())
+    }
+
+    fn fat_entry(&mut self, cluster: u32) -> Result<u32, FatError> {
+        // Note: FAT12の12ビットエントリはセクタ境界をまたぐことがあるので、1バイトずつ読む
+        let (offset, width) = match self.fat_type {
+            FatType::Fat12 => (cluster as u64 * 3 / 2, 2),
+            FatType::Fat16 => (cluster as u64 * 2, 2),
+            FatType::Fat32 => (cluster as u64 * 4, 4),
+        };
+        let mut value = 0;
+        for n in 0..width {
+            value |= (self.fat_byte(offset + n)? as u32) << (n * 8);
+        }
+
+        Ok(match self.fat_type {
+            FatType::Fat12 if (cluster & 1) != 0 => value >> 4,
+            FatType::Fat12 => value & 0x0fff,
+            FatType::Fat16 => value,
+            FatType::Fat32 => value & 0x0fff_ffff,
+        })
+    }
+
+    fn set_fat_entry(&mut self, cluster: u32, value: u32) -> Result<(), FatError> {
+        match self.fat_type {
+            FatType::Fat12 => {
+                let offset = cluster as u64 * 3 / 2;
+                let (low, high) = (self.fat_byte(offset)?, self.fat_byte(offset + 1)?);
+                let (low, high) = if (cluster & 1) != 0 {
+                    ((low & 0x0f) | (value << 4) as u8, (value >> 4) as u8)
+                } else {
+                    (value as u8, (high & 0xf0) | ((value >> 8) & 0x0f) as u8)
+                };
+                self.set_fat_byte(offset, low)?;
+                self.set_fat_byte(offset + 1, high)
+            }
+            FatType::Fat16 => {
+                let offset = cluster as u64 * 2;
+                for (n, b) in (value as u16).to_le_bytes().into_iter().enumerate() {
+                    self.set_fat_byte(offset + n as u64, b)?;
+                }
+                Ok(())
+            }
+            FatType::Fat32 => {
+                // the upper 4 bits are reserved and must be preserved
+                let offset = cluster as u64 * 4;
+                let reserved = self.fat_byte(offset + 3)? & 0xf0;
+                let mut bytes = (value & 0x0fff_ffff).to_le_bytes();
+                bytes[3] |= reserved;
+                for (n, b) in bytes.into_iter().enumerate() {
+                    self.set_fat_byte(offset + n as u64, b)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Clusters of the chain starting at `first` (empty for cluster 0)
+    fn chain(&mut self, first: u32) -> Result<Vec<u32>, FatError> {
+        let mut clusters = Vec::new();
+        let mut cluster = first;
+        while cluster != 0 {
+            if !self.is_data_cluster(cluster) || clusters.len() >= self.cluster_count as usize {
+                return Err(FatError::Corrupted);
+            }
+            clusters.push(cluster);
+
+            let next = self.fat_entry(cluster)?;
+            if next >= self.end_of_chain() {
+                break;
+            }
+            cluster = next;
+        }
+
+        Ok(clusters)
+    }
+
+    /// Allocates a free cluster, marks it as the end of a chain and links it after `previous`
+    fn allocate_cluster(&mut self, previous: Option<u32>) -> Result<u32, FatError> {
+        for n in 0..self.cluster_count {
+            let cluster = 2 + (self.next_free - 2 + n) % self.cluster_count;
+            if self.fat_entry(cluster)? != 0 {
+                continue;
+            }
+
+            // mark with the canonical 0xFFF / 0xFFFF / 0x0FFFFFFF
+            self.set_fat_entry(cluster, self.end_of_chain() | 7)?;
+            if let Some(p) = previous {
+                self.set_fat_entry(p, cluster)?;
+            }
+            self.next_free = if self.is_data_cluster(cluster + 1) {
+                cluster + 1
+            } else {
+                2
+            };
+            self.modified = true;
+
+            return Ok(cluster);
+        }
+
+        Err(FatError::NoSpace)
+    }
+
+    /// Allocates a chain of `count` clusters (nothing is left allocated on failure)
+    fn allocate_chain(&mut self, count: usize) -> Result<Vec<u32>, FatError> {
+        let mut clusters = Vec::with_capacity(count);
+        for _ in 0..count {
+            match self.allocate_cluster(clusters.last().copied()) {
+                Ok(c) => clusters.push(c),
+                Err(e) => {
+                    if let Some(&first) = clusters.first() {
+                        self.free_chain(first)?;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(clusters)
+    }
+
+    fn free_chain(&mut self, first: u32) -> Result<(), FatError> {
+        for c in self.chain(first)? {
+            self.set_fat_entry(c, 0)?;
+        }
+        self.next_free = self.next_free.min(first);
+        self.modified = true;
+
+        Ok(())
+    }
+}
+
+/// Directories
+impl<D: BlockDevice> FileSystem<D> {
+    #[inline]
+    fn root_dir(&self) -> Dir {
+        match self.fat_type {
+            FatType::Fat32 => Dir::Cluster(self.root_cluster),
+            _ => Dir::Root,
+        }
+    }
+
+    fn load_dir(&mut self, dir: Dir) -> Result<DirBuffer, FatError> {
+        let sectors: Vec<u64> = match dir {
+            Dir::Root => (self.root_start..self.root_start + self.root_sectors).collect(),
+            Dir::Cluster(first) => {
+                let spc = self.sectors_per_cluster as u64;
+                self.chain(first)?
+                    .into_iter()
+                    .flat_map(|c| {
+                        let s = self.cluster_sector(c);
+                        s..s + spc
+                    })
+                    .collect()
+            }
+        };
+
+        let mut data = vec![0; sectors.len() * self.bytes_per_sector];
+        for (&s, buf) in sectors.iter().zip(data.chunks_mut(self.bytes_per_sector)) {
+            self.read_sectors(s, buf)?;
+        }
+
+        Ok(DirBuffer { dir, data, sectors })
+    }
+
+    /// Writes back the sectors of `buf` covering `slots`
+    fn store_dir(&mut self, buf: &DirBuffer, slots: Range<usize>) -> Result<(), FatError> {
+        let slots_per_sector = self.bytes_per_sector / DIR_ENTRY_SIZE;
+        for n in slots.start / slots_per_sector..=(slots.end - 1) / slots_per_sector {
+            self.write_sectors(
+                buf.sectors[n],
+                &buf.data[n * self.bytes_per_sector..][..self.bytes_per_sector],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Appends a zeroed cluster to the directory
+    fn extend_dir(&mut self, buf: &mut DirBuffer) -> Result<(), FatError> {
+        let Dir::Cluster(first) = buf.dir else {
+            return Err(FatError::NoSpace);
+        };
+
+        let last = self.chain(first)?.last().copied();
+        let cluster = self.allocate_cluster(last)?;
+        let zero = vec![0; self.cluster_size()];
+        let sector = self.cluster_sector(cluster);
+        self.write_sectors(sector, &zero)?;
+
+        buf.data.extend_from_slice(&zero);
+        buf.sectors
+            .extend(sector..sector + self.sectors_per_cluster as u64);
+
+        Ok(())
+    }
+
+    fn find(&mut self, dir: Dir, name: &str) -> Result<Option<DirEntry>, FatError> {
+        Ok(self
+            .load_dir(dir)?
+            .entries()
+            .into_iter()
+            .find(|e| e.matches(name)))
+    }
+
+    fn resolve_dir(&mut self, path: &str) -> Result<Dir, FatError> {
+        let mut dir = self.root_dir();
+        for name in components(path) {
+            let entry = self.find(dir, name)?.ok_or(FatError::NotFound)?;
+            if !entry.is_dir() {
+                return Err(FatError::NotADirectory);
+            }
+            dir = match entry.first_cluster {
+                0 => self.root_dir(),
+                c => Dir::Cluster(c),
+            };
+        }
+
+        Ok(dir)
+    }
+
+    fn update_entry(
+        &mut self,
+        entry: &DirEntry,
+        first_cluster: u32,
+        size: u32,
+    ) -> Result<(), FatError> {
+        let mut buf = self.load_dir(entry.parent)?;
+        let n = entry.slots.end - 1;
+        let s = buf.slot_mut(n);
+        s[20..22].copy_from_slice(&((first_cluster >> 16) as u16).to_le_bytes());
+        s[26..28].copy_from_slice(&(first_cluster as u16).to_le_bytes());
+        s[28..32].copy_from_slice(&size.to_le_bytes());
+        s[24..26].copy_from_slice(&DEFAULT_DATE.to_le_bytes());
+
+        self.store_dir(&buf, n..n + 1)
+    }
+
+    /// Adds an entry (with long name entries unless `name` is a valid 8.3 name) to `dir`
+    fn create_entry(
+        &mut self,
+        dir: Dir,
+        name: &str,
+        attributes: Attributes,
+        first_cluster: u32,
+        size: u32,
+    ) -> Result<(), FatError> {
+        validate_name(name)?;
+        let mut buf = self.load_dir(dir)?;
+        let existing = buf.entries();
+        if existing.iter().any(|e| e.matches(name)) {
+            return Err(FatError::AlreadyExists);
+        }
+
+        let (short_name, case_flags, lfn) = match to_short_name(name) {
+            Some((short_name, case_flags)) => (short_name, case_flags, Vec::new()),
+            None => (
+                generate_short_name(name, &existing)?,
+                0,
+                name.encode_utf16().collect(),
+            ),
+        };
+        let lfn_count = lfn.len().div_ceil(LFN_CHARS_PER_ENTRY);
+        let start = loop {
+            if let Some(start) = buf.find_free_slots(lfn_count + 1) {
+                break start;
+            }
+            self.extend_dir(&mut buf)?;
+        };
+
+        // Note: 長い名前のエントリは最後の断片から順に並べ、短いエントリの直前に置く
+        let checksum = short_name_checksum(&short_name);
+        for i in 0..lfn_count {
+            let ordinal = lfn_count - i;
+            let s = buf.slot_mut(start + i);
+            s.fill(0);
+            s[0] = ordinal as u8 | if i == 0 { LFN_LAST } else { 0 };
+            s[11] = Attributes::LONG_NAME.0;
+            s[13] = checksum;
+            for (j, &o) in LFN_CHAR_OFFSETS.iter().enumerate() {
+                let index = (ordinal - 1) * LFN_CHARS_PER_ENTRY + j;
+                let c = match index.cmp(&lfn.len()) {
+                    core::cmp::Ordering::Less => lfn[index],
+                    core::cmp::Ordering::Equal => 0,
+                    core::cmp::Ordering::Greater => 0xffff,
+                };
+                s[o..o + 2].copy_from_slice(&c.to_le_bytes());
+            }
+        }
+        write_short_entry(
+            buf.slot_mut(start + lfn_count),
+            &short_name,
+            attributes,
+            case_flags,
+            first_cluster,
+            size,
+        );
+
+        self.store_dir(&buf, start..start + lfn_count + 1)
+    }
+}
+
+fn write_short_entry(
+    s: &mut [u8],
+    short_name: &[u8; 11],
+    attributes: Attributes,
+    case_flags: u8,
+    first_cluster: u32,
+    size: u32,
+) {
+    s.fill(0);
+    s[..11].copy_from_slice(short_name);
+    s[11] = attributes.0;
+    s[12] = case_flags;
+    for o in [16, 18, 24] {
+        // creation, last access and write dates
+        s[o..o + 2].copy_from_slice(&DEFAULT_DATE.to_le_bytes());
+    }
+    s[20..22].copy_from_slice(&((first_cluster >> 16) as u16).to_le_bytes());
+    s[26..28].copy_from_slice(&(first_cluster as u16).to_le_bytes());
+    s[28..32].copy_from_slice(&size.to_le_bytes());
+}
+
+fn short_name_checksum(short_name: &[u8; 11]) -> u8 {
+    short_name
+        .iter()
+        .fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c))
+}
+
+/// "NAME.EXT" form of a stored short name, lower-cased as told by the DIR_NTRes flags
+fn display_short_name(short_name: &[u8; 11], case_flags: u8) -> String {
+    let part = |bytes: &[u8], lower: bool| -> String {
+        bytes
+            .iter()
+            .enumerate()
+            .map(|(n, &c)| if n == 0 && c == KANJI_E5 { DELETED } else { c })
+            .map(|c| if lower { c.to_ascii_lowercase() } else { c })
+            .map(char::from)
+            .collect::<String>()
+            .trim_end_matches(' ')
+            .into()
+    };
+
+    let base = part(&short_name[..8], (case_flags & NT_LOWERCASE_BASE) != 0);
+    let ext = part(&short_name[8..], (case_flags & NT_LOWERCASE_EXT) != 0);
+    if ext.is_empty() {
+        base
+    } else {
+        format!("{base}.{ext}")
+    }
+}
+
+#[inline]
+fn is_short_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'()-@^_`{}~".contains(c)
+}
+
+/// `name` as a short name with DIR_NTRes case flags, if it is a valid 8.3 name in which
+/// the base and the extension are each all upper or all lower case
+fn to_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, ext) = match name.rsplit_once('.') {
+        Some((base, ext)) => (base, ext),
+        None => (name, ""),
+    };
+    if !(1..=8).contains(&base.len())
+        || ext.len() > 3
+        || (name.contains('.') && ext.is_empty())
+        || !base.chars().chain(ext.chars()).all(is_short_name_char)
+    {
+        return None;
+    }
+
+    let case_flag = |part: &str, flag: u8| {
+        let upper = part.chars().any(|c| c.is_ascii_uppercase());
+        let lower = part.chars().any(|c| c.is_ascii_lowercase());
+        match (upper, lower) {
+            (true, true) => None,
+            (_, true) => Some(flag),
+            _ => Some(0),
+        }
+    };
+    let flags = case_flag(base, NT_LOWERCASE_BASE)? | case_flag(ext, NT_LOWERCASE_EXT)?;
+
+    let mut short_name = [b' '; 11];
+    for (n, c) in base.bytes().enumerate() {
+        short_name[n] = c.to_ascii_uppercase();
+    }
+    for (n, c) in ext.bytes().enumerate() {
+        short_name[8 + n] = c.to_ascii_uppercase();
+    }
+
+    Some((short_name, flags))
+}
+
+/// Numeric-tail short name ("LONGFI~1.TXT") for a long name, unique in the directory
+fn generate_short_name(name: &str, existing: &[DirEntry]) -> Result<[u8; 11], FatError> {
+    let convert = |part: &str| -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| {
+                if is_short_name_char(c) {
+                    c.to_ascii_uppercase() as u8
+                } else {
+                    b'_'
+                }
+            })
+            .collect()
+    };
+
+    let trimmed = name.trim_start_matches('.');
+    let (base, ext) = match trimmed.rsplit_once('.') {
+        Some((base, ext)) => (convert(base), convert(ext)),
+        None => (convert(trimmed), Vec::new()),
+    };
+
+    for n in 1..1_000_000 {
+        let tail = format!("~{n}");
+        let base_length = base.len().min(8 - tail.len());
+
+        let mut short_name = [b' '; 11];
+        short_name[..base_length].copy_from_slice(&base[..base_length]);
+        short_name[base_length..base_length + tail.len()].copy_from_slice(tail.as_bytes());
+        for (n, &c) in ext.iter().take(3).enumerate() {
+            short_name[8 + n] = c;
+        }
+
+        if existing.iter().all(|e| e.short_name != short_name) {
+            return Ok(short_name);
+        }
+    }
+
+    Err(FatError::NoSpace)
+}
+
+fn validate_name(name: &str) -> Result<(), FatError> {
+    let valid = !name.is_empty()
+        && name.encode_utf16().count() <= LFN_MAX_LENGTH
+        && !name.ends_with(['.', ' '])
+        && !name.chars().any(|c| c < ' ' || "\"*/:<>?\\|".contains(c));
+
+    if valid {
+        Ok(())
+    } else {
+        Err(FatError::InvalidName)
+    }
+}
+
+fn names_equal(a: &str, b: &str) -> bool {
+    a.chars()
+        .flat_map(char::to_uppercase)
+        .eq(b.chars().flat_map(char::to_uppercase))
+}
+
+/// Path components, accepting both '/' and '\' as separators
+fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split(['/', '\\'])
+        .filter(|c| !c.is_empty() && *c != ".")
+}
+
+/// Splits `path` into its parent directory and the last component
+fn split_path(path: &str) -> Result<(&str, &str), FatError> {
+    let path = path.trim_end_matches(['/', '\\']);
+    let (parent, name) = match path.rfind(['/', '\\']) {
+        Some(n) => (&path[..n], &path[n + 1..]),
+        None => ("", path),
+    };
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(FatError::InvalidName);
+    }
+
+    Ok((parent, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::rc::Rc;
+    use core::cell::{RefCell, RefMut};
+
+    const SECTOR_SIZE: usize = 512;
+
+    /// A RAM disk whose clones share the contents, so that a volume can be mounted again
+    #[derive(Clone)]
+    struct MemoryDisk(Rc<RefCell<Vec<u8>>>);
+    impl MemoryDisk {
+        fn sector(&self, n: u64) -> RefMut<'_, [u8]> {
+            RefMut::map(self.0.borrow_mut(), |d| {
+                &mut d[n as usize * SECTOR_SIZE..][..SECTOR_SIZE]
+            })
+        }
+    }
+    impl BlockDevice for MemoryDisk {
+        fn block_size(&self) -> usize {
+            SECTOR_SIZE
+        }
+
+        fn block_count(&self) -> u64 {
+            (self.0.borrow().len() / SECTOR_SIZE) as u64
+        }
+
+        fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+            self.check_range(lba, buf.len())?;
+            buf.copy_from_slice(&self.0.borrow()[lba as usize * SECTOR_SIZE..][..buf.len()]);
+            Ok(())
+        }
+
+        fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+            self.check_range(lba, buf.len())?;
+            self.0.borrow_mut()[lba as usize * SECTOR_SIZE..][..buf.len()].copy_from_slice(buf);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<(), BlockError> {
+            Ok(())
+        }
+    }
+
+    /// (total sectors, reserved sectors, FAT size, root entries) of the test volumes,
+    /// all with two FATs and one sector per cluster
+    const fn geometry(fat_type: FatType) -> (u32, u16, u32, u16) {
+        match fat_type {
+            // 100 clusters
+            FatType::Fat12 => (107, 1, 1, 64),
+            // 4200 clusters
+            FatType::Fat16 => (4239, 1, 17, 64),
+            // 66000 clusters
+            FatType::Fat32 => (67064, 32, 516, 0),
+        }
+    }
+
+    /// An empty volume (the FAT32 one has an FSInfo sector pointing at cluster 3)
+    fn format(fat_type: FatType) -> MemoryDisk {
+        let (total_sectors, reserved, fat_size, root_entries) = geometry(fat_type);
+        let disk = MemoryDisk(Rc::new(RefCell::new(vec![
+            0;
+            total_sectors as usize
+                * SECTOR_SIZE
+        ])));
+
+        {
+            let mut b = disk.sector(0);
+            b[0..3].copy_from_slice(&[0xeb, 0x3c, 0x90]);
+            b[11..13].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+            b[13] = 1;
+            b[14..16].copy_from_slice(&reserved.to_le_bytes());
+            b[16] = 2;
+            b[17..19].copy_from_slice(&root_entries.to_le_bytes());
+            match u16::try_from(total_sectors) {
+                Ok(n) => b[19..21].copy_from_slice(&n.to_le_bytes()),
+                Err(_) => b[32..36].copy_from_slice(&total_sectors.to_le_bytes()),
+            }
+            b[21] = 0xf8;
+            if fat_type == FatType::Fat32 {
+                b[36..40].copy_from_slice(&fat_size.to_le_bytes());
+                b[44..48].copy_from_slice(&2u32.to_le_bytes());
+                b[48..50].copy_from_slice(&1u16.to_le_bytes());
+            } else {
+                b[22..24].copy_from_slice(&(fat_size as u16).to_le_bytes());
+            }
+            b[510..512].copy_from_slice(&BOOT_SIGNATURE);
+        }
+        if fat_type == FatType::Fat32 {
+            let mut s = disk.sector(1);
+            s[0..4].copy_from_slice(&FSINFO_LEAD_SIGNATURE.to_le_bytes());
+            s[484..488].copy_from_slice(&FSINFO_STRUCT_SIGNATURE.to_le_bytes());
+            s[FSINFO_FREE_COUNT..][..4].copy_from_slice(&u32::MAX.to_le_bytes());
+            s[FSINFO_NEXT_FREE..][..4].copy_from_slice(&3u32.to_le_bytes());
+            s[508..512].copy_from_slice(&0xaa55_0000u32.to_le_bytes());
+        }
+
+        // media descriptor and end of chain markers (and the FAT32 root directory in cluster 2)
+        let reserved_entries: &[u8] = match fat_type {
+            FatType::Fat12 => &[0xf8, 0xff, 0xff],
+            FatType::Fat16 => &[0xf8, 0xff, 0xff, 0xff],
+            FatType::Fat32 => &[
+                0xf8, 0xff, 0xff, 0x0f, 0xff, 0xff, 0xff, 0x0f, 0xff, 0xff, 0xff, 0x0f,
+            ],
+        };
+        for n in 0..2 {
+            disk.sector(reserved as u64 + n * fat_size as u64)[..reserved_entries.len()]
+                .copy_from_slice(reserved_entries);
+        }
+
+        disk
+    }
+
+    /// Long name entries for `name` as they precede the short entry `short_name`
+    fn lfn_slots(name: &str, short_name: &[u8; 11]) -> Vec<[u8; DIR_ENTRY_SIZE]> {
+        let units: Vec<u16> = name.encode_utf16().collect();
+        let count = units.len().div_ceil(LFN_CHARS_PER_ENTRY);
+        (1..=count)
+            .rev()
+            .map(|ordinal| {
+                let mut s = [0; DIR_ENTRY_SIZE];
+                s[0] = ordinal as u8 | if ordinal == count { LFN_LAST } else { 0 };
+                s[11] = Attributes::LONG_NAME.0;
+                s[13] = short_name_checksum(short_name);
+                for (i, &o) in LFN_CHAR_OFFSETS.iter().enumerate() {
+                    let index = (ordinal - 1) * LFN_CHARS_PER_ENTRY + i;
+                    let c = match index.cmp(&units.len()) {
+                        core::cmp::Ordering::Less => units[index],
+                        core::cmp::Ordering::Equal => 0,
+                        core::cmp::Ordering::Greater => 0xffff,
+                    };
+                    s[o..o + 2].copy_from_slice(&c.to_le_bytes());
+                }
+                s
+            })
+            .collect()
+    }
+
+    /// A FAT16 volume with a label, a deleted entry, "Long File Name.txt" (1300 bytes in
+    /// clusters 3, 5 and 4, filled with 'a', 'b' and 'c') and an empty "readme.txt"
+    fn fat16_with_files() -> MemoryDisk {
+        let disk = format(FatType::Fat16);
+        let (_, reserved, fat_size, root_entries) = geometry(FatType::Fat16);
+        let root_start = reserved as u64 + 2 * fat_size as u64;
+        let data_start = root_start + (root_entries as usize * DIR_ENTRY_SIZE / SECTOR_SIZE) as u64;
+
+        for n in 0..2 {
+            let mut fat = disk.sector(reserved as u64 + n * fat_size as u64);
+            for (cluster, next) in [(3, 5u16), (5, 4), (4, 0xffff)] {
+                fat[cluster * 2..][..2].copy_from_slice(&next.to_le_bytes());
+            }
+        }
+        for (cluster, c) in [(3, b'a'), (5, b'b'), (4, b'c')] {
+            disk.sector(data_start + cluster - 2).fill(c);
+        }
+
+        let short_name = *b"LONGFI~1TXT";
+        let mut slots = vec![];
+        let mut label = [0; DIR_ENTRY_SIZE];
+        label[..11].copy_from_slice(b"TESTVOL    ");
+        label[11] = Attributes::VOLUME_ID.0;
+        slots.push(label);
+        let mut deleted = [0; DIR_ENTRY_SIZE];
+        deleted[..11].copy_from_slice(b"\xe5OLD    TXT");
+        slots.push(deleted);
+        slots.extend(lfn_slots("Long File Name.txt", &short_name));
+        let mut file = [0; DIR_ENTRY_SIZE];
+        write_short_entry(&mut file, &short_name, Attributes::ARCHIVE, 0, 3, 1300);
+        slots.push(file);
+        let mut readme = [0; DIR_ENTRY_SIZE];
+        write_short_entry(
+            &mut readme,
+            b"README  TXT",
+            Attributes::ARCHIVE,
+            NT_LOWERCASE_BASE | NT_LOWERCASE_EXT,
+            0,
+            0,
+        );
+        slots.push(readme);
+
+        let mut root = disk.sector(root_start);
+        for (n, s) in slots.iter().enumerate() {
+            root[n * DIR_ENTRY_SIZE..][..DIR_ENTRY_SIZE].copy_from_slice(s);
+        }
+        drop(root);
+
+        disk
+    }
+
+    fn free_clusters<D: BlockDevice>(fs: &mut FileSystem<D>) -> usize {
+        (2..fs.cluster_count() + 2)
+            .filter(|&c| fs.fat_entry(c).unwrap() == 0)
+            .count()
+    }
+
+    fn names(entries: &[DirEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.name.as_str()).collect()
+    }
+
+    #[test]
+    fn fat_type_is_detected_by_cluster_count() {
+        for (fat_type, clusters) in [
+            (FatType::Fat12, 100),
+            (FatType::Fat16, 4200),
+            (FatType::Fat32, 66000),
+        ] {
+            let fs = FileSystem::mount(format(fat_type)).unwrap();
+            assert_eq!(fs.fat_type(), fat_type);
+            assert_eq!(fs.cluster_count(), clusters);
+            assert_eq!(fs.cluster_size(), SECTOR_SIZE);
+            // the FSInfo hint
+            if fat_type == FatType::Fat32 {
+                assert_eq!(
+                    (fs.root_cluster, fs.fsinfo_sector, fs.next_free),
+                    (2, Some(1), 3)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_bpb() {
+        let disk = format(FatType::Fat16);
+        disk.sector(0)[511] = 0;
+        assert!(matches!(FileSystem::mount(disk), Err(FatError::NotFat)));
+
+        let disk = format(FatType::Fat16);
+        // 3 sectors per cluster
+        disk.sector(0)[13] = 3;
+        assert!(matches!(FileSystem::mount(disk), Err(FatError::NotFat)));
+
+        let disk = format(FatType::Fat12);
+        // data region past the end of the volume
+        disk.sector(0)[22..24].copy_from_slice(&100u16.to_le_bytes());
+        assert!(matches!(FileSystem::mount(disk), Err(FatError::NotFat)));
+    }
+
+    #[test]
+    fn long_names_and_cluster_chain() {
+        let mut fs = FileSystem::mount(fat16_with_files()).unwrap();
+
+        let root = fs.read_dir("").unwrap();
+        assert_eq!(names(&root), ["Long File Name.txt", "readme.txt"]);
+        assert_eq!(root[0].short_name, *b"LONGFI~1TXT");
+        assert_eq!(root[0].slots, 2..5);
+
+        // by long or short name, case-insensitively
+        for path in ["long file name.TXT", "/LONGFI~1.TXT", "\\Readme.txt"] {
+            assert!(fs.metadata(path).is_ok(), "{path}");
+        }
+
+        let data = fs.read_file("Long File Name.txt").unwrap();
+        assert_eq!(data.len(), 1300);
+        assert!(data[..512].iter().all(|&b| b == b'a'));
+        assert!(data[512..1024].iter().all(|&b| b == b'b'));
+        assert!(data[1024..].iter().all(|&b| b == b'c'));
+        assert!(fs.read_file("readme.txt").unwrap().is_empty());
+
+        assert!(matches!(fs.read_file("old.txt"), Err(FatError::NotFound)));
+        assert!(matches!(
+            fs.read_dir("readme.txt"),
+            Err(FatError::NotADirectory)
+        ));
+    }
+
+    #[test]
+    fn broken_cluster_chain() {
+        let mut fs = FileSystem::mount(fat16_with_files()).unwrap();
+        // cluster 5 points outside the data region
+        fs.set_fat_entry(5, 0xfff0).unwrap();
+        assert!(matches!(
+            fs.read_file("Long File Name.txt"),
+            Err(FatError::Corrupted)
+        ));
+
+        // the file is longer than its chain
+        fs.set_fat_entry(5, 0xffff).unwrap();
+        assert!(matches!(
+            fs.read_file("Long File Name.txt"),
+            Err(FatError::Corrupted)
+        ));
+    }
+
+    #[test]
+    fn write_and_extend() {
+        for fat_type in [FatType::Fat12, FatType::Fat16, FatType::Fat32] {
+            let disk = format(fat_type);
+            let mut fs = FileSystem::mount(disk.clone()).unwrap();
+            let free = free_clusters(&mut fs);
+
+            fs.write_file("hello.txt", b"hello").unwrap();
+            assert_eq!(fs.read_file("HELLO.TXT").unwrap(), b"hello");
+            assert_eq!(free_clusters(&mut fs), free - 1);
+
+            // replaced by 3000 bytes in 6 clusters, the old cluster is freed
+            let data: Vec<u8> = (0..3000).map(|n| n as u8).collect();
+            fs.write_file("hello.txt", &data).unwrap();
+            let entry = fs.metadata("hello.txt").unwrap();
+            assert_eq!(entry.size, 3000);
+            assert_eq!(fs.chain(entry.first_cluster).unwrap().len(), 6);
+            assert_eq!(free_clusters(&mut fs), free - 6);
+            fs.flush().unwrap();
+
+            let mut fs = FileSystem::mount(disk).unwrap();
+            assert_eq!(fs.read_file("hello.txt").unwrap(), data, "{fat_type:?}");
+            assert_eq!(names(&fs.read_dir("").unwrap()), ["hello.txt"]);
+        }
+    }
+
+    #[test]
+    fn create_nested_directories() {
+        for fat_type in [FatType::Fat12, FatType::Fat32] {
+            let mut fs = FileSystem::mount(format(fat_type)).unwrap();
+            fs.create_dir("Some Directory").unwrap();
+            fs.create_dir("some directory/sub").unwrap();
+            fs.write_file("Some Directory/SUB/A long file name.bin", &[1, 2, 3])
+                .unwrap();
+
+            let sub = fs.read_dir("SOME DIRECTORY/sub").unwrap();
+            assert_eq!(names(&sub), ["A long file name.bin"]);
+            assert_eq!(sub[0].short_name, *b"ALONGF~1BIN");
+            assert_eq!(
+                fs.read_file("some directory/sub/alongf~1.bin").unwrap(),
+                [1, 2, 3]
+            );
+
+            // dot entries: "." is the directory itself, ".." of a child of the root is 0
+            let dir = fs.metadata("Some Directory").unwrap();
+            let sub = fs.metadata("Some Directory/sub").unwrap();
+            let buf = fs.load_dir(Dir::Cluster(sub.first_cluster)).unwrap();
+            assert_eq!(&buf.slot(0)[..11], b".          ");
+            assert_eq!(
+                u16::from_le_bytes([buf.slot(1)[26], buf.slot(1)[27]]) as u32,
+                dir.first_cluster
+            );
+            let buf = fs.load_dir(Dir::Cluster(dir.first_cluster)).unwrap();
+            assert_eq!(u16::from_le_bytes([buf.slot(1)[26], buf.slot(1)[27]]), 0);
+
+            assert!(matches!(
+                fs.create_dir("Some Directory"),
+                Err(FatError::AlreadyExists)
+            ));
+            assert!(matches!(
+                fs.read_file("Some Directory"),
+                Err(FatError::IsADirectory)
+            ));
+            assert!(matches!(
+                fs.write_file("missing/file", b""),
+                Err(FatError::NotFound)
+            ));
+            assert!(matches!(
+                fs.write_file("Some Directory/a?b", b""),
+                Err(FatError::InvalidName)
+            ));
+        }
+    }
+
+    #[test]
+    fn directory_grows_by_clusters() {
+        let mut fs = FileSystem::mount(format(FatType::Fat16)).unwrap();
+        fs.create_dir("dir").unwrap();
+        // three slots each, 16 slots per cluster
+        for n in 0..20 {
+            fs.write_file(&format!("dir/long name {n:02}.txt"), b"x")
+                .unwrap();
+        }
+
+        let entries = fs.read_dir("dir").unwrap();
+        assert_eq!(entries.len(), 20);
+        assert_eq!(entries[19].name, "long name 19.txt");
+        assert_eq!(entries[19].short_name, *b"LONGN~20TXT");
+        let dir = fs.metadata("dir").unwrap();
+        assert_eq!(fs.chain(dir.first_cluster).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn remove_entries() {
+        let mut fs = FileSystem::mount(format(FatType::Fat12)).unwrap();
+        let free = free_clusters(&mut fs);
+        fs.create_dir("dir").unwrap();
+        fs.write_file("dir/A file with a long name", &[0; 1000])
+            .unwrap();
+        assert_eq!(free_clusters(&mut fs), free - 3);
+
+        assert!(matches!(fs.remove("dir"), Err(FatError::DirectoryNotEmpty)));
+        fs.remove("DIR/a file with a long name").unwrap();
+        assert!(fs.read_dir("dir").unwrap().is_empty());
+        assert_eq!(free_clusters(&mut fs), free - 1);
+
+        fs.remove("dir").unwrap();
+        assert!(matches!(fs.metadata("dir"), Err(FatError::NotFound)));
+        assert!(fs.read_dir("").unwrap().is_empty());
+        assert_eq!(free_clusters(&mut fs), free);
+    }
+
+    #[test]
+    fn free_cluster_allocation() {
+        let mut fs = FileSystem::mount(format(FatType::Fat12)).unwrap();
+        // 12-bit entries of odd and even clusters share bytes
+        fs.write_file("a", &[b'a'; 60 * SECTOR_SIZE]).unwrap();
+        let a = fs.metadata("a").unwrap();
+        assert_eq!(a.first_cluster, 2);
+        assert_eq!(fs.chain(2).unwrap(), (2..62).collect::<Vec<_>>());
+
+        // nothing is left allocated when the volume is full
+        assert!(matches!(
+            fs.write_file("b", &[b'b'; 50 * SECTOR_SIZE]),
+            Err(FatError::NoSpace)
+        ));
+        assert_eq!(free_clusters(&mut fs), 40);
+        assert!(matches!(fs.metadata("b"), Err(FatError::NotFound)));
+
+        // freed clusters are reused first, then the search continues past the allocated ones
+        fs.write_file("b", &[b'b'; 30 * SECTOR_SIZE]).unwrap();
+        fs.remove("a").unwrap();
+        fs.write_file("c", &[b'c'; 70 * SECTOR_SIZE]).unwrap();
+        assert_eq!(free_clusters(&mut fs), 0);
+        let c = fs.metadata("c").unwrap();
+        let chain = fs.chain(c.first_cluster).unwrap();
+        assert_eq!(chain[..60], (2..62).collect::<Vec<_>>());
+        assert_eq!(chain[60..], (92..102).collect::<Vec<_>>());
+        assert_eq!(fs.read_file("c").unwrap(), [b'c'; 70 * SECTOR_SIZE]);
+        assert_eq!(fs.read_file("b").unwrap(), [b'b'; 30 * SECTOR_SIZE]);
+    }
+
+    #[test]
+    fn fixed_root_directory_is_full() {
+        let mut fs = FileSystem::mount(format(FatType::Fat16)).unwrap();
+        for n in 0..64 {
+            fs.write_file(&format!("FILE{n}"), b"").unwrap();
+        }
+        assert!(matches!(
+            fs.write_file("FILE64", b""),
+            Err(FatError::NoSpace)
+        ));
+    }
+}
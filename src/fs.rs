@@ -0,0 +1,156 @@
+//! Files on the volume the image was loaded from, through the UEFI file protocols
+//! (only while boot services are available)
+
+use crate::uefi::{
+    EfiFileInfo, EfiFileProtocol, EfiLoadedImageProtocol, EfiSimpleFileSystemProtocol, EfiStatus,
+};
+use alloc::{vec, vec::Vec};
+
+const EFI_BUFFER_TOO_SMALL: EfiStatus = 0x8000_0000_0000_0005;
+
+#[derive(Debug)]
+#[allow(dead_code)]
+pub enum FsError {
+    /// ExitBootServices was already called
+    BootServicesUnavailable,
+    /// a UEFI call failed with the status
+    Efi {
+        operation: &'static str,
+        status: EfiStatus,
+    },
+    IsADirectory,
+}
+
+#[inline]
+fn check(operation: &'static str, status: EfiStatus) -> Result<(), FsError> {
+    if status == 0 {
+        Ok(())
+    } else {
+        Err(FsError::Efi { operation, status })
+    }
+}
+
+/// An open EFI_FILE_PROTOCOL handle, closed on drop
+struct File(*mut EfiFileProtocol);
+impl File {
+    #[inline]
+    fn protocol(&mut self) -> &mut EfiFileProtocol {
+        unsafe { &mut *self.0 }
+    }
+
+    /// Opens `path` (relative to this directory) for reading.
+    /// Both '/' and '\' are accepted as separators.
+    fn open(&mut self, path: &str) -> Result<Self, FsError> {
+        let name = path
+            .encode_utf16()
+            .map(|c| if c == '/' as u16 { '\\' as u16 } else { c })
+            .chain([0])
+            .collect::<Vec<u16>>();
+
+        let mut handle = core::ptr::null_mut();
+        check(
+            "EFI_FILE_PROTOCOL.Open",
+            self.protocol()
+                .open(&mut handle, &name, EfiFileProtocol::MODE_READ, 0),
+        )?;
+
+        Ok(Self(handle))
+    }
+
+    /// (file size, attributes)
+    fn info(&mut self) -> Result<(u64, u64), FsError> {
+        // Note: EFI_FILE_INFOはファイル名の分だけ可変長なので、まず必要なサイズを問い合わせる
+        let mut size = 0;
+        let r = self
+            .protocol()
+            .get_info(&EfiFileInfo::GUID, &mut size, core::ptr::null_mut());
+        if r != EFI_BUFFER_TOO_SMALL {
+            check("EFI_FILE_PROTOCOL.GetInfo", r)?;
+        }
+
+        let mut buffer = vec![0u64; size.div_ceil(8)];
+        check(
+            "EFI_FILE_PROTOCOL.GetInfo",
+            self.protocol()
+                .get_info(&EfiFileInfo::GUID, &mut size, buffer.as_mut_ptr() as _),
+        )?;
+        let info = unsafe { &*(buffer.as_ptr() as *const EfiFileInfo) };
+
+        Ok((info.file_size, info.attribute))
+    }
+
+    fn read_to_end(&mut self) -> Result<Vec<u8>, FsError> {
+        let (size, attributes) = self.info()?;
+        if (attributes & EfiFileInfo::ATTRIBUTE_DIRECTORY) != 0 {
+            return Err(FsError::IsADirectory);
+        }
+
+        let mut data = vec![0; size as usize];
+        let mut offset = 0;
+        while offset < data.len() {
+            let mut length = data.len() - offset;
+            check(
+                "EFI_FILE_PROTOCOL.Read",
+                self.protocol().read(&mut length, &mut data[offset..]),
+            )?;
+            if length == 0 {
+                // the file shrank meanwhile
+                data.truncate(offset);
+                break;
+            }
+            offset += length;
+        }
+
+        Ok(data)
+    }
+}
+impl Drop for File {
+    fn drop(&mut self) {
+        self.protocol().close();
+    }
+}
+
+/// Root directory of the volume holding the running image
+fn open_boot_volume() -> Result<File, FsError> {
+    let (boot_services, image_handle) =
+        unsafe { ((*crate::SYSTEM_TABLE).boot_services, crate::IMAGE_HANDLE) };
+    // Note: ExitBootServicesの後はefi_mainがBootServicesポインタをNULLにしている
+    if boot_services.is_null() {
+        return Err(FsError::BootServicesUnavailable);
+    }
+    let boot_services = unsafe { &*boot_services };
+
+    let mut loaded_image = core::ptr::null_mut::<EfiLoadedImageProtocol>();
+    check(
+        "HandleProtocol(EFI_LOADED_IMAGE_PROTOCOL)",
+        (boot_services.handle_protocol)(
+            image_handle,
+            &EfiLoadedImageProtocol::GUID,
+            &mut loaded_image as *mut _ as _,
+        ),
+    )?;
+    let device_handle = unsafe { (*loaded_image).device_handle };
+
+    let mut file_system = core::ptr::null_mut::<EfiSimpleFileSystemProtocol>();
+    check(
+        "HandleProtocol(EFI_SIMPLE_FILE_SYSTEM_PROTOCOL)",
+        (boot_services.handle_protocol)(
+            device_handle,
+            &EfiSimpleFileSystemProtocol::GUID,
+            &mut file_system as *mut _ as _,
+        ),
+    )?;
+
+    let mut root = core::ptr::null_mut();
+    check(
+        "EFI_SIMPLE_FILE_SYSTEM_PROTOCOL.OpenVolume",
+        unsafe { &mut *file_system }.open_volume(&mut root),
+    )?;
+
+    Ok(File(root))
+}
+
+/// Reads a whole file from the boot volume, e.g. `\EFI\BOOT\kernel.elf`
+pub fn read_file(path: &str) -> Result<Vec<u8>, FsError> {
+    open_boot_volume()?.open(path)?.read_to_end()
+}
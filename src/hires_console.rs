@@ -1,23 +1,27 @@
+use alloc::boxed::Box;
 use core::fmt::Write;
 
-struct FontDriver;
+/// 8x12 glyphs of the 256 code points, one byte per row (bit 0 is the leftmost pixel)
+struct FontDriver(&'static [u8]);
 impl FontDriver {
     const BLOB: &'static [u8] = include_bytes!("../font.bin");
     pub const CHAR_W: u32 = 8;
     pub const CHAR_H: u32 = 12;
+    /// bytes of a font (`BLOB` or one given to `HiResConsole::set_font`)
+    pub const SIZE: usize = 256 * Self::CHAR_H as usize;
 
-    fn get_row(c: char, y: u32) -> u8 {
+    fn get_row(&self, c: char, y: u32) -> u8 {
         assert!(
             (0..256).contains(&(c as u32)),
             "out of ascii character cannot render"
         );
 
-        Self::BLOB[(c as u32 * Self::CHAR_H + y) as usize]
+        self.0[(c as u32 * Self::CHAR_H + y) as usize]
     }
 
-    pub fn render1(fb_base: &mut [[u8; 4]], fb_stride: u32, x: u32, y: u32, c: char) {
+    pub fn render1(&self, fb_base: &mut [[u8; 4]], fb_stride: u32, x: u32, y: u32, c: char) {
         for yo in 0..Self::CHAR_H {
-            let r = Self::get_row(c, yo);
+            let r = self.get_row(c, yo);
             for xo in 0..Self::CHAR_W {
                 fb_base[((x + xo) + (y + yo) * fb_stride) as usize] = if (r >> xo) & 0x01 != 0 {
                     [255, 255, 255, 255]
@@ -29,28 +33,87 @@ impl FontDriver {
     }
 }
 
+/// Presents updated areas of a framebuffer that is not scanned out directly (e.g. a virtio-gpu resource)
+pub trait DisplayFlush {
+    /// Returns false if the area could not be presented (the console retries it with the next flush)
+    fn flush(&mut self, x: u32, y: u32, width: u32, height: u32) -> bool;
+}
+
 pub struct HiResConsole {
     fb_base: &'static mut [[u8; 4]],
+    font: FontDriver,
     fb_stride: u32,
+    fb_height: u32,
     max_cols: u32,
     max_lines: u32,
     cursor_x: u32,
     cursor_y: u32,
+    /// (left, top, right, bottom) in pixels, exclusive
+    damage: Option<(u32, u32, u32, u32)>,
+    flush_target: Option<Box<dyn DisplayFlush>>,
 }
 impl HiResConsole {
     pub fn new(fb_base: &'static mut [[u8; 4]], fb_stride: u32, fb_height: u32) -> Self {
         Self {
             fb_base,
+            font: FontDriver(FontDriver::BLOB),
             fb_stride,
+            fb_height,
             max_cols: fb_stride / FontDriver::CHAR_W,
             max_lines: fb_height / FontDriver::CHAR_H,
             cursor_x: 0,
             cursor_y: 0,
+            damage: None,
+            flush_target: None,
+        }
+    }
+
+    /// Replaces the built-in font with `font` (in the same format) for the following output.
+    /// Returns false and keeps the current one if the size does not match.
+    pub fn set_font(&mut self, font: &'static [u8]) -> bool {
+        if font.len() != FontDriver::SIZE {
+            return false;
+        }
+        self.font = FontDriver(font);
+
+        true
+    }
+
+    /// the font in use (see `set_font`)
+    #[inline]
+    pub fn font(&self) -> &'static [u8] {
+        self.font.0
+    }
+
+    /// Damaged areas are handed to `target` on every newline and on `flush`
+    pub fn set_flush_target(&mut self, target: Box<dyn DisplayFlush>) {
+        self.flush_target = Some(target);
+    }
+
+    fn add_damage(&mut self, left: u32, top: u32, right: u32, bottom: u32) {
+        let (right, bottom) = (right.min(self.fb_stride), bottom.min(self.fb_height));
+        self.damage = Some(match self.damage {
+            Some((l, t, r, b)) => (l.min(left), t.min(top), r.max(right), b.max(bottom)),
+            None => (left, top, right, bottom),
+        });
+    }
+
+    /// Presents the area changed since the last flush (no-op without a flush target)
+    pub fn flush(&mut self) {
+        let Some(target) = self.flush_target.as_mut() else {
+            return;
+        };
+
+        if let Some((l, t, r, b)) = self.damage.take() {
+            if l < r && t < b && !target.flush(l, t, r - l, b - t) {
+                self.damage = Some((l, t, r, b));
+            }
         }
     }
 
     pub fn scroll_lines(&mut self, lines: u32) {
         let lines = lines.min(self.max_lines);
+        self.add_damage(0, 0, self.fb_stride, self.fb_height);
         if lines == self.max_lines {
             // clear operation
             self.fb_base.fill([0; 4]);
@@ -73,16 +136,16 @@ impl HiResConsole {
             self.cursor_y += 1;
         }
         self.cursor_x = 0;
+        self.flush();
     }
 
     pub fn write_char(&mut self, c: char) {
-        FontDriver::render1(
-            self.fb_base,
-            self.fb_stride,
+        let (x, y) = (
             self.cursor_x * FontDriver::CHAR_W,
             self.cursor_y * FontDriver::CHAR_H,
-            c,
         );
+        self.font.render1(self.fb_base, self.fb_stride, x, y, c);
+        self.add_damage(x, y, x + FontDriver::CHAR_W, y + FontDriver::CHAR_H);
         self.cursor_x += 1;
         if self.cursor_x >= self.max_cols {
             self.newline();
@@ -0,0 +1,266 @@
+//! High Precision Event Timer
+
+use crate::{acpi, apic::LocalAPIC, rdtsc};
+
+const FEMTOSECONDS_PER_NANOSECOND: u64 = 1_000_000;
+const FEMTOSECONDS_PER_SECOND: u64 = 1_000_000_000_000_000;
+
+pub struct HighPrecisionEventTimer {
+    base_address: usize,
+}
+impl HighPrecisionEventTimer {
+    const GENERAL_CAPABILITIES_ID: usize = 0x000;
+    const GENERAL_CONFIGURATION: usize = 0x010;
+    const MAIN_COUNTER_VALUE: usize = 0x0f0;
+
+    const ENABLE_CNF: u64 = 1 << 0;
+    const LEG_RT_CNF: u64 = 1 << 1;
+
+    /// Uses the MMIO block described by the HPET table (identity mapped)
+    pub unsafe fn new(table: &acpi::HighPrecisionEventTimerTable) -> Self {
+        assert_eq!(
+            table.base_address.address_space_id,
+            acpi::GenericAddressStructure::SPACE_SYSTEM_MEMORY,
+            "HPET registers must be memory mapped"
+        );
+
+        Self {
+            base_address: table.base_address.address() as _,
+        }
+    }
+
+    fn read(&self, offset: usize) -> u64 {
+        unsafe { core::ptr::read_volatile((self.base_address + offset) as *const u64) }
+    }
+
+    fn write(&self, offset: usize, value: u64) {
+        unsafe { core::ptr::write_volatile((self.base_address + offset) as *mut u64, value) }
+    }
+
+    #[inline]
+    pub fn revision(&self) -> u8 {
+        self.read(Self::GENERAL_CAPABILITIES_ID) as _
+    }
+
+    #[inline]
+    pub fn comparator_count(&self) -> u8 {
+        (((self.read(Self::GENERAL_CAPABILITIES_ID) >> 8) & 0x1f) + 1) as _
+    }
+
+    #[inline]
+    pub fn is_counter_64bit(&self) -> bool {
+        (self.read(Self::GENERAL_CAPABILITIES_ID) & (1 << 13)) != 0
+    }
+
+    /// main counter tick period in femtoseconds
+    #[inline]
+    pub fn counter_period(&self) -> u32 {
+        (self.read(Self::GENERAL_CAPABILITIES_ID) >> 32) as _
+    }
+
+    /// main counter frequency in Hz
+    #[inline]
+    pub fn frequency(&self) -> u64 {
+        FEMTOSECONDS_PER_SECOND / self.counter_period() as u64
+    }
+
+    /// Starts the main counter (legacy replacement routing stays disabled)
+    pub fn enable(&self) {
+        let c = self.read(Self::GENERAL_CONFIGURATION) & !Self::LEG_RT_CNF;
+        self.write(Self::GENERAL_CONFIGURATION, c | Self::ENABLE_CNF);
+    }
+
+    #[allow(dead_code)]
+    pub fn disable(&self) {
+        let c = self.read(Self::GENERAL_CONFIGURATION);
+        self.write(Self::GENERAL_CONFIGURATION, c & !Self::ENABLE_CNF);
+    }
+
+    #[inline]
+    pub fn main_counter(&self) -> u64 {
+        self.read(Self::MAIN_COUNTER_VALUE)
+    }
+
+    /// mask of the valid main counter bits (COUNT_SIZE_CAP)
+    #[inline]
+    fn counter_mask(&self) -> u64 {
+        if self.is_counter_64bit() {
+            u64::MAX
+        } else {
+            u32::MAX as u64
+        }
+    }
+
+    /// ticks elapsed since the main counter value `start` (wraps at 32 bits on a 32-bit counter)
+    #[inline]
+    pub fn ticks_since(&self, start: u64) -> u64 {
+        self.main_counter().wrapping_sub(start) & self.counter_mask()
+    }
+
+    #[inline]
+    pub fn nanoseconds_to_ticks(&self, ns: u64) -> u64 {
+        ns * FEMTOSECONDS_PER_NANOSECOND / self.counter_period() as u64
+    }
+
+    #[inline]
+    pub fn ticks_to_nanoseconds(&self, ticks: u64) -> u64 {
+        ticks * self.counter_period() as u64 / FEMTOSECONDS_PER_NANOSECOND
+    }
+
+    /// Busy-waits for `ns` nanoseconds. The main counter must be enabled.
+    pub fn wait_nanoseconds(&self, ns: u64) {
+        let mask = self.counter_mask();
+        let ticks = self.nanoseconds_to_ticks(ns);
+        // Note: 32bitカウンタは数分で一周するので差分を積算して長い待ち時間にも対応する
+        let mut last = self.main_counter();
+        let mut elapsed = 0;
+        while elapsed < ticks {
+            core::hint::spin_loop();
+            let now = self.main_counter();
+            elapsed += now.wrapping_sub(last) & mask;
+            last = now;
+        }
+    }
+
+    pub fn comparator(&self, index: u8) -> Comparator<'_> {
+        assert!(
+            index < self.comparator_count(),
+            "no such HPET comparator: {index}"
+        );
+
+        Comparator { hpet: self, index }
+    }
+
+    /// TSC frequency in Hz, measured against the main counter for `ms` milliseconds
+    pub fn measure_tsc_frequency(&self, ms: u64) -> u64 {
+        let start_counter = self.main_counter();
+        let start_tsc = unsafe { rdtsc!() };
+        self.wait_nanoseconds(ms * 1_000_000);
+        let tsc = unsafe { rdtsc!() } - start_tsc;
+        let elapsed = self.ticks_to_nanoseconds(self.ticks_since(start_counter));
+
+        tsc * 1_000_000_000 / elapsed
+    }
+
+    /// Local APIC timer frequency in Hz with `divider`, measured for `ms` milliseconds
+    pub fn measure_local_apic_timer_frequency(
+        &self,
+        local_apic: &LocalAPIC,
+        divider: u8,
+        ms: u64,
+    ) -> u64 {
+        local_apic.set_timer_divider(divider);
+        let start_counter = self.main_counter();
+        local_apic.start_timer(u32::MAX, None, false);
+        self.wait_nanoseconds(ms * 1_000_000);
+        let remaining = local_apic.timer_current_count();
+        let elapsed = self.ticks_to_nanoseconds(self.ticks_since(start_counter));
+        local_apic.stop_timer();
+
+        (u32::MAX - remaining) as u64 * 1_000_000_000 / elapsed
+    }
+}
+
+/// A timer (comparator) of the HPET block
+pub struct Comparator<'a> {
+    hpet: &'a HighPrecisionEventTimer,
+    index: u8,
+}
+impl Comparator<'_> {
+    const INT_TYPE_CNF: u64 = 1 << 1;
+    const INT_ENB_CNF: u64 = 1 << 2;
+    const TYPE_CNF: u64 = 1 << 3;
+    const PER_INT_CAP: u64 = 1 << 4;
+    const SIZE_CAP: u64 = 1 << 5;
+    const VAL_SET_CNF: u64 = 1 << 6;
+    const MODE32_CNF: u64 = 1 << 8;
+    const INT_ROUTE_CNF_SHIFT: u64 = 9;
+    const INT_ROUTE_CNF_MASK: u64 = 0x1f << Self::INT_ROUTE_CNF_SHIFT;
+    const FSB_EN_CNF: u64 = 1 << 14;
+
+    #[inline]
+    const fn configuration_register(&self) -> usize {
+        0x100 + 0x20 * self.index as usize
+    }
+
+    #[inline]
+    const fn comparator_register(&self) -> usize {
+        0x108 + 0x20 * self.index as usize
+    }
+
+    fn configuration(&self) -> u64 {
+        self.hpet.read(self.configuration_register())
+    }
+
+    fn set_configuration(&self, value: u64) {
+        self.hpet.write(self.configuration_register(), value);
+    }
+
+    #[inline]
+    pub fn supports_periodic(&self) -> bool {
+        (self.configuration() & Self::PER_INT_CAP) != 0
+    }
+
+    #[inline]
+    pub fn is_64bit(&self) -> bool {
+        (self.configuration() & Self::SIZE_CAP) != 0
+    }
+
+    /// bit mask of I/O APIC inputs this comparator can be routed to
+    #[inline]
+    pub fn interrupt_route_capability(&self) -> u32 {
+        (self.configuration() >> 32) as _
+    }
+
+    /// Routes the comparator to I/O APIC input `gsi` (edge triggered).
+    /// The caller sets up the I/O APIC redirection entry for `gsi`.
+    pub fn route(&self, gsi: u8) {
+        assert!(
+            gsi < 32 && (self.interrupt_route_capability() & (1 << gsi)) != 0,
+            "HPET comparator {} cannot be routed to GSI {gsi}",
+            self.index
+        );
+
+        let c = self.configuration()
+            & !(Self::INT_ROUTE_CNF_MASK
+                | Self::FSB_EN_CNF
+                | Self::INT_TYPE_CNF
+                | Self::MODE32_CNF);
+        self.set_configuration(c | ((gsi as u64) << Self::INT_ROUTE_CNF_SHIFT));
+    }
+
+    /// Fires once after `ticks` main counter ticks
+    pub fn set_one_shot(&self, ticks: u64) {
+        let c = self.configuration() & !(Self::TYPE_CNF | Self::INT_ENB_CNF);
+        self.set_configuration(c);
+        self.hpet.write(
+            self.comparator_register(),
+            self.hpet.main_counter().wrapping_add(ticks),
+        );
+        self.set_configuration(c | Self::INT_ENB_CNF);
+    }
+
+    /// Fires every `ticks` main counter ticks
+    pub fn set_periodic(&self, ticks: u64) {
+        assert!(
+            self.supports_periodic(),
+            "HPET comparator {} does not support periodic mode",
+            self.index
+        );
+
+        let c = self.configuration() & !Self::INT_ENB_CNF;
+        // Note: VAL_SET_CNFを立てた直後の書き込みがコンパレータ値、その次の書き込みが周期（アキュムレータ）になる
+        self.set_configuration(c | Self::TYPE_CNF | Self::VAL_SET_CNF);
+        self.hpet.write(
+            self.comparator_register(),
+            self.hpet.main_counter().wrapping_add(ticks),
+        );
+        self.hpet.write(self.comparator_register(), ticks);
+        self.set_configuration((c | Self::TYPE_CNF | Self::INT_ENB_CNF) & !Self::VAL_SET_CNF);
+    }
+
+    pub fn disable(&self) {
+        let c = self.configuration();
+        self.set_configuration(c & !(Self::INT_ENB_CNF | Self::TYPE_CNF));
+    }
+}
@@ -0,0 +1,160 @@
+//! External interrupt (vector 32..=255) entry stubs and handler dispatch
+
+use crate::{apic::LocalAPIC, cli, sti};
+
+pub const FIRST_EXTERNAL_VECTOR: u8 = 32;
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+/// vectors below this are kept for legacy PIC remapping
+const FIRST_ALLOCATABLE_VECTOR: u8 = 0x30;
+const STUB_SIZE: usize = 16;
+
+pub type Handler = fn(vector: u8);
+
+static mut HANDLERS: [Option<Handler>; 256] = [None; 256];
+static mut ALLOCATED_VECTORS: [u64; 4] = [0; 4];
+
+// Note: ベクタ番号をpushしてから共通処理に飛ぶだけのスタブを16バイト間隔で並べる（アドレスはinterrupt_stub_base + (vector - 32) * 16）
+// Note: x86_64-unknown-uefiはsoft-floatなのでSSEレジスタは退避しない
+core::arch::global_asm!(
+    ".pushsection .text",
+    ".p2align 4",
+    ".global interrupt_stub_base",
+    "interrupt_stub_base:",
+    ".set vector, 32",
+    ".rept 224",
+    ".p2align 4",
+    "pushq $vector",
+    "jmp interrupt_common",
+    ".set vector, vector + 1",
+    ".endr",
+    "interrupt_common:",
+    "pushq %rax",
+    "pushq %rcx",
+    "pushq %rdx",
+    "pushq %rsi",
+    "pushq %rdi",
+    "pushq %r8",
+    "pushq %r9",
+    "pushq %r10",
+    "pushq %r11",
+    "pushq %rbx",
+    "cld",
+    // vector number (pushed by the stub)
+    "movq 80(%rsp), %rcx",
+    "movq %rsp, %rbx",
+    "andq $-16, %rsp",
+    "subq $32, %rsp",
+    "call interrupt_dispatch",
+    "movq %rbx, %rsp",
+    "popq %rbx",
+    "popq %r11",
+    "popq %r10",
+    "popq %r9",
+    "popq %r8",
+    "popq %rdi",
+    "popq %rsi",
+    "popq %rdx",
+    "popq %rcx",
+    "popq %rax",
+    // discard the vector number
+    "addq $8, %rsp",
+    "iretq",
+    ".popsection",
+    options(att_syntax)
+);
+
+extern "C" {
+    static interrupt_stub_base: u8;
+}
+
+/// Entry address to be set in the IDT for `vector`
+pub fn stub_address(vector: u8) -> u64 {
+    assert!(
+        vector >= FIRST_EXTERNAL_VECTOR,
+        "no stub for exception vectors"
+    );
+
+    core::ptr::addr_of!(interrupt_stub_base) as u64
+        + (vector - FIRST_EXTERNAL_VECTOR) as u64 * STUB_SIZE as u64
+}
+
+#[no_mangle]
+extern "win64" fn interrupt_dispatch(vector: u64) {
+    let vector = vector as u8;
+    if let Some(h) = unsafe { HANDLERS[vector as usize] } {
+        h(vector);
+    }
+
+    if vector != SPURIOUS_VECTOR {
+        LocalAPIC::get().end_of_interrupt();
+    }
+}
+
+/// Reserves a free vector for a device interrupt
+pub fn allocate_vector() -> Option<u8> {
+    allocate_vectors(1)
+}
+
+/// Reserves `count` (a power of two, up to 32) contiguous vectors aligned to `count`
+/// and returns the first one. Multiple-message MSI needs such a block.
+pub fn allocate_vectors(count: u8) -> Option<u8> {
+    assert!(
+        count.is_power_of_two() && count <= 32,
+        "invalid vector block size: {count}"
+    );
+
+    without_interrupts(|| {
+        let allocated = unsafe { &mut *core::ptr::addr_of_mut!(ALLOCATED_VECTORS) };
+        let is_free = |v: u8| (allocated[v as usize / 64] & (1 << (v % 64))) == 0;
+
+        let first = (FIRST_ALLOCATABLE_VECTOR.next_multiple_of(count)..SPURIOUS_VECTOR)
+            .step_by(count as usize)
+            .find(|&v| {
+                (v as u16 + count as u16) <= SPURIOUS_VECTOR as u16 && (v..v + count).all(is_free)
+            })?;
+        for v in first..first + count {
+            allocated[v as usize / 64] |= 1 << (v % 64);
+        }
+        Some(first)
+    })
+}
+
+pub fn free_vector(vector: u8) {
+    without_interrupts(|| unsafe {
+        HANDLERS[vector as usize] = None;
+        (*core::ptr::addr_of_mut!(ALLOCATED_VECTORS))[vector as usize / 64] &=
+            !(1 << (vector % 64));
+    })
+}
+
+pub fn register_handler(vector: u8, handler: Handler) {
+    without_interrupts(|| unsafe {
+        HANDLERS[vector as usize] = Some(handler);
+    })
+}
+
+/// whether RFLAGS.IF is set
+#[inline]
+pub fn are_enabled() -> bool {
+    let rflags: u64;
+    unsafe {
+        core::arch::asm!("pushfq; pop {}", out(reg) rflags, options(nomem, preserves_flags));
+    }
+    (rflags & 0x200) != 0
+}
+
+/// Runs `f` with interrupts disabled, restoring IF afterwards
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let enabled = are_enabled();
+    unsafe {
+        cli!();
+    }
+    let r = f();
+    if enabled {
+        unsafe {
+            sti!();
+        }
+    }
+
+    r
+}
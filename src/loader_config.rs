@@ -0,0 +1,51 @@
+//! `\EFI\BOOT\loader.cfg`: one `key = value` per line, `#` starts a comment
+//!
+//! - `kernel = \path\to\kernel.elf` (default `\EFI\BOOT\kernel.elf`)
+//! - `cmdline = ...`: the command line handed to the kernel
+//! - `module = \path\to\file`: a file loaded along with the kernel (repeatable)
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+pub const DEFAULT_KERNEL_PATH: &str = "\\EFI\\BOOT\\kernel.elf";
+
+#[derive(Debug)]
+pub struct LoaderConfig {
+    pub kernel: String,
+    pub command_line: String,
+    pub modules: Vec<String>,
+    /// lines that are neither empty, a comment nor a known `key = value`
+    pub unknown_lines: Vec<String>,
+}
+impl Default for LoaderConfig {
+    fn default() -> Self {
+        Self {
+            kernel: DEFAULT_KERNEL_PATH.to_string(),
+            command_line: String::new(),
+            modules: Vec::new(),
+            unknown_lines: Vec::new(),
+        }
+    }
+}
+impl LoaderConfig {
+    pub fn parse(text: &str) -> Self {
+        let mut config = Self::default();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            match line.split_once('=').map(|(k, v)| (k.trim(), v.trim())) {
+                Some(("kernel", v)) => config.kernel = v.to_string(),
+                Some(("cmdline", v)) => config.command_line = v.to_string(),
+                Some(("module", v)) => config.modules.push(v.to_string()),
+                _ => config.unknown_lines.push(line.to_string()),
+            }
+        }
+
+        config
+    }
+}
@@ -1,25 +1,51 @@
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
+// Note: テストビルドではefi_mainから辿られないのでほぼ全てがdead codeになる
+#![cfg_attr(test, allow(dead_code))]
 
-use core::{fmt::Write, panic::PanicInfo};
+extern crate alloc;
+
+use core::fmt::Write;
 
 mod acpi;
+mod allocator;
+mod aml;
+mod apic;
 mod asm;
+mod block;
+mod boot_info;
+mod dma;
+mod edid;
+mod elf;
+mod fat;
+mod fs;
 mod hires_console;
+mod hpet;
+mod interrupt;
+mod loader_config;
+mod msi;
+mod multiboot2;
+mod paging;
+mod partition;
 mod pci;
+mod pci_ids;
 mod uefi;
 mod virtio;
+use acpi::SystemDescriptionTable;
 use hires_console::HiResConsole;
 
 static mut SYSTEM_TABLE: *mut uefi::EfiSystemTable = core::ptr::null_mut();
+static mut IMAGE_HANDLE: uefi::EfiHandle = core::ptr::null_mut();
 static mut HIRES_CONSOLE: *mut HiResConsole = core::ptr::null_mut();
 
+#[cfg(not(test))]
 #[panic_handler]
-fn panic_handler<'a, 'b>(info: &'a PanicInfo<'b>) -> ! {
+fn panic_handler<'a, 'b>(info: &'a core::panic::PanicInfo<'b>) -> ! {
     let hires_console = unsafe { HIRES_CONSOLE };
     if !hires_console.is_null() {
         // use hires console as output
         writeln!(unsafe { &mut *hires_console }, "[PANIC OCCURRED] {info}").unwrap();
+        unsafe { &mut *hires_console }.flush();
 
         loop {}
     }
@@ -76,7 +102,7 @@ impl Write for ConsoleWriter {
     }
 }
 
-const ARROW_BITMAP: &'static [[u8; 16]; 16] = &[
+const ARROW_BITMAP: &[[u8; 16]; 16] = &[
     *b"@               ",
     *b"@@              ",
     *b"@.@             ",
@@ -96,7 +122,7 @@ const ARROW_BITMAP: &'static [[u8; 16]; 16] = &[
 ];
 
 #[repr(transparent)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default)]
 pub struct SegmentDescriptor(pub u64);
 impl SegmentDescriptor {
     pub const fn new() -> Self {
@@ -161,7 +187,7 @@ impl SegmentDescriptor {
     }
 }
 
-const GDT_PLACEMENT: *mut SegmentDescriptor = 0x0010_0000 as usize as _;
+const GDT_PLACEMENT: *mut SegmentDescriptor = 0x0010_0000_usize as _;
 const GDT_ENTRY_COUNT: u16 = 8192;
 
 #[repr(transparent)]
@@ -215,7 +241,7 @@ impl InterruptGateDescriptor {
 
 // Note: {pointer}::addは使えなかった（コンパイルエラーになる）
 const IDT_PLACEMENT: *mut InterruptGateDescriptor = unsafe {
-    (core::mem::transmute::<_, usize>(GDT_PLACEMENT)
+    (core::mem::transmute::<*mut SegmentDescriptor, usize>(GDT_PLACEMENT)
         + core::mem::size_of::<SegmentDescriptor>() * GDT_ENTRY_COUNT as usize) as _
 };
 const IDT_ENTRY_COUNT: u16 = 256;
@@ -303,7 +329,7 @@ impl PageDirectoryPointerTableEntry {
         );
 
         // set with present flag
-        Self((page_directory_phys_address & !0xfff) & 0x01)
+        Self((page_directory_phys_address & !0xfff) | 0x01)
     }
 
     #[inline]
@@ -349,6 +375,18 @@ impl PageDirectoryEntry {
         Self((page_table_phys_address & !0xfff) | 0x01)
     }
 
+    /// maps a 2MiB page (2MiB aligned) directly instead of referring a page table
+    #[inline]
+    pub const fn new_large_page(page_phys_address: u64) -> Self {
+        assert!(
+            page_phys_address & 0x1f_ffff == 0,
+            "Large page is not aligned by 2M"
+        );
+
+        // set with present and page size flags
+        Self(page_phys_address | 0x81)
+    }
+
     #[inline]
     pub const fn writable(self) -> Self {
         Self(self.0 | 0x02)
@@ -434,6 +472,7 @@ impl PageTableEntry {
 fn efi_main(efi_handle: uefi::EfiHandle, system_table: *mut uefi::EfiSystemTable) {
     unsafe {
         SYSTEM_TABLE = system_table;
+        IMAGE_HANDLE = efi_handle;
     }
 
     let system_table = unsafe { &mut *system_table };
@@ -448,6 +487,9 @@ fn efi_main(efi_handle: uefi::EfiHandle, system_table: *mut uefi::EfiSystemTable
     )
     .unwrap();
 
+    let mut rsdp = None::<&acpi::RootSystemDescriptionPointer>;
+    let mut fadt = None::<&acpi::FixedDescriptionTable>;
+    let mut io_apics = alloc::vec::Vec::<apic::IOAPIC>::new();
     for cfg in system_table.configuration_table_entries() {
         writeln!(
             &mut con_out,
@@ -462,6 +504,8 @@ fn efi_main(efi_handle: uefi::EfiHandle, system_table: *mut uefi::EfiSystemTable
                 panic!("invalid rsdt signature?");
             }
             writeln!(&mut con_out, "ACPI RSDP Structure: {s:?}").unwrap();
+            rsdp = Some(s);
+            fadt = unsafe { s.find_table::<acpi::FixedDescriptionTable>() };
 
             if s.revision >= 2 {
                 // acpi 2.0
@@ -537,6 +581,10 @@ fn efi_main(efi_handle: uefi::EfiHandle, system_table: *mut uefi::EfiSystemTable
                                         s.global_system_interrupt_base
                                     )
                                     .unwrap();
+                                    io_apics.push(apic::IOAPIC::new(
+                                        s.io_apic_address as _,
+                                        s.global_system_interrupt_base,
+                                    ));
                                 }
                                 acpi::InterruptSourceOverrideStructure::TYPE => {
                                     let s = unsafe {
@@ -612,6 +660,59 @@ fn efi_main(efi_handle: uefi::EfiHandle, system_table: *mut uefi::EfiSystemTable
         }
     }
 
+    if let Some(rsdp) = rsdp {
+        fn print_table<T: SystemDescriptionTable + core::fmt::Debug>(
+            con: &mut impl Write,
+            rsdp: &acpi::RootSystemDescriptionPointer,
+        ) {
+            for t in unsafe { rsdp.find_tables::<T>() } {
+                writeln!(con, "{t:?}").unwrap();
+            }
+        }
+
+        print_table::<acpi::SystemResourceAffinityTable>(&mut con_out, rsdp);
+        print_table::<acpi::SystemLocalityInformationTable>(&mut con_out, rsdp);
+        print_table::<acpi::BootGraphicsResourceTable>(&mut con_out, rsdp);
+        print_table::<acpi::SerialPortConsoleRedirectionTable>(&mut con_out, rsdp);
+        print_table::<acpi::DebugPort2Table>(&mut con_out, rsdp);
+        print_table::<acpi::WindowsACPIEmulatedDevicesTable>(&mut con_out, rsdp);
+
+        if let Some(spcr) = unsafe { rsdp.find_table::<acpi::SerialPortConsoleRedirectionTable>() }
+        {
+            writeln!(
+                &mut con_out,
+                "- serial console: type={} address=0x{:x} baud_rate={:?} gsi={}",
+                spcr.interface_type,
+                spcr.base_address.address(),
+                spcr.baud_rate(),
+                spcr.global_system_interrupt()
+            )
+            .unwrap();
+        }
+        if let Some(bgrt) = unsafe { rsdp.find_table::<acpi::BootGraphicsResourceTable>() } {
+            if let Some(image) = unsafe { bgrt.image() } {
+                writeln!(
+                    &mut con_out,
+                    "- boot logo: {} bytes bitmap at ({}, {})",
+                    image.len(),
+                    bgrt.image_offset_x,
+                    bgrt.image_offset_y
+                )
+                .unwrap();
+            }
+        }
+    }
+    if let Some(facs) = fadt.and_then(|f| unsafe { f.facs() }) {
+        if facs.has_correct_signature() {
+            writeln!(
+                &mut con_out,
+                "FACS: {facs:?} waking_vector=0x{:016x}",
+                facs.waking_vector()
+            )
+            .unwrap();
+        }
+    }
+
     // setup hires console
     let mut gop = core::ptr::null_mut::<uefi::EfiGraphicsOutputProtocol>();
     let r = unsafe {
@@ -675,28 +776,64 @@ fn efi_main(efi_handle: uefi::EfiHandle, system_table: *mut uefi::EfiSystemTable
     )
     .unwrap();
 
-    let mut memory_map_size = 0;
-    let mut memory_map = [];
-    let mut map_key = 0;
-    let mut descriptor_size = 0;
-    let mut descriptor_version = 0;
-    let r = unsafe {
-        ((&*system_table.boot_services).get_memory_map)(
-            &mut memory_map_size,
-            memory_map.as_mut_ptr(),
-            &mut map_key,
-            &mut descriptor_size,
-            &mut descriptor_version,
-        )
+    // files the loader needs, read from the ESP while boot services are still available
+    let config = read_boot_file(&mut hrc, "\\EFI\\BOOT\\loader.cfg")
+        .map(|c| loader_config::LoaderConfig::parse(&alloc::string::String::from_utf8_lossy(&c)))
+        .unwrap_or_default();
+    for l in &config.unknown_lines {
+        writeln!(&mut hrc, "loader.cfg: ignored \"{l}\"").unwrap();
+    }
+    let kernel_image = read_boot_file(&mut hrc, &config.kernel);
+    if let Some(font) = read_boot_file(&mut hrc, "\\EFI\\BOOT\\font.bin") {
+        // Note: コンソールはローダーの最後まで使うのでリークさせて'staticにする
+        if !hrc.set_font(font.leak()) {
+            writeln!(
+                &mut hrc,
+                "font.bin: not an 8x12 font, keeping the built-in one"
+            )
+            .unwrap();
+        }
+    }
+    let modules = load_modules(&mut hrc, &config.modules);
+    // Note: virtio-gpuへ切り替えた後はこのフレームバッファ（GOP）の内容は表示されなくなる
+    let framebuffer = boot_info::Framebuffer {
+        base: gop.mode().frame_buffer_base,
+        size: gop.mode().frame_buffer_size as _,
+        width: mode_info.horizontal_resolution,
+        height: mode_info.vertical_resolution,
+        stride: mode_info.pixels_per_scan_line,
+        pixel_format: mode_info.pixel_format as _,
+        pixel_bitmask: [
+            mode_info.pixel_information.red,
+            mode_info.pixel_information.green,
+            mode_info.pixel_information.blue,
+            mode_info.pixel_information.reserved,
+        ],
     };
-    if r != 0 && r != 0x8000_0000_0000_0005 {
-        panic!("GetMemoryMap failed: 0x{r:016x}");
+    let mut kernel = None;
+    if let Some(image) = kernel_image.as_deref() {
+        match multiboot2::Header::find(image) {
+            Some(header) => {
+                let Err(e) = header.and_then(|header| {
+                    boot_multiboot2(
+                        &mut hrc,
+                        system_table,
+                        efi_handle,
+                        image,
+                        &header,
+                        &config,
+                        modules,
+                        &framebuffer,
+                        rsdp,
+                    )
+                });
+                writeln!(&mut hrc, "failed to boot the Multiboot2 kernel: {e:?}").unwrap();
+            }
+            None => kernel = prepare_kernel(&mut hrc, image, &mut paging::UefiFrameAllocator),
+        }
     }
 
-    let r = unsafe { ((&*system_table.boot_services).exit_boot_services)(efi_handle, map_key) };
-    if r != 0 {
-        panic!("ExitBootServices failed: 0x{r:016x}");
-    }
+    let memory_map = exit_boot_services(system_table, efi_handle);
 
     unsafe {
         cli!();
@@ -724,7 +861,7 @@ fn efi_main(efi_handle: uefi::EfiHandle, system_table: *mut uefi::EfiSystemTable
         unimplemented!("pcid support");
     }
 
-    // TODO: ページング再設定するならちゃんとカーネル切り離してロードしたほうがいい（OS Loader自体がどこに入るのかがこっちからはわからないからコードページ設定できない）
+    // Note: ここではページングを触らない。カーネルは別にロードしておき、最後にカーネル用のページテーブルへ切り替える（ローダ自身は恒等マップのまま残す）
 
     let global_descriptor_table =
         unsafe { core::slice::from_raw_parts_mut(GDT_PLACEMENT, GDT_ENTRY_COUNT as _) };
@@ -760,270 +897,1033 @@ fn efi_main(efi_handle: uefi::EfiHandle, system_table: *mut uefi::EfiSystemTable
     )
     .privilege_level(0)
     .size_32bit();
+    for v in interrupt::FIRST_EXTERNAL_VECTOR..=u8::MAX {
+        interrupt_descriptor_table[v as usize] = InterruptGateDescriptor::new_interrupt(
+            SegmentSelector::global(1).requested_privilege_level(0),
+            interrupt::stub_address(v),
+        )
+        .privilege_level(0)
+        .size_32bit();
+    }
     unsafe {
         lidt!(IDT_PLACEMENT, IDT_ENTRY_COUNT - 1);
     }
 
-    struct LocalAPIC {
-        base_address: usize,
+    let local_apic = apic::LocalAPIC::get();
+    writeln!(
+        &mut hrc,
+        "local apic base: 0x{:016x}",
+        local_apic.base_address()
+    )
+    .unwrap();
+    let v = local_apic.read_version_register();
+    writeln!(&mut hrc, "local apic version: 0x{v:08x}").unwrap();
+    local_apic.enable(interrupt::SPURIOUS_VECTOR);
+
+    if let Some(table) =
+        rsdp.and_then(|s| unsafe { s.find_table::<acpi::HighPrecisionEventTimerTable>() })
+    {
+        test_hpet(&mut hrc, table, &local_apic, &io_apics);
     }
-    impl LocalAPIC {
-        pub fn get(con: &mut impl Write) -> Self {
-            let apic_base = unsafe { rdmsr!(0x1b) };
-            writeln!(con, "apic_base register: 0x{apic_base:016x}").unwrap();
 
-            Self {
-                base_address: apic_base as usize & !0xfff,
-            }
+    if let Some(mcfg) =
+        rsdp.and_then(|s| unsafe { s.find_table::<acpi::MemoryMappedConfigurationTable>() })
+    {
+        for e in mcfg.entries() {
+            writeln!(&mut hrc, "MCFG: {e:?}").unwrap();
         }
 
-        pub fn read_version_register(&self) -> u32 {
-            unsafe { core::ptr::read_volatile((self.base_address + 0x30) as *const u32) }
+        let ecam = alloc::boxed::Box::leak(alloc::boxed::Box::new(
+            pci::EcamConfigAccess::from_mcfg(mcfg),
+        ));
+        unsafe {
+            pci::set_config_access(ecam);
+        }
+    }
+    let host_bridge = pci::DeviceIdentifier {
+        segment: 0,
+        bus: 0,
+        device: 0,
+        function: 0,
+    };
+    writeln!(
+        &mut hrc,
+        "PCI configuration space size: {} bytes",
+        host_bridge.config_space_size()
+    )
+    .unwrap();
+    let pci_devices = pci::enumerate();
+    pci_ids::write_tree(&mut hrc, &pci_devices).unwrap();
+    for d in &pci_devices {
+        let status = d.id.read_status();
+        writeln!(
+            &mut hrc,
+            "pci {}: {:04x}:{:04x} class={:02x}:{:02x}:{:02x} rev={:02x} {:?} cmd={:?} st={:?}",
+            d.id,
+            d.ids.vendor_id,
+            d.ids.device_id,
+            d.class_code.class,
+            d.class_code.subclass,
+            d.class_code.prog_if,
+            d.revision_id,
+            d.header_type,
+            d.id.read_command(),
+            status
+        )
+        .unwrap();
+        if status.has_error() {
+            // Note: ファームウェアが残したエラービットを消しておく（以降のエラーを見分けられるように）
+            d.id.clear_status(pci::Status::ERRORS);
+        }
+        for (n, bar) in d.id.bars() {
+            writeln!(&mut hrc, "  - bar #{n}: {bar:?}").unwrap();
+        }
+        for c in d.id.capabilities() {
+            match c {
+                pci::Capability::VendorSpecific(v) => match virtio::PCICapability::decode(&v) {
+                    Some(v) => writeln!(&mut hrc, "  - cap: virtio {v:?}").unwrap(),
+                    None => {
+                        writeln!(&mut hrc, "  - cap: vendor specific len={}", v.length).unwrap()
+                    }
+                },
+                pci::Capability::MsiX(m) => writeln!(
+                    &mut hrc,
+                    "  - cap: MSI-X size={} table={:?} pba={:?}",
+                    m.table_size(),
+                    m.table_location(),
+                    m.pending_bit_array_location()
+                )
+                .unwrap(),
+                pci::Capability::Msi(m) => writeln!(
+                    &mut hrc,
+                    "  - cap: MSI 64bit={} vectors={} masking={}",
+                    m.is_64bit(),
+                    m.multiple_message_capable(),
+                    m.per_vector_masking()
+                )
+                .unwrap(),
+                pci::Capability::PowerManagement(p) => writeln!(
+                    &mut hrc,
+                    "  - cap: PM v{} D{}",
+                    p.version(),
+                    p.power_state()
+                )
+                .unwrap(),
+                pci::Capability::PciExpress(p) => {
+                    let (speed, width) = p.link_status();
+                    writeln!(
+                        &mut hrc,
+                        "  - cap: PCIe v{} port_type={} link=x{width} speed={speed}",
+                        p.version(),
+                        p.device_port_type()
+                    )
+                    .unwrap()
+                }
+                pci::Capability::Other { id, offset } => {
+                    writeln!(&mut hrc, "  - cap: 0x{id:02x} at 0x{offset:02x}").unwrap()
+                }
+            }
+        }
+        if let Some(t) = virtio::pci::Transport::new(d.id) {
+            writeln!(
+                &mut hrc,
+                "  - virtio: type={} {:?} queues={} status={:?} config_len={}",
+                t.device_type(),
+                t.device_features(),
+                t.num_queues(),
+                t.device_status(),
+                t.device_config_length()
+            )
+            .unwrap();
+        }
+        for c in d.id.extended_capabilities() {
+            writeln!(
+                &mut hrc,
+                "  - ext cap: {} (0x{:04x}) v{} at 0x{:03x}",
+                c.name(),
+                c.id,
+                c.version,
+                c.offset
+            )
+            .unwrap();
         }
     }
 
-    let local_apic = LocalAPIC::get(&mut hrc);
-    let v = local_apic.read_version_register();
-    writeln!(&mut hrc, "local apic version: 0x{v:08x}").unwrap();
-
-    loop {}
+    let _secondary_consoles = switch_to_virtio_gpu(&mut hrc, &pci_devices);
+    let block_devices = probe_virtio_block(&mut hrc, &pci_devices);
+    let partitions = scan_partitions(&mut hrc, block_devices);
+    let mut data_volume = mount_fat(&mut hrc, partitions);
 
-    let mut gop = core::ptr::null_mut::<uefi::EfiGraphicsOutputProtocol>();
-    let r = unsafe {
-        ((&*system_table.boot_services).locate_protocol)(
-            &uefi::EfiGraphicsOutputProtocol::GUID,
-            core::ptr::null(),
-            &mut gop as *mut _ as _,
-        )
-    };
-    if r != 0 {
-        panic!("Failed to locate gop: {r}");
+    if kernel.is_none() {
+        if let Some(fs) = data_volume.as_mut() {
+            match fs.read_file("/kernel.elf") {
+                Ok(image) => {
+                    kernel = prepare_kernel(&mut hrc, &image, &mut paging::HeapFrameAllocator)
+                }
+                Err(fat::FatError::NotFound) => (),
+                Err(e) => writeln!(&mut hrc, "/kernel.elf: {e:?}").unwrap(),
+            }
+        }
     }
-    let gop = unsafe { &mut *gop };
+    if let Some(k) = kernel {
+        let info = boot_info::BootInfo {
+            framebuffer,
+            memory_map,
+            rsdp_address: rsdp.map_or(0, |r| r as *const _ as u64),
+            smbios_address: find_configuration_table(
+                system_table,
+                &uefi::EfiConfigurationTable::SMBIOS_GUID,
+            ),
+            smbios3_address: find_configuration_table(
+                system_table,
+                &uefi::EfiConfigurationTable::SMBIOS3_GUID,
+            ),
+            command_line_address: config.command_line.as_ptr() as _,
+            command_line_length: config.command_line.len() as _,
+            modules_address: modules.as_ptr() as _,
+            module_count: modules.len() as _,
+            ..boot_info::BootInfo::new(k.boot_info.kernel)
+        };
+        enter_kernel(&mut hrc, k, info);
+    }
+
+    let Some(fadt) = fadt else {
+        writeln!(&mut hrc, "no FADT found: cannot power off").unwrap();
+        loop {
+            unsafe { hlt!() }
+        }
+    };
 
-    let current_info = gop.mode().info();
-    writeln!(&mut con_out, "current graphics mode:").unwrap();
+    let mut aml = aml::Interpreter::new(acpi::AmlPlatformHandler);
+    let dsdt = unsafe { fadt.dsdt_table() };
+    if let Err(e) = aml.load_table(dsdt.as_bytes()) {
+        writeln!(&mut hrc, "Failed to load DSDT: {e:?}").unwrap();
+    }
+    if let Some(rsdp) = rsdp {
+        for ssdt in unsafe { rsdp.find_tables::<acpi::SecondarySystemDescriptionTable>() } {
+            if let Err(e) = aml.load_table(ssdt.as_bytes()) {
+                writeln!(&mut hrc, "Failed to load SSDT: {e:?}").unwrap();
+            }
+        }
+    }
+    for e in aml.load_errors() {
+        writeln!(&mut hrc, "Skipped an AML term: {e:?}").unwrap();
+    }
+    if let Err(e) = aml.initialize_devices() {
+        writeln!(&mut hrc, "Failed to initialize devices: {e:?}").unwrap();
+    }
     writeln!(
-        &mut con_out,
-        "- res: {}x{}",
-        current_info.horizontal_resolution, current_info.vertical_resolution
+        &mut hrc,
+        "AML namespace loaded: {} devices",
+        aml.namespace().devices().count()
     )
     .unwrap();
+
+    // Note: 割り込みはIOAPIC経由で受けるので、_PRTがGSIを返すようにAPICモードを通知する
+    match aml.evaluate_str("\\_PIC", alloc::vec![aml::object::Object::Integer(1)]) {
+        Ok(_) | Err(aml::AmlError::NameNotFound(_)) => (),
+        Err(e) => writeln!(&mut hrc, "Failed to evaluate \\_PIC: {e:?}").unwrap(),
+    }
+    dump_pci_root_bridges(&mut hrc, &mut aml, &pci_devices);
+
+    let s5 = match aml.sleep_types(5) {
+        Ok(x) => x,
+        Err(e) => {
+            writeln!(&mut hrc, "Failed to evaluate \\_S5: {e:?}").unwrap();
+            let Some(x) = dsdt.find_s5_sleep_types() else {
+                panic!("no \\_S5 object found in DSDT");
+            };
+            x
+        }
+    };
+    writeln!(&mut hrc, "shutting down...").unwrap();
+    unsafe { acpi::shutdown(fadt, s5) }
+}
+
+const KERNEL_STACK_SIZE: usize = 64 * 1024;
+/// identity mapped in the kernel page table from the start
+/// (the rest of the memory map and the framebuffer are added at the handoff)
+const LOADER_IDENTITY_MAP_END: u64 = 4 * 1024 * 1024 * 1024;
+
+/// A kernel loaded into memory with its page table, ready to be entered
+struct KernelHandoff {
+    address_space: paging::AddressSpace,
+    entry: u64,
+    stack_top: u64,
+    boot_info: &'static mut boot_info::BootInfo,
+}
+
+/// Loads an ELF kernel with frames from `allocator` and builds the page table to enter it with
+fn prepare_kernel(
+    hrc: &mut HiResConsole,
+    image: &[u8],
+    allocator: &mut dyn paging::FrameAllocator,
+) -> Option<KernelHandoff> {
+    match load_kernel(image, allocator) {
+        Ok((k, loaded)) => {
+            writeln!(
+                hrc,
+                "kernel: {} bytes at 0x{:x} mapped to 0x{:016x}, entry=0x{:016x}",
+                loaded.size, loaded.physical_base, loaded.virtual_base, loaded.entry
+            )
+            .unwrap();
+            for s in &loaded.segments {
+                writeln!(
+                    hrc,
+                    "  - 0x{:016x} {:?} {} bytes",
+                    s.virtual_address, s.flags, s.memory_size
+                )
+                .unwrap();
+            }
+
+            Some(k)
+        }
+        Err(e) => {
+            writeln!(hrc, "failed to load the kernel: {e:?}").unwrap();
+            None
+        }
+    }
+}
+
+fn load_kernel(
+    image: &[u8],
+    allocator: &mut dyn paging::FrameAllocator,
+) -> Result<(KernelHandoff, elf::LoadedImage), elf::ElfError> {
+    let loaded = elf::ElfFile::parse(image)?.load(allocator)?;
+
+    let mut address_space = paging::AddressSpace::new(allocator)?;
+    address_space.identity_map(0, LOADER_IDENTITY_MAP_END, allocator)?;
+    loaded.map(&mut address_space, allocator)?;
+
+    let stack_pages = KERNEL_STACK_SIZE / paging::PAGE_SIZE as usize;
+    let stack_base = allocator.allocate_frames(stack_pages)?;
+    let boot_info = allocator.allocate_frames(1)? as *mut boot_info::BootInfo;
+    let boot_info = unsafe {
+        boot_info.write(boot_info::BootInfo::new(boot_info::KernelLayout {
+            physical_base: loaded.physical_base,
+            virtual_base: loaded.virtual_base,
+            size: loaded.size,
+            relocation_offset: loaded.bias,
+            page_table: address_space.root().0,
+            stack_base,
+            stack_size: KERNEL_STACK_SIZE as _,
+        }));
+        &mut *boot_info
+    };
+
+    let k = KernelHandoff {
+        address_space,
+        entry: loaded.entry,
+        stack_top: stack_base + KERNEL_STACK_SIZE as u64,
+        boot_info,
+    };
+
+    Ok((k, loaded))
+}
+
+/// Fills the boot information and switches to the kernel
+fn enter_kernel(hrc: &mut HiResConsole, k: KernelHandoff, info: boot_info::BootInfo) -> ! {
+    // Note: 4GiBより上のRAMとフレームバッファも恒等マップに加える（ページテーブルはもうヒープから取るしかない）
+    let mut address_space = k.address_space;
+    let fb = &info.framebuffer;
+    let extended = address_space
+        .identity_map(
+            LOADER_IDENTITY_MAP_END,
+            info.memory_map.end(),
+            &mut paging::HeapFrameAllocator,
+        )
+        .and_then(|_| {
+            address_space.identity_map(
+                fb.base.max(LOADER_IDENTITY_MAP_END),
+                fb.base + fb.size,
+                &mut paging::HeapFrameAllocator,
+            )
+        });
+    if let Err(e) = extended {
+        panic!("failed to map the memory for the kernel: {e:?}");
+    }
+
     writeln!(
-        &mut con_out,
-        "- pixel format: {:?} bitmask={:?}",
-        current_info.pixel_format, current_info.pixel_information
+        hrc,
+        "jumping to the kernel at 0x{:016x} (memory map: {} entries, {} modules, cmdline: {:?})...",
+        k.entry,
+        info.memory_map.descriptor_count,
+        info.module_count,
+        unsafe {
+            core::str::from_utf8_unchecked(core::slice::from_raw_parts(
+                info.command_line_address as *const u8,
+                info.command_line_length as _,
+            ))
+        }
     )
     .unwrap();
+
+    *k.boot_info = info;
+    unsafe {
+        paging::enter(
+            address_space.root(),
+            k.entry,
+            k.stack_top,
+            k.boot_info as *mut _ as u64,
+        )
+    }
+}
+
+/// Loads a Multiboot2 kernel and enters it (in 64-bit mode with boot services running when it asks
+/// for that, in 32-bit protected mode after ExitBootServices otherwise). Returns only on failure.
+#[allow(clippy::too_many_arguments)]
+fn boot_multiboot2(
+    hrc: &mut HiResConsole,
+    system_table: &mut uefi::EfiSystemTable,
+    image_handle: uefi::EfiHandle,
+    image: &[u8],
+    header: &multiboot2::Header,
+    config: &loader_config::LoaderConfig,
+    modules: &[boot_info::Module],
+    framebuffer: &boot_info::Framebuffer,
+    rsdp: Option<&acpi::RootSystemDescriptionPointer>,
+) -> Result<core::convert::Infallible, multiboot2::Multiboot2Error> {
+    let loaded = multiboot2::load(image, header)?;
     writeln!(
-        &mut con_out,
-        "- pixels per scan-line: {}",
-        current_info.pixels_per_scan_line
+        hrc,
+        "multiboot2 kernel: loaded at 0x{:x}, entry=0x{:x}{}, requests={:?}",
+        loaded.load_base,
+        loaded.entry,
+        if header.enters_with_boot_services() {
+            " (EFI amd64)"
+        } else {
+            ""
+        },
+        header.requests
     )
     .unwrap();
 
-    writeln!(&mut con_out, "Enumerating graphics mode:").unwrap();
-    for n in 0..gop.mode().max_mode {
-        let mut info = core::ptr::null_mut::<uefi::EfiGraphicsOutputModeInformation>();
-        let mut info_size = core::mem::size_of::<uefi::EfiGraphicsOutputModeInformation>();
-        let r = gop.query_mode(n, &mut info_size, &mut info);
+    let mut info = multiboot2::InfoBuilder::new()?;
+    info.command_line(&config.command_line);
+    info.boot_loader_name(env!("CARGO_PKG_NAME"));
+    for m in modules {
+        let name = core::str::from_utf8(&m.name).unwrap_or_default();
+        info.module(m.address, m.address + m.size, name.trim_end_matches('\0'));
+    }
+    info.load_base(loaded.load_base);
+    info.framebuffer(framebuffer);
+    info.efi_system_table(system_table as *mut _ as u64);
+    if let Some(rsdp) = rsdp {
+        info.acpi(rsdp);
+    }
+    for guid in [
+        uefi::EfiConfigurationTable::SMBIOS3_GUID,
+        uefi::EfiConfigurationTable::SMBIOS_GUID,
+    ] {
+        let address = find_configuration_table(system_table, &guid);
+        if address != 0 {
+            info.smbios(address);
+        }
+    }
+
+    if header.enters_with_boot_services() {
+        // Note: ブートサービスを使い続けるカーネルはメモリマップを自分で取るので、mmapタグは渡さない
+        info.efi_image_handle(image_handle as u64);
+        info.efi_boot_services_not_terminated();
+        let info = info.finish();
+        writeln!(hrc, "jumping to the Multiboot2 kernel...").unwrap();
+        unsafe { multiboot2::enter_efi_amd64(loaded.entry, info) }
+    }
+
+    let trampoline = multiboot2::Trampoline::new()?;
+    let memory_map = exit_boot_services(system_table, image_handle);
+    unsafe {
+        cli!();
+    }
+    info.memory_map(&memory_map);
+    let info = info.finish();
+
+    // Note: UEFIのページテーブルはLoaderDataを実行不可にしていることがあるので、トランポリン用に恒等マップを作り直す
+    let mut address_space = paging::AddressSpace::new(&mut paging::HeapFrameAllocator)?;
+    address_space.identity_map(
+        0,
+        memory_map.end().max(LOADER_IDENTITY_MAP_END),
+        &mut paging::HeapFrameAllocator,
+    )?;
+    writeln!(hrc, "jumping to the Multiboot2 kernel...").unwrap();
+    unsafe { trampoline.enter(address_space.root(), loaded.entry, info) }
+}
+
+/// Loads the `module =` files of loader.cfg into pages of their own (below 4GiB for Multiboot2)
+fn load_modules(
+    hrc: &mut HiResConsole,
+    paths: &[alloc::string::String],
+) -> &'static [boot_info::Module] {
+    use paging::FrameAllocator;
+
+    let mut modules = alloc::vec::Vec::new();
+    for path in paths {
+        let Some(data) = read_boot_file(hrc, path) else {
+            continue;
+        };
+
+        let pages = data.len().div_ceil(paging::PAGE_SIZE as usize).max(1);
+        let address = match paging::LowUefiFrameAllocator.allocate_frames(pages) {
+            Ok(a) => a,
+            Err(e) => {
+                writeln!(hrc, "{path}: {e:?}").unwrap();
+                continue;
+            }
+        };
+        unsafe {
+            core::ptr::copy_nonoverlapping(data.as_ptr(), address as *mut u8, data.len());
+        }
+        modules.push(boot_info::Module::new(address, data.len() as _, path));
+    }
+
+    modules.leak()
+}
+
+/// Prints the resources (_CRS) of each PCI root bridge in the AML namespace and
+/// the interrupt routing (_PRT) of the devices found on its bus
+fn dump_pci_root_bridges(
+    hrc: &mut HiResConsole,
+    aml: &mut aml::Interpreter<acpi::AmlPlatformHandler>,
+    pci_devices: &[pci::Device],
+) {
+    use aml::object::Object;
+
+    let devices = aml
+        .namespace()
+        .devices()
+        .cloned()
+        .collect::<alloc::vec::Vec<_>>();
+    for d in devices {
+        let hid = match aml.evaluate_child(&d, b"_HID") {
+            Ok(Some(Object::Integer(id))) => aml::decode_eisa_id(id as u32),
+            _ => continue,
+        };
+        // PCI Bus / PCI Express Bus
+        if &hid != b"PNP0A03" && &hid != b"PNP0A08" {
+            continue;
+        }
+        let bus = match aml.evaluate_child(&d, b"_BBN") {
+            Ok(Some(Object::Integer(x))) => x as u8,
+            _ => 0,
+        };
+        writeln!(hrc, "PCI root bridge {d}: bus {bus:02x}").unwrap();
+
+        match aml.evaluate_child(&d, b"_CRS") {
+            Ok(Some(Object::Buffer(b))) => match aml::resource::parse(&b.borrow()) {
+                Ok(resources) => {
+                    for r in resources {
+                        writeln!(hrc, "- {r:?}").unwrap();
+                    }
+                }
+                Err(e) => writeln!(hrc, "- failed to decode _CRS: {e:?}").unwrap(),
+            },
+            Ok(_) => (),
+            Err(e) => writeln!(hrc, "- failed to evaluate _CRS: {e:?}").unwrap(),
+        }
+
+        let prt = match aml.pci_routing_table(&d) {
+            Ok(x) => x,
+            Err(e) => {
+                writeln!(hrc, "- failed to evaluate _PRT: {e:?}").unwrap();
+                continue;
+            }
+        };
+        let mut device_numbers = pci_devices
+            .iter()
+            .filter(|x| x.id.bus == bus)
+            .map(|x| x.id.device as u16)
+            .collect::<alloc::vec::Vec<_>>();
+        device_numbers.dedup();
+        for n in device_numbers {
+            write!(hrc, "- _PRT {bus:02x}:{n:02x}:").unwrap();
+            for e in prt.iter().filter(|e| e.device == n) {
+                let pin = (b'A' + e.pin) as char;
+                match &e.source {
+                    aml::PciRoutingSource::GlobalSystemInterrupt(gsi) => {
+                        write!(hrc, " INT{pin}=GSI{gsi}").unwrap()
+                    }
+                    aml::PciRoutingSource::Link(link, 0) => {
+                        write!(hrc, " INT{pin}={link}").unwrap()
+                    }
+                    aml::PciRoutingSource::Link(link, index) => {
+                        write!(hrc, " INT{pin}={link}[{index}]").unwrap()
+                    }
+                }
+            }
+            writeln!(hrc).unwrap();
+        }
+    }
+}
+
+fn find_configuration_table(system_table: &uefi::EfiSystemTable, guid: &uefi::EfiGuid) -> u64 {
+    system_table
+        .configuration_table_entries()
+        .iter()
+        .find(|c| c.vendor_guid == *guid)
+        .map_or(0, |c| c.vendor_table as u64)
+}
+
+/// Gets the final memory map and exits boot services
+fn exit_boot_services(
+    system_table: &mut uefi::EfiSystemTable,
+    image_handle: uefi::EfiHandle,
+) -> boot_info::MemoryMap {
+    const EFI_BUFFER_TOO_SMALL: uefi::EfiStatus = 0x8000_0000_0000_0005;
+    const RETRY_COUNT: usize = 4;
+
+    let boot_services = unsafe { &*system_table.boot_services };
+    let mut size = 0;
+    let mut map_key = 0;
+    let mut descriptor_size = 0;
+    let mut descriptor_version = 0;
+    let r = (boot_services.get_memory_map)(
+        &mut size,
+        core::ptr::null_mut(),
+        &mut map_key,
+        &mut descriptor_size,
+        &mut descriptor_version,
+    );
+    if r != EFI_BUFFER_TOO_SMALL {
+        panic!("GetMemoryMap failed: 0x{r:016x}");
+    }
+
+    // Note: バッファはUEFIを経由しないヒープから取るのでマップ自体は増えないが、失敗時の再取得に備えて余裕を持たせる
+    let buffer = alloc::vec![0u64; (size + descriptor_size * 8).div_ceil(8)].leak();
+    for _ in 0..RETRY_COUNT {
+        size = buffer.len() * 8;
+        let r = (boot_services.get_memory_map)(
+            &mut size,
+            buffer.as_mut_ptr() as _,
+            &mut map_key,
+            &mut descriptor_size,
+            &mut descriptor_version,
+        );
         if r != 0 {
-            panic!("Failed to query mode: {r}");
+            panic!("GetMemoryMap failed: 0x{r:016x}");
         }
 
-        let info = unsafe { &*info };
+        // Note: 取得からExitBootServicesまでの間にイベント等でマップが変わると失敗するので、取り直す
+        let r = (boot_services.exit_boot_services)(image_handle, map_key);
+        if r == 0 {
+            system_table.boot_services = core::ptr::null_mut();
+
+            return boot_info::MemoryMap::new(
+                buffer.as_ptr() as _,
+                (size / descriptor_size) as _,
+                descriptor_size as _,
+                descriptor_version,
+            );
+        }
+    }
+
+    panic!("ExitBootServices failed");
+}
+
+fn read_boot_file(hrc: &mut HiResConsole, path: &str) -> Option<alloc::vec::Vec<u8>> {
+    match fs::read_file(path) {
+        Ok(data) => {
+            writeln!(hrc, "{path}: {} bytes", data.len()).unwrap();
+            Some(data)
+        }
+        Err(e) => {
+            writeln!(hrc, "{path}: {e:?}").unwrap();
+            None
+        }
+    }
+}
+
+/// Moves `hrc` to the first virtio-gpu display and opens a console on each of the others,
+/// all at their native (EDID preferred) resolution. Returns the secondary consoles.
+fn switch_to_virtio_gpu(
+    hrc: &mut HiResConsole,
+    devices: &[pci::Device],
+) -> alloc::vec::Vec<HiResConsole> {
+    use virtio::{
+        gpu::{ConsoleFlush, GpuDevice},
+        VirtioDevice,
+    };
+
+    let Some(transport) = devices.iter().find_map(|d| {
+        let t = virtio::pci::Transport::new(d.id)?;
+        (t.device_type() == GpuDevice::DEVICE_TYPE).then_some(t)
+    }) else {
+        return alloc::vec::Vec::new();
+    };
+    let device = transport.device();
+
+    let mut gpu = match virtio::initialize::<GpuDevice>(transport) {
+        Ok(g) => g,
+        Err(e) => {
+            writeln!(hrc, "virtio-gpu {device}: initialization failed: {e:?}").unwrap();
+            return alloc::vec::Vec::new();
+        }
+    };
+    let displays = match gpu.display_info() {
+        Ok(d) => d,
+        Err(e) => {
+            writeln!(hrc, "virtio-gpu {device}: GET_DISPLAY_INFO failed: {e:?}").unwrap();
+            return alloc::vec::Vec::new();
+        }
+    };
+    writeln!(hrc, "virtio-gpu {device}: displays={displays:?}").unwrap();
+
+    let mut framebuffers = alloc::vec::Vec::new();
+    for display in &displays {
+        let edid = match gpu.edid(display.scanout_id) {
+            Ok(e) => e,
+            Err(e) => {
+                writeln!(hrc, "virtio-gpu {device}: GET_EDID failed: {e:?}").unwrap();
+                None
+            }
+        };
+        if let Some(e) = &edid {
+            let (size_mm, pixel_clock) = e
+                .preferred_timing
+                .map_or(((0, 0), 0), |t| ((t.width_mm, t.height_mm), t.pixel_clock));
+            writeln!(
+                hrc,
+                "  - scanout {}: {} {:04x} EDID {}.{} native={:?} clock={}kHz size={}x{}mm ({}x{}cm)",
+                display.scanout_id,
+                core::str::from_utf8(&e.manufacturer).unwrap_or("???"),
+                e.product_code,
+                e.version.0,
+                e.version.1,
+                e.preferred_resolution(),
+                pixel_clock,
+                size_mm.0,
+                size_mm.1,
+                e.screen_size_cm.0,
+                e.screen_size_cm.1
+            )
+            .unwrap();
+        }
+
+        let (width, height) = edid
+            .and_then(|e| e.preferred_resolution())
+            .unwrap_or((display.rect.width, display.rect.height));
+        match gpu.create_framebuffer(display.scanout_id, width, height) {
+            Ok(f) => framebuffers.push(f),
+            Err(e) => writeln!(
+                hrc,
+                "virtio-gpu {device}: framebuffer setup failed on scanout {}: {e:?}",
+                display.scanout_id
+            )
+            .unwrap(),
+        }
+    }
+    if framebuffers.is_empty() {
+        return alloc::vec::Vec::new();
+    }
+
+    let cursor = ARROW_BITMAP.as_flattened().iter().map(|c| match c {
+        b'@' => [0, 0, 0, 255],
+        b'.' => [255, 255, 255, 255],
+        _ => [0, 0, 0, 0],
+    });
+    if let Err(e) = gpu.set_cursor_image(
+        &framebuffers[0].0,
+        &cursor.collect::<alloc::vec::Vec<_>>(),
+        16,
+        16,
+        (0, 0),
+    ) {
+        writeln!(hrc, "virtio-gpu {device}: cursor setup failed: {e:?}").unwrap();
+    }
+
+    // Note: 全ディスプレイのコンソールで同じデバイス（制御キュー）を共有する
+    let gpu = alloc::rc::Rc::new(core::cell::RefCell::new(gpu));
+    let font = hrc.font();
+    let mut consoles = framebuffers.into_iter().map(|(scanout, pixels)| {
+        let mut con = HiResConsole::new(pixels, scanout.width, scanout.height);
+        con.set_font(font);
+        con.set_flush_target(alloc::boxed::Box::new(ConsoleFlush {
+            gpu: gpu.clone(),
+            scanout,
+        }));
         writeln!(
-            &mut con_out,
-            "- #{n}: {}x{} {:?} {:?} {}",
-            info.horizontal_resolution,
-            info.vertical_resolution,
-            info.pixel_format,
-            info.pixel_information,
-            info.pixels_per_scan_line
+            con,
+            "HiResConsole moved to virtio-gpu {device}: scanout={} {}x{}",
+            scanout.scanout_id, scanout.width, scanout.height
+        )
+        .unwrap();
+        con
+    });
+
+    *hrc = consoles.next().unwrap();
+    consoles.collect()
+}
+
+/// Brings up every virtio-blk device
+fn probe_virtio_block(
+    hrc: &mut HiResConsole,
+    devices: &[pci::Device],
+) -> alloc::vec::Vec<virtio::blk::BlkDevice> {
+    use block::BlockDevice;
+    use virtio::{blk::BlkDevice, VirtioDevice};
+
+    let mut block_devices = alloc::vec::Vec::new();
+    for transport in devices.iter().filter_map(|d| {
+        let t = virtio::pci::Transport::new(d.id)?;
+        (t.device_type() == BlkDevice::DEVICE_TYPE).then_some(t)
+    }) {
+        let device = transport.device();
+        let mut blk = match virtio::initialize::<BlkDevice>(transport) {
+            Ok(b) => b,
+            Err(e) => {
+                writeln!(hrc, "virtio-blk {device}: initialization failed: {e:?}").unwrap();
+                continue;
+            }
+        };
+
+        writeln!(
+            hrc,
+            "virtio-blk {device}: serial={:?} {} blocks x {} bytes ({} MiB) queues={} ro={}",
+            blk.serial().unwrap_or_default(),
+            blk.block_count(),
+            blk.block_size(),
+            blk.capacity() >> 20,
+            blk.queue_count(),
+            blk.is_read_only()
         )
         .unwrap();
+        match blk.read_to_vec(0, 1) {
+            Ok(b) => writeln!(
+                hrc,
+                "  - LBA 0: boot signature={:02x}{:02x}",
+                b[510], b[511]
+            )
+            .unwrap(),
+            Err(e) => writeln!(hrc, "  - LBA 0: read failed: {e:?}").unwrap(),
+        }
+
+        block_devices.push(blk);
     }
 
-    // let r = unsafe { ((*gop).set_mode)(gop, 0) };
-    // if r != 0 {
-    //     panic!("Unable to set graphics mode: {r}");
-    // }
-
-    let mut arrow_blt_buffer = [uefi::EfiGraphicsOutputBltPixel {
-        red: 0,
-        green: 0,
-        blue: 0,
-        reserved: 0,
-    }; 16 * 16];
-    for (y, r) in ARROW_BITMAP.into_iter().enumerate() {
-        for (x, c) in r.into_iter().enumerate() {
-            arrow_blt_buffer[x + y * 16] = match c {
-                b'@' => uefi::EfiGraphicsOutputBltPixel {
-                    red: 0,
-                    green: 0,
-                    blue: 0,
-                    reserved: 255,
-                },
-                b'.' => uefi::EfiGraphicsOutputBltPixel {
-                    red: 255,
-                    green: 255,
-                    blue: 255,
-                    reserved: 255,
-                },
-                _ => uefi::EfiGraphicsOutputBltPixel {
-                    red: 0,
-                    green: 128,
-                    blue: 128,
-                    reserved: 255,
-                },
-            };
+    block_devices
+}
+
+/// Reads the partition table of each disk and returns all partitions as block devices
+fn scan_partitions(
+    hrc: &mut HiResConsole,
+    disks: alloc::vec::Vec<virtio::blk::BlkDevice>,
+) -> alloc::vec::Vec<partition::Partition<virtio::blk::BlkDevice>> {
+    let mut partitions = alloc::vec::Vec::new();
+    for (n, disk) in disks.into_iter().enumerate() {
+        let disk = alloc::rc::Rc::new(core::cell::RefCell::new(disk));
+        let (table, parts) = match partition::partitions(&disk) {
+            Ok(p) => p,
+            Err(e) => {
+                writeln!(hrc, "disk {n}: no partition table: {e:?}").unwrap();
+                continue;
+            }
+        };
+
+        match &table.scheme {
+            partition::PartitioningScheme::Gpt {
+                header,
+                used_backup,
+            } => writeln!(
+                hrc,
+                "disk {n}: GPT disk_guid={} entries={} usable={}..={} alternate={}{}",
+                header.disk_guid,
+                header.number_of_partition_entries,
+                header.first_usable_lba,
+                header.last_usable_lba,
+                header.alternate_lba,
+                if *used_backup {
+                    " (primary header corrupted, using backup)"
+                } else {
+                    ""
+                }
+            ),
+            partition::PartitioningScheme::Mbr { disk_signature } => {
+                writeln!(hrc, "disk {n}: MBR disk_signature={disk_signature:08x}")
+            }
+        }
+        .unwrap();
+        for e in &table.entries {
+            match &e.kind {
+                partition::PartitionKind::Gpt {
+                    type_guid,
+                    unique_guid,
+                    attributes,
+                    ..
+                } => writeln!(
+                    hrc,
+                    "  - #{} {:?} type={type_guid} lba={}..={} unique={unique_guid} attributes={attributes:016x}",
+                    e.index,
+                    e.name(),
+                    e.starting_lba,
+                    e.ending_lba,
+                ),
+                partition::PartitionKind::Mbr {
+                    system_id,
+                    bootable,
+                } => writeln!(
+                    hrc,
+                    "  - #{} type={system_id:02x} lba={}..={}{}",
+                    e.index,
+                    e.starting_lba,
+                    e.ending_lba,
+                    if *bootable { " (bootable)" } else { "" }
+                ),
+            }
+            .unwrap();
         }
+
+        partitions.extend(parts);
     }
-    let r = gop.blt(
-        &mut arrow_blt_buffer,
-        uefi::EfiGraphicsOutputBltOperation::BltBufferToVideo,
-        0,
-        0,
-        0,
-        0,
-        16,
-        16,
-        0,
-    );
-    if r != 0 {
-        panic!("Failed to blt arrow: {r}");
-    }
-
-    // for n in 0..32 {
-    //     let root_device = pci::DeviceIdentifier {
-    //         bus: 0,
-    //         device: n,
-    //         function: 0,
-    //     };
-
-    //     let [vendor_id, device_id] = root_device.read_device_vendor_ids();
-    //     if vendor_id == 0xffff {
-    //         continue;
-    //     }
-    //     let [x, pif, subclass, cls] = root_device.read_class_pif_revision_values();
-    //     let [_, _, ht, _] = root_device.read_bist_ht_lt_cls_values();
-    //     writeln!(
-    //         &mut con_out,
-    //         "root pci s#{n}.0: 0x{device_id:04x} 0x{vendor_id:04x} header_type=0x{ht:02x} cls={cls}:{subclass} pif={pif} x={x}"
-    //     )
-    //     .unwrap();
-
-    //     if (ht & 0x80) != 0 {
-    //         // multifunction
-    //         for f in 1..8 {
-    //             let root_device = pci::DeviceIdentifier {
-    //                 bus: 0,
-    //                 device: n,
-    //                 function: f,
-    //             };
-
-    //             let [vendor_id, device_id] = root_device.read_device_vendor_ids();
-    //             if vendor_id == 0xffff {
-    //                 continue;
-    //             }
-    //             let [x, pif, subclass, cls] = root_device.read_class_pif_revision_values();
-    //             let [_, _, ht, _] = root_device.read_bist_ht_lt_cls_values();
-    //             writeln!(
-    //                 &mut con_out,
-    //                 "root pci s#{n}.{f}: 0x{device_id:04x} 0x{vendor_id:04x} header_type=0x{ht:02x} cls={cls}:{subclass} pif={pif} x={x}"
-    //             )
-    //             .unwrap();
-    //         }
-    //     }
-
-    //     if vendor_id == 0x1234 && device_id == 0x1111 {
-    //         // QEMU/Bochs VGA Device
-    //         let fb_address = root_device.read_base_address_register(0);
-    //         let mmio_base = root_device.read_base_address_register(2);
-    //         let endian = unsafe { *((mmio_base as usize + 0x0604) as *const u32) };
-    //         writeln!(
-    //             &mut con_out,
-    //             "- VGA: fb=0x{fb_address:08x} mmio=0x{mmio_base:08x} end=0x{endian:08x}"
-    //         )
-    //         .unwrap();
-    //     }
-
-    //     if vendor_id == 0x1af4 && device_id == 0x1040 + 16 {
-    //         // virtio-gpu
-    //         let [_, st] = root_device.read_status_command_values();
-    //         let cap = root_device.read_capabilities_pointer();
-    //         writeln!(&mut con_out, "- Virtio GPU: st=0x{st:04x}, cap=0x{cap:02x}").unwrap();
-    //         let mut cap_pointer = cap;
-    //         while cap_pointer != 0 {
-    //             let extra_config = root_device.read_config(cap_pointer >> 2);
-    //             writeln!(&mut con_out, "- extra config: 0x{extra_config:08x}").unwrap();
-
-    //             if extra_config & 0xff == 0x11 {
-    //                 // msi-x caps
-    //                 let message_control = (extra_config >> 16) as u16;
-    //                 let (table_address, pending_bit_array_address) = (
-    //                     root_device.read_config((cap_pointer >> 2) + 1),
-    //                     root_device.read_config((cap_pointer >> 2) + 2),
-    //                 );
-
-    //                 writeln!(
-    //                     &mut con_out,
-    //                     "  - msi-x message control: 0x{message_control:04x}"
-    //                 )
-    //                 .unwrap();
-    //                 writeln!(
-    //                     &mut con_out,
-    //                     "  - msi-x table address: 0x{table_address:08x}"
-    //                 )
-    //                 .unwrap();
-    //                 writeln!(
-    //                     &mut con_out,
-    //                     "  - msi-x pending bit array address: 0x{pending_bit_array_address:08x}"
-    //                 )
-    //                 .unwrap();
-    //             }
-    //             if extra_config & 0xff == 0x09 {
-    //                 // vendor specific: virtio caps
-    //                 let ([_, _, _id, bar], offs, len) = (
-    //                     root_device
-    //                         .read_config((cap_pointer >> 2) + 1)
-    //                         .to_le_bytes(),
-    //                     root_device.read_config((cap_pointer >> 2) + 2),
-    //                     root_device.read_config((cap_pointer >> 2) + 3),
-    //                 );
-
-    //                 let cap_type = (extra_config >> 24) as u8;
-    //                 if cap_type == virtio::PCICapabilityType::CommonConfig as u8 {
-    //                     writeln!(
-    //                         &mut con_out,
-    //                         "  - PCI Common Config at bar #{bar} +{offs} ~{len}"
-    //                     )
-    //                     .unwrap();
-
-    //                     for n in 0..6 {
-    //                         writeln!(
-    //                             &mut con_out,
-    //                             "  - bar #{n}: 0x{:08x}",
-    //                             root_device.read_base_address_register(n as _)
-    //                         )
-    //                         .unwrap();
-    //                     }
-
-    //                     let common_config = unsafe {
-    //                         &mut *((root_device.read_base_address_register(bar) + offs) as usize
-    //                             as *mut virtio::CommonConfiguration)
-    //                     };
-    //                     writeln!(&mut con_out, "  - ptr: {common_config:p}").unwrap();
-    //                     writeln!(&mut con_out, "  - cfg: {common_config:?}").unwrap();
-    //                 }
-    //             }
-
-    //             cap_pointer = ((extra_config >> 8) & 0xfc) as u8;
-    //         }
-    //     }
-    // }
 
-    loop {}
+    partitions
+}
+
+/// Mounts the first FAT formatted partition and lists its root directory
+fn mount_fat(
+    hrc: &mut HiResConsole,
+    partitions: alloc::vec::Vec<partition::Partition<virtio::blk::BlkDevice>>,
+) -> Option<fat::FileSystem<partition::Partition<virtio::blk::BlkDevice>>> {
+    for p in partitions {
+        let index = p.entry().index;
+        let mut fs = match fat::FileSystem::mount(p) {
+            Ok(fs) => fs,
+            Err(e) => {
+                writeln!(hrc, "partition #{index}: not mountable as FAT: {e:?}").unwrap();
+                continue;
+            }
+        };
+
+        writeln!(
+            hrc,
+            "partition #{index}: {:?} {} clusters x {} bytes",
+            fs.fat_type(),
+            fs.cluster_count(),
+            fs.cluster_size()
+        )
+        .unwrap();
+        match fs.read_dir("/") {
+            Ok(entries) => {
+                for e in entries {
+                    writeln!(
+                        hrc,
+                        "  - {}{} {} bytes",
+                        e.name,
+                        if e.is_dir() { "/" } else { "" },
+                        e.size
+                    )
+                    .unwrap();
+                }
+            }
+            Err(e) => writeln!(hrc, "  - failed to read the root directory: {e:?}").unwrap(),
+        }
+
+        return Some(fs);
+    }
+
+    None
+}
+
+static HPET_FIRED: core::sync::atomic::AtomicU32 = core::sync::atomic::AtomicU32::new(0);
+
+fn test_hpet(
+    con: &mut impl Write,
+    table: &acpi::HighPrecisionEventTimerTable,
+    local_apic: &apic::LocalAPIC,
+    io_apics: &[apic::IOAPIC],
+) {
+    use core::sync::atomic::Ordering;
+
+    writeln!(
+        con,
+        "HPET table: number={} comparators={} min_tick={} base={:?}",
+        table.hpet_number,
+        table.comparator_count(),
+        table.minimum_clock_tick(),
+        table.base_address
+    )
+    .unwrap();
+    let hpet = unsafe { hpet::HighPrecisionEventTimer::new(table) };
+    hpet.enable();
+    writeln!(
+        con,
+        "HPET: rev={} comparators={} period={}fs ({}Hz) 64bit={}",
+        hpet.revision(),
+        hpet.comparator_count(),
+        hpet.counter_period(),
+        hpet.frequency(),
+        hpet.is_counter_64bit()
+    )
+    .unwrap();
+
+    writeln!(con, "- TSC: {}Hz", hpet.measure_tsc_frequency(10)).unwrap();
+    writeln!(
+        con,
+        "- local apic timer (div=16): {}Hz",
+        hpet.measure_local_apic_timer_frequency(local_apic, 16, 10)
+    )
+    .unwrap();
+
+    let comparator = hpet.comparator(0);
+    writeln!(
+        con,
+        "- comparator 0: periodic={} 64bit={} route_cap=0x{:08x}",
+        comparator.supports_periodic(),
+        comparator.is_64bit(),
+        comparator.interrupt_route_capability()
+    )
+    .unwrap();
+    // Note: 上のほうのGSIはたいてい空いているので、使えるもののうち一番大きいものを選ぶ
+    let route_cap = comparator.interrupt_route_capability();
+    if route_cap == 0 {
+        writeln!(con, "- comparator 0 has no I/O APIC route").unwrap();
+        return;
+    }
+    let gsi = 31 - route_cap.leading_zeros();
+    let Some(io_apic) = io_apics.iter().find(|a| a.handles(gsi)) else {
+        writeln!(con, "- no I/O APIC handles GSI {gsi}").unwrap();
+        return;
+    };
+    let Some(vector) = interrupt::allocate_vector() else {
+        writeln!(con, "- no free interrupt vector").unwrap();
+        return;
+    };
+    interrupt::register_handler(vector, |_| {
+        HPET_FIRED.fetch_add(1, Ordering::Relaxed);
+    });
+    io_apic.set_redirection(gsi, apic::RedirectionEntry::new(vector, local_apic.id()));
+    comparator.route(gsi as _);
+
+    unsafe {
+        sti!();
+    }
+
+    HPET_FIRED.store(0, Ordering::Relaxed);
+    comparator.set_one_shot(hpet.nanoseconds_to_ticks(1_000_000));
+    hpet.wait_nanoseconds(5_000_000);
+    writeln!(
+        con,
+        "- one-shot (1ms) via GSI {gsi} -> vector 0x{vector:02x}: fired {} time(s)",
+        HPET_FIRED.load(Ordering::Relaxed)
+    )
+    .unwrap();
+
+    if comparator.supports_periodic() {
+        HPET_FIRED.store(0, Ordering::Relaxed);
+        comparator.set_periodic(hpet.nanoseconds_to_ticks(1_000_000));
+        hpet.wait_nanoseconds(50_000_000);
+        comparator.disable();
+        writeln!(
+            con,
+            "- periodic (1ms) for 50ms: fired {} time(s)",
+            HPET_FIRED.load(Ordering::Relaxed)
+        )
+        .unwrap();
+    }
+
+    unsafe {
+        cli!();
+    }
+    io_apic.mask(gsi);
+    interrupt::free_vector(vector);
 }
 
 extern "system" fn general_protection_fault() -> ! {
@@ -1032,5 +1932,7 @@ extern "system" fn general_protection_fault() -> ! {
         "[ERR] General Protection Fault!"
     )
     .unwrap();
-    loop {}
+    loop {
+        unsafe { hlt!() }
+    }
 }
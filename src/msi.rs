@@ -0,0 +1,176 @@
+//! Message signaled interrupt (MSI / MSI-X) setup for PCI drivers
+
+use crate::{apic::InterruptMessage, interrupt, pci};
+use alloc::vec::Vec;
+
+enum Mechanism {
+    Msi(pci::MsiCapability),
+    MsiX(pci::MsiXCapability, pci::MsiXTable),
+}
+
+/// Vectors enabled on a PCI function. Vector `index` calls `handlers[index]` passed to `enable`.
+pub struct MessageSignaledInterrupts {
+    device: pci::DeviceIdentifier,
+    mechanism: Mechanism,
+    vectors: Vec<u8>,
+}
+impl MessageSignaledInterrupts {
+    /// Enables MSI-X (or MSI if the function has no MSI-X) with one vector per handler,
+    /// delivered to the local APIC `destination_apic_id`. INTx is disabled.
+    /// None if the function supports neither or cannot provide enough vectors.
+    pub fn enable(
+        device: pci::DeviceIdentifier,
+        destination_apic_id: u8,
+        handlers: &[interrupt::Handler],
+    ) -> Option<Self> {
+        assert!(!handlers.is_empty(), "no interrupt handlers");
+
+        let (mut msi, mut msix) = (None, None);
+        for c in device.capabilities() {
+            match c {
+                pci::Capability::Msi(m) => msi = Some(m),
+                pci::Capability::MsiX(m) => msix = Some(m),
+                _ => (),
+            }
+        }
+
+        // the MSI-X table lives in a memory BAR and messages are memory writes
+        device.enable(pci::Command::MEM | pci::Command::BUSMASTER);
+        let r = match (msix, msi) {
+            (Some(m), _) if m.table_size() as usize >= handlers.len() => {
+                Self::enable_msix(device, m, destination_apic_id, handlers)
+            }
+            (_, Some(m)) => Self::enable_msi(device, m, destination_apic_id, handlers),
+            _ => None,
+        }?;
+        device.enable(pci::Command::INTX_DISABLE);
+
+        Some(r)
+    }
+
+    fn enable_msix(
+        device: pci::DeviceIdentifier,
+        capability: pci::MsiXCapability,
+        destination_apic_id: u8,
+        handlers: &[interrupt::Handler],
+    ) -> Option<Self> {
+        let table = capability.table()?;
+        let mut vectors = Vec::with_capacity(handlers.len());
+        for _ in handlers {
+            let Some(v) = interrupt::allocate_vector() else {
+                vectors.into_iter().for_each(interrupt::free_vector);
+                return None;
+            };
+            vectors.push(v);
+        }
+
+        // Note: エントリ書き換え中に中途半端なメッセージが飛ばないよう、ファンクション全体をマスクしてから設定する
+        capability.set_function_mask(true);
+        capability.enable();
+        for (n, (&v, &h)) in vectors.iter().zip(handlers).enumerate() {
+            interrupt::register_handler(v, h);
+            table.mask(n as _);
+            table.set_message(n as _, InterruptMessage::new(v, destination_apic_id));
+            table.unmask(n as _);
+        }
+        capability.set_function_mask(false);
+
+        Some(Self {
+            device,
+            mechanism: Mechanism::MsiX(capability, table),
+            vectors,
+        })
+    }
+
+    fn enable_msi(
+        device: pci::DeviceIdentifier,
+        capability: pci::MsiCapability,
+        destination_apic_id: u8,
+        handlers: &[interrupt::Handler],
+    ) -> Option<Self> {
+        let count = u8::try_from(handlers.len().next_power_of_two()).ok()?;
+        if count > capability.multiple_message_capable() {
+            return None;
+        }
+
+        // Note: マルチメッセージMSIはデータの下位ビットにベクタ番号を埋めるので、countでアラインされた連続ブロックが必要
+        let first = interrupt::allocate_vectors(count)?;
+        for v in first + handlers.len() as u8..first + count {
+            interrupt::free_vector(v);
+        }
+        let vectors: Vec<u8> = (first..first + handlers.len() as u8).collect();
+        for (&v, &h) in vectors.iter().zip(handlers) {
+            interrupt::register_handler(v, h);
+        }
+
+        capability.disable();
+        capability.set_message(InterruptMessage::new(first, destination_apic_id));
+        if capability.per_vector_masking() {
+            for n in 0..count {
+                if n as usize >= handlers.len() {
+                    capability.mask(n);
+                } else {
+                    capability.unmask(n);
+                }
+            }
+        }
+        capability.enable(count);
+
+        Some(Self {
+            device,
+            mechanism: Mechanism::Msi(capability),
+            vectors,
+        })
+    }
+
+    #[inline]
+    pub const fn is_msix(&self) -> bool {
+        matches!(self.mechanism, Mechanism::MsiX(..))
+    }
+
+    /// Disables MSI/MSI-X, releases the vectors and re-enables INTx
+    pub fn disable(self) {
+        match &self.mechanism {
+            Mechanism::Msi(c) => c.disable(),
+            Mechanism::MsiX(c, t) => {
+                (0..self.vectors.len()).for_each(|n| t.mask(n as _));
+                c.disable();
+            }
+        }
+        self.device.disable(pci::Command::INTX_DISABLE);
+        self.vectors.into_iter().for_each(interrupt::free_vector);
+    }
+}
+// Note: ドライバが個々のベクタを止めたいとき用（virtioは今のところ使っていない）
+#[allow(dead_code)]
+impl MessageSignaledInterrupts {
+    #[inline]
+    pub fn count(&self) -> usize {
+        self.vectors.len()
+    }
+
+    /// IDT vector of `index`
+    #[inline]
+    pub fn vector(&self, index: usize) -> u8 {
+        self.vectors[index]
+    }
+
+    /// Masks vector `index`. MSI without per-vector masking cannot mask individual vectors.
+    pub fn mask(&self, index: usize) {
+        assert!(index < self.vectors.len(), "no such vector: {index}");
+
+        match &self.mechanism {
+            Mechanism::Msi(c) => c.mask(index as _),
+            Mechanism::MsiX(_, t) => t.mask(index as _),
+        }
+    }
+
+    pub fn unmask(&self, index: usize) {
+        assert!(index < self.vectors.len(), "no such vector: {index}");
+
+        match &self.mechanism {
+            Mechanism::Msi(c) => c.unmask(index as _),
+            Mechanism::MsiX(_, t) => t.unmask(index as _),
+        }
+    }
+}
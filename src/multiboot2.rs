@@ -0,0 +1,691 @@
+//! Multiboot2 kernels (The Multiboot2 Specification version 2.0)
+
+use crate::{
+    acpi::RootSystemDescriptionPointer,
+    boot_info::{Framebuffer, MemoryMap},
+    elf::{self, ElfError, ProgramHeader, SegmentFlags},
+    paging::{self, FrameAllocator, LowUefiFrameAllocator, OutOfFrames, PAGE_SIZE},
+    ControlRegister3,
+};
+use alloc::vec::Vec;
+
+/// EAX at the kernel entry
+pub const BOOTLOADER_MAGIC: u32 = 0x36d7_6289;
+const HEADER_MAGIC: u32 = 0xe852_50d6;
+/// the header has to be completely within this many bytes from the beginning of the image
+const SEARCH_LIMIT: usize = 32768;
+const HEADER_ALIGN: usize = 8;
+const HEADER_SIZE: usize = 16;
+const ARCHITECTURE_I386: u32 = 0;
+/// header tag flag: the loader may ignore the tag when it does not support it
+const TAG_OPTIONAL: u16 = 0x01;
+
+const HEADER_TAG_END: u16 = 0;
+const HEADER_TAG_INFORMATION_REQUEST: u16 = 1;
+const HEADER_TAG_ADDRESS: u16 = 2;
+const HEADER_TAG_ENTRY_ADDRESS: u16 = 3;
+const HEADER_TAG_CONSOLE_FLAGS: u16 = 4;
+const HEADER_TAG_FRAMEBUFFER: u16 = 5;
+const HEADER_TAG_MODULE_ALIGN: u16 = 6;
+const HEADER_TAG_EFI_BS: u16 = 7;
+const HEADER_TAG_ENTRY_ADDRESS_EFI32: u16 = 8;
+const HEADER_TAG_ENTRY_ADDRESS_EFI64: u16 = 9;
+const HEADER_TAG_RELOCATABLE: u16 = 10;
+
+const TAG_END: u32 = 0;
+const TAG_CMDLINE: u32 = 1;
+const TAG_BOOT_LOADER_NAME: u32 = 2;
+const TAG_MODULE: u32 = 3;
+const TAG_BASIC_MEMINFO: u32 = 4;
+const TAG_MMAP: u32 = 6;
+const TAG_FRAMEBUFFER: u32 = 8;
+const TAG_EFI64: u32 = 12;
+const TAG_SMBIOS: u32 = 13;
+const TAG_ACPI_OLD: u32 = 14;
+const TAG_ACPI_NEW: u32 = 15;
+const TAG_EFI_MMAP: u32 = 17;
+const TAG_EFI_BS: u32 = 18;
+const TAG_EFI64_IH: u32 = 20;
+const TAG_LOAD_BASE_ADDR: u32 = 21;
+/// information tags this loader can provide (for information request tags)
+const PROVIDED_TAGS: [u32; 15] = [
+    TAG_END,
+    TAG_CMDLINE,
+    TAG_BOOT_LOADER_NAME,
+    TAG_MODULE,
+    TAG_BASIC_MEMINFO,
+    TAG_MMAP,
+    TAG_FRAMEBUFFER,
+    TAG_EFI64,
+    TAG_SMBIOS,
+    TAG_ACPI_OLD,
+    TAG_ACPI_NEW,
+    TAG_EFI_MMAP,
+    TAG_EFI_BS,
+    TAG_EFI64_IH,
+    TAG_LOAD_BASE_ADDR,
+];
+
+const MEMORY_AVAILABLE: u32 = 1;
+const MEMORY_RESERVED: u32 = 2;
+const MEMORY_ACPI_RECLAIMABLE: u32 = 3;
+const MEMORY_NVS: u32 = 4;
+const MEMORY_BADRAM: u32 = 5;
+const MMAP_ENTRY_SIZE: u32 = 24;
+const FRAMEBUFFER_TYPE_RGB: u8 = 1;
+
+/// size of the buffer the boot information is built in
+const INFO_PAGES: usize = 16;
+
+#[derive(Debug)]
+#[allow(dead_code)]
+pub enum Multiboot2Error {
+    /// the header does not fit in the first 32KiB or a tag is broken
+    InvalidHeader,
+    UnsupportedArchitecture(u32),
+    /// a header tag this loader does not know is not marked optional
+    UnsupportedTag(u16),
+    /// the kernel requires an information tag this loader cannot provide
+    UnsupportedRequest(u32),
+    Elf(ElfError),
+    /// the image does not fit below 4GiB or its load addresses are inconsistent
+    Malformed(&'static str),
+    /// the pages at the address the kernel has to be loaded are not free
+    AddressInUse(u64),
+    OutOfMemory,
+}
+impl From<ElfError> for Multiboot2Error {
+    fn from(e: ElfError) -> Self {
+        Self::Elf(e)
+    }
+}
+impl From<OutOfFrames> for Multiboot2Error {
+    fn from(_: OutOfFrames) -> Self {
+        Self::OutOfMemory
+    }
+}
+
+#[inline]
+fn u16_at(b: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(b[offset..offset + 2].try_into().unwrap())
+}
+
+#[inline]
+fn u32_at(b: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(b[offset..offset + 4].try_into().unwrap())
+}
+
+/// Address tag: where to load a non-ELF image (the "a.out kludge")
+#[derive(Debug, Clone, Copy)]
+pub struct AddressTag {
+    /// physical address the header itself is loaded at
+    pub header_addr: u32,
+    pub load_addr: u32,
+    /// 0: up to the end of the file
+    pub load_end_addr: u32,
+    /// 0: no bss
+    pub bss_end_addr: u32,
+}
+
+#[derive(Debug)]
+pub struct Header {
+    /// offset of the header in the image
+    pub offset: usize,
+    pub address: Option<AddressTag>,
+    /// i386 entry overriding the ELF entry
+    pub entry: Option<u32>,
+    /// 64-bit entry used together with `keep_boot_services`
+    pub efi_amd64_entry: Option<u32>,
+    /// EFI boot services tag: the kernel wants to be entered before ExitBootServices
+    pub keep_boot_services: bool,
+    /// preferred (width, height, depth); only informational, the current mode is reported
+    pub framebuffer: Option<(u32, u32, u32)>,
+    /// information tag types requested by the kernel
+    pub requests: Vec<u32>,
+}
+impl Header {
+    /// Finds the header in the first 32KiB of `image`. None if it is not a Multiboot2 kernel.
+    pub fn find(image: &[u8]) -> Option<Result<Self, Multiboot2Error>> {
+        let limit = image.len().min(SEARCH_LIMIT);
+        let offset = (0..limit.saturating_sub(HEADER_SIZE - 1))
+            .step_by(HEADER_ALIGN)
+            .find(|&o| {
+                let fields = [0, 4, 8, 12].map(|n| u32_at(image, o + n));
+                fields[0] == HEADER_MAGIC
+                    && fields.iter().fold(0u32, |a, &b| a.wrapping_add(b)) == 0
+            })?;
+
+        Some(Self::parse(&image[offset..limit], offset))
+    }
+
+    /// `b` starts with the header, whose magic and checksum are correct
+    fn parse(b: &[u8], offset: usize) -> Result<Self, Multiboot2Error> {
+        let architecture = u32_at(b, 4);
+        if architecture != ARCHITECTURE_I386 {
+            return Err(Multiboot2Error::UnsupportedArchitecture(architecture));
+        }
+        let length = u32_at(b, 8) as usize;
+        if !(HEADER_SIZE..=b.len()).contains(&length) {
+            return Err(Multiboot2Error::InvalidHeader);
+        }
+
+        let mut header = Self {
+            offset,
+            address: None,
+            entry: None,
+            efi_amd64_entry: None,
+            keep_boot_services: false,
+            framebuffer: None,
+            requests: Vec::new(),
+        };
+        let mut p = HEADER_SIZE;
+        loop {
+            if p + 8 > length {
+                return Err(Multiboot2Error::InvalidHeader);
+            }
+            let (r#type, flags, size) = (u16_at(b, p), u16_at(b, p + 2), u32_at(b, p + 4) as usize);
+            if size < 8 || p + size > length {
+                return Err(Multiboot2Error::InvalidHeader);
+            }
+            let tag = &b[p..p + size];
+            let field = |n: usize| {
+                (n + 4 <= size)
+                    .then(|| u32_at(tag, n))
+                    .ok_or(Multiboot2Error::InvalidHeader)
+            };
+
+            match r#type {
+                HEADER_TAG_END => break,
+                HEADER_TAG_INFORMATION_REQUEST => {
+                    for t in tag[8..].chunks_exact(4).map(|c| u32_at(c, 0)) {
+                        if (flags & TAG_OPTIONAL) == 0 && !PROVIDED_TAGS.contains(&t) {
+                            return Err(Multiboot2Error::UnsupportedRequest(t));
+                        }
+                        header.requests.push(t);
+                    }
+                }
+                HEADER_TAG_ADDRESS => {
+                    header.address = Some(AddressTag {
+                        header_addr: field(8)?,
+                        load_addr: field(12)?,
+                        load_end_addr: field(16)?,
+                        bss_end_addr: field(20)?,
+                    })
+                }
+                HEADER_TAG_ENTRY_ADDRESS => header.entry = Some(field(8)?),
+                HEADER_TAG_ENTRY_ADDRESS_EFI64 => header.efi_amd64_entry = Some(field(8)?),
+                HEADER_TAG_EFI_BS => header.keep_boot_services = true,
+                HEADER_TAG_FRAMEBUFFER => {
+                    header.framebuffer = Some((field(8)?, field(12)?, field(16)?))
+                }
+                // Note: コンソールは常にフレームバッファがあり、モジュールはページ境界に置き、
+                // カーネルはリンクされたアドレスにそのまま置くので、どれも満たしている。i386 EFIのエントリは関係ない
+                HEADER_TAG_CONSOLE_FLAGS
+                | HEADER_TAG_MODULE_ALIGN
+                | HEADER_TAG_RELOCATABLE
+                | HEADER_TAG_ENTRY_ADDRESS_EFI32 => (),
+                _ if (flags & TAG_OPTIONAL) != 0 => (),
+                t => return Err(Multiboot2Error::UnsupportedTag(t)),
+            }
+
+            p += size.next_multiple_of(8);
+        }
+
+        Ok(header)
+    }
+
+    /// Whether the kernel is entered in 64-bit mode with boot services running
+    #[inline]
+    pub fn enters_with_boot_services(&self) -> bool {
+        self.keep_boot_services && self.efi_amd64_entry.is_some()
+    }
+}
+
+/// A kernel placed at its physical addresses
+#[derive(Debug)]
+pub struct LoadedKernel {
+    /// the address the i386 entry (or the EFI amd64 entry) jumps to
+    pub entry: u32,
+    /// lowest loaded physical address
+    pub load_base: u32,
+}
+
+/// Places the image at the physical addresses given by the address tag or the ELF program
+/// headers, with pages allocated exactly there. Only while boot services are available.
+pub fn load(image: &[u8], header: &Header) -> Result<LoadedKernel, Multiboot2Error> {
+    let (elf_entry, segments) = match header.address {
+        Some(a) => (None, alloc::vec![kludge_segment(image, header.offset, a)?]),
+        None => {
+            let (entry, segments) = elf::physical_segments(image)?;
+            (Some(entry), segments)
+        }
+    };
+    if segments.is_empty() {
+        return Err(Multiboot2Error::Malformed("nothing to load"));
+    }
+    for s in &segments {
+        let below_4g = s
+            .physical_address
+            .checked_add(s.memory_size)
+            .is_some_and(|end| end <= 1 << 32);
+        if !below_4g {
+            return Err(Multiboot2Error::Malformed("segment above 4GiB"));
+        }
+    }
+
+    let entry = if header.enters_with_boot_services() {
+        header.efi_amd64_entry
+    } else {
+        header
+            .entry
+            .or(elf_entry.and_then(|e| u32::try_from(e).ok()))
+    }
+    .ok_or(Multiboot2Error::Malformed("no 32-bit entry address"))?;
+
+    // Note: 同じページにかかるセグメントがあると二重に確保できないので、ページ単位でまとめてから確保する
+    let mut ranges = segments
+        .iter()
+        .map(|s| {
+            let start = s.physical_address & !(PAGE_SIZE - 1);
+            (
+                start,
+                (s.physical_address + s.memory_size).next_multiple_of(PAGE_SIZE),
+            )
+        })
+        .collect::<Vec<_>>();
+    ranges.sort_unstable();
+    let mut merged = Vec::<(u64, u64)>::new();
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    for &(start, end) in &merged {
+        paging::allocate_frames_at(start, ((end - start) / PAGE_SIZE) as _)
+            .map_err(|_| Multiboot2Error::AddressInUse(start))?;
+    }
+
+    for s in &segments {
+        let data = &image[s.offset as usize..][..s.file_size as usize];
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                s.physical_address as *mut u8,
+                data.len(),
+            );
+        }
+    }
+
+    Ok(LoadedKernel {
+        entry,
+        load_base: segments.iter().map(|s| s.physical_address).min().unwrap() as _,
+    })
+}
+
+/// The single segment described by the address tag
+fn kludge_segment(
+    image: &[u8],
+    header_offset: usize,
+    a: AddressTag,
+) -> Result<ProgramHeader, Multiboot2Error> {
+    let before_header = a
+        .header_addr
+        .checked_sub(a.load_addr)
+        .ok_or(Multiboot2Error::Malformed("load_addr above header_addr"))?;
+    let offset = (header_offset as u64)
+        .checked_sub(before_header as _)
+        .ok_or(Multiboot2Error::Malformed("load_addr before the file"))?;
+    let file_size = if a.load_end_addr == 0 {
+        image.len() as u64 - offset
+    } else {
+        (a.load_end_addr as u64)
+            .checked_sub(a.load_addr as _)
+            .filter(|&s| offset + s <= image.len() as u64)
+            .ok_or(Multiboot2Error::Malformed("load_end_addr"))?
+    };
+    let memory_size = if a.bss_end_addr == 0 {
+        file_size
+    } else {
+        (a.bss_end_addr as u64)
+            .checked_sub(a.load_addr as _)
+            .filter(|&s| s >= file_size)
+            .ok_or(Multiboot2Error::Malformed("bss_end_addr"))?
+    };
+
+    Ok(ProgramHeader {
+        r#type: elf::PT_LOAD,
+        flags: SegmentFlags::READ | SegmentFlags::WRITE | SegmentFlags::EXECUTE,
+        offset,
+        virtual_address: a.load_addr as _,
+        physical_address: a.load_addr as _,
+        file_size,
+        memory_size,
+        align: PAGE_SIZE,
+    })
+}
+
+/// The boot information structure, built in memory below 4GiB
+pub struct InfoBuilder {
+    buffer: &'static mut [u8],
+    length: usize,
+}
+impl InfoBuilder {
+    /// Only while boot services are available
+    pub fn new() -> Result<Self, OutOfFrames> {
+        let address = LowUefiFrameAllocator.allocate_frames(INFO_PAGES)?;
+        let buffer = unsafe {
+            core::slice::from_raw_parts_mut(address as *mut u8, INFO_PAGES * PAGE_SIZE as usize)
+        };
+
+        // total_size and reserved come first
+        Ok(Self { buffer, length: 8 })
+    }
+
+    /// Appends a tag made of `parts`
+    fn tag(&mut self, r#type: u32, parts: &[&[u8]]) {
+        let size = 8 + parts.iter().map(|p| p.len()).sum::<usize>();
+        let end = self.length + size.next_multiple_of(8);
+        assert!(end <= self.buffer.len(), "multiboot2 information overflow");
+
+        let b = &mut self.buffer[self.length..end];
+        b[..4].copy_from_slice(&r#type.to_le_bytes());
+        b[4..8].copy_from_slice(&(size as u32).to_le_bytes());
+        let mut p = 8;
+        for part in parts {
+            b[p..p + part.len()].copy_from_slice(part);
+            p += part.len();
+        }
+        b[p..].fill(0);
+        self.length = end;
+    }
+
+    pub fn command_line(&mut self, command_line: &str) {
+        self.tag(TAG_CMDLINE, &[command_line.as_bytes(), &[0]]);
+    }
+
+    pub fn boot_loader_name(&mut self, name: &str) {
+        self.tag(TAG_BOOT_LOADER_NAME, &[name.as_bytes(), &[0]]);
+    }
+
+    /// [start, end) below 4GiB
+    pub fn module(&mut self, start: u64, end: u64, name: &str) {
+        self.tag(
+            TAG_MODULE,
+            &[
+                &(start as u32).to_le_bytes(),
+                &(end as u32).to_le_bytes(),
+                name.as_bytes(),
+                &[0],
+            ],
+        );
+    }
+
+    pub fn load_base(&mut self, address: u32) {
+        self.tag(TAG_LOAD_BASE_ADDR, &[&address.to_le_bytes()]);
+    }
+
+    /// Nothing for BltOnly modes
+    pub fn framebuffer(&mut self, fb: &Framebuffer) {
+        // EFI_GRAPHICS_PIXEL_FORMAT to (red, green, blue) masks
+        let masks = match fb.pixel_format {
+            0 => [0x0000ff, 0x00ff00, 0xff0000],
+            1 => [0xff0000, 0x00ff00, 0x0000ff],
+            2 => [
+                fb.pixel_bitmask[0],
+                fb.pixel_bitmask[1],
+                fb.pixel_bitmask[2],
+            ],
+            _ => return,
+        };
+        let all_bits = masks.iter().fold(fb.pixel_bitmask[3], |a, &m| a | m);
+        let bpp = if fb.pixel_format == 2 {
+            (32 - all_bits.leading_zeros()).next_multiple_of(8)
+        } else {
+            32
+        };
+        let color_info = masks.map(|m| [m.trailing_zeros() as u8, m.count_ones() as u8]);
+
+        self.tag(
+            TAG_FRAMEBUFFER,
+            &[
+                &fb.base.to_le_bytes(),
+                &(fb.stride * bpp / 8).to_le_bytes(),
+                &fb.width.to_le_bytes(),
+                &fb.height.to_le_bytes(),
+                &[bpp as u8, FRAMEBUFFER_TYPE_RGB, 0, 0],
+                &color_info.concat(),
+            ],
+        );
+    }
+
+    pub fn efi_system_table(&mut self, address: u64) {
+        self.tag(TAG_EFI64, &[&address.to_le_bytes()]);
+    }
+
+    pub fn efi_image_handle(&mut self, handle: u64) {
+        self.tag(TAG_EFI64_IH, &[&handle.to_le_bytes()]);
+    }
+
+    pub fn efi_boot_services_not_terminated(&mut self) {
+        self.tag(TAG_EFI_BS, &[]);
+    }
+
+    /// old RSDP tag (the ACPI 1.0 part), and the new one for ACPI 2.0+
+    pub fn acpi(&mut self, rsdp: &RootSystemDescriptionPointer) {
+        let bytes = |length: usize| unsafe {
+            core::slice::from_raw_parts(rsdp as *const _ as *const u8, length)
+        };
+        self.tag(TAG_ACPI_OLD, &[bytes(20)]);
+        if rsdp.revision >= 2 {
+            self.tag(TAG_ACPI_NEW, &[bytes(rsdp.length as _)]);
+        }
+    }
+
+    /// `address` points a SMBIOS 2.x ("_SM_") or 3.x ("_SM3_") entry point structure
+    pub fn smbios(&mut self, address: u64) {
+        let head = unsafe { core::slice::from_raw_parts(address as *const u8, 9) };
+        let (length, major, minor) = if head.starts_with(b"_SM3_") {
+            (head[6], head[7], head[8])
+        } else if head.starts_with(b"_SM_") {
+            (head[5], head[6], head[7])
+        } else {
+            return;
+        };
+        let entry_point = unsafe { core::slice::from_raw_parts(address as *const u8, length as _) };
+
+        self.tag(
+            TAG_SMBIOS,
+            &[&[major, minor, 0, 0, 0, 0, 0, 0], entry_point],
+        );
+    }
+
+    /// memory map, basic memory information and the EFI memory map as is
+    pub fn memory_map(&mut self, map: &MemoryMap) {
+        let available = |t: u32| matches!(t, 1..=4 | 7);
+        let entries = map
+            .descriptors()
+            .map(|d| {
+                let r#type = match d.r#type {
+                    // Note: ローダのメモリ（カーネル・モジュール・この情報も含む）は利用可能として渡す（GRUBと同じ）
+                    t if available(t) => MEMORY_AVAILABLE,
+                    8 => MEMORY_BADRAM,
+                    9 => MEMORY_ACPI_RECLAIMABLE,
+                    10 => MEMORY_NVS,
+                    _ => MEMORY_RESERVED,
+                };
+                let mut e = [0u8; MMAP_ENTRY_SIZE as usize];
+                e[..8].copy_from_slice(&d.physical_start.to_le_bytes());
+                e[8..16].copy_from_slice(&(d.number_of_pages * PAGE_SIZE).to_le_bytes());
+                e[16..20].copy_from_slice(&r#type.to_le_bytes());
+                e
+            })
+            .collect::<Vec<_>>();
+        self.tag(
+            TAG_MMAP,
+            &[
+                &MMAP_ENTRY_SIZE.to_le_bytes(),
+                &0u32.to_le_bytes(),
+                &entries.concat(),
+            ],
+        );
+
+        // end of the available memory contiguous from `start`
+        let contiguous_end = |start: u64| {
+            let mut end = start;
+            while let Some(d) = map.descriptors().find(|d| {
+                available(d.r#type)
+                    && d.physical_start <= end
+                    && end < d.physical_start + d.number_of_pages * PAGE_SIZE
+            }) {
+                end = d.physical_start + d.number_of_pages * PAGE_SIZE;
+            }
+            end
+        };
+        let lower = contiguous_end(0).min(640 * 1024) / 1024;
+        let upper = (contiguous_end(1024 * 1024) - 1024 * 1024) / 1024;
+        self.tag(
+            TAG_BASIC_MEMINFO,
+            &[&(lower as u32).to_le_bytes(), &(upper as u32).to_le_bytes()],
+        );
+
+        let descriptors = unsafe {
+            core::slice::from_raw_parts(
+                map.descriptors_address as *const u8,
+                (map.descriptor_count * map.descriptor_size) as _,
+            )
+        };
+        self.tag(
+            TAG_EFI_MMAP,
+            &[
+                &(map.descriptor_size as u32).to_le_bytes(),
+                &map.descriptor_version.to_le_bytes(),
+                descriptors,
+            ],
+        );
+    }
+
+    /// Terminates the structure; returns its physical address (below 4GiB)
+    pub fn finish(mut self) -> u32 {
+        self.tag(TAG_END, &[]);
+        let total_size = self.length as u32;
+        self.buffer[..4].copy_from_slice(&total_size.to_le_bytes());
+
+        self.buffer.as_ptr() as u32
+    }
+}
+
+// Note: i386のエントリはページングなしの保護モードで呼ぶ。ロングモードを抜けるには恒等マップされた場所で
+// 互換モード（32ビットコードセグメント）に移ってからCR0.PGを下ろし、EFER.LMEを落とす。
+// このコードは4GiB未満のページにコピーして使うので位置独立に書く（RDI=情報のアドレス、末尾4バイト=エントリ）
+core::arch::global_asm!(
+    ".global MULTIBOOT2_TRAMPOLINE",
+    ".global MULTIBOOT2_TRAMPOLINE_END",
+    ".p2align 4",
+    "MULTIBOOT2_TRAMPOLINE:",
+    ".code64",
+    "lea rax, [rip + 4f]",
+    "mov [rip + 5f + 2], rax",
+    "lgdt [rip + 5f]",
+    "mov esi, edi",
+    "mov edi, [rip + 6f]",
+    "lea rax, [rip + 3f]",
+    "push 0x08",
+    "push rax",
+    "retfq",
+    ".code32",
+    "3:",
+    "mov ax, 0x10",
+    "mov ds, ax",
+    "mov es, ax",
+    "mov fs, ax",
+    "mov gs, ax",
+    "mov ss, ax",
+    "mov eax, cr0",
+    "and eax, 0x7fffffff",
+    "mov cr0, eax",
+    "mov ecx, 0xc0000080",
+    "rdmsr",
+    "and eax, 0xfffffeff",
+    "wrmsr",
+    "mov eax, 0x36d76289",
+    "mov ebx, esi",
+    "jmp edi",
+    ".p2align 3",
+    // null, 32-bit flat code (0x08), 32-bit flat data (0x10)
+    "4:",
+    ".quad 0",
+    ".quad 0x00cf9a000000ffff",
+    ".quad 0x00cf92000000ffff",
+    "5:",
+    ".word 23",
+    ".quad 0",
+    ".p2align 2",
+    "6:",
+    ".long 0",
+    "MULTIBOOT2_TRAMPOLINE_END:",
+    ".code64",
+);
+
+extern "C" {
+    static MULTIBOOT2_TRAMPOLINE: [u8; 0];
+    static MULTIBOOT2_TRAMPOLINE_END: [u8; 0];
+}
+
+/// A copy of the code that leaves long mode for the i386 entry, placed below 4GiB
+pub struct Trampoline {
+    base: u64,
+}
+impl Trampoline {
+    /// one page for the code and one for the stack
+    const PAGES: usize = 2;
+
+    /// Only while boot services are available
+    pub fn new() -> Result<Self, OutOfFrames> {
+        let base = LowUefiFrameAllocator.allocate_frames(Self::PAGES)?;
+        let code = unsafe {
+            let start = MULTIBOOT2_TRAMPOLINE.as_ptr();
+            let length = MULTIBOOT2_TRAMPOLINE_END.as_ptr().offset_from(start) as usize;
+            core::slice::from_raw_parts(start, length)
+        };
+        unsafe {
+            core::ptr::copy_nonoverlapping(code.as_ptr(), base as *mut u8, code.len());
+        }
+
+        Ok(Self { base })
+    }
+
+    /// Switches to `root`, which has to identity map the trampoline, the loader and the kernel,
+    /// and enters the kernel in 32-bit protected mode with paging disabled.
+    ///
+    /// # Safety
+    /// Boot services must have been exited and interrupts disabled.
+    pub unsafe fn enter(self, root: ControlRegister3, entry: u32, info: u32) -> ! {
+        let length = MULTIBOOT2_TRAMPOLINE_END
+            .as_ptr()
+            .offset_from(MULTIBOOT2_TRAMPOLINE.as_ptr()) as u64;
+        ((self.base + length - 4) as *mut u32).write(entry);
+
+        paging::enter(
+            root,
+            self.base,
+            self.base + (Self::PAGES as u64) * PAGE_SIZE,
+            info as _,
+        )
+    }
+}
+
+/// Jumps to the EFI amd64 entry of a kernel that keeps boot services running
+///
+/// # Safety
+/// Boot services must still be available.
+pub unsafe fn enter_efi_amd64(entry: u32, info: u32) -> ! {
+    core::arch::asm!(
+        "mov ebx, {info:e}",
+        "jmp {entry}",
+        info = in(reg) info as u64,
+        entry = in(reg) entry as u64,
+        in("eax") BOOTLOADER_MAGIC,
+        options(noreturn)
+    )
+}
@@ -0,0 +1,232 @@
+//! 4-level page tables built from scratch for handing the machine over to a kernel
+
+use crate::{
+    dma::DmaRegion,
+    load_cr, rdmsr, store_cr,
+    uefi::{EfiAllocateType, EfiMemoryDescriptor},
+    wrmsr, ControlRegister3, PML4Entry, PageDirectoryEntry, PageDirectoryPointerTableEntry,
+    PageTableEntry,
+};
+
+pub const PAGE_SIZE: u64 = 4096;
+pub const LARGE_PAGE_SIZE: u64 = 2 * 1024 * 1024;
+const ENTRY_COUNT: usize = 512;
+
+const PRESENT: u64 = 0x01;
+const WRITABLE: u64 = 0x02;
+const PAGE_SIZE_FLAG: u64 = 0x80;
+const EXECUTE_DISABLE: u64 = 0x8000_0000_0000_0000;
+const ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+const CR0_WP: u64 = 1 << 16;
+const EFER_NXE: u64 = 1 << 11;
+
+/// Page tables could not be allocated
+#[derive(Debug)]
+pub struct OutOfFrames;
+
+/// Source of physical memory for loaded images and page tables.
+/// Every frame must be accessible at its physical address (identity mapped) while the loader runs.
+pub trait FrameAllocator {
+    /// `count` physically contiguous zeroed 4KiB frames
+    fn allocate_frames(&mut self, count: usize) -> Result<u64, OutOfFrames>;
+}
+
+/// Zeroed pages from AllocatePages as EfiLoaderData (so that they stay in use in the final memory
+/// map); `address` is the requested or maximum address depending on `r#type`
+fn allocate_pages(
+    r#type: EfiAllocateType,
+    count: usize,
+    mut address: u64,
+) -> Result<u64, OutOfFrames> {
+    let boot_services = unsafe { (*crate::SYSTEM_TABLE).boot_services };
+    if boot_services.is_null() {
+        return Err(OutOfFrames);
+    }
+
+    let r = unsafe {
+        ((*boot_services).allocate_pages)(
+            r#type,
+            EfiMemoryDescriptor::TYPE_LOADER_DATA,
+            count,
+            &mut address,
+        )
+    };
+    if r != 0 {
+        return Err(OutOfFrames);
+    }
+    // Note: AllocatePagesはゼロクリアしてくれない
+    unsafe {
+        core::ptr::write_bytes(address as *mut u8, 0, count * PAGE_SIZE as usize);
+    }
+
+    Ok(address)
+}
+
+/// Allocates `count` zeroed frames exactly at `address`; fails when any of them is in use.
+/// Only while boot services are available.
+pub fn allocate_frames_at(address: u64, count: usize) -> Result<(), OutOfFrames> {
+    allocate_pages(EfiAllocateType::AllocateAddress, count, address).map(|_| ())
+}
+
+/// AllocatePages; only while boot services are available
+pub struct UefiFrameAllocator;
+impl FrameAllocator for UefiFrameAllocator {
+    fn allocate_frames(&mut self, count: usize) -> Result<u64, OutOfFrames> {
+        allocate_pages(EfiAllocateType::AllocateAnyPages, count, 0)
+    }
+}
+
+/// AllocatePages below 4GiB, for what 32-bit code has to reach; only while boot services are available
+pub struct LowUefiFrameAllocator;
+impl FrameAllocator for LowUefiFrameAllocator {
+    fn allocate_frames(&mut self, count: usize) -> Result<u64, OutOfFrames> {
+        allocate_pages(EfiAllocateType::AllocateMaxAddress, count, 0xffff_ffff)
+    }
+}
+
+/// Frames carved out of the (identity mapped) heap, never freed; usable after ExitBootServices
+pub struct HeapFrameAllocator;
+impl FrameAllocator for HeapFrameAllocator {
+    fn allocate_frames(&mut self, count: usize) -> Result<u64, OutOfFrames> {
+        let frames = DmaRegion::pages(count * PAGE_SIZE as usize).leak();
+
+        Ok(frames.as_ptr() as u64)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageFlags {
+    pub writable: bool,
+    pub executable: bool,
+}
+
+/// A page table hierarchy under construction (not the one in CR3)
+pub struct AddressSpace {
+    root: u64,
+}
+impl AddressSpace {
+    pub fn new(allocator: &mut dyn FrameAllocator) -> Result<Self, OutOfFrames> {
+        Ok(Self {
+            root: allocator.allocate_frames(1)?,
+        })
+    }
+
+    #[inline]
+    pub const fn root(&self) -> ControlRegister3 {
+        ControlRegister3::new(self.root)
+    }
+
+    #[inline]
+    fn table(address: u64) -> &'static mut [u64; ENTRY_COUNT] {
+        unsafe { &mut *(address as *mut [u64; ENTRY_COUNT]) }
+    }
+
+    #[inline]
+    const fn index(address: u64, level: u32) -> usize {
+        ((address >> (12 + 9 * level)) & 0x1ff) as usize
+    }
+
+    /// Table referred by `entry`, created when not present.
+    /// Upper level entries are writable and executable; the permissions are decided by the leaves.
+    fn next_table(
+        entry: &mut u64,
+        level: u32,
+        allocator: &mut dyn FrameAllocator,
+    ) -> Result<&'static mut [u64; ENTRY_COUNT], OutOfFrames> {
+        if (*entry & PRESENT) == 0 {
+            let table = allocator.allocate_frames(1)?;
+            *entry = match level {
+                4 => PML4Entry::new(table).writable().0,
+                3 => PageDirectoryPointerTableEntry::new(table).writable().0,
+                _ => PageDirectoryEntry::new(table).writable().0,
+            };
+        } else if level == 2 && (*entry & PAGE_SIZE_FLAG) != 0 {
+            // Note: 2MiBページの一部だけ差し替える場合は、同じ内容の4KiBページに分割してから書き換える
+            let table = allocator.allocate_frames(1)?;
+            let base = *entry & ADDRESS_MASK & !(LARGE_PAGE_SIZE - 1);
+            for (n, e) in Self::table(table).iter_mut().enumerate() {
+                *e = PageTableEntry::new(base + n as u64 * PAGE_SIZE).0
+                    | (*entry & (WRITABLE | EXECUTE_DISABLE));
+            }
+            *entry = PageDirectoryEntry::new(table).writable().0;
+        }
+
+        Ok(Self::table(*entry & ADDRESS_MASK))
+    }
+
+    /// Maps [`start`, `end`) (rounded out to 2MiB) to the same physical addresses with writable
+    /// and executable 2MiB pages. 2MiB ranges already mapped with 4KiB pages are left as is.
+    pub fn identity_map(
+        &mut self,
+        start: u64,
+        end: u64,
+        allocator: &mut dyn FrameAllocator,
+    ) -> Result<(), OutOfFrames> {
+        let mut address = start & !(LARGE_PAGE_SIZE - 1);
+        while address < end {
+            let pml4 = Self::table(self.root);
+            let pdpt = Self::next_table(&mut pml4[Self::index(address, 3)], 4, allocator)?;
+            let pd = Self::next_table(&mut pdpt[Self::index(address, 2)], 3, allocator)?;
+            let entry = &mut pd[Self::index(address, 1)];
+            if (*entry & PRESENT) == 0 || (*entry & PAGE_SIZE_FLAG) != 0 {
+                *entry = PageDirectoryEntry::new_large_page(address).writable().0;
+            }
+
+            address += LARGE_PAGE_SIZE;
+        }
+
+        Ok(())
+    }
+
+    /// Maps the 4KiB page at `virtual_address` to `physical_address`, replacing any previous mapping
+    pub fn map(
+        &mut self,
+        virtual_address: u64,
+        physical_address: u64,
+        flags: PageFlags,
+        allocator: &mut dyn FrameAllocator,
+    ) -> Result<(), OutOfFrames> {
+        let pml4 = Self::table(self.root);
+        let pdpt = Self::next_table(&mut pml4[Self::index(virtual_address, 3)], 4, allocator)?;
+        let pd = Self::next_table(&mut pdpt[Self::index(virtual_address, 2)], 3, allocator)?;
+        let pt = Self::next_table(&mut pd[Self::index(virtual_address, 1)], 2, allocator)?;
+
+        let mut entry = PageTableEntry::new(physical_address);
+        if flags.writable {
+            entry = entry.writable();
+        }
+        if !flags.executable {
+            entry = entry.execute_disable();
+        }
+        pt[Self::index(virtual_address, 0)] = entry.0;
+
+        Ok(())
+    }
+}
+
+/// Switches to `root` and jumps to `entry` as `extern "sysv64" fn(argument) -> !` on the stack
+/// ending at `stack_top` (16-byte aligned).
+///
+/// # Safety
+/// The code and data of the loader that are still in use (this function, the stack top) must be
+/// identity mapped in `root`. Interrupts must be disabled.
+pub unsafe fn enter(root: ControlRegister3, entry: u64, stack_top: u64, argument: u64) -> ! {
+    // Note: 非実行ページ（XDビット）と書き込み禁止ページ（Ring0でも）を有効にしておく
+    wrmsr!(efer, rdmsr!(efer) | EFER_NXE);
+    store_cr!(0, load_cr!(0) | CR0_WP);
+
+    // Note: 呼び出し直後と同じくRSP+8が16バイト境界になるように、ダミーの戻りアドレスを積む
+    core::arch::asm!(
+        "mov cr3, {root}",
+        "mov rsp, {stack_top}",
+        "xor ebp, ebp",
+        "push 0",
+        "jmp {entry}",
+        root = in(reg) root.0,
+        stack_top = in(reg) stack_top,
+        entry = in(reg) entry,
+        in("rdi") argument,
+        options(noreturn)
+    )
+}
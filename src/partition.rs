@@ -0,0 +1,673 @@
+//! MBR and GUID Partition Table parsing (UEFI 2.10 section 5)
+
+use crate::{
+    block::{BlockDevice, BlockError},
+    uefi::EfiGuid,
+};
+use alloc::{rc::Rc, string::String, vec::Vec};
+use core::cell::RefCell;
+
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+const MBR_PARTITION_TABLE_OFFSET: usize = 446;
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xee;
+/// extended partition containers (the logical partitions inside are not walked)
+const MBR_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_HEADER_MIN_SIZE: usize = 92;
+const GPT_NAME_LENGTH: usize = 36;
+/// upper bound for the entry array to read (the usual size is 128 entries x 128 bytes)
+const MAX_ENTRY_ARRAY_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug)]
+#[allow(dead_code)]
+pub enum PartitionError {
+    Block(BlockError),
+    /// LBA 0 has no boot signature
+    NoMbr,
+    /// the device is too small to hold a GPT (protective MBR, primary and backup header)
+    DeviceTooSmall(u64),
+    /// neither the primary nor the backup header (with its entry array) is valid
+    NoValidGpt,
+}
+impl From<BlockError> for PartitionError {
+    fn from(e: BlockError) -> Self {
+        Self::Block(e)
+    }
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if (c & 1) != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+};
+
+/// CRC-32 (IEEE 802.3) as used by the GPT header and entry array
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |c, &b| {
+        CRC32_TABLE[((c ^ b as u32) & 0xff) as usize] ^ (c >> 8)
+    })
+}
+
+#[inline]
+fn u32_at(b: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(b[offset..offset + 4].try_into().unwrap())
+}
+
+#[inline]
+fn u64_at(b: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(b[offset..offset + 8].try_into().unwrap())
+}
+
+#[inline]
+fn guid_at(b: &[u8], offset: usize) -> EfiGuid {
+    EfiGuid::from_bytes(b[offset..offset + 16].try_into().unwrap())
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GptHeader {
+    pub my_lba: u64,
+    pub alternate_lba: u64,
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    pub disk_guid: EfiGuid,
+    pub partition_entry_lba: u64,
+    pub number_of_partition_entries: u32,
+    pub size_of_partition_entry: u32,
+    pub partition_entry_array_crc32: u32,
+}
+impl GptHeader {
+    /// None unless the signature, size, CRC and location (`lba`) are consistent
+    fn parse(block: &[u8], lba: u64) -> Option<Self> {
+        if &block[..8] != GPT_SIGNATURE {
+            return None;
+        }
+
+        let header_size = u32_at(block, 12) as usize;
+        if !(GPT_HEADER_MIN_SIZE..=block.len()).contains(&header_size) {
+            return None;
+        }
+        // the CRC is calculated with the CRC field itself zeroed
+        let mut header = Vec::from(&block[..header_size]);
+        header[16..20].fill(0);
+        if crc32(&header) != u32_at(block, 16) {
+            return None;
+        }
+
+        let h = Self {
+            my_lba: u64_at(block, 24),
+            alternate_lba: u64_at(block, 32),
+            first_usable_lba: u64_at(block, 40),
+            last_usable_lba: u64_at(block, 48),
+            disk_guid: guid_at(block, 56),
+            partition_entry_lba: u64_at(block, 72),
+            number_of_partition_entries: u32_at(block, 80),
+            size_of_partition_entry: u32_at(block, 84),
+            partition_entry_array_crc32: u32_at(block, 88),
+        };
+        let entry_size = h.size_of_partition_entry as usize;
+        let valid = h.my_lba == lba
+            && entry_size >= 128
+            && entry_size.is_power_of_two()
+            && h.entry_array_size() <= MAX_ENTRY_ARRAY_SIZE;
+
+        valid.then_some(h)
+    }
+
+    #[inline]
+    const fn entry_array_size(&self) -> usize {
+        self.number_of_partition_entries as usize * self.size_of_partition_entry as usize
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum PartitionKind {
+    Gpt {
+        type_guid: EfiGuid,
+        unique_guid: EfiGuid,
+        attributes: u64,
+        /// UTF-16LE, NUL padded
+        name: [u16; GPT_NAME_LENGTH],
+    },
+    Mbr {
+        /// partition type (system ID) byte
+        system_id: u8,
+        bootable: bool,
+    },
+}
+
+/// A used entry of the GPT partition entry array or the MBR partition table
+#[derive(Debug, Clone)]
+pub struct PartitionEntry {
+    /// index in the entry array (0-3 for MBR)
+    pub index: u32,
+    pub kind: PartitionKind,
+    pub starting_lba: u64,
+    /// inclusive
+    pub ending_lba: u64,
+}
+impl PartitionEntry {
+    /// None for unused entries (zero type GUID)
+    fn parse_gpt(b: &[u8], index: u32) -> Option<Self> {
+        let type_guid = guid_at(b, 0);
+        if type_guid == EfiGuid::ZERO {
+            return None;
+        }
+
+        let mut name = [0; GPT_NAME_LENGTH];
+        for (n, c) in name.iter_mut().enumerate() {
+            *c = u16::from_le_bytes([b[56 + n * 2], b[57 + n * 2]]);
+        }
+
+        Some(Self {
+            index,
+            kind: PartitionKind::Gpt {
+                type_guid,
+                unique_guid: guid_at(b, 16),
+                attributes: u64_at(b, 48),
+                name,
+            },
+            starting_lba: u64_at(b, 32),
+            ending_lba: u64_at(b, 40),
+        })
+    }
+
+    /// None for unused entries and extended partition containers
+    fn parse_mbr(b: &[u8], index: u32) -> Option<Self> {
+        let system_id = b[4];
+        let starting_lba = u32_at(b, 8) as u64;
+        let sectors = u32_at(b, 12) as u64;
+        if system_id == 0 || MBR_TYPES_EXTENDED.contains(&system_id) || sectors == 0 {
+            return None;
+        }
+
+        Some(Self {
+            index,
+            kind: PartitionKind::Mbr {
+                system_id,
+                bootable: b[0] == 0x80,
+            },
+            starting_lba,
+            ending_lba: starting_lba + sectors - 1,
+        })
+    }
+
+    #[inline]
+    pub const fn block_count(&self) -> u64 {
+        self.ending_lba + 1 - self.starting_lba
+    }
+
+    /// Partition name with invalid UTF-16 replaced (empty for MBR partitions)
+    pub fn name(&self) -> String {
+        let PartitionKind::Gpt { name, .. } = &self.kind else {
+            return String::new();
+        };
+        let length = name.iter().position(|&c| c == 0).unwrap_or(GPT_NAME_LENGTH);
+
+        char::decode_utf16(name[..length].iter().copied())
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum PartitioningScheme {
+    Gpt {
+        /// the header that was used (the backup one if the primary was broken)
+        header: GptHeader,
+        used_backup: bool,
+    },
+    Mbr {
+        disk_signature: u32,
+    },
+}
+
+#[derive(Debug)]
+pub struct PartitionTable {
+    pub scheme: PartitioningScheme,
+    pub entries: Vec<PartitionEntry>,
+}
+impl PartitionTable {
+    /// Reads the partition table of `device`. A protective MBR (0xEE partition) selects the GPT,
+    /// falling back to the backup header at the end of the disk when the primary header or its
+    /// entry array fails validation. Otherwise the four primary MBR partitions are returned.
+    pub fn read<D: BlockDevice + ?Sized>(device: &mut D) -> Result<Self, PartitionError> {
+        let mbr = device.read_to_vec(0, 1)?;
+        if mbr[510..512] != MBR_SIGNATURE {
+            return Err(PartitionError::NoMbr);
+        }
+        let mbr_entries = &mbr[MBR_PARTITION_TABLE_OFFSET..][..64];
+        if !mbr_entries
+            .chunks(16)
+            .any(|e| e[4] == MBR_TYPE_GPT_PROTECTIVE)
+        {
+            let entries = mbr_entries
+                .chunks(16)
+                .enumerate()
+                .filter_map(|(n, e)| PartitionEntry::parse_mbr(e, n as _))
+                .filter(|e| e.ending_lba < device.block_count())
+                .collect();
+
+            return Ok(Self {
+                scheme: PartitioningScheme::Mbr {
+                    disk_signature: u32_at(&mbr, 440),
+                },
+                entries,
+            });
+        }
+
+        if device.block_count() < 3 {
+            return Err(PartitionError::DeviceTooSmall(device.block_count()));
+        }
+
+        let primary = Self::read_at(device, 1)?;
+        if let Some((header, entries)) = primary {
+            return Ok(Self {
+                scheme: PartitioningScheme::Gpt {
+                    header,
+                    used_backup: false,
+                },
+                entries,
+            });
+        }
+
+        // Note: プライマリのヘッダ自体が壊れているとalternate_lbaも信用できないので、ディスク末尾を見る
+        let backup_lba = device.block_count() - 1;
+        match Self::read_at(device, backup_lba)? {
+            Some((header, entries)) => Ok(Self {
+                scheme: PartitioningScheme::Gpt {
+                    header,
+                    used_backup: true,
+                },
+                entries,
+            }),
+            None => Err(PartitionError::NoValidGpt),
+        }
+    }
+
+    /// Reads and validates the header at `lba` and its entry array
+    fn read_at<D: BlockDevice + ?Sized>(
+        device: &mut D,
+        lba: u64,
+    ) -> Result<Option<(GptHeader, Vec<PartitionEntry>)>, BlockError> {
+        let Some(header) = GptHeader::parse(&device.read_to_vec(lba, 1)?, lba) else {
+            return Ok(None);
+        };
+
+        let size = header.entry_array_size();
+        let blocks = size.div_ceil(device.block_size()) as u64;
+        let array = match device.read_to_vec(header.partition_entry_lba, blocks) {
+            Ok(a) => a,
+            Err(BlockError::OutOfRange { .. }) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if crc32(&array[..size]) != header.partition_entry_array_crc32 {
+            return Ok(None);
+        }
+
+        let entries = array[..size]
+            .chunks(header.size_of_partition_entry as usize)
+            .enumerate()
+            .filter_map(|(n, e)| PartitionEntry::parse_gpt(e, n as _))
+            .filter(|e| e.starting_lba <= e.ending_lba && e.ending_lba < device.block_count())
+            .collect();
+
+        Ok(Some((header, entries)))
+    }
+}
+
+/// A partition exposed as a block device of its own (LBA 0 = the partition's first block)
+pub struct Partition<D: BlockDevice + ?Sized> {
+    device: Rc<RefCell<D>>,
+    entry: PartitionEntry,
+}
+impl<D: BlockDevice + ?Sized> Partition<D> {
+    pub fn new(device: Rc<RefCell<D>>, entry: PartitionEntry) -> Self {
+        Self { device, entry }
+    }
+
+    #[inline]
+    pub const fn entry(&self) -> &PartitionEntry {
+        &self.entry
+    }
+}
+impl<D: BlockDevice + ?Sized> BlockDevice for Partition<D> {
+    fn block_size(&self) -> usize {
+        self.device.borrow().block_size()
+    }
+
+    #[inline]
+    fn block_count(&self) -> u64 {
+        self.entry.block_count()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.device.borrow().is_read_only()
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.check_range(lba, buf.len())?;
+        self.device
+            .borrow_mut()
+            .read_blocks(self.entry.starting_lba + lba, buf)
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        self.check_range(lba, buf.len())?;
+        self.device
+            .borrow_mut()
+            .write_blocks(self.entry.starting_lba + lba, buf)
+    }
+
+    fn flush(&mut self) -> Result<(), BlockError> {
+        self.device.borrow_mut().flush()
+    }
+}
+
+/// Reads the partition table of `device` and wraps every partition as a block device
+pub fn partitions<D: BlockDevice + ?Sized>(
+    device: &Rc<RefCell<D>>,
+) -> Result<(PartitionTable, Vec<Partition<D>>), PartitionError> {
+    let table = PartitionTable::read(&mut *device.borrow_mut())?;
+    let partitions = table
+        .entries
+        .iter()
+        .map(|e| Partition::new(device.clone(), e.clone()))
+        .collect();
+
+    Ok((table, partitions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    const BLOCK_SIZE: usize = 512;
+    const DISK_BLOCKS: u64 = 128;
+    const ENTRY_COUNT: usize = 128;
+    const ENTRY_SIZE: usize = 128;
+    /// blocks taken by the entry array
+    const ENTRY_BLOCKS: u64 = (ENTRY_COUNT * ENTRY_SIZE / BLOCK_SIZE) as u64;
+
+    const TYPE_ESP: EfiGuid = EfiGuid {
+        data1: 0xc12a7328,
+        data2: 0xf81f,
+        data3: 0x11d2,
+        data4: [0xba, 0x4b, 0x00, 0xa0, 0xc9, 0x3e, 0xc9, 0x3b],
+    };
+    const TYPE_BASIC_DATA: EfiGuid = EfiGuid {
+        data1: 0xebd0a0a2,
+        data2: 0xb9e5,
+        data3: 0x4433,
+        data4: [0x87, 0xc0, 0x68, 0xb6, 0xb7, 0x26, 0x99, 0xc7],
+    };
+
+    struct MemoryDisk(Vec<u8>);
+    impl MemoryDisk {
+        fn block_mut(&mut self, lba: u64) -> &mut [u8] {
+            &mut self.0[lba as usize * BLOCK_SIZE..][..BLOCK_SIZE]
+        }
+    }
+    impl BlockDevice for MemoryDisk {
+        fn block_size(&self) -> usize {
+            BLOCK_SIZE
+        }
+
+        fn block_count(&self) -> u64 {
+            (self.0.len() / BLOCK_SIZE) as u64
+        }
+
+        fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+            self.check_range(lba, buf.len())?;
+            buf.copy_from_slice(&self.0[lba as usize * BLOCK_SIZE..][..buf.len()]);
+            Ok(())
+        }
+
+        fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+            self.check_range(lba, buf.len())?;
+            self.0[lba as usize * BLOCK_SIZE..][..buf.len()].copy_from_slice(buf);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<(), BlockError> {
+            Ok(())
+        }
+    }
+
+    fn guid_bytes(g: &EfiGuid) -> [u8; 16] {
+        let mut b = [0; 16];
+        b[0..4].copy_from_slice(&g.data1.to_le_bytes());
+        b[4..6].copy_from_slice(&g.data2.to_le_bytes());
+        b[6..8].copy_from_slice(&g.data3.to_le_bytes());
+        b[8..16].copy_from_slice(&g.data4);
+        b
+    }
+
+    fn mbr(disk: &mut MemoryDisk, entries: &[(u8, u32, u32)]) {
+        let b = disk.block_mut(0);
+        for (n, &(system_id, start, sectors)) in entries.iter().enumerate() {
+            let e = &mut b[MBR_PARTITION_TABLE_OFFSET + n * 16..][..16];
+            e[4] = system_id;
+            e[8..12].copy_from_slice(&start.to_le_bytes());
+            e[12..16].copy_from_slice(&sectors.to_le_bytes());
+        }
+        b[510..512].copy_from_slice(&MBR_SIGNATURE);
+    }
+
+    /// A disk with a protective MBR, primary and backup GPT and two partitions (an ESP and "data")
+    fn gpt_disk() -> MemoryDisk {
+        let mut disk = MemoryDisk(vec![0; DISK_BLOCKS as usize * BLOCK_SIZE]);
+        mbr(
+            &mut disk,
+            &[(MBR_TYPE_GPT_PROTECTIVE, 1, DISK_BLOCKS as u32 - 1)],
+        );
+
+        let mut array = vec![0; ENTRY_COUNT * ENTRY_SIZE];
+        for (n, (type_guid, start, end, name)) in [
+            (TYPE_ESP, 34, 63, "EFI system"),
+            (TYPE_BASIC_DATA, 64, 93, "data"),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let e = &mut array[n * ENTRY_SIZE..][..ENTRY_SIZE];
+            e[0..16].copy_from_slice(&guid_bytes(&type_guid));
+            e[16] = n as u8 + 1;
+            e[32..40].copy_from_slice(&u64::to_le_bytes(start));
+            e[40..48].copy_from_slice(&u64::to_le_bytes(end));
+            for (i, c) in name.encode_utf16().enumerate() {
+                e[56 + i * 2..][..2].copy_from_slice(&c.to_le_bytes());
+            }
+        }
+        let array_crc = crc32(&array);
+
+        let last = DISK_BLOCKS - 1;
+        for (my_lba, alternate_lba, entry_lba) in [(1, last, 2), (last, 1, last - ENTRY_BLOCKS)] {
+            disk.0[entry_lba as usize * BLOCK_SIZE..][..array.len()].copy_from_slice(&array);
+
+            let h = disk.block_mut(my_lba);
+            h[0..8].copy_from_slice(GPT_SIGNATURE);
+            h[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+            h[12..16].copy_from_slice(&(GPT_HEADER_MIN_SIZE as u32).to_le_bytes());
+            h[24..32].copy_from_slice(&u64::to_le_bytes(my_lba));
+            h[32..40].copy_from_slice(&u64::to_le_bytes(alternate_lba));
+            h[40..48].copy_from_slice(&u64::to_le_bytes(2 + ENTRY_BLOCKS));
+            h[48..56].copy_from_slice(&u64::to_le_bytes(last - ENTRY_BLOCKS - 1));
+            h[56] = 0x42;
+            h[72..80].copy_from_slice(&u64::to_le_bytes(entry_lba));
+            h[80..84].copy_from_slice(&(ENTRY_COUNT as u32).to_le_bytes());
+            h[84..88].copy_from_slice(&(ENTRY_SIZE as u32).to_le_bytes());
+            h[88..92].copy_from_slice(&array_crc.to_le_bytes());
+            let header_crc = crc32(&h[..GPT_HEADER_MIN_SIZE]);
+            h[16..20].copy_from_slice(&header_crc.to_le_bytes());
+        }
+
+        disk
+    }
+
+    fn assert_gpt_entries(table: &PartitionTable) {
+        let e = &table.entries;
+        assert_eq!(e.len(), 2);
+        assert!(matches!(e[0].kind, PartitionKind::Gpt { type_guid, .. } if type_guid == TYPE_ESP));
+        assert_eq!(
+            (
+                e[0].index,
+                e[0].starting_lba,
+                e[0].ending_lba,
+                e[0].name().as_str()
+            ),
+            (0, 34, 63, "EFI system")
+        );
+        assert!(matches!(
+            e[1].kind,
+            PartitionKind::Gpt { type_guid, unique_guid, .. }
+                if type_guid == TYPE_BASIC_DATA && unique_guid.data1 == 2
+        ));
+        assert_eq!(
+            (e[1].index, e[1].block_count(), e[1].name().as_str()),
+            (1, 30, "data")
+        );
+    }
+
+    fn used_backup(table: &PartitionTable) -> bool {
+        match table.scheme {
+            PartitioningScheme::Gpt { used_backup, .. } => used_backup,
+            PartitioningScheme::Mbr { .. } => panic!("not a GPT"),
+        }
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn primary_gpt() {
+        let table = PartitionTable::read(&mut gpt_disk()).unwrap();
+        assert!(!used_backup(&table));
+        assert_gpt_entries(&table);
+    }
+
+    #[test]
+    fn corrupted_primary_header_falls_back_to_backup() {
+        let mut disk = gpt_disk();
+        // disk GUID (covered by the header CRC)
+        disk.block_mut(1)[60] ^= 0xff;
+
+        let table = PartitionTable::read(&mut disk).unwrap();
+        assert!(used_backup(&table));
+        assert!(matches!(
+            table.scheme,
+            PartitioningScheme::Gpt { header, .. } if header.my_lba == DISK_BLOCKS - 1
+        ));
+        assert_gpt_entries(&table);
+    }
+
+    #[test]
+    fn corrupted_entry_array() {
+        let mut disk = gpt_disk();
+        // name of the first primary entry
+        disk.block_mut(2)[60] ^= 0xff;
+        let table = PartitionTable::read(&mut disk).unwrap();
+        assert!(used_backup(&table));
+        assert_gpt_entries(&table);
+
+        disk.block_mut(DISK_BLOCKS - 1 - ENTRY_BLOCKS)[60] ^= 0xff;
+        assert!(matches!(
+            PartitionTable::read(&mut disk),
+            Err(PartitionError::NoValidGpt)
+        ));
+    }
+
+    #[test]
+    fn bad_protective_mbr() {
+        let mut disk = gpt_disk();
+        disk.block_mut(0)[511] = 0;
+        assert!(matches!(
+            PartitionTable::read(&mut disk),
+            Err(PartitionError::NoMbr)
+        ));
+    }
+
+    #[test]
+    fn too_small_for_gpt() {
+        let mut disk = MemoryDisk(vec![0; 2 * BLOCK_SIZE]);
+        mbr(&mut disk, &[(MBR_TYPE_GPT_PROTECTIVE, 1, 1)]);
+        assert!(matches!(
+            PartitionTable::read(&mut disk),
+            Err(PartitionError::DeviceTooSmall(2))
+        ));
+    }
+
+    #[test]
+    fn plain_mbr() {
+        let mut disk = MemoryDisk(vec![0; DISK_BLOCKS as usize * BLOCK_SIZE]);
+        // FAT32 (LBA), an extended partition and one running past the end of the disk
+        mbr(
+            &mut disk,
+            &[(0x0c, 8, 32), (0x05, 40, 8), (0x83, 48, 16), (0x83, 96, 64)],
+        );
+        disk.block_mut(0)[446] = 0x80;
+        disk.block_mut(0)[440..444].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+
+        let table = PartitionTable::read(&mut disk).unwrap();
+        assert!(matches!(
+            table.scheme,
+            PartitioningScheme::Mbr {
+                disk_signature: 0x1234_5678
+            }
+        ));
+        let e = &table.entries;
+        assert_eq!(e.len(), 2);
+        assert!(matches!(
+            e[0].kind,
+            PartitionKind::Mbr {
+                system_id: 0x0c,
+                bootable: true
+            }
+        ));
+        assert_eq!((e[0].index, e[0].starting_lba, e[0].ending_lba), (0, 8, 39));
+        assert!(matches!(
+            e[1].kind,
+            PartitionKind::Mbr {
+                system_id: 0x83,
+                bootable: false
+            }
+        ));
+        assert_eq!((e[1].index, e[1].block_count()), (2, 16));
+    }
+
+    #[test]
+    fn partition_block_device() {
+        let mut disk = gpt_disk();
+        disk.block_mut(64).fill(0xaa);
+        let disk = Rc::new(RefCell::new(disk));
+
+        let (_, mut parts) = partitions(&disk).unwrap();
+        let data = &mut parts[1];
+        assert_eq!(data.block_count(), 30);
+        assert!(data.read_to_vec(0, 1).unwrap().iter().all(|&b| b == 0xaa));
+        assert!(matches!(
+            data.read_to_vec(29, 2),
+            Err(BlockError::OutOfRange { lba: 29, blocks: 2 })
+        ));
+
+        data.write_blocks(29, &[0x55; BLOCK_SIZE]).unwrap();
+        assert!(disk.borrow_mut().block_mut(93).iter().all(|&b| b == 0x55));
+    }
+}
@@ -1,48 +1,431 @@
-use crate::{in32, out32};
+use crate::{acpi, apic, in32, out32};
+use alloc::{vec, vec::Vec};
 
+/// Access method to the PCI configuration space
+pub trait ConfigAccess {
+    /// `offset` is in bytes and must be dword aligned
+    fn read(&self, device: &DeviceIdentifier, offset: u16) -> u32;
+    fn write(&self, device: &DeviceIdentifier, offset: u16, value: u32);
+    /// accessible size of the configuration space (256 or 4096)
+    fn config_space_size(&self, device: &DeviceIdentifier) -> u16;
+    /// (segment group, first bus number) of each hierarchy reachable through this method
+    fn root_buses(&self) -> Vec<(u16, u8)> {
+        vec![(0, 0)]
+    }
+}
+
+static mut CONFIG_ACCESS: &'static dyn ConfigAccess = &PortIOConfigAccess;
+
+#[inline]
+pub fn config_access() -> &'static dyn ConfigAccess {
+    unsafe { CONFIG_ACCESS }
+}
+
+/// Replaces the access method used by `DeviceIdentifier` (e.g. with `EcamConfigAccess` after MCFG is found)
+pub unsafe fn set_config_access(access: &'static dyn ConfigAccess) {
+    CONFIG_ACCESS = access;
+}
+
+/// Legacy configuration mechanism #1 (port 0xCF8/0xCFC): segment 0 and the first 256 bytes only
+pub struct PortIOConfigAccess;
+impl PortIOConfigAccess {
+    const fn address(device: &DeviceIdentifier, offset: u16) -> u32 {
+        0x8000_0000
+            | ((device.bus as u32) << 16)
+            | (((device.device as u32) & 0x1f) << 11)
+            | (((device.function as u32) & 0x07) << 8)
+            | ((offset as u32) & 0xfc)
+    }
+
+    const fn is_accessible(device: &DeviceIdentifier, offset: u16) -> bool {
+        device.segment == 0 && offset < 256
+    }
+}
+impl ConfigAccess for PortIOConfigAccess {
+    fn read(&self, device: &DeviceIdentifier, offset: u16) -> u32 {
+        if !Self::is_accessible(device, offset) {
+            return 0xffff_ffff;
+        }
+
+        unsafe {
+            out32!(0xcf8, Self::address(device, offset));
+            in32!(0xcfc)
+        }
+    }
+
+    fn write(&self, device: &DeviceIdentifier, offset: u16, value: u32) {
+        if !Self::is_accessible(device, offset) {
+            return;
+        }
+
+        unsafe {
+            out32!(0xcf8, Self::address(device, offset));
+            out32!(0xcfc, value);
+        }
+    }
+
+    fn config_space_size(&self, _device: &DeviceIdentifier) -> u16 {
+        256
+    }
+}
+
+/// A memory mapped bus range of a PCI segment group
+#[derive(Debug, Clone)]
+pub struct EcamRegion {
+    /// address corresponding to bus 0 (even if `start_bus` is not 0)
+    pub base_address: u64,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+impl EcamRegion {
+    /// address of the function's configuration space, if the bus is in this region
+    fn address_of(&self, device: &DeviceIdentifier) -> Option<usize> {
+        if device.segment != self.segment || !(self.start_bus..=self.end_bus).contains(&device.bus)
+        {
+            return None;
+        }
+
+        // Note: MCFGのベースアドレスはバス0に対応する(start_busの位置ではない)
+        Some(
+            (self.base_address
+                + ((device.bus as u64) << 20)
+                + (((device.device & 0x1f) as u64) << 15)
+                + (((device.function & 0x07) as u64) << 12)) as usize,
+        )
+    }
+}
+
+/// PCI Express Enhanced Configuration Access Mechanism.
+/// Buses not covered by any region fall back to the legacy port access.
+pub struct EcamConfigAccess {
+    regions: Vec<EcamRegion>,
+}
+impl EcamConfigAccess {
+    pub fn from_mcfg(table: &acpi::MemoryMappedConfigurationTable) -> Self {
+        Self {
+            regions: table
+                .entries()
+                .iter()
+                .map(|e| EcamRegion {
+                    base_address: e.base_address(),
+                    segment: e.segment_group,
+                    start_bus: e.start_bus,
+                    end_bus: e.end_bus,
+                })
+                .collect(),
+        }
+    }
+
+    // Note: UEFIが作ったページテーブル（恒等写像）のままMMIO領域を触る前提
+    fn address_of(&self, device: &DeviceIdentifier) -> Option<usize> {
+        self.regions.iter().find_map(|r| r.address_of(device))
+    }
+}
+impl ConfigAccess for EcamConfigAccess {
+    fn read(&self, device: &DeviceIdentifier, offset: u16) -> u32 {
+        match self.address_of(device) {
+            Some(a) if offset < 4096 => unsafe {
+                core::ptr::read_volatile((a + (offset as usize & !0x03)) as *const u32)
+            },
+            Some(_) => 0xffff_ffff,
+            None => PortIOConfigAccess.read(device, offset),
+        }
+    }
+
+    fn write(&self, device: &DeviceIdentifier, offset: u16, value: u32) {
+        match self.address_of(device) {
+            Some(a) if offset < 4096 => unsafe {
+                core::ptr::write_volatile((a + (offset as usize & !0x03)) as *mut u32, value)
+            },
+            Some(_) => (),
+            None => PortIOConfigAccess.write(device, offset, value),
+        }
+    }
+
+    fn config_space_size(&self, device: &DeviceIdentifier) -> u16 {
+        if self.address_of(device).is_some() {
+            4096
+        } else {
+            PortIOConfigAccess.config_space_size(device)
+        }
+    }
+
+    fn root_buses(&self) -> Vec<(u16, u8)> {
+        // Note: MCFGに載っていなくてもセグメント0のバス0はポートI/Oで届く
+        let mut roots: Vec<_> = self
+            .regions
+            .iter()
+            .map(|r| (r.segment, r.start_bus))
+            .chain([(0, 0)])
+            .collect();
+        roots.sort_unstable();
+        roots.dedup_by_key(|&mut (segment, _)| segment);
+
+        roots
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct DeviceIdentifier {
+    pub segment: u16,
     pub bus: u8,
     pub device: u8,
     pub function: u8,
 }
+impl core::fmt::Display for DeviceIdentifier {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:04x}:{:02x}:{:02x}.{}",
+            self.segment, self.bus, self.device, self.function
+        )
+    }
+}
+impl core::fmt::Debug for DeviceIdentifier {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Display::fmt(self, f)
+    }
+}
 impl DeviceIdentifier {
-    pub fn read_config(&self, dword_offset: u8) -> u32 {
-        let addr = 0x8000_0000
-            | ((self.bus as u32) << 16)
-            | (((self.device as u32) & 0x1f) << 11)
-            | (((self.function as u32) & 0x07) << 8)
-            | (((dword_offset as u32) & 0x3f) << 2);
+    #[inline]
+    pub const fn with_function(&self, function: u8) -> Self {
+        Self { function, ..*self }
+    }
 
-        unsafe {
-            out32!(0xcf8, addr);
-            in32!(0xcfc)
+    pub fn read_config(&self, dword_offset: u16) -> u32 {
+        config_access().read(self, dword_offset << 2)
+    }
+
+    pub fn write_config(&self, dword_offset: u16, value: u32) {
+        config_access().write(self, dword_offset << 2, value);
+    }
+
+    /// `offset` is in bytes
+    pub fn read_config_u8(&self, offset: u16) -> u8 {
+        (self.read_config(offset >> 2) >> ((offset & 0x03) * 8)) as _
+    }
+
+    /// `offset` is in bytes and must be 2-byte aligned
+    pub fn read_config_u16(&self, offset: u16) -> u16 {
+        (self.read_config(offset >> 2) >> ((offset & 0x02) * 8)) as _
+    }
+
+    /// Read-modify-write of the containing dword.
+    /// Note that RW1C bits in the other bytes (e.g. Status) are written back as read.
+    pub fn write_config_u8(&self, offset: u16, value: u8) {
+        let shift = (offset & 0x03) * 8;
+        let v = self.read_config(offset >> 2) & !(0xff << shift);
+        self.write_config(offset >> 2, v | ((value as u32) << shift));
+    }
+
+    /// Read-modify-write of the containing dword (`offset` must be 2-byte aligned).
+    /// Note that RW1C bits in the other half (e.g. Status) are written back as read.
+    pub fn write_config_u16(&self, offset: u16, value: u16) {
+        let shift = (offset & 0x02) * 8;
+        let v = self.read_config(offset >> 2) & !(0xffff << shift);
+        self.write_config(offset >> 2, v | ((value as u32) << shift));
+    }
+
+    /// 4096 if the extended configuration space is reachable
+    pub fn config_space_size(&self) -> u16 {
+        config_access().config_space_size(self)
+    }
+
+    pub fn read_ids(&self) -> DeviceIds {
+        let v = self.read_config(0);
+
+        DeviceIds {
+            vendor_id: v as _,
+            device_id: (v >> 16) as _,
         }
     }
 
-    pub fn read_device_vendor_ids(&self) -> [u16; 2] {
-        unsafe { core::mem::transmute(self.read_config(0)) }
+    pub fn read_command(&self) -> Command {
+        Command(self.read_config(1) as _)
+    }
+
+    pub fn write_command(&self, command: Command) {
+        // writes zeros to the Status half so that no error bit is cleared
+        self.write_config(1, command.0 as _);
+    }
+
+    /// Sets `flags` in the Command register, keeping the others
+    pub fn enable(&self, flags: Command) {
+        self.write_command(self.read_command() | flags);
+    }
+
+    /// Clears `flags` in the Command register, keeping the others
+    pub fn disable(&self, flags: Command) {
+        self.write_command(self.read_command() & !flags);
     }
 
-    pub fn read_status_command_values(&self) -> [u16; 2] {
-        unsafe { core::mem::transmute(self.read_config(1)) }
+    pub fn read_status(&self) -> Status {
+        Status((self.read_config(1) >> 16) as _)
     }
 
-    pub fn read_class_pif_revision_values(&self) -> [u8; 4] {
-        unsafe { core::mem::transmute(self.read_config(2)) }
+    /// Clears the given RW1C bits (e.g. `Status::ERRORS`) of the Status register
+    pub fn clear_status(&self, bits: Status) {
+        self.write_config(1, ((bits.0 as u32) << 16) | self.read_command().0 as u32);
     }
 
-    pub fn read_bist_ht_lt_cls_values(&self) -> [u8; 4] {
-        unsafe { core::mem::transmute(self.read_config(3)) }
+    pub fn read_class_code(&self) -> ClassCode {
+        let [_, prog_if, subclass, class] = self.read_config(2).to_le_bytes();
+
+        ClassCode {
+            class,
+            subclass,
+            prog_if,
+        }
+    }
+
+    pub fn read_revision_id(&self) -> u8 {
+        self.read_config(2) as _
+    }
+
+    pub fn read_header_type(&self) -> HeaderType {
+        let [_, _, ht, _] = self.read_config(3).to_le_bytes();
+
+        match ht & 0x7f {
+            0x00 => HeaderType::General,
+            0x01 => {
+                let [primary_bus, secondary_bus, subordinate_bus, _] =
+                    self.read_config(6).to_le_bytes();
+                HeaderType::PciToPciBridge {
+                    primary_bus,
+                    secondary_bus,
+                    subordinate_bus,
+                }
+            }
+            0x02 => HeaderType::CardBusBridge,
+            x => HeaderType::Unknown(x),
+        }
+    }
+
+    pub fn is_multifunction(&self) -> bool {
+        (self.read_config(3) & 0x0080_0000) != 0
     }
 
     pub fn read_base_address_register(&self, n: u8) -> u32 {
-        self.read_config(4 + n)
+        self.read_config(4 + n as u16)
+    }
+
+    pub fn write_base_address_register(&self, n: u8, value: u32) {
+        self.write_config(4 + n as u16, value);
+    }
+
+    /// number of BARs in the header (6 for type 0, 2 for a PCI-to-PCI bridge)
+    pub fn bar_count(&self) -> u8 {
+        match self.read_header_type() {
+            HeaderType::General => 6,
+            HeaderType::PciToPciBridge { .. } => 2,
+            _ => 0,
+        }
+    }
+
+    /// Decodes and sizes BAR #`n`. None if it is not implemented, is the upper half of a 64-bit BAR
+    /// or is beyond `bar_count`.
+    ///
+    /// Sizing temporarily disables I/O and memory decoding of the function.
+    pub fn read_bar(&self, n: u8) -> Option<Bar> {
+        let count = self.bar_count();
+        if n >= count {
+            return None;
+        }
+        // Note: 64ビットBARの上位半分かどうかは、BAR0から順にたどらないと分からない
+        let mut i = 0;
+        while i < n {
+            let is_mem64 = (self.read_base_address_register(i) & 0x07) == 0x04;
+            i += if is_mem64 { 2 } else { 1 };
+        }
+        if i != n {
+            return None;
+        }
+
+        let raw = self.read_base_address_register(n);
+        let command = self.read_command();
+        self.write_command(command & !(Command::IO | Command::MEM));
+
+        let bar = if (raw & 0x01) != 0 {
+            self.write_base_address_register(n, 0xffff_ffff);
+            let mask = self.read_base_address_register(n) & !0x03;
+            self.write_base_address_register(n, raw);
+
+            // Note: 上位16bitが実装されていないI/O BARは上位が0で返ってくる
+            let mask = if (mask & 0xffff_0000) == 0 {
+                mask | 0xffff_0000
+            } else {
+                mask
+            };
+            (mask != 0xffff_0000).then_some(Bar::Io {
+                base: raw & !0x03,
+                length: (!mask).wrapping_add(1),
+            })
+        } else {
+            let prefetchable = (raw & 0x08) != 0;
+            match (raw >> 1) & 0x03 {
+                // the upper half would be past the last BAR
+                0x02 if n + 1 >= count => None,
+                0x02 => {
+                    let raw_hi = self.read_base_address_register(n + 1);
+                    self.write_base_address_register(n, 0xffff_ffff);
+                    self.write_base_address_register(n + 1, 0xffff_ffff);
+                    let mask = (self.read_base_address_register(n) & !0x0f) as u64
+                        | ((self.read_base_address_register(n + 1) as u64) << 32);
+                    self.write_base_address_register(n, raw);
+                    self.write_base_address_register(n + 1, raw_hi);
+
+                    (mask != 0).then_some(Bar::Mem64 {
+                        base: (raw & !0x0f) as u64 | ((raw_hi as u64) << 32),
+                        length: (!mask).wrapping_add(1),
+                        prefetchable,
+                    })
+                }
+                _ => {
+                    self.write_base_address_register(n, 0xffff_ffff);
+                    let mask = self.read_base_address_register(n) & !0x0f;
+                    self.write_base_address_register(n, raw);
+
+                    (mask != 0).then_some(Bar::Mem32 {
+                        base: raw & !0x0f,
+                        length: (!mask).wrapping_add(1),
+                        prefetchable,
+                    })
+                }
+            }
+        };
+
+        self.write_command(command);
+        bar
+    }
+
+    /// All implemented BARs with their index (the upper half of a 64-bit BAR is skipped)
+    pub fn bars(&self) -> Vec<(u8, Bar)> {
+        let count = self.bar_count();
+
+        let mut bars = Vec::new();
+        let mut n = 0;
+        while n < count {
+            let bar = self.read_bar(n);
+            let next = if matches!(bar, Some(Bar::Mem64 { .. })) {
+                n + 2
+            } else {
+                n + 1
+            };
+            if let Some(b) = bar {
+                bars.push((n, b));
+            }
+            n = next;
+        }
+
+        bars
     }
 
     pub fn read_subsystem_ids(&self) -> [u16; 2] {
         unsafe { core::mem::transmute(self.read_config(11)) }
     }
 
+    #[allow(dead_code)]
     pub fn read_expansion_rom_base_address(&self) -> u32 {
         self.read_config(12)
     }
@@ -50,4 +433,868 @@ impl DeviceIdentifier {
     pub fn read_capabilities_pointer(&self) -> u8 {
         (self.read_config(13) & 0xfc) as _
     }
+
+    /// Walks the capability list (empty if the Status register says there is none)
+    pub fn capabilities(&self) -> Capabilities {
+        let next = if self.read_status().contains(Status::CAPABILITIES_LIST) {
+            self.read_capabilities_pointer()
+        } else {
+            0
+        };
+
+        Capabilities {
+            device: *self,
+            next,
+            remaining: 48,
+        }
+    }
+
+    /// Walks the PCI Express extended capability list (needs the 4 KiB configuration space, e.g. ECAM)
+    pub fn extended_capabilities(&self) -> ExtendedCapabilities {
+        ExtendedCapabilities {
+            device: *self,
+            next: if self.config_space_size() > 256 {
+                0x100
+            } else {
+                0
+            },
+            remaining: (4096 - 256) / 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceIds {
+    pub vendor_id: u16,
+    pub device_id: u16,
+}
+impl DeviceIds {
+    /// no function responds at the address
+    #[inline]
+    pub const fn is_absent(&self) -> bool {
+        self.vendor_id == 0xffff
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClassCode {
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Io {
+        base: u32,
+        length: u32,
+    },
+    Mem32 {
+        base: u32,
+        length: u32,
+        prefetchable: bool,
+    },
+    Mem64 {
+        base: u64,
+        length: u64,
+        prefetchable: bool,
+    },
+}
+impl Bar {
+    #[inline]
+    pub const fn base(&self) -> u64 {
+        match *self {
+            Self::Io { base, .. } | Self::Mem32 { base, .. } => base as _,
+            Self::Mem64 { base, .. } => base,
+        }
+    }
+
+    #[inline]
+    pub const fn length(&self) -> u64 {
+        match *self {
+            Self::Io { length, .. } | Self::Mem32 { length, .. } => length as _,
+            Self::Mem64 { length, .. } => length,
+        }
+    }
+
+    #[inline]
+    pub const fn is_io(&self) -> bool {
+        matches!(self, Self::Io { .. })
+    }
+}
+impl core::fmt::Debug for Bar {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match *self {
+            Self::Io { base, length } => write!(f, "I/O 0x{base:04x}+0x{length:x}"),
+            Self::Mem32 {
+                base,
+                length,
+                prefetchable,
+            } => write!(
+                f,
+                "Mem32 0x{base:08x}+0x{length:x}{}",
+                if prefetchable { " prefetchable" } else { "" }
+            ),
+            Self::Mem64 {
+                base,
+                length,
+                prefetchable,
+            } => write!(
+                f,
+                "Mem64 0x{base:016x}+0x{length:x}{}",
+                if prefetchable { " prefetchable" } else { "" }
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderType {
+    General,
+    PciToPciBridge {
+        primary_bus: u8,
+        secondary_bus: u8,
+        subordinate_bus: u8,
+    },
+    CardBusBridge,
+    Unknown(u8),
+}
+
+#[derive(Debug, Clone)]
+pub struct Device {
+    pub id: DeviceIdentifier,
+    pub ids: DeviceIds,
+    pub class_code: ClassCode,
+    pub revision_id: u8,
+    pub header_type: HeaderType,
+    pub multifunction: bool,
+}
+impl Device {
+    fn read(id: DeviceIdentifier) -> Option<Self> {
+        let ids = id.read_ids();
+        if ids.is_absent() {
+            return None;
+        }
+
+        Some(Self {
+            id,
+            ids,
+            class_code: id.read_class_code(),
+            revision_id: id.read_revision_id(),
+            header_type: id.read_header_type(),
+            multifunction: id.is_multifunction(),
+        })
+    }
+}
+
+/// Enumerates every function of every segment group reachable through `config_access`
+/// (the MCFG allocations once ECAM is set up, otherwise segment 0 only)
+pub fn enumerate() -> Vec<Device> {
+    config_access()
+        .root_buses()
+        .into_iter()
+        .flat_map(|(segment, root_bus)| enumerate_segment(segment, root_bus))
+        .collect()
+}
+
+/// Enumerates every function in `segment` from `root_bus`, descending into PCI-to-PCI bridges
+pub fn enumerate_segment(segment: u16, root_bus: u8) -> Vec<Device> {
+    let mut devices = Vec::new();
+    let mut scanned = BusSet::default();
+    let host = DeviceIdentifier {
+        segment,
+        bus: root_bus,
+        device: 0,
+        function: 0,
+    };
+
+    if root_bus == 0 && host.is_multifunction() {
+        // Note: ホストブリッジがマルチファンクションの場合、ファンクション番号がそのままルートバス番号になる
+        for f in 0..8 {
+            if host.with_function(f).read_ids().is_absent() {
+                continue;
+            }
+            scan_bus(segment, f, &mut scanned, &mut devices);
+        }
+    } else {
+        scan_bus(segment, root_bus, &mut scanned, &mut devices);
+    }
+
+    devices
+}
+
+/// Set of the bus numbers already scanned
+#[derive(Default)]
+struct BusSet([u64; 4]);
+impl BusSet {
+    /// false if `bus` is already in the set
+    fn insert(&mut self, bus: u8) -> bool {
+        let (word, bit) = ((bus >> 6) as usize, 1u64 << (bus & 0x3f));
+        let inserted = (self.0[word] & bit) == 0;
+        self.0[word] |= bit;
+        inserted
+    }
+}
+
+fn scan_bus(segment: u16, bus: u8, scanned: &mut BusSet, sink: &mut Vec<Device>) {
+    // Note: 誤設定されたブリッジが同じセカンダリバスを指していても二重に列挙しない
+    if !scanned.insert(bus) {
+        return;
+    }
+
+    for device in 0..32 {
+        let id = DeviceIdentifier {
+            segment,
+            bus,
+            device,
+            function: 0,
+        };
+        let Some(d) = Device::read(id) else {
+            continue;
+        };
+
+        let multifunction = d.multifunction;
+        scan_function(d, scanned, sink);
+        if multifunction {
+            for f in 1..8 {
+                if let Some(d) = Device::read(id.with_function(f)) {
+                    scan_function(d, scanned, sink);
+                }
+            }
+        }
+    }
+}
+
+fn scan_function(device: Device, scanned: &mut BusSet, sink: &mut Vec<Device>) {
+    let header_type = device.header_type;
+    let (segment, bus) = (device.id.segment, device.id.bus);
+    sink.push(device);
+
+    if let HeaderType::PciToPciBridge { secondary_bus, .. } = header_type {
+        // unconfigured bridges (or loops) have secondary bus <= their own bus
+        if secondary_bus > bus {
+            scan_bus(segment, secondary_bus, scanned, sink);
+        }
+    }
+}
+
+pub struct Capabilities {
+    device: DeviceIdentifier,
+    next: u8,
+    // guard against broken (looping) lists
+    remaining: u8,
+}
+impl Iterator for Capabilities {
+    type Item = Capability;
+
+    fn next(&mut self) -> Option<Capability> {
+        if self.next < 0x40 || self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let (device, offset) = (self.device, self.next & 0xfc);
+        let header = device.read_config(offset as u16 >> 2);
+        self.next = (header >> 8) as u8;
+        let upper = (header >> 16) as u16;
+
+        Some(match header as u8 {
+            Capability::ID_POWER_MANAGEMENT => {
+                Capability::PowerManagement(PowerManagementCapability {
+                    device,
+                    offset,
+                    capabilities: upper,
+                })
+            }
+            Capability::ID_MSI => Capability::Msi(MsiCapability {
+                device,
+                offset,
+                message_control: upper,
+            }),
+            Capability::ID_VENDOR_SPECIFIC => {
+                Capability::VendorSpecific(VendorSpecificCapability {
+                    device,
+                    offset,
+                    length: upper as u8,
+                })
+            }
+            Capability::ID_PCI_EXPRESS => Capability::PciExpress(PciExpressCapability {
+                device,
+                offset,
+                capabilities: upper,
+            }),
+            Capability::ID_MSIX => Capability::MsiX(MsiXCapability {
+                device,
+                offset,
+                message_control: upper,
+            }),
+            id => Capability::Other { id, offset },
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Capability {
+    PowerManagement(PowerManagementCapability),
+    Msi(MsiCapability),
+    MsiX(MsiXCapability),
+    PciExpress(PciExpressCapability),
+    VendorSpecific(VendorSpecificCapability),
+    Other { id: u8, offset: u8 },
+}
+impl Capability {
+    pub const ID_POWER_MANAGEMENT: u8 = 0x01;
+    pub const ID_MSI: u8 = 0x05;
+    pub const ID_VENDOR_SPECIFIC: u8 = 0x09;
+    pub const ID_PCI_EXPRESS: u8 = 0x10;
+    pub const ID_MSIX: u8 = 0x11;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PowerManagementCapability {
+    pub device: DeviceIdentifier,
+    pub offset: u8,
+    /// PMC register
+    pub capabilities: u16,
+}
+impl PowerManagementCapability {
+    #[inline]
+    pub const fn version(&self) -> u8 {
+        (self.capabilities & 0x07) as _
+    }
+
+    /// 0 = D0 .. 3 = D3hot
+    pub fn power_state(&self) -> u8 {
+        (self.device.read_config_u16(self.offset as u16 + 4) & 0x03) as _
+    }
+
+    #[allow(dead_code)]
+    pub fn set_power_state(&self, state: u8) {
+        // Note: PME_Status(bit15)はRW1Cなので書き戻さない
+        let v = self.device.read_config_u16(self.offset as u16 + 4) & !(0x8000 | 0x03);
+        self.device
+            .write_config_u16(self.offset as u16 + 4, v | (state as u16 & 0x03));
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MsiCapability {
+    pub device: DeviceIdentifier,
+    pub offset: u8,
+    pub message_control: u16,
+}
+impl MsiCapability {
+    #[inline]
+    pub const fn is_64bit(&self) -> bool {
+        (self.message_control & (1 << 7)) != 0
+    }
+
+    #[inline]
+    pub const fn per_vector_masking(&self) -> bool {
+        (self.message_control & (1 << 8)) != 0
+    }
+
+    /// number of vectors the function can request (1..=32)
+    #[inline]
+    pub const fn multiple_message_capable(&self) -> u8 {
+        1 << ((self.message_control >> 1) & 0x07)
+    }
+
+    #[inline]
+    const fn message_control_offset(&self) -> u16 {
+        self.offset as u16 + 2
+    }
+
+    #[inline]
+    const fn message_data_offset(&self) -> u16 {
+        self.offset as u16 + if self.is_64bit() { 0x0c } else { 0x08 }
+    }
+
+    #[inline]
+    const fn mask_bits_offset(&self) -> u16 {
+        self.offset as u16 + if self.is_64bit() { 0x10 } else { 0x0c }
+    }
+
+    /// Programs the message. With multiple vectors enabled, the function modifies
+    /// the low bits of `data` so the vector must be aligned to the vector count.
+    pub fn set_message(&self, message: apic::InterruptMessage) {
+        let dword = self.offset as u16 >> 2;
+        self.device.write_config(dword + 1, message.address as u32);
+        if self.is_64bit() {
+            self.device
+                .write_config(dword + 2, (message.address >> 32) as u32);
+        } else {
+            assert!(
+                message.address >> 32 == 0,
+                "32-bit MSI cannot target {:#x}",
+                message.address
+            );
+        }
+        self.device
+            .write_config_u16(self.message_data_offset(), message.data as u16);
+    }
+
+    /// Enables MSI with `vectors` (a power of two up to `multiple_message_capable`) vectors
+    pub fn enable(&self, vectors: u8) {
+        assert!(
+            vectors.is_power_of_two() && vectors <= self.multiple_message_capable(),
+            "{} cannot request {vectors} MSI vectors",
+            self.device
+        );
+
+        let c = self.device.read_config_u16(self.message_control_offset()) & !(0x07 << 4);
+        self.device.write_config_u16(
+            self.message_control_offset(),
+            c | ((vectors.trailing_zeros() as u16) << 4) | 0x01,
+        );
+    }
+
+    pub fn disable(&self) {
+        let c = self.device.read_config_u16(self.message_control_offset());
+        self.device
+            .write_config_u16(self.message_control_offset(), c & !0x01);
+    }
+
+    /// Masks vector `index` (requires `per_vector_masking`)
+    pub fn mask(&self, index: u8) {
+        self.set_masked(index, true);
+    }
+
+    pub fn unmask(&self, index: u8) {
+        self.set_masked(index, false);
+    }
+
+    fn set_masked(&self, index: u8, masked: bool) {
+        assert!(
+            self.per_vector_masking(),
+            "{} does not support MSI per-vector masking",
+            self.device
+        );
+
+        let dword = self.mask_bits_offset() >> 2;
+        let bits = self.device.read_config(dword);
+        self.device.write_config(
+            dword,
+            if masked {
+                bits | (1 << index)
+            } else {
+                bits & !(1 << index)
+            },
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MsiXCapability {
+    pub device: DeviceIdentifier,
+    pub offset: u8,
+    pub message_control: u16,
+}
+impl MsiXCapability {
+    #[inline]
+    pub const fn table_size(&self) -> u16 {
+        (self.message_control & 0x7ff) + 1
+    }
+
+    /// (BAR index, offset in the BAR) of the MSI-X table
+    pub fn table_location(&self) -> (u8, u32) {
+        let v = self.device.read_config((self.offset as u16 >> 2) + 1);
+        ((v & 0x07) as _, v & !0x07)
+    }
+
+    /// (BAR index, offset in the BAR) of the pending bit array
+    pub fn pending_bit_array_location(&self) -> (u8, u32) {
+        let v = self.device.read_config((self.offset as u16 >> 2) + 2);
+        ((v & 0x07) as _, v & !0x07)
+    }
+
+    const ENABLE: u16 = 1 << 15;
+    const FUNCTION_MASK: u16 = 1 << 14;
+
+    /// Maps the MSI-X table and pending bit array (BARs are identity mapped).
+    /// None if the BAR holding them is not a memory BAR.
+    pub fn table(&self) -> Option<MsiXTable> {
+        let bar_address = |bir: u8| match self.device.read_bar(bir)? {
+            Bar::Io { .. } => None,
+            b => Some(b.base() as usize),
+        };
+
+        let (table_bir, table_offset) = self.table_location();
+        let (pba_bir, pba_offset) = self.pending_bit_array_location();
+        Some(MsiXTable {
+            base_address: bar_address(table_bir)? + table_offset as usize,
+            pending_bit_array_address: bar_address(pba_bir)? + pba_offset as usize,
+            size: self.table_size(),
+        })
+    }
+
+    fn update_message_control(&self, set: u16, clear: u16) {
+        let offset = self.offset as u16 + 2;
+        let c = self.device.read_config_u16(offset);
+        self.device.write_config_u16(offset, (c & !clear) | set);
+    }
+
+    pub fn enable(&self) {
+        self.update_message_control(Self::ENABLE, 0);
+    }
+
+    pub fn disable(&self) {
+        self.update_message_control(0, Self::ENABLE);
+    }
+
+    /// Masks (or unmasks) all vectors of the function regardless of per-entry masks
+    pub fn set_function_mask(&self, masked: bool) {
+        if masked {
+            self.update_message_control(Self::FUNCTION_MASK, 0);
+        } else {
+            self.update_message_control(0, Self::FUNCTION_MASK);
+        }
+    }
+}
+
+/// Memory mapped MSI-X table
+#[derive(Debug)]
+pub struct MsiXTable {
+    base_address: usize,
+    pending_bit_array_address: usize,
+    size: u16,
+}
+impl MsiXTable {
+    const ENTRY_SIZE: usize = 16;
+    const VECTOR_CONTROL_MASKED: u32 = 1 << 0;
+
+    #[inline]
+    #[allow(dead_code)]
+    pub const fn size(&self) -> u16 {
+        self.size
+    }
+
+    fn entry(&self, index: u16) -> *mut u32 {
+        assert!(index < self.size, "no such MSI-X entry: {index}");
+
+        (self.base_address + index as usize * Self::ENTRY_SIZE) as *mut u32
+    }
+
+    /// Programs entry `index`. The entry should be masked while it is rewritten.
+    pub fn set_message(&self, index: u16, message: apic::InterruptMessage) {
+        let e = self.entry(index);
+        unsafe {
+            core::ptr::write_volatile(e, message.address as u32);
+            core::ptr::write_volatile(e.add(1), (message.address >> 32) as u32);
+            core::ptr::write_volatile(e.add(2), message.data);
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn is_masked(&self, index: u16) -> bool {
+        let v = unsafe { core::ptr::read_volatile(self.entry(index).add(3)) };
+        (v & Self::VECTOR_CONTROL_MASKED) != 0
+    }
+
+    pub fn mask(&self, index: u16) {
+        self.set_masked(index, true);
+    }
+
+    pub fn unmask(&self, index: u16) {
+        self.set_masked(index, false);
+    }
+
+    fn set_masked(&self, index: u16, masked: bool) {
+        let p = unsafe { self.entry(index).add(3) };
+        unsafe {
+            let v = core::ptr::read_volatile(p) & !Self::VECTOR_CONTROL_MASKED;
+            core::ptr::write_volatile(
+                p,
+                if masked {
+                    v | Self::VECTOR_CONTROL_MASKED
+                } else {
+                    v
+                },
+            );
+        }
+    }
+
+    /// whether entry `index` has a message pending (set while it is masked)
+    #[allow(dead_code)]
+    pub fn is_pending(&self, index: u16) -> bool {
+        assert!(index < self.size, "no such MSI-X entry: {index}");
+
+        let qword = unsafe {
+            core::ptr::read_volatile(
+                (self.pending_bit_array_address as *const u64).add(index as usize / 64),
+            )
+        };
+        (qword & (1 << (index % 64))) != 0
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PciExpressCapability {
+    pub device: DeviceIdentifier,
+    pub offset: u8,
+    /// PCI Express Capabilities register
+    pub capabilities: u16,
+}
+impl PciExpressCapability {
+    #[inline]
+    pub const fn version(&self) -> u8 {
+        (self.capabilities & 0x0f) as _
+    }
+
+    /// 0 = endpoint, 4 = root port, 5/6 = switch ports, 9 = RC integrated endpoint, ...
+    #[inline]
+    pub const fn device_port_type(&self) -> u8 {
+        ((self.capabilities >> 4) & 0x0f) as _
+    }
+
+    /// Current link speed (1 = 2.5GT/s, 2 = 5GT/s, ...) and negotiated width from the Link Status register
+    /// (both 0 for functions without a link, e.g. RC integrated endpoints)
+    pub fn link_status(&self) -> (u8, u8) {
+        let v = self.device.read_config_u16(self.offset as u16 + 0x12);
+        ((v & 0x0f) as _, ((v >> 4) & 0x3f) as _)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VendorSpecificCapability {
+    pub device: DeviceIdentifier,
+    pub offset: u8,
+    /// including the 3-byte header
+    pub length: u8,
+}
+impl VendorSpecificCapability {
+    /// reads a byte at `at` bytes from the capability head
+    pub fn read_u8(&self, at: u8) -> u8 {
+        self.device.read_config_u8(self.offset as u16 + at as u16)
+    }
+
+    /// reads a dword at `at` (4-byte aligned) bytes from the capability head
+    pub fn read_u32(&self, at: u8) -> u32 {
+        self.device
+            .read_config((self.offset as u16 + at as u16) >> 2)
+    }
+}
+
+pub struct ExtendedCapabilities {
+    device: DeviceIdentifier,
+    next: u16,
+    remaining: u16,
+}
+impl Iterator for ExtendedCapabilities {
+    type Item = ExtendedCapability;
+
+    fn next(&mut self) -> Option<ExtendedCapability> {
+        if self.next < 0x100 || self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let offset = self.next & 0xffc;
+        let header = self.device.read_config(offset >> 2);
+        if header == 0 || header == 0xffff_ffff {
+            // no extended capabilities at all
+            return None;
+        }
+        self.next = (header >> 20) as u16;
+
+        Some(ExtendedCapability {
+            id: header as u16,
+            version: ((header >> 16) & 0x0f) as _,
+            offset,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ExtendedCapability {
+    pub id: u16,
+    pub version: u8,
+    pub offset: u16,
+}
+impl ExtendedCapability {
+    pub const ID_ADVANCED_ERROR_REPORTING: u16 = 0x0001;
+    pub const ID_VIRTUAL_CHANNEL: u16 = 0x0002;
+    pub const ID_DEVICE_SERIAL_NUMBER: u16 = 0x0003;
+    pub const ID_ACS: u16 = 0x000d;
+    pub const ID_ARI: u16 = 0x000e;
+    pub const ID_SR_IOV: u16 = 0x0010;
+    pub const ID_RESIZABLE_BAR: u16 = 0x0015;
+
+    pub const fn name(&self) -> &'static str {
+        match self.id {
+            Self::ID_ADVANCED_ERROR_REPORTING => "Advanced Error Reporting",
+            Self::ID_VIRTUAL_CHANNEL => "Virtual Channel",
+            Self::ID_DEVICE_SERIAL_NUMBER => "Device Serial Number",
+            Self::ID_ACS => "Access Control Services",
+            Self::ID_ARI => "Alternative Routing-ID Interpretation",
+            Self::ID_SR_IOV => "Single Root I/O Virtualization",
+            Self::ID_RESIZABLE_BAR => "Resizable BAR",
+            _ => "Unknown",
+        }
+    }
+}
+
+fn write_flags(
+    f: &mut core::fmt::Formatter<'_>,
+    mut v: u16,
+    names: &[(u16, &str)],
+) -> core::fmt::Result {
+    let mut wrote = false;
+
+    for &(bit, name) in names {
+        if (v & bit) != 0 {
+            f.write_str(if wrote { " | " } else { "" })?;
+            f.write_str(name)?;
+            wrote = true;
+            v &= !bit;
+        }
+    }
+
+    if v != 0 {
+        if wrote {
+            write!(f, " | {v:04x}")?;
+        } else {
+            write!(f, "{v:04x}")?;
+        }
+    } else if !wrote {
+        f.write_str("0")?;
+    }
+
+    Ok(())
+}
+
+/// Command register (offset 0x04)
+#[repr(transparent)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Command(pub u16);
+impl Command {
+    pub const IO: Self = Self(1 << 0);
+    pub const MEM: Self = Self(1 << 1);
+    pub const BUSMASTER: Self = Self(1 << 2);
+    pub const SPECIAL_CYCLES: Self = Self(1 << 3);
+    pub const MEMORY_WRITE_AND_INVALIDATE: Self = Self(1 << 4);
+    pub const VGA_PALETTE_SNOOP: Self = Self(1 << 5);
+    pub const PARITY_ERROR_RESPONSE: Self = Self(1 << 6);
+    pub const SERR: Self = Self(1 << 8);
+    pub const FAST_BACK_TO_BACK: Self = Self(1 << 9);
+    pub const INTX_DISABLE: Self = Self(1 << 10);
+
+    #[allow(dead_code)]
+    #[inline]
+    pub const fn contains(self, other: Self) -> bool {
+        (self.0 & other.0) == other.0
+    }
+}
+impl core::ops::BitOr for Command {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+impl core::ops::BitAnd for Command {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+impl core::ops::Not for Command {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self(!self.0)
+    }
+}
+impl core::fmt::Debug for Command {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write_flags(
+            f,
+            self.0,
+            &[
+                (Self::IO.0, "IO"),
+                (Self::MEM.0, "MEM"),
+                (Self::BUSMASTER.0, "BUSMASTER"),
+                (Self::SPECIAL_CYCLES.0, "SPECIAL_CYCLES"),
+                (Self::MEMORY_WRITE_AND_INVALIDATE.0, "MWI"),
+                (Self::VGA_PALETTE_SNOOP.0, "VGA_PALETTE_SNOOP"),
+                (Self::PARITY_ERROR_RESPONSE.0, "PARITY_ERROR_RESPONSE"),
+                (Self::SERR.0, "SERR"),
+                (Self::FAST_BACK_TO_BACK.0, "FAST_BACK_TO_BACK"),
+                (Self::INTX_DISABLE.0, "INTX_DISABLE"),
+            ],
+        )
+    }
+}
+
+/// Status register (offset 0x06)
+#[repr(transparent)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Status(pub u16);
+impl Status {
+    pub const INTERRUPT: Self = Self(1 << 3);
+    pub const CAPABILITIES_LIST: Self = Self(1 << 4);
+    pub const MHZ66: Self = Self(1 << 5);
+    pub const FAST_BACK_TO_BACK: Self = Self(1 << 7);
+    pub const MASTER_DATA_PARITY_ERROR: Self = Self(1 << 8);
+    pub const SIGNALED_TARGET_ABORT: Self = Self(1 << 11);
+    pub const RECEIVED_TARGET_ABORT: Self = Self(1 << 12);
+    pub const RECEIVED_MASTER_ABORT: Self = Self(1 << 13);
+    pub const SIGNALED_SYSTEM_ERROR: Self = Self(1 << 14);
+    pub const DETECTED_PARITY_ERROR: Self = Self(1 << 15);
+    /// all RW1C error bits
+    pub const ERRORS: Self = Self(
+        Self::MASTER_DATA_PARITY_ERROR.0
+            | Self::SIGNALED_TARGET_ABORT.0
+            | Self::RECEIVED_TARGET_ABORT.0
+            | Self::RECEIVED_MASTER_ABORT.0
+            | Self::SIGNALED_SYSTEM_ERROR.0
+            | Self::DETECTED_PARITY_ERROR.0,
+    );
+
+    const DEVSEL_TIMING_MASK: u16 = 0x0600;
+
+    #[inline]
+    pub const fn contains(self, other: Self) -> bool {
+        (self.0 & other.0) == other.0
+    }
+
+    /// 0 = fast, 1 = medium, 2 = slow
+    #[inline]
+    pub const fn devsel_timing(self) -> u8 {
+        ((self.0 & Self::DEVSEL_TIMING_MASK) >> 9) as _
+    }
+
+    #[inline]
+    pub const fn has_error(self) -> bool {
+        (self.0 & Self::ERRORS.0) != 0
+    }
+}
+impl core::ops::BitOr for Status {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+impl core::fmt::Debug for Status {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write_flags(
+            f,
+            self.0 & !Self::DEVSEL_TIMING_MASK,
+            &[
+                (Self::INTERRUPT.0, "INTERRUPT"),
+                (Self::CAPABILITIES_LIST.0, "CAPABILITIES_LIST"),
+                (Self::MHZ66.0, "66MHZ"),
+                (Self::FAST_BACK_TO_BACK.0, "FAST_BACK_TO_BACK"),
+                (Self::MASTER_DATA_PARITY_ERROR.0, "MASTER_DATA_PARITY_ERROR"),
+                (Self::SIGNALED_TARGET_ABORT.0, "SIGNALED_TARGET_ABORT"),
+                (Self::RECEIVED_TARGET_ABORT.0, "RECEIVED_TARGET_ABORT"),
+                (Self::RECEIVED_MASTER_ABORT.0, "RECEIVED_MASTER_ABORT"),
+                (Self::SIGNALED_SYSTEM_ERROR.0, "SIGNALED_SYSTEM_ERROR"),
+                (Self::DETECTED_PARITY_ERROR.0, "DETECTED_PARITY_ERROR"),
+            ],
+        )?;
+        write!(f, " (DEVSEL={})", self.devsel_timing())
+    }
 }
@@ -0,0 +1,193 @@
+//! Names from the pci.ids database, embedded with the `pci-ids` feature
+//! (`pci_ids.bin` is generated by `utils/src/bin/pci-ids-builder.rs`).
+//! Without the feature every lookup returns None.
+
+use crate::pci;
+
+#[cfg(feature = "pci-ids")]
+const BLOB: &[u8] = include_bytes!("../pci_ids.bin");
+#[cfg(not(feature = "pci-ids"))]
+const BLOB: &[u8] = &[0, 0, 0];
+
+struct Reader {
+    position: usize,
+}
+impl Reader {
+    fn u8(&mut self) -> u8 {
+        self.position += 1;
+        BLOB[self.position - 1]
+    }
+
+    fn u16(&mut self) -> u16 {
+        self.position += 2;
+        u16::from_le_bytes([BLOB[self.position - 2], BLOB[self.position - 1]])
+    }
+
+    fn name(&mut self) -> &'static str {
+        let len = self.u8() as usize;
+        self.position += len;
+        // Note: ビルダーがASCII以外を置き換えているのでUTF-8として不正にはならない
+        core::str::from_utf8(&BLOB[self.position - len..self.position]).unwrap_or("?")
+    }
+
+    fn skip_name(&mut self) {
+        let len = self.u8() as usize;
+        self.position += len;
+    }
+}
+
+pub fn vendor_name(vendor_id: u16) -> Option<&'static str> {
+    find_vendor(vendor_id).map(|mut r| r.name())
+}
+
+pub fn device_name(vendor_id: u16, device_id: u16) -> Option<&'static str> {
+    let mut r = find_vendor(vendor_id)?;
+    r.skip_name();
+    for _ in 0..r.u16() {
+        if r.u16() == device_id {
+            return Some(r.name());
+        }
+        r.skip_name();
+    }
+
+    None
+}
+
+/// Reader positioned at the name of `vendor_id`
+fn find_vendor(vendor_id: u16) -> Option<Reader> {
+    let mut r = Reader { position: 0 };
+    for _ in 0..r.u16() {
+        if r.u16() == vendor_id {
+            return Some(r);
+        }
+        r.skip_name();
+        for _ in 0..r.u16() {
+            r.u16();
+            r.skip_name();
+        }
+    }
+
+    None
+}
+
+/// Most specific names known for `class_code`: (class, subclass, prog-if)
+pub fn class_names(
+    class_code: &pci::ClassCode,
+) -> (
+    Option<&'static str>,
+    Option<&'static str>,
+    Option<&'static str>,
+) {
+    let mut r = Reader { position: 0 };
+    // skip the vendor section
+    for _ in 0..r.u16() {
+        r.u16();
+        r.skip_name();
+        for _ in 0..r.u16() {
+            r.u16();
+            r.skip_name();
+        }
+    }
+
+    let mut names = (None, None, None);
+    for _ in 0..r.u8() {
+        let class = r.u8();
+        let name = r.name();
+        let subclass_count = r.u8();
+        if class != class_code.class {
+            for _ in 0..subclass_count {
+                r.u8();
+                r.skip_name();
+                for _ in 0..r.u8() {
+                    r.u8();
+                    r.skip_name();
+                }
+            }
+            continue;
+        }
+
+        names.0 = Some(name);
+        for _ in 0..subclass_count {
+            let subclass = r.u8();
+            let name = r.name();
+            let prog_if_count = r.u8();
+            for _ in 0..prog_if_count {
+                let prog_if = r.u8();
+                let prog_if_name = r.name();
+                if subclass == class_code.subclass && prog_if == class_code.prog_if {
+                    names.2 = Some(prog_if_name);
+                }
+            }
+            if subclass == class_code.subclass {
+                names.1 = Some(name);
+                break;
+            }
+        }
+        break;
+    }
+
+    names
+}
+
+/// Writes an lspci-like tree: one line per function, indented below the bridge it sits behind
+pub fn write_tree(w: &mut impl core::fmt::Write, devices: &[pci::Device]) -> core::fmt::Result {
+    // (segment, secondary bus, subordinate bus) of the bridges above the current device
+    let mut bridges: alloc::vec::Vec<(u16, u8, u8)> = alloc::vec::Vec::new();
+    for d in devices {
+        while bridges
+            .last()
+            .is_some_and(|&(g, s, e)| g != d.id.segment || !(s..=e).contains(&d.id.bus))
+        {
+            bridges.pop();
+        }
+
+        for _ in 0..bridges.len() {
+            w.write_str("  ")?;
+        }
+        if !bridges.is_empty() {
+            w.write_str("\\-")?;
+        }
+
+        write!(w, "{} ", d.id)?;
+        match class_names(&d.class_code) {
+            (_, Some(subclass), Some(prog_if)) => write!(w, "{subclass} ({prog_if})")?,
+            (_, Some(subclass), None) => w.write_str(subclass)?,
+            (Some(class), None, _) => write!(w, "{class} [{:02x}]", d.class_code.subclass)?,
+            (None, ..) => write!(
+                w,
+                "Class {:02x}{:02x}",
+                d.class_code.class, d.class_code.subclass
+            )?,
+        }
+        w.write_str(": ")?;
+        match vendor_name(d.ids.vendor_id) {
+            Some(v) => w.write_str(v)?,
+            None => write!(w, "Vendor {:04x}", d.ids.vendor_id)?,
+        }
+        match device_name(d.ids.vendor_id, d.ids.device_id) {
+            Some(n) => write!(w, " {n}")?,
+            None => write!(w, " Device {:04x}", d.ids.device_id)?,
+        }
+        if d.revision_id != 0 {
+            write!(w, " (rev {:02x})", d.revision_id)?;
+        }
+        w.write_char('\n')?;
+
+        if let pci::HeaderType::PciToPciBridge {
+            secondary_bus,
+            subordinate_bus,
+            ..
+        } = d.header_type
+        {
+            if secondary_bus > d.id.bus {
+                bridges.push((
+                    d.id.segment,
+                    secondary_bus,
+                    subordinate_bus.max(secondary_bus),
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
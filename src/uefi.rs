@@ -4,13 +4,31 @@ pub type EfiHandle = *mut core::ffi::c_void;
 pub type EfiStatus = usize;
 
 #[repr(C)]
-#[derive(PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct EfiGuid {
     pub data1: u32,
     pub data2: u16,
     pub data3: u16,
     pub data4: [u8; 8],
 }
+impl EfiGuid {
+    pub const ZERO: Self = Self {
+        data1: 0,
+        data2: 0,
+        data3: 0,
+        data4: [0; 8],
+    };
+
+    /// Decodes the on-disk (mixed-endian) representation used by GPT
+    pub fn from_bytes(b: &[u8; 16]) -> Self {
+        Self {
+            data1: u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            data2: u16::from_le_bytes([b[4], b[5]]),
+            data3: u16::from_le_bytes([b[6], b[7]]),
+            data4: [b[8], b[9], b[10], b[11], b[12], b[13], b[14], b[15]],
+        }
+    }
+}
 impl core::fmt::Display for EfiGuid {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(
@@ -30,6 +48,11 @@ impl core::fmt::Display for EfiGuid {
         )
     }
 }
+impl core::fmt::Debug for EfiGuid {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Display::fmt(self, f)
+    }
+}
 
 #[repr(C)]
 pub struct EfiTableHeader {
@@ -84,7 +107,12 @@ pub struct EfiBootServices {
     pub raise_tpl: *const c_void,
     pub restore_tpl: *const c_void,
     // Memory Services
-    pub allocate_pages: *const c_void,
+    pub allocate_pages: extern "system" fn(
+        r#type: EfiAllocateType,
+        memory_type: u32,
+        pages: usize,
+        memory: *mut u64,
+    ) -> EfiStatus,
     pub free_pages: *const c_void,
     pub get_memory_map: extern "system" fn(
         memory_map_size: *mut usize,
@@ -106,7 +134,11 @@ pub struct EfiBootServices {
     pub install_protocol_interface: *const c_void,
     pub reinstall_protocol_interface: *const c_void,
     pub uninstall_protocol_interface: *const c_void,
-    pub handle_protocol: *const c_void,
+    pub handle_protocol: extern "system" fn(
+        handle: EfiHandle,
+        protocol: *const EfiGuid,
+        interface: *mut *mut c_void,
+    ) -> EfiStatus,
     _reserved: *const c_void,
     pub register_protocol_notify: *const c_void,
     pub locate_handle: *const c_void,
@@ -153,6 +185,22 @@ pub struct EfiConfigurationTable {
     pub vendor_guid: EfiGuid,
     pub vendor_table: *mut core::ffi::c_void,
 }
+impl EfiConfigurationTable {
+    /// SMBIOS 2.x entry point structure
+    pub const SMBIOS_GUID: EfiGuid = EfiGuid {
+        data1: 0xeb9d2d31,
+        data2: 0x2d88,
+        data3: 0x11d3,
+        data4: [0x9a, 0x16, 0x00, 0x90, 0x27, 0x3f, 0xc1, 0x4d],
+    };
+    /// SMBIOS 3.x entry point structure
+    pub const SMBIOS3_GUID: EfiGuid = EfiGuid {
+        data1: 0xf2fd1544,
+        data2: 0x9794,
+        data3: 0x4a2c,
+        data4: [0x99, 0x2e, 0xe5, 0xbb, 0xcf, 0x20, 0xe3, 0x94],
+    };
+}
 
 #[repr(C)]
 pub struct EfiMemoryAttributeTable {
@@ -171,6 +219,14 @@ impl EfiMemoryAttributeTable {
     };
 }
 
+#[repr(C)]
+#[allow(clippy::enum_variant_names)]
+pub enum EfiAllocateType {
+    AllocateAnyPages,
+    AllocateMaxAddress,
+    AllocateAddress,
+}
+
 #[repr(C)]
 pub struct EfiMemoryDescriptor {
     pub r#type: u32,
@@ -179,6 +235,9 @@ pub struct EfiMemoryDescriptor {
     pub number_of_pages: u64,
     pub attribute: u64,
 }
+impl EfiMemoryDescriptor {
+    pub const TYPE_LOADER_DATA: u32 = 2;
+}
 
 #[repr(C)]
 pub struct EfiGraphicsOutputProtocol {
@@ -227,6 +286,7 @@ impl EfiGraphicsOutputProtocol {
     }
 
     #[inline]
+    #[allow(dead_code, clippy::too_many_arguments)]
     pub fn blt(
         &mut self,
         blt_buffer: &mut [EfiGraphicsOutputBltPixel],
@@ -260,7 +320,7 @@ impl EfiGraphicsOutputProtocol {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct EfiPixelBitmask {
     pub red: u32,
     pub green: u32,
@@ -269,7 +329,8 @@ pub struct EfiPixelBitmask {
 }
 
 #[repr(C)]
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
 pub enum EfiGraphicsPixelFormat {
     RedGreenBlueReserved8BitPerColor,
     BlueGreenRedReserved8BitPerColor,
@@ -299,6 +360,7 @@ pub struct EfiGraphicsOutputProtocolMode {
 }
 impl EfiGraphicsOutputProtocolMode {
     #[inline]
+    #[allow(dead_code)]
     pub const fn info(&self) -> &EfiGraphicsOutputModeInformation {
         unsafe { &*self.info }
     }
@@ -314,9 +376,189 @@ pub struct EfiGraphicsOutputBltPixel {
 }
 
 #[repr(C)]
+#[allow(dead_code, clippy::enum_variant_names)]
 pub enum EfiGraphicsOutputBltOperation {
     BltVideoFill,
     BltVideoToBltBuffer,
     BltBufferToVideo,
     BltVideoToVideo,
 }
+
+#[repr(C)]
+pub struct EfiLoadedImageProtocol {
+    pub revision: u32,
+    pub parent_handle: EfiHandle,
+    pub system_table: *mut EfiSystemTable,
+    // Source location of the image
+    pub device_handle: EfiHandle,
+    pub file_path: *mut c_void,
+    _reserved: *mut c_void,
+    // Image's load options
+    pub load_options_size: u32,
+    pub load_options: *mut c_void,
+    // Location where image was loaded
+    pub image_base: *mut c_void,
+    pub image_size: u64,
+    pub image_code_type: u32,
+    pub image_data_type: u32,
+    pub unload: *const c_void,
+}
+impl EfiLoadedImageProtocol {
+    pub const GUID: EfiGuid = EfiGuid {
+        data1: 0x5b1b31a1,
+        data2: 0x9562,
+        data3: 0x11d2,
+        data4: [0x8e, 0x3f, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b],
+    };
+}
+
+#[repr(C)]
+pub struct EfiSimpleFileSystemProtocol {
+    pub revision: u64,
+    pub open_volume:
+        extern "system" fn(this: *mut Self, root: *mut *mut EfiFileProtocol) -> EfiStatus,
+}
+impl EfiSimpleFileSystemProtocol {
+    pub const GUID: EfiGuid = EfiGuid {
+        data1: 0x964e5b22,
+        data2: 0x6459,
+        data3: 0x11d2,
+        data4: [0x8e, 0x39, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b],
+    };
+
+    #[inline]
+    pub fn open_volume(&mut self, root: &mut *mut EfiFileProtocol) -> EfiStatus {
+        (self.open_volume)(self, root)
+    }
+}
+
+#[repr(C)]
+pub struct EfiFileProtocol {
+    pub revision: u64,
+    pub open: extern "system" fn(
+        this: *mut Self,
+        new_handle: *mut *mut Self,
+        file_name: *const u16,
+        open_mode: u64,
+        attributes: u64,
+    ) -> EfiStatus,
+    pub close: extern "system" fn(this: *mut Self) -> EfiStatus,
+    pub delete: extern "system" fn(this: *mut Self) -> EfiStatus,
+    pub read: extern "system" fn(
+        this: *mut Self,
+        buffer_size: *mut usize,
+        buffer: *mut c_void,
+    ) -> EfiStatus,
+    pub write: extern "system" fn(
+        this: *mut Self,
+        buffer_size: *mut usize,
+        buffer: *const c_void,
+    ) -> EfiStatus,
+    pub get_position: extern "system" fn(this: *mut Self, position: *mut u64) -> EfiStatus,
+    pub set_position: extern "system" fn(this: *mut Self, position: u64) -> EfiStatus,
+    pub get_info: extern "system" fn(
+        this: *mut Self,
+        information_type: *const EfiGuid,
+        buffer_size: *mut usize,
+        buffer: *mut c_void,
+    ) -> EfiStatus,
+    pub set_info: extern "system" fn(
+        this: *mut Self,
+        information_type: *const EfiGuid,
+        buffer_size: usize,
+        buffer: *const c_void,
+    ) -> EfiStatus,
+    pub flush: extern "system" fn(this: *mut Self) -> EfiStatus,
+    // Revision 2
+    pub open_ex: *const c_void,
+    pub read_ex: *const c_void,
+    pub write_ex: *const c_void,
+    pub flush_ex: *const c_void,
+}
+impl EfiFileProtocol {
+    pub const MODE_READ: u64 = 0x0000_0000_0000_0001;
+    #[allow(dead_code)]
+    pub const MODE_WRITE: u64 = 0x0000_0000_0000_0002;
+    #[allow(dead_code)]
+    pub const MODE_CREATE: u64 = 0x8000_0000_0000_0000;
+
+    /// `file_name` must be NUL terminated
+    #[inline]
+    pub fn open(
+        &mut self,
+        new_handle: &mut *mut Self,
+        file_name: &[u16],
+        open_mode: u64,
+        attributes: u64,
+    ) -> EfiStatus {
+        (self.open)(self, new_handle, file_name.as_ptr(), open_mode, attributes)
+    }
+
+    #[inline]
+    pub fn close(&mut self) -> EfiStatus {
+        (self.close)(self)
+    }
+
+    /// On return `buffer_size` holds the number of bytes read (0 at the end of the file)
+    #[inline]
+    pub fn read(&mut self, buffer_size: &mut usize, buffer: &mut [u8]) -> EfiStatus {
+        *buffer_size = (*buffer_size).min(buffer.len());
+        (self.read)(self, buffer_size, buffer.as_mut_ptr() as _)
+    }
+
+    #[allow(dead_code)]
+    #[inline]
+    pub fn set_position(&mut self, position: u64) -> EfiStatus {
+        (self.set_position)(self, position)
+    }
+
+    /// `buffer` must be suitably aligned for the information type
+    #[inline]
+    pub fn get_info(
+        &mut self,
+        information_type: &EfiGuid,
+        buffer_size: &mut usize,
+        buffer: *mut c_void,
+    ) -> EfiStatus {
+        (self.get_info)(self, information_type, buffer_size, buffer)
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct EfiTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    _pad1: u8,
+    pub nanosecond: u32,
+    pub time_zone: i16,
+    pub daylight: u8,
+    _pad2: u8,
+}
+
+/// EFI_FILE_INFO; followed by the NUL terminated file name
+#[repr(C)]
+pub struct EfiFileInfo {
+    pub size: u64,
+    pub file_size: u64,
+    pub physical_size: u64,
+    pub create_time: EfiTime,
+    pub last_access_time: EfiTime,
+    pub modification_time: EfiTime,
+    pub attribute: u64,
+    pub file_name: [u16; 0],
+}
+impl EfiFileInfo {
+    pub const GUID: EfiGuid = EfiGuid {
+        data1: 0x09576e92,
+        data2: 0x6d3f,
+        data3: 0x11d2,
+        data4: [0x8e, 0x39, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b],
+    };
+
+    pub const ATTRIBUTE_DIRECTORY: u64 = 0x10;
+}
@@ -0,0 +1,304 @@
+//! virtio-blk driver (virtio 1.2 section 5.2)
+
+use super::{
+    pci::Transport,
+    queue::{self, Segment, Virtqueue},
+    Features, InitError, VirtioDevice,
+};
+use crate::{
+    block::{BlockDevice, BlockError},
+    dma::DmaRegion,
+};
+use alloc::{boxed::Box, string::String, vec::Vec};
+
+const FEATURE_SIZE_MAX: Features = Features(1 << 1);
+const FEATURE_RO: Features = Features(1 << 5);
+const FEATURE_BLK_SIZE: Features = Features(1 << 6);
+const FEATURE_FLUSH: Features = Features(1 << 9);
+const FEATURE_MQ: Features = Features(1 << 12);
+
+// offsets in struct virtio_blk_config
+const CONFIG_CAPACITY: u32 = 0;
+const CONFIG_SIZE_MAX: u32 = 8;
+const CONFIG_BLK_SIZE: u32 = 20;
+const CONFIG_NUM_QUEUES: u32 = 34;
+
+const T_IN: u32 = 0;
+const T_OUT: u32 = 1;
+const T_FLUSH: u32 = 4;
+const T_GET_ID: u32 = 8;
+
+const S_OK: u8 = 0;
+const S_UNSUPP: u8 = 2;
+
+/// `sector` in requests and `capacity` are always in 512-byte units
+const SECTOR_SIZE: usize = 512;
+const QUEUE_SIZE: u16 = 16;
+const MAX_QUEUES: u16 = 8;
+/// largest transfer per request (the size of each queue's bounce buffer)
+const MAX_CHUNK_SIZE: usize = 64 * 1024;
+const ID_LENGTH: usize = 20;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RequestHeader {
+    r#type: u32,
+    _reserved: u32,
+    sector: u64,
+}
+
+/// A virtqueue with the buffers for its single in-flight request
+struct RequestQueue {
+    queue: Box<dyn Virtqueue>,
+    /// request header, status byte and bounce buffer
+    buffer: DmaRegion,
+}
+impl RequestQueue {
+    const STATUS_OFFSET: usize = 16;
+    const DATA_OFFSET: usize = SECTOR_SIZE;
+
+    fn data(&mut self, length: usize) -> &mut [u8] {
+        &mut self.buffer.as_mut_slice()[Self::DATA_OFFSET..][..length]
+    }
+
+    /// Queues a request over the first `length` bytes of the bounce buffer without waiting
+    fn submit(
+        &mut self,
+        r#type: u32,
+        sector: u64,
+        length: usize,
+        device_writable: bool,
+    ) -> Result<(), BlockError> {
+        unsafe {
+            self.buffer.as_ptr::<RequestHeader>().write(RequestHeader {
+                r#type,
+                _reserved: 0,
+                sector,
+            });
+        }
+        self.buffer.as_mut_slice()[Self::STATUS_OFFSET] = 0xff;
+
+        let base = self.buffer.physical_address();
+        let header = Segment::readable(base, core::mem::size_of::<RequestHeader>() as _);
+        let data = Segment {
+            address: base + Self::DATA_OFFSET as u64,
+            length: length as _,
+            device_writable,
+        };
+        let status = Segment::writable(base + Self::STATUS_OFFSET as u64, 1);
+        let pushed = if length == 0 {
+            self.queue.push(&[header, status])
+        } else {
+            self.queue.push(&[header, data, status])
+        };
+        pushed.ok_or(BlockError::QueueFull)?;
+        self.queue.kick();
+
+        Ok(())
+    }
+
+    /// Waits for the request queued by `submit` and checks its status
+    fn wait(&mut self) -> Result<(), BlockError> {
+        while self.queue.pop_used().is_none() {
+            core::hint::spin_loop();
+        }
+
+        match self.buffer.as_slice()[Self::STATUS_OFFSET] {
+            S_OK => Ok(()),
+            S_UNSUPP => Err(BlockError::Unsupported),
+            _ => Err(BlockError::IoError),
+        }
+    }
+}
+
+enum Transfer<'a> {
+    Read(&'a mut [u8]),
+    Write(&'a [u8]),
+}
+
+pub struct BlkDevice {
+    transport: Transport,
+    features: Features,
+    capacity: u64,
+    block_size: usize,
+    chunk_size: usize,
+    queues: Vec<RequestQueue>,
+}
+impl VirtioDevice for BlkDevice {
+    const DEVICE_TYPE: u16 = 2;
+    const FEATURES: Features = Features(
+        FEATURE_SIZE_MAX.0 | FEATURE_RO.0 | FEATURE_BLK_SIZE.0 | FEATURE_FLUSH.0 | FEATURE_MQ.0,
+    );
+
+    fn setup(transport: Transport, features: Features) -> Result<Self, InitError> {
+        // Note: 64ビットのcapacityは2回に分けて読むので、途中で構成が変わっていないか世代番号で確認する
+        let capacity = loop {
+            let generation = transport.config_generation();
+            let low = transport.read_device_config::<u32>(CONFIG_CAPACITY);
+            let high = transport.read_device_config::<u32>(CONFIG_CAPACITY + 4);
+            if transport.config_generation() == generation {
+                break (high as u64) << 32 | low as u64;
+            }
+        };
+
+        let block_size = if features.contains(FEATURE_BLK_SIZE) {
+            transport.read_device_config::<u32>(CONFIG_BLK_SIZE) as usize
+        } else {
+            SECTOR_SIZE
+        };
+        if block_size < SECTOR_SIZE || !block_size.is_power_of_two() || block_size > MAX_CHUNK_SIZE
+        {
+            return Err(InitError::Driver("unsupported virtio-blk block size"));
+        }
+
+        let mut chunk_size = MAX_CHUNK_SIZE;
+        if features.contains(FEATURE_SIZE_MAX) {
+            let size_max = transport.read_device_config::<u32>(CONFIG_SIZE_MAX) as usize;
+            if size_max >= block_size {
+                chunk_size = chunk_size.min(size_max / block_size * block_size);
+            }
+        }
+
+        let queue_count = if features.contains(FEATURE_MQ) {
+            transport.read_device_config::<u16>(CONFIG_NUM_QUEUES)
+        } else {
+            1
+        }
+        .clamp(1, MAX_QUEUES);
+        let queues = (0..queue_count)
+            .map(|n| {
+                Ok(RequestQueue {
+                    queue: queue::create(
+                        &transport,
+                        n,
+                        QUEUE_SIZE,
+                        features,
+                        transport.queue_msix_vector(),
+                    )?,
+                    buffer: DmaRegion::pages(RequestQueue::DATA_OFFSET + chunk_size),
+                })
+            })
+            .collect::<Result<Vec<_>, InitError>>()?;
+
+        Ok(Self {
+            transport,
+            features,
+            capacity,
+            block_size,
+            chunk_size,
+            queues,
+        })
+    }
+
+    fn transport(&self) -> &Transport {
+        &self.transport
+    }
+}
+impl BlkDevice {
+    /// capacity in bytes
+    pub const fn capacity(&self) -> u64 {
+        self.capacity * SECTOR_SIZE as u64
+    }
+
+    /// number of request queues in use
+    pub fn queue_count(&self) -> usize {
+        self.queues.len()
+    }
+
+    /// Device serial number (GET_ID)
+    pub fn serial(&mut self) -> Result<String, BlockError> {
+        let q = &mut self.queues[0];
+        q.submit(T_GET_ID, 0, ID_LENGTH, true)?;
+        q.wait()?;
+
+        let id = q.data(ID_LENGTH);
+        let length = id.iter().position(|&c| c == 0).unwrap_or(ID_LENGTH);
+
+        Ok(id[..length].iter().map(|&c| c as char).collect())
+    }
+
+    /// Splits the transfer into chunks and keeps one chunk in flight on each queue
+    fn transfer(&mut self, lba: u64, mut data: Transfer) -> Result<(), BlockError> {
+        let (r#type, length) = match &data {
+            Transfer::Read(b) => (T_IN, b.len()),
+            Transfer::Write(b) => (T_OUT, b.len()),
+        };
+        if r#type == T_OUT && self.is_read_only() {
+            return Err(BlockError::ReadOnly);
+        }
+        self.check_range(lba, length)?;
+
+        let mut sector = lba * (self.block_size / SECTOR_SIZE) as u64;
+        let mut offset = 0;
+        while offset < length {
+            let mut in_flight = Vec::with_capacity(self.queues.len());
+            let mut result = Ok(());
+            for (n, q) in self.queues.iter_mut().enumerate() {
+                if offset >= length {
+                    break;
+                }
+
+                let chunk = (length - offset).min(self.chunk_size);
+                if let Transfer::Write(b) = &data {
+                    q.data(chunk).copy_from_slice(&b[offset..][..chunk]);
+                }
+                result = q.submit(r#type, sector, chunk, r#type == T_IN);
+                if result.is_err() {
+                    break;
+                }
+
+                in_flight.push((n, offset, chunk));
+                sector += (chunk / SECTOR_SIZE) as u64;
+                offset += chunk;
+            }
+
+            // Note: エラーがあっても投入済みのリクエストは全て回収しておく（次の要求で古い完了を拾わないように）
+            for (n, offset, chunk) in in_flight {
+                let q = &mut self.queues[n];
+                let r = q.wait();
+                if let (Ok(()), Transfer::Read(b)) = (&r, &mut data) {
+                    b[offset..][..chunk].copy_from_slice(q.data(chunk));
+                }
+                result = result.and(r);
+            }
+            result?;
+        }
+
+        Ok(())
+    }
+}
+impl BlockDevice for BlkDevice {
+    #[inline]
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    #[inline]
+    fn block_count(&self) -> u64 {
+        self.capacity() / self.block_size as u64
+    }
+
+    #[inline]
+    fn is_read_only(&self) -> bool {
+        self.features.contains(FEATURE_RO)
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.transfer(lba, Transfer::Read(buf))
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        self.transfer(lba, Transfer::Write(buf))
+    }
+
+    fn flush(&mut self) -> Result<(), BlockError> {
+        // without VIRTIO_BLK_F_FLUSH the device writes through
+        if !self.features.contains(FEATURE_FLUSH) {
+            return Ok(());
+        }
+
+        let q = &mut self.queues[0];
+        q.submit(T_FLUSH, 0, 0, false)?;
+        q.wait()
+    }
+}
@@ -0,0 +1,599 @@
+//! virtio-gpu 2D driver (virtio 1.2 section 5.7)
+
+use super::{
+    pci::Transport,
+    queue::{self, Segment, Virtqueue},
+    Features, InitError, VirtioDevice,
+};
+use crate::{dma::DmaRegion, edid::Edid, hires_console::DisplayFlush};
+use alloc::{boxed::Box, rc::Rc, vec::Vec};
+use core::cell::RefCell;
+
+const CONTROL_QUEUE: u16 = 0;
+const CURSOR_QUEUE: u16 = 1;
+const QUEUE_SIZE: u16 = 64;
+const MAX_SCANOUTS: usize = 16;
+
+/// VIRTIO_GPU_F_EDID: GET_EDID is supported
+const FEATURE_EDID: Features = Features(1 << 1);
+
+const CMD_GET_DISPLAY_INFO: u32 = 0x0100;
+const CMD_RESOURCE_CREATE_2D: u32 = 0x0101;
+const CMD_SET_SCANOUT: u32 = 0x0103;
+const CMD_RESOURCE_FLUSH: u32 = 0x0104;
+const CMD_TRANSFER_TO_HOST_2D: u32 = 0x0105;
+const CMD_RESOURCE_ATTACH_BACKING: u32 = 0x0106;
+const CMD_GET_EDID: u32 = 0x010a;
+const CMD_UPDATE_CURSOR: u32 = 0x0300;
+const CMD_MOVE_CURSOR: u32 = 0x0301;
+
+const RESP_OK_NODATA: u32 = 0x1100;
+const RESP_OK_DISPLAY_INFO: u32 = 0x1101;
+const RESP_OK_EDID: u32 = 0x1104;
+const RESP_ERR_FIRST: u32 = 0x1200;
+
+/// VIRTIO_GPU_FORMAT_* (B8G8R8X8 for the framebuffers, B8G8R8A8 for the cursor)
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code, clippy::enum_variant_names)]
+pub enum Format {
+    B8G8R8A8Unorm = 1,
+    B8G8R8X8Unorm = 2,
+    A8R8G8B8Unorm = 3,
+    X8R8G8B8Unorm = 4,
+    R8G8B8A8Unorm = 67,
+    X8B8G8R8Unorm = 68,
+    A8B8G8R8Unorm = 121,
+    R8G8B8X8Unorm = 134,
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+pub enum GpuError {
+    /// no free descriptors in the control queue
+    QueueFull,
+    /// VIRTIO_GPU_RESP_ERR_* (0x1200 = unspecified, 0x1201 = out of memory, 0x1202 = invalid scanout id, ...)
+    Response(u32),
+    UnexpectedResponse(u32),
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct ControlHeader {
+    r#type: u32,
+    flags: u32,
+    fence_id: u64,
+    context_id: u32,
+    ring_index: u8,
+    _padding: [u8; 3],
+}
+impl ControlHeader {
+    const fn new(r#type: u32) -> Self {
+        Self {
+            r#type,
+            flags: 0,
+            fence_id: 0,
+            context_id: 0,
+            ring_index: 0,
+            _padding: [0; 3],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+impl Rect {
+    pub const fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct DisplayOne {
+    rect: Rect,
+    enabled: u32,
+    flags: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RespDisplayInfo {
+    header: ControlHeader,
+    modes: [DisplayOne; MAX_SCANOUTS],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct GetEdid {
+    header: ControlHeader,
+    scanout: u32,
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RespEdid {
+    header: ControlHeader,
+    size: u32,
+    _padding: u32,
+    edid: [u8; 1024],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ResourceCreate2D {
+    header: ControlHeader,
+    resource_id: u32,
+    format: u32,
+    width: u32,
+    height: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct MemoryEntry {
+    address: u64,
+    length: u32,
+    _padding: u32,
+}
+
+/// RESOURCE_ATTACH_BACKING with a single (physically contiguous) entry
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ResourceAttachBacking {
+    header: ControlHeader,
+    resource_id: u32,
+    entry_count: u32,
+    entry: MemoryEntry,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SetScanout {
+    header: ControlHeader,
+    rect: Rect,
+    scanout_id: u32,
+    resource_id: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct TransferToHost2D {
+    header: ControlHeader,
+    rect: Rect,
+    offset: u64,
+    resource_id: u32,
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ResourceFlush {
+    header: ControlHeader,
+    rect: Rect,
+    resource_id: u32,
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CursorPosition {
+    scanout_id: u32,
+    x: u32,
+    y: u32,
+    _padding: u32,
+}
+
+/// UPDATE_CURSOR and MOVE_CURSOR (which only looks at `position`)
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UpdateCursor {
+    header: ControlHeader,
+    position: CursorPosition,
+    resource_id: u32,
+    hot_x: u32,
+    hot_y: u32,
+    _padding: u32,
+}
+
+/// An enabled display reported by GET_DISPLAY_INFO
+#[derive(Debug, Clone, Copy)]
+pub struct Display {
+    pub scanout_id: u32,
+    /// preferred position and size
+    pub rect: Rect,
+}
+
+/// A 2D resource bound to a scanout
+#[derive(Debug, Clone, Copy)]
+pub struct Scanout {
+    pub scanout_id: u32,
+    pub resource_id: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Hardware cursor state
+struct Cursor {
+    scanout_id: u32,
+    resource_id: u32,
+    hot_spot: (u32, u32),
+    position: (u32, u32),
+    /// size of the scanout the cursor moves on
+    bounds: (u32, u32),
+}
+
+pub struct GpuDevice {
+    transport: Transport,
+    features: Features,
+    control: Box<dyn Virtqueue>,
+    cursor_queue: Box<dyn Virtqueue>,
+    request: DmaRegion,
+    response: DmaRegion,
+    cursor_request: DmaRegion,
+    next_resource_id: u32,
+    cursor: Option<Cursor>,
+}
+impl VirtioDevice for GpuDevice {
+    const DEVICE_TYPE: u16 = 16;
+    const FEATURES: Features = FEATURE_EDID;
+
+    fn setup(transport: Transport, features: Features) -> Result<Self, InitError> {
+        let control = queue::create(
+            &transport,
+            CONTROL_QUEUE,
+            QUEUE_SIZE,
+            features,
+            transport.queue_msix_vector(),
+        )?;
+        let cursor_queue = queue::create(
+            &transport,
+            CURSOR_QUEUE,
+            QUEUE_SIZE,
+            features,
+            transport.queue_msix_vector(),
+        )?;
+
+        Ok(Self {
+            transport,
+            features,
+            control,
+            cursor_queue,
+            request: DmaRegion::pages(DmaRegion::PAGE_SIZE),
+            response: DmaRegion::pages(DmaRegion::PAGE_SIZE),
+            cursor_request: DmaRegion::new(core::mem::size_of::<UpdateCursor>(), 16),
+            next_resource_id: 1,
+            cursor: None,
+        })
+    }
+
+    fn transport(&self) -> &Transport {
+        &self.transport
+    }
+}
+impl GpuDevice {
+    /// Sends `request` on the control queue and waits for the response of type `expected`
+    fn command<Q: Copy, R: Copy>(&mut self, request: Q, expected: u32) -> Result<R, GpuError> {
+        let (request_size, response_size) = (core::mem::size_of::<Q>(), core::mem::size_of::<R>());
+        assert!(
+            request_size <= self.request.len() && response_size <= self.response.len(),
+            "virtio-gpu command too large"
+        );
+
+        unsafe {
+            self.request.as_ptr::<Q>().write(request);
+        }
+        self.response.as_mut_slice()[..response_size].fill(0);
+        self.control
+            .submit_and_wait(&[
+                Segment::readable(self.request.physical_address(), request_size as _),
+                Segment::writable(self.response.physical_address(), response_size as _),
+            ])
+            .ok_or(GpuError::QueueFull)?;
+
+        let header = unsafe { self.response.as_ptr::<ControlHeader>().read() };
+        if header.r#type >= RESP_ERR_FIRST {
+            return Err(GpuError::Response(header.r#type));
+        }
+        if header.r#type != expected {
+            return Err(GpuError::UnexpectedResponse(header.r#type));
+        }
+
+        Ok(unsafe { self.response.as_ptr::<R>().read() })
+    }
+
+    fn command_nodata<Q: Copy>(&mut self, request: Q) -> Result<(), GpuError> {
+        self.command::<Q, ControlHeader>(request, RESP_OK_NODATA)
+            .map(drop)
+    }
+
+    /// Enabled displays
+    pub fn display_info(&mut self) -> Result<Vec<Display>, GpuError> {
+        let r: RespDisplayInfo = self.command(
+            ControlHeader::new(CMD_GET_DISPLAY_INFO),
+            RESP_OK_DISPLAY_INFO,
+        )?;
+
+        Ok(r.modes
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| m.enabled != 0)
+            .map(|(n, m)| Display {
+                scanout_id: n as _,
+                rect: m.rect,
+            })
+            .collect())
+    }
+
+    /// EDID of `scanout_id` (None if the device does not offer VIRTIO_GPU_F_EDID or the blob is invalid)
+    pub fn edid(&mut self, scanout_id: u32) -> Result<Option<Edid>, GpuError> {
+        if !self.features.contains(FEATURE_EDID) {
+            return Ok(None);
+        }
+
+        let r: RespEdid = self.command(
+            GetEdid {
+                header: ControlHeader::new(CMD_GET_EDID),
+                scanout: scanout_id,
+                _padding: 0,
+            },
+            RESP_OK_EDID,
+        )?;
+        let size = (r.size as usize).min(r.edid.len());
+
+        Ok(Edid::parse(&r.edid[..size]))
+    }
+
+    /// Creates a host-side 2D resource and returns its ID
+    pub fn create_resource_2d(
+        &mut self,
+        format: Format,
+        width: u32,
+        height: u32,
+    ) -> Result<u32, GpuError> {
+        let resource_id = self.next_resource_id;
+        self.command_nodata(ResourceCreate2D {
+            header: ControlHeader::new(CMD_RESOURCE_CREATE_2D),
+            resource_id,
+            format: format as _,
+            width,
+            height,
+        })?;
+        self.next_resource_id += 1;
+
+        Ok(resource_id)
+    }
+
+    /// Attaches guest memory at physical `address` as the backing store of `resource_id`
+    pub fn attach_backing(
+        &mut self,
+        resource_id: u32,
+        address: u64,
+        length: u32,
+    ) -> Result<(), GpuError> {
+        self.command_nodata(ResourceAttachBacking {
+            header: ControlHeader::new(CMD_RESOURCE_ATTACH_BACKING),
+            resource_id,
+            entry_count: 1,
+            entry: MemoryEntry {
+                address,
+                length,
+                _padding: 0,
+            },
+        })
+    }
+
+    /// Displays `rect` of `resource_id` on `scanout_id` (resource 0 disables the scanout)
+    pub fn set_scanout(
+        &mut self,
+        scanout_id: u32,
+        resource_id: u32,
+        rect: Rect,
+    ) -> Result<(), GpuError> {
+        self.command_nodata(SetScanout {
+            header: ControlHeader::new(CMD_SET_SCANOUT),
+            rect,
+            scanout_id,
+            resource_id,
+        })
+    }
+
+    /// Copies `rect` from the backing store (starting at byte `offset`) into the host resource
+    pub fn transfer_to_host_2d(
+        &mut self,
+        resource_id: u32,
+        rect: Rect,
+        offset: u64,
+    ) -> Result<(), GpuError> {
+        self.command_nodata(TransferToHost2D {
+            header: ControlHeader::new(CMD_TRANSFER_TO_HOST_2D),
+            rect,
+            offset,
+            resource_id,
+            _padding: 0,
+        })
+    }
+
+    /// Updates the displays showing `rect` of `resource_id`
+    pub fn flush_resource(&mut self, resource_id: u32, rect: Rect) -> Result<(), GpuError> {
+        self.command_nodata(ResourceFlush {
+            header: ControlHeader::new(CMD_RESOURCE_FLUSH),
+            rect,
+            resource_id,
+            _padding: 0,
+        })
+    }
+
+    /// Creates a `width` x `height` B8G8R8X8 resource backed by guest memory and shows it on
+    /// `scanout_id`. Returns the scanout and its pixels (stride = width).
+    pub fn create_framebuffer(
+        &mut self,
+        scanout_id: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<(Scanout, &'static mut [[u8; 4]]), GpuError> {
+        let backing = DmaRegion::pages(width as usize * height as usize * 4);
+
+        let resource_id = self.create_resource_2d(Format::B8G8R8X8Unorm, width, height)?;
+        self.attach_backing(resource_id, backing.physical_address(), backing.len() as _)?;
+        self.set_scanout(scanout_id, resource_id, Rect::new(0, 0, width, height))?;
+
+        let scanout = Scanout {
+            scanout_id,
+            resource_id,
+            width,
+            height,
+        };
+        let pixels = backing.leak();
+        let pixels = unsafe {
+            core::slice::from_raw_parts_mut(pixels.as_mut_ptr() as *mut [u8; 4], pixels.len() / 4)
+        };
+        self.flush(&scanout, Rect::new(0, 0, width, height))?;
+
+        Ok((scanout, pixels))
+    }
+
+    /// Transfers `rect` of the scanout's backing store to the host and presents it
+    pub fn flush(&mut self, scanout: &Scanout, rect: Rect) -> Result<(), GpuError> {
+        let offset = (rect.y as u64 * scanout.width as u64 + rect.x as u64) * 4;
+        self.transfer_to_host_2d(scanout.resource_id, rect, offset)?;
+        self.flush_resource(scanout.resource_id, rect)
+    }
+}
+
+impl GpuDevice {
+    pub const CURSOR_SIZE: u32 = 64;
+
+    /// Sends UPDATE_CURSOR/MOVE_CURSOR on the cursor queue (the device does not answer these)
+    fn cursor_command(&mut self, r#type: u32) -> Result<(), GpuError> {
+        let Some(c) = self.cursor.as_ref() else {
+            return Ok(());
+        };
+
+        let request = UpdateCursor {
+            header: ControlHeader::new(r#type),
+            position: CursorPosition {
+                scanout_id: c.scanout_id,
+                x: c.position.0,
+                y: c.position.1,
+                _padding: 0,
+            },
+            resource_id: c.resource_id,
+            hot_x: c.hot_spot.0,
+            hot_y: c.hot_spot.1,
+            _padding: 0,
+        };
+        unsafe {
+            self.cursor_request.as_ptr::<UpdateCursor>().write(request);
+        }
+        self.cursor_queue
+            .submit_and_wait(&[Segment::readable(
+                self.cursor_request.physical_address(),
+                core::mem::size_of::<UpdateCursor>() as _,
+            )])
+            .ok_or(GpuError::QueueFull)
+            .map(drop)
+    }
+
+    /// Uploads a `width` x `height` (up to 64x64) B8G8R8A8 image as the cursor of `scanout`
+    /// and shows it at the center. `hot_spot` is the pointing position inside the image.
+    pub fn set_cursor_image(
+        &mut self,
+        scanout: &Scanout,
+        pixels: &[[u8; 4]],
+        width: u32,
+        height: u32,
+        hot_spot: (u32, u32),
+    ) -> Result<(), GpuError> {
+        assert!(
+            width <= Self::CURSOR_SIZE
+                && height <= Self::CURSOR_SIZE
+                && pixels.len() == (width * height) as usize,
+            "invalid cursor image: {width}x{height}"
+        );
+
+        // Note: カーソルリソースは64x64固定なので、画像は左上に置いて残りを透明にする
+        let mut backing = DmaRegion::pages((Self::CURSOR_SIZE * Self::CURSOR_SIZE * 4) as usize);
+        for (y, row) in pixels.chunks(width as usize).enumerate() {
+            let at = y * Self::CURSOR_SIZE as usize * 4;
+            backing.as_mut_slice()[at..at + row.len() * 4].copy_from_slice(row.as_flattened());
+        }
+
+        let resource_id =
+            self.create_resource_2d(Format::B8G8R8A8Unorm, Self::CURSOR_SIZE, Self::CURSOR_SIZE)?;
+        self.attach_backing(resource_id, backing.physical_address(), backing.len() as _)?;
+        self.transfer_to_host_2d(
+            resource_id,
+            Rect::new(0, 0, Self::CURSOR_SIZE, Self::CURSOR_SIZE),
+            0,
+        )?;
+        // the host keeps reading the backing store
+        backing.leak();
+
+        self.cursor = Some(Cursor {
+            scanout_id: scanout.scanout_id,
+            resource_id,
+            hot_spot,
+            position: (scanout.width / 2, scanout.height / 2),
+            bounds: (scanout.width, scanout.height),
+        });
+        self.cursor_command(CMD_UPDATE_CURSOR)
+    }
+}
+// Note: ポインタ入力ドライバ（未実装）から呼ぶためのもの
+#[allow(dead_code)]
+impl GpuDevice {
+    /// Current cursor position (None if no cursor image was set)
+    pub fn cursor_position(&self) -> Option<(u32, u32)> {
+        self.cursor.as_ref().map(|c| c.position)
+    }
+
+    /// Moves the cursor to an absolute position (e.g. from a tablet), clamped to the scanout
+    pub fn move_cursor_to(&mut self, x: u32, y: u32) -> Result<(), GpuError> {
+        let Some(c) = self.cursor.as_mut() else {
+            return Ok(());
+        };
+
+        c.position = (x.min(c.bounds.0 - 1), y.min(c.bounds.1 - 1));
+        self.cursor_command(CMD_MOVE_CURSOR)
+    }
+
+    /// Moves the cursor by a relative motion (e.g. from a mouse), clamped to the scanout
+    pub fn move_cursor_by(&mut self, dx: i32, dy: i32) -> Result<(), GpuError> {
+        let Some((x, y)) = self.cursor_position() else {
+            return Ok(());
+        };
+
+        self.move_cursor_to(x.saturating_add_signed(dx), y.saturating_add_signed(dy))
+    }
+}
+
+/// Flushes a `HiResConsole` drawn into a scanout framebuffer
+pub struct ConsoleFlush {
+    pub gpu: Rc<RefCell<GpuDevice>>,
+    pub scanout: Scanout,
+}
+impl DisplayFlush for ConsoleFlush {
+    fn flush(&mut self, x: u32, y: u32, width: u32, height: u32) -> bool {
+        // Note: パニック表示中など、既に借用されている場合は次のフラッシュに回す
+        let Ok(mut gpu) = self.gpu.try_borrow_mut() else {
+            return false;
+        };
+
+        // Note: 失敗してもコンソール自身には書けない（書くとまたフラッシュになる）ので、次のフラッシュで再送する
+        gpu.flush(&self.scanout, Rect::new(x, y, width, height))
+            .is_ok()
+    }
+}
@@ -0,0 +1,299 @@
+pub mod blk;
+pub mod gpu;
+pub mod packed;
+pub mod pci;
+pub mod queue;
+pub mod split;
+
+pub const PCI_VENDOR_ID: u16 = 0x1af4;
+
+/// Device status field (virtio 1.2 section 2.1)
+#[repr(transparent)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct DeviceStatus(pub u8);
+impl DeviceStatus {
+    pub const ACKNOWLEDGE: Self = Self(1 << 0);
+    pub const DRIVER: Self = Self(1 << 1);
+    pub const DRIVER_OK: Self = Self(1 << 2);
+    pub const FEATURES_OK: Self = Self(1 << 3);
+    pub const DEVICE_NEEDS_RESET: Self = Self(1 << 6);
+    pub const FAILED: Self = Self(1 << 7);
+
+    #[inline]
+    pub const fn contains(self, other: Self) -> bool {
+        (self.0 & other.0) == other.0
+    }
+}
+impl core::ops::BitOr for DeviceStatus {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+impl core::fmt::Debug for DeviceStatus {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let names = [
+            (Self::ACKNOWLEDGE, "ACKNOWLEDGE"),
+            (Self::DRIVER, "DRIVER"),
+            (Self::DRIVER_OK, "DRIVER_OK"),
+            (Self::FEATURES_OK, "FEATURES_OK"),
+            (Self::DEVICE_NEEDS_RESET, "DEVICE_NEEDS_RESET"),
+            (Self::FAILED, "FAILED"),
+        ];
+
+        let mut wrote = false;
+        for (bit, name) in names {
+            if self.contains(bit) {
+                f.write_str(if wrote { " | " } else { "" })?;
+                f.write_str(name)?;
+                wrote = true;
+            }
+        }
+        if !wrote {
+            f.write_str("RESET")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Feature bits. Bits 0..24 are device specific, the rest are reserved for the transport/rings.
+#[repr(transparent)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Features(pub u64);
+impl Features {
+    pub const RING_INDIRECT_DESC: Self = Self(1 << 28);
+    pub const RING_EVENT_IDX: Self = Self(1 << 29);
+    pub const VERSION_1: Self = Self(1 << 32);
+    pub const ACCESS_PLATFORM: Self = Self(1 << 33);
+    pub const RING_PACKED: Self = Self(1 << 34);
+    pub const IN_ORDER: Self = Self(1 << 35);
+    pub const NOTIFICATION_DATA: Self = Self(1 << 38);
+
+    /// transport/ring features this driver stack implements
+    pub const TRANSPORT: Self =
+        Self(Self::VERSION_1.0 | Self::RING_EVENT_IDX.0 | Self::RING_PACKED.0);
+
+    #[inline]
+    pub const fn contains(self, other: Self) -> bool {
+        (self.0 & other.0) == other.0
+    }
+}
+impl core::ops::BitOr for Features {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+impl core::ops::BitAnd for Features {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+impl core::fmt::Debug for Features {
+    /// transport/ring features by name, followed by the remaining (device specific) bits
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("Features(")?;
+        let mut rest = self.0;
+        for (bit, name) in [
+            (Self::RING_INDIRECT_DESC, "RING_INDIRECT_DESC"),
+            (Self::RING_EVENT_IDX, "RING_EVENT_IDX"),
+            (Self::VERSION_1, "VERSION_1"),
+            (Self::ACCESS_PLATFORM, "ACCESS_PLATFORM"),
+            (Self::RING_PACKED, "RING_PACKED"),
+            (Self::IN_ORDER, "IN_ORDER"),
+            (Self::NOTIFICATION_DATA, "NOTIFICATION_DATA"),
+        ] {
+            if self.contains(bit) {
+                write!(f, "{name} | ")?;
+                rest &= !bit.0;
+            }
+        }
+        write!(f, "0x{rest:x})")
+    }
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+pub enum InitError {
+    /// the driver was given a device of another type
+    WrongDeviceType { expected: u16, actual: u16 },
+    /// legacy-only device (no VERSION_1)
+    LegacyDevice,
+    /// FEATURES_OK did not stick after writing the driver features
+    FeaturesRejected(Features),
+    /// a queue the driver requires does not exist (or has size 0)
+    QueueUnavailable(u16),
+    QueueTooLarge {
+        index: u16,
+        requested: u16,
+        max: u16,
+    },
+    /// the device could not assign the MSI-X vector
+    MsixVectorRejected(u16),
+    /// the device set DEVICE_NEEDS_RESET (or FAILED) during initialization
+    DeviceError(DeviceStatus),
+    /// driver specific failure
+    Driver(&'static str),
+}
+
+/// A driver for one virtio device type, brought up by `initialize`
+pub trait VirtioDevice: Sized {
+    /// virtio device ID (1 = network, 2 = block, 16 = GPU, ...)
+    const DEVICE_TYPE: u16;
+    /// device specific feature bits the driver understands
+    const FEATURES: Features;
+
+    /// Sets up queues and reads the configuration. Called after FEATURES_OK, before DRIVER_OK.
+    fn setup(transport: pci::Transport, features: Features) -> Result<Self, InitError>;
+
+    fn transport(&self) -> &pci::Transport;
+}
+
+/// Runs the initialization sequence (virtio 1.2 section 3.1.1) and hands the device to `D`.
+/// On failure the FAILED status bit is set and the error returned.
+pub fn initialize<D: VirtioDevice>(mut transport: pci::Transport) -> Result<D, InitError> {
+    let device_type = transport.device_type();
+    if device_type != D::DEVICE_TYPE {
+        return Err(InitError::WrongDeviceType {
+            expected: D::DEVICE_TYPE,
+            actual: device_type,
+        });
+    }
+
+    // the structures live in memory BARs and the rings are read/written by the device (DMA)
+    transport
+        .device()
+        .enable(crate::pci::Command::MEM | crate::pci::Command::BUSMASTER);
+    transport.reset();
+    transport.set_device_status(DeviceStatus::ACKNOWLEDGE);
+    transport.set_device_status(DeviceStatus::ACKNOWLEDGE | DeviceStatus::DRIVER);
+
+    let fail = |transport: &pci::Transport, e| {
+        transport.set_device_status(transport.device_status() | DeviceStatus::FAILED);
+        Err(e)
+    };
+
+    let offered = transport.device_features();
+    if !offered.contains(Features::VERSION_1) {
+        return fail(&transport, InitError::LegacyDevice);
+    }
+    let features = offered & (D::FEATURES | Features::TRANSPORT);
+    transport.set_driver_features(features);
+    transport.set_device_status(
+        DeviceStatus::ACKNOWLEDGE | DeviceStatus::DRIVER | DeviceStatus::FEATURES_OK,
+    );
+    if !transport
+        .device_status()
+        .contains(DeviceStatus::FEATURES_OK)
+    {
+        return fail(&transport, InitError::FeaturesRejected(features));
+    }
+
+    // queues are polled if this fails
+    transport.enable_msix(crate::apic::LocalAPIC::get().id());
+
+    // Note: setupがトランスポートを引き取るので、失敗時にFAILEDを立てるための複製を残しておく
+    let device = match D::setup(transport.clone(), features) {
+        Ok(d) => d,
+        Err(e) => return fail(&transport, e),
+    };
+
+    let transport = device.transport();
+    transport.set_device_status(transport.device_status() | DeviceStatus::DRIVER_OK);
+    let status = transport.device_status();
+    if status.contains(DeviceStatus::DEVICE_NEEDS_RESET) || status.contains(DeviceStatus::FAILED) {
+        return fail(transport, InitError::DeviceError(status));
+    }
+
+    Ok(device)
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::enum_variant_names)]
+pub enum PCICapabilityType {
+    CommonConfig = 1,
+    NotifyConfig = 2,
+    ISRConfig = 3,
+    DeviceConfig = 4,
+    PCIConfig = 5,
+    SharedMemoryConfig = 8,
+    VendorConfig = 9,
+}
+impl PCICapabilityType {
+    pub const fn from_u8(v: u8) -> Option<Self> {
+        Some(match v {
+            1 => Self::CommonConfig,
+            2 => Self::NotifyConfig,
+            3 => Self::ISRConfig,
+            4 => Self::DeviceConfig,
+            5 => Self::PCIConfig,
+            8 => Self::SharedMemoryConfig,
+            9 => Self::VendorConfig,
+            _ => return None,
+        })
+    }
+}
+
+/// Decoded `struct virtio_pci_cap` (virtio 1.2 section 4.1.4)
+#[derive(Debug, Clone, Copy)]
+pub struct PCICapability {
+    pub cfg_type: PCICapabilityType,
+    pub bar: u8,
+    /// tells apart capabilities of the same type (e.g. several shared memory regions)
+    #[allow(dead_code)]
+    pub id: u8,
+    pub offset: u32,
+    pub length: u32,
+    /// only for `NotifyConfig`
+    pub notify_off_multiplier: Option<u32>,
+}
+impl PCICapability {
+    /// None if `cap` is not a virtio structure
+    pub fn decode(cap: &crate::pci::VendorSpecificCapability) -> Option<Self> {
+        if cap.device.read_ids().vendor_id != PCI_VENDOR_ID || cap.length < 16 {
+            return None;
+        }
+
+        let cfg_type = PCICapabilityType::from_u8(cap.read_u8(3))?;
+        Some(Self {
+            cfg_type,
+            bar: cap.read_u8(4),
+            id: cap.read_u8(5),
+            offset: cap.read_u32(8),
+            length: cap.read_u32(12),
+            notify_off_multiplier: (cfg_type == PCICapabilityType::NotifyConfig
+                && cap.length >= 20)
+                .then(|| cap.read_u32(16)),
+        })
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct CommonConfiguration {
+    pub device_feature_select: u32,
+    pub device_feature: u32,
+    pub driver_feature_select: u32,
+    pub driver_feature: u32,
+    pub config_msix_vector: u16,
+    pub num_queues: u16,
+    pub device_status: u8,
+    pub config_generation: u8,
+    // About a specific virtqueue
+    pub queue_select: u16,
+    pub queue_size: u16,
+    pub queue_msix_vector: u16,
+    pub queue_enable: u16,
+    pub queue_notify_off: u16,
+    pub queue_desc: u64,
+    pub queue_driver: u64,
+    pub queue_device: u64,
+    pub queue_notify_data: u16,
+    pub queue_reset: u16,
+}
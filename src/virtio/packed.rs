@@ -0,0 +1,325 @@
+//! Packed virtqueue (virtio 1.2 section 2.8), used when VIRTIO_F_RING_PACKED is negotiated
+
+use super::{
+    pci::{Notifier, Transport},
+    queue::{Segment, UsedBuffer, Virtqueue},
+    Features, InitError,
+};
+use crate::dma::DmaRegion;
+use alloc::{vec, vec::Vec};
+use core::{
+    ptr::{addr_of, addr_of_mut, read_volatile, write_volatile},
+    sync::atomic::{fence, Ordering},
+};
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Descriptor {
+    address: u64,
+    length: u32,
+    id: u16,
+    flags: u16,
+}
+impl Descriptor {
+    const F_NEXT: u16 = 1 << 0;
+    const F_WRITE: u16 = 1 << 1;
+    const F_AVAIL: u16 = 1 << 7;
+    const F_USED: u16 = 1 << 15;
+}
+
+/// driver/device event suppression structure
+#[repr(C)]
+struct EventSuppression {
+    /// descriptor ring offset (bits 0..15) and wrap counter (bit 15)
+    off_wrap: u16,
+    flags: u16,
+}
+impl EventSuppression {
+    const FLAGS_ENABLE: u16 = 0;
+    const FLAGS_DISABLE: u16 = 1;
+    /// only with VIRTIO_F_RING_EVENT_IDX
+    const FLAGS_DESC: u16 = 2;
+}
+
+pub struct PackedQueue {
+    index: u16,
+    size: u16,
+    notifier: Notifier,
+    event_idx: bool,
+    descriptors: DmaRegion,
+    driver_event: DmaRegion,
+    device_event: DmaRegion,
+    /// descriptor count of the chain behind each buffer ID (0 if not outstanding)
+    chain_lengths: Vec<u16>,
+    free_ids: Vec<u16>,
+    free_count: u16,
+    next_avail_idx: u16,
+    avail_wrap_counter: bool,
+    /// descriptors made available since the last kick
+    added: u16,
+    next_used_idx: u16,
+    used_wrap_counter: bool,
+    interrupts_enabled: bool,
+    msix_vector: u16,
+}
+impl PackedQueue {
+    /// Allocates the ring for queue `index` and activates it on the device. Interrupts start disabled.
+    pub fn new(
+        transport: &Transport,
+        index: u16,
+        size: u16,
+        features: Features,
+        msix_vector: u16,
+    ) -> Result<Self, InitError> {
+        assert!(
+            (1..=0x8000).contains(&size),
+            "invalid packed virtqueue size: {size}"
+        );
+
+        let descriptors = DmaRegion::new(
+            core::mem::size_of::<Descriptor>() * size as usize,
+            DmaRegion::PAGE_SIZE,
+        );
+        let driver_event = DmaRegion::new(core::mem::size_of::<EventSuppression>(), 4);
+        let device_event = DmaRegion::new(core::mem::size_of::<EventSuppression>(), 4);
+
+        let notifier = transport.activate_queue(
+            index,
+            size,
+            (
+                descriptors.physical_address(),
+                driver_event.physical_address(),
+                device_event.physical_address(),
+            ),
+            msix_vector,
+        )?;
+
+        let mut q = Self {
+            index,
+            size,
+            notifier,
+            event_idx: features.contains(Features::RING_EVENT_IDX),
+            descriptors,
+            driver_event,
+            device_event,
+            chain_lengths: vec![0; size as usize],
+            free_ids: (0..size).rev().collect(),
+            free_count: size,
+            next_avail_idx: 0,
+            avail_wrap_counter: true,
+            added: 0,
+            next_used_idx: 0,
+            used_wrap_counter: true,
+            interrupts_enabled: true,
+            msix_vector,
+        };
+        q.disable_interrupts();
+
+        Ok(q)
+    }
+
+    #[inline]
+    fn descriptor(&self, n: u16) -> *mut Descriptor {
+        unsafe { self.descriptors.as_ptr::<Descriptor>().add(n as usize) }
+    }
+
+    #[inline]
+    fn driver_event(&self) -> *mut EventSuppression {
+        self.driver_event.as_ptr()
+    }
+
+    #[inline]
+    fn device_event(&self) -> *const EventSuppression {
+        self.device_event.as_ptr()
+    }
+
+    /// AVAIL/USED bits marking a descriptor available in the current driver wrap
+    #[inline]
+    const fn avail_flags(&self) -> u16 {
+        if self.avail_wrap_counter {
+            Descriptor::F_AVAIL
+        } else {
+            Descriptor::F_USED
+        }
+    }
+
+    fn set_driver_event_flags(&self, flags: u16) {
+        unsafe { write_volatile(addr_of_mut!((*self.driver_event()).flags), flags) }
+    }
+}
+impl Virtqueue for PackedQueue {
+    #[inline]
+    fn index(&self) -> u16 {
+        self.index
+    }
+
+    #[inline]
+    fn size(&self) -> u16 {
+        self.size
+    }
+
+    #[inline]
+    fn free_descriptors(&self) -> u16 {
+        self.free_count
+    }
+
+    fn push(&mut self, segments: &[Segment]) -> Option<u16> {
+        assert!(!segments.is_empty(), "empty descriptor chain");
+        if segments.len() > self.free_count as usize {
+            return None;
+        }
+        let id = self.free_ids.pop()?;
+
+        let segment_flags = |i: usize| {
+            let s = &segments[i];
+            (if s.device_writable {
+                Descriptor::F_WRITE
+            } else {
+                0
+            }) | if i == segments.len() - 1 {
+                0
+            } else {
+                Descriptor::F_NEXT
+            }
+        };
+
+        let (head, head_flags) = (self.next_avail_idx, segment_flags(0) | self.avail_flags());
+        for (i, s) in segments.iter().enumerate() {
+            let d = self.descriptor(self.next_avail_idx);
+            unsafe {
+                write_volatile(addr_of_mut!((*d).address), s.address);
+                write_volatile(addr_of_mut!((*d).length), s.length);
+                write_volatile(addr_of_mut!((*d).id), id);
+                // Note: 先頭の記述子のフラグは最後に書き込んでチェーン全体を一度に公開する
+                if i != 0 {
+                    write_volatile(
+                        addr_of_mut!((*d).flags),
+                        segment_flags(i) | self.avail_flags(),
+                    );
+                }
+            }
+
+            self.next_avail_idx += 1;
+            if self.next_avail_idx == self.size {
+                self.next_avail_idx = 0;
+                self.avail_wrap_counter = !self.avail_wrap_counter;
+            }
+        }
+
+        fence(Ordering::Release);
+        unsafe { write_volatile(addr_of_mut!((*self.descriptor(head)).flags), head_flags) };
+
+        self.chain_lengths[id as usize] = segments.len() as u16;
+        self.free_count -= segments.len() as u16;
+        self.added = self.added.wrapping_add(segments.len() as u16);
+
+        Some(id)
+    }
+
+    fn kick(&mut self) {
+        // the descriptors must be visible before reading the device's suppression state
+        fence(Ordering::SeqCst);
+
+        let added = core::mem::replace(&mut self.added, 0);
+        if added == 0 {
+            return;
+        }
+
+        let (off_wrap, flags) = unsafe {
+            (
+                read_volatile(addr_of!((*self.device_event()).off_wrap)),
+                read_volatile(addr_of!((*self.device_event()).flags)),
+            )
+        };
+        let needed = match flags {
+            EventSuppression::FLAGS_DESC => {
+                // Note: ラップカウンタが現在と異なるイベント位置は1周前のものとして扱う
+                let mut event = off_wrap & 0x7fff;
+                if ((off_wrap >> 15) != 0) != self.avail_wrap_counter {
+                    event = event.wrapping_sub(self.size);
+                }
+                let new = self.next_avail_idx;
+                let old = new.wrapping_sub(added);
+                new.wrapping_sub(event).wrapping_sub(1) < new.wrapping_sub(old)
+            }
+            EventSuppression::FLAGS_DISABLE => false,
+            _ => true,
+        };
+
+        if needed {
+            self.notifier.notify();
+        }
+    }
+
+    fn has_used(&self) -> bool {
+        let flags =
+            unsafe { read_volatile(addr_of!((*self.descriptor(self.next_used_idx)).flags)) };
+        let avail = (flags & Descriptor::F_AVAIL) != 0;
+        let used = (flags & Descriptor::F_USED) != 0;
+
+        avail == used && used == self.used_wrap_counter
+    }
+
+    fn pop_used(&mut self) -> Option<UsedBuffer> {
+        if !self.has_used() {
+            return None;
+        }
+        // read the element only after seeing the flags
+        fence(Ordering::Acquire);
+
+        let d = self.descriptor(self.next_used_idx);
+        let (id, written) = unsafe {
+            (
+                read_volatile(addr_of!((*d).id)),
+                read_volatile(addr_of!((*d).length)),
+            )
+        };
+
+        // Note: idはデバイスが書く値なので、範囲外や返却済みのチェーンを指していたら受け取らない
+        if id >= self.size || self.chain_lengths[id as usize] == 0 {
+            return None;
+        }
+        let count = core::mem::take(&mut self.chain_lengths[id as usize]);
+        self.next_used_idx += count;
+        if self.next_used_idx >= self.size {
+            self.next_used_idx -= self.size;
+            self.used_wrap_counter = !self.used_wrap_counter;
+        }
+        self.free_count += count;
+        self.free_ids.push(id);
+
+        if self.event_idx && self.interrupts_enabled {
+            self.enable_interrupts();
+        }
+
+        Some(UsedBuffer { token: id, written })
+    }
+
+    fn enable_interrupts(&mut self) {
+        self.interrupts_enabled = true;
+        if self.event_idx {
+            let off_wrap = self.next_used_idx | ((self.used_wrap_counter as u16) << 15);
+            unsafe { write_volatile(addr_of_mut!((*self.driver_event()).off_wrap), off_wrap) };
+            fence(Ordering::Release);
+            self.set_driver_event_flags(EventSuppression::FLAGS_DESC);
+        } else {
+            self.set_driver_event_flags(EventSuppression::FLAGS_ENABLE);
+        }
+        fence(Ordering::SeqCst);
+    }
+
+    fn disable_interrupts(&mut self) {
+        self.interrupts_enabled = false;
+        self.set_driver_event_flags(EventSuppression::FLAGS_DISABLE);
+    }
+
+    #[inline]
+    fn interrupts_enabled(&self) -> bool {
+        self.interrupts_enabled
+    }
+
+    #[inline]
+    fn msix_vector(&self) -> u16 {
+        self.msix_vector
+    }
+}
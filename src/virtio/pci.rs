@@ -0,0 +1,350 @@
+//! Virtio over PCI bus (virtio 1.2 section 4.1)
+
+use super::{
+    CommonConfiguration, DeviceStatus, Features, InitError, PCICapability, PCICapabilityType,
+    PCI_VENDOR_ID,
+};
+use crate::{interrupt, msi, pci};
+use alloc::rc::Rc;
+use core::ptr::{addr_of, addr_of_mut, read_volatile, write_volatile};
+
+macro_rules! read_common {
+    ($self: ident . $field: ident) => {
+        unsafe { read_volatile(addr_of!((*$self.common).$field)) }
+    };
+}
+
+macro_rules! write_common {
+    ($self: ident . $field: ident, $value: expr) => {
+        unsafe { write_volatile(addr_of_mut!((*$self.common).$field), $value) }
+    };
+}
+
+/// Memory mapped configuration structures of a modern (virtio 1.0+) PCI function.
+/// BARs are identity mapped.
+#[derive(Clone)]
+pub struct Transport {
+    device: pci::DeviceIdentifier,
+    common: *mut CommonConfiguration,
+    notify_base: usize,
+    notify_off_multiplier: u32,
+    isr: *const u8,
+    /// (address, length)
+    device_config: Option<(usize, u32)>,
+    /// shared by the clones (see `enable_msix`)
+    msix: Option<Rc<msi::MessageSignaledInterrupts>>,
+}
+impl Transport {
+    /// `msix_vector` value meaning "no vector"
+    pub const NO_VECTOR: u16 = 0xffff;
+    /// MSI-X table entry for configuration change interrupts (see `enable_msix`)
+    pub const CONFIG_MSIX_ENTRY: u16 = 0;
+    /// MSI-X table entry shared by all queues (see `enable_msix`)
+    pub const QUEUE_MSIX_ENTRY: u16 = 1;
+
+    /// Locates the virtio structures of `device` (the command register is left untouched).
+    /// None if `device` is not a virtio device or lacks the common/notify/ISR structures.
+    pub fn new(device: pci::DeviceIdentifier) -> Option<Self> {
+        if device.read_ids().vendor_id != PCI_VENDOR_ID {
+            return None;
+        }
+
+        let bars = device.bars();
+        let map = |c: &PCICapability| {
+            let (_, bar) = bars.iter().find(|(n, _)| *n == c.bar)?;
+            if bar.is_io() || c.offset as u64 + c.length as u64 > bar.length() {
+                return None;
+            }
+
+            Some((bar.base() + c.offset as u64) as usize)
+        };
+
+        let (mut common, mut notify, mut isr, mut device_config) = (None, None, None, None);
+        for c in device.capabilities() {
+            let pci::Capability::VendorSpecific(v) = c else {
+                continue;
+            };
+            let Some(c) = PCICapability::decode(&v) else {
+                continue;
+            };
+            let Some(address) = map(&c) else {
+                continue;
+            };
+
+            // Note: 同じ種類の構造が複数ある場合は最初に見つかったものを使う（virtio 1.2 4.1.4）
+            match c.cfg_type {
+                PCICapabilityType::CommonConfig
+                    if common.is_none()
+                        && c.length as usize
+                            >= core::mem::offset_of!(CommonConfiguration, queue_notify_data) =>
+                {
+                    common = Some(address);
+                }
+                PCICapabilityType::NotifyConfig if notify.is_none() => {
+                    notify = c.notify_off_multiplier.map(|m| (address, m));
+                }
+                PCICapabilityType::ISRConfig if isr.is_none() => isr = Some(address),
+                PCICapabilityType::DeviceConfig if device_config.is_none() => {
+                    device_config = Some((address, c.length));
+                }
+                _ => (),
+            }
+        }
+
+        let (notify_base, notify_off_multiplier) = notify?;
+        Some(Self {
+            device,
+            common: common? as _,
+            notify_base,
+            notify_off_multiplier,
+            isr: isr? as _,
+            device_config,
+            msix: None,
+        })
+    }
+
+    #[inline]
+    pub const fn device(&self) -> pci::DeviceIdentifier {
+        self.device
+    }
+
+    /// Virtio device ID (1 = network, 2 = block, 16 = GPU, ...)
+    pub fn device_type(&self) -> u16 {
+        match self.device.read_ids().device_id {
+            // transitional devices (virtio 1.2, 4.1.2.3)
+            0x1000 => 1,
+            0x1001 => 2,
+            0x1002 => 5,
+            0x1003 => 3,
+            0x1004 => 8,
+            0x1005 => 4,
+            0x1009 => 9,
+            // other transitional IDs carry the type in the subsystem ID
+            0x1006..=0x1008 | 0x100a..=0x103f => self.device.read_subsystem_ids()[1],
+            // Note: 0x1000未満はvirtioのIDではないので予約値の0を返す
+            id => id.saturating_sub(0x1040),
+        }
+    }
+
+    pub fn device_features(&self) -> Features {
+        write_common!(self.device_feature_select, 0);
+        let lo = read_common!(self.device_feature);
+        write_common!(self.device_feature_select, 1);
+        let hi = read_common!(self.device_feature);
+
+        Features(lo as u64 | ((hi as u64) << 32))
+    }
+
+    pub fn set_driver_features(&self, features: Features) {
+        write_common!(self.driver_feature_select, 0);
+        write_common!(self.driver_feature, features.0 as u32);
+        write_common!(self.driver_feature_select, 1);
+        write_common!(self.driver_feature, (features.0 >> 32) as u32);
+    }
+
+    /// Sets the configuration change vector and returns the value read back
+    /// (`NO_VECTOR` if the device could not allocate it)
+    pub fn set_config_msix_vector(&self, vector: u16) -> u16 {
+        write_common!(self.config_msix_vector, vector);
+        read_common!(self.config_msix_vector)
+    }
+
+    /// Enables MSI-X with one vector for configuration changes and one shared by all queues,
+    /// delivered to the local APIC `destination_apic_id`. The interrupts only wake up a CPU halted
+    /// in `Virtqueue::submit_and_wait`. Must be called after `reset`.
+    /// False (the queues stay without vectors) if the function has no MSI-X or the device rejects it.
+    pub fn enable_msix(&mut self, destination_apic_id: u8) -> bool {
+        fn wake_up(_vector: u8) {}
+
+        let Some(m) = msi::MessageSignaledInterrupts::enable(
+            self.device,
+            destination_apic_id,
+            &[wake_up as interrupt::Handler; 2],
+        ) else {
+            return false;
+        };
+        // Note: virtioのキュー毎のベクタはMSI-Xでしか設定できないので、MSIにフォールバックした場合は使わない
+        if !m.is_msix()
+            || self.set_config_msix_vector(Self::CONFIG_MSIX_ENTRY) != Self::CONFIG_MSIX_ENTRY
+        {
+            m.disable();
+            return false;
+        }
+
+        self.msix = Some(Rc::new(m));
+        true
+    }
+
+    /// MSI-X table entry for new queues (`NO_VECTOR` unless `enable_msix` succeeded)
+    #[inline]
+    pub fn queue_msix_vector(&self) -> u16 {
+        if self.msix.is_some() {
+            Self::QUEUE_MSIX_ENTRY
+        } else {
+            Self::NO_VECTOR
+        }
+    }
+
+    #[inline]
+    pub fn num_queues(&self) -> u16 {
+        read_common!(self.num_queues)
+    }
+
+    #[inline]
+    pub fn device_status(&self) -> DeviceStatus {
+        DeviceStatus(read_common!(self.device_status))
+    }
+
+    #[inline]
+    pub fn set_device_status(&self, status: DeviceStatus) {
+        write_common!(self.device_status, status.0);
+    }
+
+    /// Resets the device and waits for the reset to complete
+    pub fn reset(&self) {
+        write_common!(self.device_status, 0);
+        while read_common!(self.device_status) != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    #[inline]
+    pub fn config_generation(&self) -> u8 {
+        read_common!(self.config_generation)
+    }
+
+    /// Selects the queue the `queue_*` accessors refer to
+    #[inline]
+    pub fn select_queue(&self, index: u16) {
+        write_common!(self.queue_select, index);
+    }
+
+    /// maximum size of the selected queue (0 = unavailable)
+    #[inline]
+    pub fn queue_size(&self) -> u16 {
+        read_common!(self.queue_size)
+    }
+
+    #[inline]
+    pub fn set_queue_size(&self, size: u16) {
+        write_common!(self.queue_size, size);
+    }
+
+    /// Sets the vector of the selected queue and returns the value read back
+    pub fn set_queue_msix_vector(&self, vector: u16) -> u16 {
+        write_common!(self.queue_msix_vector, vector);
+        read_common!(self.queue_msix_vector)
+    }
+
+    #[allow(dead_code)]
+    #[inline]
+    pub fn is_queue_enabled(&self) -> bool {
+        read_common!(self.queue_enable) != 0
+    }
+
+    #[inline]
+    pub fn enable_queue(&self) {
+        write_common!(self.queue_enable, 1);
+    }
+
+    /// Physical addresses of the descriptor table, driver (available) area and device (used) area
+    pub fn set_queue_addresses(&self, descriptor: u64, driver: u64, device: u64) {
+        write_common!(self.queue_desc, descriptor);
+        write_common!(self.queue_driver, driver);
+        write_common!(self.queue_device, device);
+    }
+
+    /// Configures queue `index` with rings at the given physical addresses and enables it.
+    /// Returns the doorbell of the queue.
+    pub fn activate_queue(
+        &self,
+        index: u16,
+        size: u16,
+        (descriptor, driver, device): (u64, u64, u64),
+        msix_vector: u16,
+    ) -> Result<Notifier, InitError> {
+        if index >= self.num_queues() {
+            return Err(InitError::QueueUnavailable(index));
+        }
+        self.select_queue(index);
+        let max = self.queue_size();
+        if max == 0 {
+            return Err(InitError::QueueUnavailable(index));
+        }
+        if size > max {
+            return Err(InitError::QueueTooLarge {
+                index,
+                requested: size,
+                max,
+            });
+        }
+
+        self.set_queue_size(size);
+        self.set_queue_addresses(descriptor, driver, device);
+        if self.set_queue_msix_vector(msix_vector) != msix_vector {
+            return Err(InitError::MsixVectorRejected(msix_vector));
+        }
+        let notifier = Notifier {
+            address: self.queue_notify_address(),
+            index,
+        };
+        self.enable_queue();
+
+        Ok(notifier)
+    }
+
+    /// Doorbell address of the selected queue
+    pub fn queue_notify_address(&self) -> usize {
+        self.notify_base
+            + read_common!(self.queue_notify_off) as usize * self.notify_off_multiplier as usize
+    }
+
+    /// Reads and clears the ISR status (bit 0: queue interrupt, bit 1: configuration change)
+    #[allow(dead_code)]
+    #[inline]
+    pub fn read_isr(&self) -> u8 {
+        unsafe { read_volatile(self.isr) }
+    }
+
+    #[inline]
+    pub fn device_config_length(&self) -> u32 {
+        self.device_config.map_or(0, |(_, l)| l)
+    }
+
+    fn device_config_address<T>(&self, offset: u32) -> *mut T {
+        let (address, length) = self
+            .device_config
+            .expect("device has no device-specific configuration");
+        assert!(
+            offset as usize + core::mem::size_of::<T>() <= length as usize,
+            "device configuration access out of range: {offset}"
+        );
+
+        (address + offset as usize) as _
+    }
+
+    /// Reads a field of the device-specific configuration.
+    /// Fields wider than 32 bits should be read while `config_generation` stays the same.
+    pub fn read_device_config<T: Copy>(&self, offset: u32) -> T {
+        unsafe { read_volatile(self.device_config_address(offset)) }
+    }
+
+    #[allow(dead_code)]
+    pub fn write_device_config<T: Copy>(&self, offset: u32, value: T) {
+        unsafe { write_volatile(self.device_config_address(offset), value) }
+    }
+}
+
+/// Doorbell of one activated queue (see `Transport::activate_queue`), owned by the queue
+#[derive(Debug)]
+pub struct Notifier {
+    address: usize,
+    index: u16,
+}
+impl Notifier {
+    /// Tells the device that new buffers are available in the queue
+    #[inline]
+    pub fn notify(&self) {
+        unsafe { write_volatile(self.address as *mut u16, self.index) }
+    }
+}
@@ -0,0 +1,163 @@
+//! Ring-layout independent virtqueue interface
+
+use super::{packed::PackedQueue, pci::Transport, split::SplitQueue, Features, InitError};
+use crate::{cli, interrupt, sti};
+use alloc::boxed::Box;
+
+/// One buffer of a descriptor chain
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    /// physical address
+    pub address: u64,
+    pub length: u32,
+    /// written by the device (device-readable segments must come first in a chain)
+    pub device_writable: bool,
+}
+impl Segment {
+    pub const fn readable(address: u64, length: u32) -> Self {
+        Self {
+            address,
+            length,
+            device_writable: false,
+        }
+    }
+
+    pub const fn writable(address: u64, length: u32) -> Self {
+        Self {
+            address,
+            length,
+            device_writable: true,
+        }
+    }
+}
+
+/// A chain the device has finished with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UsedBuffer {
+    /// token returned by `Virtqueue::push`
+    pub token: u16,
+    /// bytes written into the device-writable segments
+    pub written: u32,
+}
+
+pub trait Virtqueue {
+    // Note: 今のドライバはキューごとに固定長で使うので参照していない
+    /// queue index on the device
+    #[allow(dead_code)]
+    fn index(&self) -> u16;
+    #[allow(dead_code)]
+    fn size(&self) -> u16;
+    #[allow(dead_code)]
+    fn free_descriptors(&self) -> u16;
+
+    /// Makes a chain of `segments` available to the device without notifying it.
+    /// Returns a token identifying the chain, or None if there are not enough free descriptors.
+    fn push(&mut self, segments: &[Segment]) -> Option<u16>;
+
+    /// Notifies the device of pushed chains unless it suppressed notifications
+    fn kick(&mut self);
+
+    /// whether a used chain is waiting to be popped
+    fn has_used(&self) -> bool;
+
+    /// Takes the next used chain and recycles its descriptors.
+    /// Returns None if the device reports a chain that is not outstanding (the entry is left in the ring).
+    fn pop_used(&mut self) -> Option<UsedBuffer>;
+
+    /// Asks the device to interrupt on used chains. Check `has_used` afterwards: chains used
+    /// before this call do not raise an interrupt.
+    fn enable_interrupts(&mut self);
+
+    /// Asks the device not to interrupt (polling mode)
+    fn disable_interrupts(&mut self);
+
+    /// whether `enable_interrupts` is in effect
+    fn interrupts_enabled(&self) -> bool;
+
+    /// MSI-X table entry the queue interrupts on (`Transport::NO_VECTOR` if none)
+    fn msix_vector(&self) -> u16;
+
+    /// Pushes `segments`, kicks the device and waits until that chain is used.
+    /// Halts between checks when interrupts are enabled on the queue and it has an MSI-X vector,
+    /// otherwise spins.
+    /// Other chains completing meanwhile are dropped, so this is meant for one-request-at-a-time use.
+    /// IF is restored to its state on entry before returning.
+    fn submit_and_wait(&mut self, segments: &[Segment]) -> Option<u32> {
+        let token = self.push(segments)?;
+        self.kick();
+
+        let interrupts_were_enabled = interrupt::are_enabled();
+        let written = 'wait: loop {
+            while let Some(u) = self.pop_used() {
+                if u.token == token {
+                    break 'wait u.written;
+                }
+            }
+
+            // Note: ベクタが割り当てられていないキューは完了しても割り込みが来ないのでポーリングする
+            if self.interrupts_enabled() && self.msix_vector() != Transport::NO_VECTOR {
+                // Note: 確認からhltまでの間に完了割り込みが来ると取りこぼすので、割り込み禁止で確認してからsti; hltする
+                // （stiの直後の1命令までは割り込みが入らない）
+                unsafe {
+                    cli!();
+                    if self.has_used() {
+                        sti!();
+                    } else {
+                        core::arch::asm!("sti; hlt", options(nomem, nostack));
+                    }
+                }
+            } else {
+                core::hint::spin_loop();
+            }
+        };
+        if !interrupts_were_enabled {
+            unsafe {
+                cli!();
+            }
+        }
+
+        Some(written)
+    }
+}
+
+/// Creates queue `index` with the ring layout chosen by feature negotiation
+/// (packed if VIRTIO_F_RING_PACKED was accepted, split otherwise).
+/// `size` is clamped to the device maximum (and rounded down to a power of two for split rings).
+/// Interrupts are enabled if `msix_vector` is not `Transport::NO_VECTOR`.
+pub fn create(
+    transport: &Transport,
+    index: u16,
+    size: u16,
+    features: Features,
+    msix_vector: u16,
+) -> Result<Box<dyn Virtqueue>, InitError> {
+    transport.select_queue(index);
+    let size = size.min(transport.queue_size());
+    if index >= transport.num_queues() || size == 0 {
+        return Err(InitError::QueueUnavailable(index));
+    }
+
+    let mut queue: Box<dyn Virtqueue> = if features.contains(Features::RING_PACKED) {
+        Box::new(PackedQueue::new(
+            transport,
+            index,
+            size,
+            features,
+            msix_vector,
+        )?)
+    } else {
+        let size = 1 << (15 - size.leading_zeros());
+        Box::new(SplitQueue::new(
+            transport,
+            index,
+            size,
+            features,
+            msix_vector,
+        )?)
+    };
+    if msix_vector != Transport::NO_VECTOR {
+        queue.enable_interrupts();
+    }
+
+    Ok(queue)
+}
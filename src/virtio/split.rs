@@ -0,0 +1,319 @@
+//! Split virtqueue (virtio 1.2 section 2.7)
+
+use super::{
+    pci::{Notifier, Transport},
+    queue::{Segment, UsedBuffer, Virtqueue},
+    Features, InitError,
+};
+use crate::dma::DmaRegion;
+use alloc::{vec, vec::Vec};
+use core::{
+    ptr::{addr_of, addr_of_mut, read_volatile, write_volatile},
+    sync::atomic::{fence, Ordering},
+};
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Descriptor {
+    address: u64,
+    length: u32,
+    flags: u16,
+    next: u16,
+}
+impl Descriptor {
+    const F_NEXT: u16 = 1 << 0;
+    const F_WRITE: u16 = 1 << 1;
+}
+
+/// available (driver) ring header; followed by `ring: [u16; size]` and `used_event: u16`
+#[repr(C)]
+struct AvailableRing {
+    flags: u16,
+    idx: u16,
+}
+impl AvailableRing {
+    const F_NO_INTERRUPT: u16 = 1 << 0;
+}
+
+/// used (device) ring header; followed by `ring: [UsedElement; size]` and `avail_event: u16`
+#[repr(C)]
+struct UsedRing {
+    flags: u16,
+    idx: u16,
+}
+impl UsedRing {
+    const F_NO_NOTIFY: u16 = 1 << 0;
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UsedElement {
+    id: u32,
+    length: u32,
+}
+
+pub struct SplitQueue {
+    index: u16,
+    size: u16,
+    notifier: Notifier,
+    event_idx: bool,
+    descriptors: DmaRegion,
+    available: DmaRegion,
+    used: DmaRegion,
+    free_head: u16,
+    free_count: u16,
+    /// descriptor count of the chain headed by each descriptor (0 if not outstanding)
+    chain_lengths: Vec<u16>,
+    /// avail idx at the last notification
+    notified_idx: u16,
+    last_used_idx: u16,
+    interrupts_enabled: bool,
+    msix_vector: u16,
+}
+impl SplitQueue {
+    /// Allocates the rings for queue `index` (`size` must be a power of two) and activates it
+    /// on the device. Interrupts start disabled.
+    pub fn new(
+        transport: &Transport,
+        index: u16,
+        size: u16,
+        features: Features,
+        msix_vector: u16,
+    ) -> Result<Self, InitError> {
+        assert!(
+            size.is_power_of_two(),
+            "split virtqueue size must be a power of two: {size}"
+        );
+
+        let descriptors = DmaRegion::new(
+            core::mem::size_of::<Descriptor>() * size as usize,
+            DmaRegion::PAGE_SIZE,
+        );
+        let available = DmaRegion::new(6 + 2 * size as usize, 2);
+        let used = DmaRegion::new(
+            6 + core::mem::size_of::<UsedElement>() * size as usize,
+            DmaRegion::PAGE_SIZE,
+        );
+
+        let notifier = transport.activate_queue(
+            index,
+            size,
+            (
+                descriptors.physical_address(),
+                available.physical_address(),
+                used.physical_address(),
+            ),
+            msix_vector,
+        )?;
+
+        let mut q = Self {
+            index,
+            size,
+            notifier,
+            event_idx: features.contains(Features::RING_EVENT_IDX),
+            descriptors,
+            available,
+            used,
+            free_head: 0,
+            free_count: size,
+            chain_lengths: vec![0; size as usize],
+            notified_idx: 0,
+            last_used_idx: 0,
+            interrupts_enabled: true,
+            msix_vector,
+        };
+        for n in 0..size {
+            q.descriptor(n).next = n + 1;
+        }
+        q.disable_interrupts();
+
+        Ok(q)
+    }
+
+    #[inline]
+    fn descriptor(&mut self, n: u16) -> &mut Descriptor {
+        unsafe { &mut *self.descriptors.as_ptr::<Descriptor>().add(n as usize) }
+    }
+
+    #[inline]
+    fn available_ring(&self) -> *mut AvailableRing {
+        self.available.as_ptr()
+    }
+
+    #[inline]
+    fn available_slot(&self, n: u16) -> *mut u16 {
+        unsafe { self.available.as_ptr::<u16>().add(2 + n as usize) }
+    }
+
+    /// `used_event` lives right after the available ring entries
+    #[inline]
+    fn used_event(&self) -> *mut u16 {
+        self.available_slot(self.size)
+    }
+
+    #[inline]
+    fn used_ring(&self) -> *mut UsedRing {
+        self.used.as_ptr()
+    }
+
+    #[inline]
+    fn used_element(&self, n: u16) -> *const UsedElement {
+        unsafe { (self.used.as_ptr::<u8>().add(4) as *const UsedElement).add(n as usize) }
+    }
+
+    /// `avail_event` lives right after the used ring entries
+    #[inline]
+    fn avail_event(&self) -> *const u16 {
+        self.used_element(self.size) as _
+    }
+
+    fn set_available_flags(&self, flags: u16) {
+        unsafe { write_volatile(addr_of_mut!((*self.available_ring()).flags), flags) }
+    }
+}
+impl Virtqueue for SplitQueue {
+    #[inline]
+    fn index(&self) -> u16 {
+        self.index
+    }
+
+    #[inline]
+    fn size(&self) -> u16 {
+        self.size
+    }
+
+    #[inline]
+    fn free_descriptors(&self) -> u16 {
+        self.free_count
+    }
+
+    fn push(&mut self, segments: &[Segment]) -> Option<u16> {
+        assert!(!segments.is_empty(), "empty descriptor chain");
+        if segments.len() > self.free_count as usize {
+            return None;
+        }
+
+        let head = self.free_head;
+        let mut n = head;
+        for (i, s) in segments.iter().enumerate() {
+            let last = i == segments.len() - 1;
+            let d = self.descriptor(n);
+            d.address = s.address;
+            d.length = s.length;
+            d.flags = if s.device_writable {
+                Descriptor::F_WRITE
+            } else {
+                0
+            } | if last { 0 } else { Descriptor::F_NEXT };
+            if last {
+                self.free_head = d.next;
+            } else {
+                n = d.next;
+            }
+        }
+        self.free_count -= segments.len() as u16;
+        self.chain_lengths[head as usize] = segments.len() as u16;
+
+        let idx = unsafe { read_volatile(addr_of!((*self.available_ring()).idx)) };
+        unsafe { write_volatile(self.available_slot(idx % self.size), head) };
+        // descriptors and the ring entry must be visible before the new index
+        fence(Ordering::Release);
+        unsafe {
+            write_volatile(
+                addr_of_mut!((*self.available_ring()).idx),
+                idx.wrapping_add(1),
+            )
+        };
+
+        Some(head)
+    }
+
+    fn kick(&mut self) {
+        // the index update must be visible before reading the device's suppression state
+        fence(Ordering::SeqCst);
+
+        let new_idx = unsafe { read_volatile(addr_of!((*self.available_ring()).idx)) };
+        let old_idx = core::mem::replace(&mut self.notified_idx, new_idx);
+        let needed = if self.event_idx {
+            // vring_need_event: the device asked to be notified once avail idx passes avail_event
+            let event = unsafe { read_volatile(self.avail_event()) };
+            new_idx.wrapping_sub(event).wrapping_sub(1) < new_idx.wrapping_sub(old_idx)
+        } else {
+            let flags = unsafe { read_volatile(addr_of!((*self.used_ring()).flags)) };
+            (flags & UsedRing::F_NO_NOTIFY) == 0
+        };
+
+        if needed && new_idx != old_idx {
+            self.notifier.notify();
+        }
+    }
+
+    fn has_used(&self) -> bool {
+        unsafe { read_volatile(addr_of!((*self.used_ring()).idx)) != self.last_used_idx }
+    }
+
+    fn pop_used(&mut self) -> Option<UsedBuffer> {
+        if !self.has_used() {
+            return None;
+        }
+        // read the element only after seeing the index
+        fence(Ordering::Acquire);
+
+        let e = unsafe { read_volatile(self.used_element(self.last_used_idx % self.size)) };
+        // Note: idはデバイスが書く値なので、範囲外や返却済みのチェーンを指していたら受け取らない
+        let head = u16::try_from(e.id).ok().filter(|&id| id < self.size)?;
+        let count = core::mem::take(&mut self.chain_lengths[head as usize]);
+        if count == 0 {
+            return None;
+        }
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+        if self.event_idx && self.interrupts_enabled {
+            unsafe { write_volatile(self.used_event(), self.last_used_idx) };
+        }
+
+        // return the chain to the free list
+        let mut n = head;
+        for _ in 1..count {
+            n = self.descriptor(n).next;
+        }
+        let free_head = self.free_head;
+        self.descriptor(n).next = free_head;
+        self.free_head = head;
+        self.free_count += count;
+
+        Some(UsedBuffer {
+            token: head,
+            written: e.length,
+        })
+    }
+
+    fn enable_interrupts(&mut self) {
+        self.interrupts_enabled = true;
+        if self.event_idx {
+            unsafe { write_volatile(self.used_event(), self.last_used_idx) };
+        } else {
+            self.set_available_flags(0);
+        }
+        fence(Ordering::SeqCst);
+    }
+
+    fn disable_interrupts(&mut self) {
+        self.interrupts_enabled = false;
+        if self.event_idx {
+            // Note: EVENT_IDXネゴシエート時はフラグが無視されるので、used_eventを遠くに置いて割り込みを抑える
+            unsafe { write_volatile(self.used_event(), self.last_used_idx.wrapping_add(0x8000)) };
+        } else {
+            self.set_available_flags(AvailableRing::F_NO_INTERRUPT);
+        }
+    }
+
+    #[inline]
+    fn interrupts_enabled(&self) -> bool {
+        self.interrupts_enabled
+    }
+
+    #[inline]
+    fn msix_vector(&self) -> u16 {
+        self.msix_vector
+    }
+}
@@ -0,0 +1,125 @@
+//! Loads DSDT/SSDT blobs (e.g. dumped with `acpidump -b` on a QEMU guest) into the loader's AML interpreter
+//! and prints the namespace and commonly used device objects.
+//!
+//! usage: aml-dump dsdt.dat [ssdt1.dat ...]
+
+extern crate alloc;
+
+#[path = "../../../src/aml/mod.rs"]
+#[allow(dead_code)]
+mod aml;
+
+use aml::{name::AmlName, object::Object, Handler, Interpreter, PciAddress};
+
+/// There is no real hardware behind: reads return all-ones like an absent device, writes are logged
+struct DummyHandler;
+impl Handler for DummyHandler {
+    fn read_memory(&self, address: u64, bit_width: u8) -> u64 {
+        println!("  [mem read{bit_width}] 0x{address:016x}");
+        u64::MAX
+    }
+
+    fn write_memory(&self, address: u64, bit_width: u8, value: u64) {
+        println!("  [mem write{bit_width}] 0x{address:016x} <- 0x{value:x}");
+    }
+
+    fn read_io(&self, port: u16, bit_width: u8) -> u64 {
+        println!("  [io read{bit_width}] 0x{port:04x}");
+        u64::MAX
+    }
+
+    fn write_io(&self, port: u16, bit_width: u8, value: u64) {
+        println!("  [io write{bit_width}] 0x{port:04x} <- 0x{value:x}");
+    }
+
+    fn read_pci_config(&self, address: PciAddress, offset: u16, bit_width: u8) -> u64 {
+        println!("  [pci read{bit_width}] {address:?} +0x{offset:03x}");
+        u64::MAX
+    }
+
+    fn write_pci_config(&self, address: PciAddress, offset: u16, bit_width: u8, value: u64) {
+        println!("  [pci write{bit_width}] {address:?} +0x{offset:03x} <- 0x{value:x}");
+    }
+
+    fn debug(&self, value: &Object) {
+        println!("  [debug] {value:?}");
+    }
+}
+
+fn describe_id(v: &Object) -> String {
+    match v {
+        Object::Integer(x) => String::from_utf8_lossy(&aml::decode_eisa_id(*x as u32)).into_owned(),
+        Object::String(s) => s.clone(),
+        x => format!("{x:?}"),
+    }
+}
+
+fn main() {
+    let paths = std::env::args().skip(1).collect::<Vec<_>>();
+    if paths.is_empty() {
+        panic!("DSDT/SSDT file path required");
+    }
+
+    let mut interpreter = Interpreter::new(DummyHandler);
+    for p in &paths {
+        let table = std::fs::read(p).expect("Failed to read table");
+        interpreter
+            .load_table(&table)
+            .unwrap_or_else(|e| panic!("Failed to load {p}: {e:?}"));
+    }
+
+    println!("{:?}", interpreter.namespace());
+
+    let devices = interpreter
+        .namespace()
+        .devices()
+        .cloned()
+        .collect::<Vec<AmlName>>();
+    for d in devices {
+        println!("Device {d}");
+        for seg in [b"_HID", b"_CID", b"_UID", b"_ADR", b"_STA"] {
+            match interpreter.evaluate_child(&d, seg) {
+                Ok(Some(v)) if seg == b"_HID" || seg == b"_CID" => {
+                    println!(
+                        "- {}: {}",
+                        std::str::from_utf8(seg).unwrap(),
+                        describe_id(&v)
+                    )
+                }
+                Ok(Some(v)) => println!("- {}: {v:?}", std::str::from_utf8(seg).unwrap()),
+                Ok(None) => (),
+                Err(e) => println!("- {}: error {e:?}", std::str::from_utf8(seg).unwrap()),
+            }
+        }
+
+        match interpreter.evaluate_child(&d, b"_CRS") {
+            Ok(Some(Object::Buffer(b))) => match aml::resource::parse(&b.borrow()) {
+                Ok(resources) => {
+                    for r in resources {
+                        println!("- _CRS: {r:?}");
+                    }
+                }
+                Err(e) => println!("- _CRS: decode error {e:?}"),
+            },
+            Ok(Some(v)) => println!("- _CRS: {v:?}"),
+            Ok(None) => (),
+            Err(e) => println!("- _CRS: error {e:?}"),
+        }
+
+        match interpreter.pci_routing_table(&d) {
+            Ok(prt) => {
+                for e in prt {
+                    println!("- _PRT: {e:?}");
+                }
+            }
+            Err(e) => println!("- _PRT: error {e:?}"),
+        }
+    }
+
+    for state in 0..=5 {
+        match interpreter.sleep_types(state) {
+            Ok((a, b)) => println!("\\_S{state}: SLP_TYPa={a} SLP_TYPb={b}"),
+            Err(e) => println!("\\_S{state}: {e:?}"),
+        }
+    }
+}
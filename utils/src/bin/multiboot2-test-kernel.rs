@@ -0,0 +1,161 @@
+//! Writes a tiny Multiboot2 kernel for testing the loader under QEMU.
+//!
+//! The kernel is a flat 32-bit image loaded at 0x200000 by the address tag. It checks the magic
+//! and that the boot information has every tag the loader is expected to provide, fills the
+//! framebuffer with green, prints the result to the debug console (port 0xe9) and exits QEMU
+//! through isa-debug-exit (port 0xf4): status 33 for success, 35 for a bad magic and 37 for
+//! missing tags.
+
+/// cmdline, boot loader name, basic meminfo, mmap, framebuffer, EFI64 system table,
+/// ACPI new RSDP, EFI mmap and load base
+const REQUIRED_TAGS: u32 = (1 << 1)
+    | (1 << 2)
+    | (1 << 4)
+    | (1 << 6)
+    | (1 << 8)
+    | (1 << 12)
+    | (1 << 15)
+    | (1 << 17)
+    | (1 << 21);
+
+// Note: AT&T構文（Intel構文ではラベル差分をオペランドに書けない）。実行時のアドレスに依存しないよう、ラベル間の差分とcall/popで得たベースアドレスだけを使う
+core::arch::global_asm!(
+    ".p2align 3",
+    "MULTIBOOT2_TEST_KERNEL_START:",
+    // header: magic, architecture (i386), length and checksum
+    ".Lheader:",
+    ".long 0xe85250d6",
+    ".long 0",
+    ".long .Lheader_end - .Lheader",
+    ".long 0x100000000 - (0xe85250d6 + (.Lheader_end - .Lheader))",
+    // information request: memory map, framebuffer, ACPI new RSDP
+    ".short 1, 0",
+    ".long 20",
+    ".long 6, 8, 15",
+    ".balign 8",
+    // address: header_addr, load_addr, load_end_addr (end of the file), bss_end_addr (stack)
+    ".short 2, 0",
+    ".long 24",
+    ".long {load}",
+    ".long {load}",
+    ".long 0",
+    ".long {load} + (.Lend - .Lheader) + {stack}",
+    // entry address
+    ".short 3, 0",
+    ".long 12",
+    ".long {load} + (.Lentry - .Lheader)",
+    ".balign 8",
+    // framebuffer (optional): no preference
+    ".short 5, 1",
+    ".long 20",
+    ".long 0, 0, 32",
+    ".balign 8",
+    ".short 0, 0",
+    ".long 8",
+    ".Lheader_end:",
+    ".code32",
+    ".Lentry:",
+    "cli",
+    "call .Lbase",
+    ".Lbase:",
+    "pop %ebp",
+    "lea (.Lend - .Lbase + {stack})(%ebp), %esp",
+    "cmp $0x36d76289, %eax",
+    "jne .Lbad_magic",
+    // EDX: bit set of the tag types found, EDI: the framebuffer tag
+    "xor %edx, %edx",
+    "xor %edi, %edi",
+    "lea 8(%ebx), %esi",
+    ".Lnext_tag:",
+    "mov (%esi), %eax",
+    "test %eax, %eax",
+    "jz .Ltags_done",
+    "cmp $31, %eax",
+    "ja .Lskip_tag",
+    "bts %eax, %edx",
+    "cmp $8, %eax",
+    "jne .Lskip_tag",
+    "mov %esi, %edi",
+    ".Lskip_tag:",
+    "mov 4(%esi), %ecx",
+    "add $7, %ecx",
+    "and $-8, %ecx",
+    "add %ecx, %esi",
+    "jmp .Lnext_tag",
+    ".Ltags_done:",
+    "and ${required}, %edx",
+    "cmp ${required}, %edx",
+    "jne .Lmissing",
+    // fill the framebuffer (32bpp only) with ((1 << green_mask_size) - 1) << green_field_position
+    "cmpb $32, 28(%edi)",
+    "jne .Lfilled",
+    "mov 35(%edi), %cl",
+    "mov $1, %ebx",
+    "shl %cl, %ebx",
+    "dec %ebx",
+    "mov 34(%edi), %cl",
+    "shl %cl, %ebx",
+    "mov 16(%edi), %eax",
+    "mull 24(%edi)",
+    "shr $2, %eax",
+    "mov %eax, %ecx",
+    "mov %ebx, %eax",
+    "mov 8(%edi), %edi",
+    "cld",
+    "rep stosl",
+    ".Lfilled:",
+    "lea (.Lmessage_ok - .Lbase)(%ebp), %esi",
+    "mov $0x10, %bl",
+    "jmp .Lexit",
+    ".Lbad_magic:",
+    "lea (.Lmessage_bad_magic - .Lbase)(%ebp), %esi",
+    "mov $0x11, %bl",
+    "jmp .Lexit",
+    ".Lmissing:",
+    "lea (.Lmessage_missing - .Lbase)(%ebp), %esi",
+    "mov $0x12, %bl",
+    // print the message at ESI and exit with BL
+    ".Lexit:",
+    "mov $0xe9, %dx",
+    ".Lputs:",
+    "lodsb",
+    "test %al, %al",
+    "jz .Lputs_done",
+    "out %al, %dx",
+    "jmp .Lputs",
+    ".Lputs_done:",
+    "mov %bl, %al",
+    "out %al, $0xf4",
+    ".Lhalt:",
+    "hlt",
+    "jmp .Lhalt",
+    ".Lmessage_ok:",
+    ".asciz \"multiboot2 test kernel: ok\\n\"",
+    ".Lmessage_bad_magic:",
+    ".asciz \"multiboot2 test kernel: bad magic\\n\"",
+    ".Lmessage_missing:",
+    ".asciz \"multiboot2 test kernel: missing tags\\n\"",
+    ".Lend:",
+    "MULTIBOOT2_TEST_KERNEL_END:",
+    ".code64",
+    load = const 0x200000,
+    stack = const 0x1000,
+    required = const REQUIRED_TAGS,
+    options(att_syntax)
+);
+
+extern "C" {
+    static MULTIBOOT2_TEST_KERNEL_START: [u8; 0];
+    static MULTIBOOT2_TEST_KERNEL_END: [u8; 0];
+}
+
+fn main() {
+    let path = std::env::args().nth(1).expect("output path required");
+    let image = unsafe {
+        let start = MULTIBOOT2_TEST_KERNEL_START.as_ptr();
+        let length = MULTIBOOT2_TEST_KERNEL_END.as_ptr().offset_from(start) as usize;
+        std::slice::from_raw_parts(start, length)
+    };
+
+    std::fs::write(path, image).unwrap();
+}
@@ -0,0 +1,193 @@
+//! Packs a subset of the pci.ids database (https://pci-ids.ucw.cz/) into `pci_ids.bin`
+//! which the loader embeds with the `pci-ids` feature.
+//!
+//! usage: pci-ids-builder pci.ids [vendor-id ...]
+//!
+//! Only the given vendors (hex, default: vendors commonly seen on virtual machines) are kept.
+//! Subsystem entries are dropped; all device classes are kept.
+//!
+//! Blob layout (little endian, every name is a u8 length followed by ASCII bytes):
+//! - u16 vendor count, then per vendor: u16 id, name, u16 device count, (u16 id, name) * count
+//! - u8 class count, then per class: u8 id, name, u8 subclass count,
+//!   per subclass: u8 id, name, u8 prog-if count, (u8 id, name) * count
+
+const DEFAULT_VENDORS: &[u16] = &[
+    0x1002, // AMD/ATI
+    0x1013, // Cirrus Logic
+    0x1022, // AMD
+    0x10de, // NVIDIA
+    0x10ec, // Realtek
+    0x1234, // Bochs/QEMU VGA
+    0x15ad, // VMware
+    0x1af4, // Red Hat (virtio)
+    0x1b36, // Red Hat (QEMU devices)
+    0x8086, // Intel
+];
+
+struct Vendor {
+    id: u16,
+    name: String,
+    devices: Vec<(u16, String)>,
+}
+
+struct Class {
+    id: u8,
+    name: String,
+    subclasses: Vec<Subclass>,
+}
+
+struct Subclass {
+    id: u8,
+    name: String,
+    prog_ifs: Vec<(u8, String)>,
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let path = std::path::PathBuf::from(args.next().expect("pci.ids path required"));
+    let vendor_filter = args
+        .map(|v| u16::from_str_radix(v.trim_start_matches("0x"), 16).expect("invalid vendor id"))
+        .collect::<Vec<_>>();
+    let vendor_filter = if vendor_filter.is_empty() {
+        DEFAULT_VENDORS.to_vec()
+    } else {
+        vendor_filter
+    };
+
+    let source = std::fs::read(&path).expect("Failed to read pci.ids");
+    // pci.ids is mostly UTF-8 but older copies contain Latin-1 names
+    let source = String::from_utf8_lossy(&source);
+
+    let (mut vendors, mut classes) = (Vec::<Vendor>::new(), Vec::<Class>::new());
+    let mut in_classes = false;
+    for line in source.lines() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let depth = line.chars().take_while(|&c| c == '\t').count();
+        let line = &line[depth..];
+        if depth == 0 {
+            in_classes = line.starts_with("C ");
+        }
+
+        if in_classes {
+            match depth {
+                0 => {
+                    let (id, name) = split_id(&line[2..]);
+                    classes.push(Class {
+                        id: id as _,
+                        name,
+                        subclasses: Vec::new(),
+                    });
+                }
+                1 => {
+                    let (id, name) = split_id(line);
+                    classes.last_mut().unwrap().subclasses.push(Subclass {
+                        id: id as _,
+                        name,
+                        prog_ifs: Vec::new(),
+                    });
+                }
+                2 => {
+                    let (id, name) = split_id(line);
+                    let class = classes.last_mut().unwrap();
+                    class
+                        .subclasses
+                        .last_mut()
+                        .unwrap()
+                        .prog_ifs
+                        .push((id as _, name));
+                }
+                _ => (),
+            }
+            continue;
+        }
+
+        // other top level lists (device types, languages, ...) are not "xxxx  name" vendor lines
+        match depth {
+            0 => match line.split_once("  ") {
+                Some((id, name)) if id.len() == 4 => {
+                    let Ok(id) = u16::from_str_radix(id, 16) else {
+                        continue;
+                    };
+                    vendors.push(Vendor {
+                        id,
+                        name: name.into(),
+                        devices: Vec::new(),
+                    });
+                }
+                _ => (),
+            },
+            1 => {
+                if let Some(v) = vendors.last_mut() {
+                    let (id, name) = split_id(line);
+                    v.devices.push((id as _, name));
+                }
+            }
+            // subsystems
+            _ => (),
+        }
+    }
+    vendors.retain(|v| vendor_filter.contains(&v.id));
+
+    let mut blob = Vec::new();
+    blob.extend((vendors.len() as u16).to_le_bytes());
+    for v in &vendors {
+        blob.extend(v.id.to_le_bytes());
+        push_name(&mut blob, &v.name);
+        blob.extend((v.devices.len() as u16).to_le_bytes());
+        for (id, name) in &v.devices {
+            blob.extend(id.to_le_bytes());
+            push_name(&mut blob, name);
+        }
+    }
+    blob.push(classes.len() as u8);
+    for c in &classes {
+        blob.push(c.id);
+        push_name(&mut blob, &c.name);
+        blob.push(c.subclasses.len() as u8);
+        for s in &c.subclasses {
+            blob.push(s.id);
+            push_name(&mut blob, &s.name);
+            blob.push(s.prog_ifs.len() as u8);
+            for (id, name) in &s.prog_ifs {
+                blob.push(*id);
+                push_name(&mut blob, name);
+            }
+        }
+    }
+
+    std::fs::write("pci_ids.bin", &blob).unwrap();
+    println!(
+        "pci_ids.bin: {} vendors, {} devices, {} classes ({} bytes)",
+        vendors.len(),
+        vendors.iter().map(|v| v.devices.len()).sum::<usize>(),
+        classes.len(),
+        blob.len()
+    );
+}
+
+/// "xxxx  name" -> (id, name)
+fn split_id(line: &str) -> (u16, String) {
+    let (id, name) = line
+        .split_once("  ")
+        .unwrap_or_else(|| panic!("malformed pci.ids line: {line:?}"));
+
+    (
+        u16::from_str_radix(id, 16).unwrap_or_else(|_| panic!("malformed id: {id:?}")),
+        name.into(),
+    )
+}
+
+/// The loader's console font only covers 8-bit characters
+fn push_name(blob: &mut Vec<u8>, name: &str) {
+    let name = name
+        .chars()
+        .map(|c| if c.is_ascii() { c as u8 } else { b'?' })
+        .take(u8::MAX as usize)
+        .collect::<Vec<_>>();
+
+    blob.push(name.len() as u8);
+    blob.extend(name);
+}